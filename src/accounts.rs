@@ -0,0 +1,116 @@
+use crate::{
+    encryption::open_snapshot,
+    error::ErrorKind,
+    snapshot::{visit_snapshot_accounts, SnapshotHeader},
+    types::AccountSummary,
+};
+use alloy::primitives::U256;
+use anyhow::{Context, Result};
+use std::path::Path;
+
+#[derive(Debug, Clone, Default)]
+pub struct AccountFilter {
+    pub has_code: bool,
+    pub min_balance: Option<U256>,
+}
+
+impl AccountFilter {
+    pub fn matches(&self, account: &AccountSummary) -> bool {
+        (!self.has_code || account.has_code()) && self.min_balance.is_none_or(|min| account.balance >= min)
+    }
+}
+
+#[derive(Debug)]
+pub struct AccountPage {
+    pub header: SnapshotHeader,
+    pub returned: u64,
+    // The offset of the next page, None if this one is the last
+    pub next_offset: Option<u64>,
+}
+
+// Pages through the accounts of a snapshot matching `filter`, in address order so that offsets stay stable between
+// calls on the same snapshot
+pub fn iter_accounts_in(
+    data: &[u8],
+    filter: &AccountFilter,
+    offset: u64,
+    limit: Option<u64>,
+    mut visit: impl FnMut(&AccountSummary) -> Result<()>,
+) -> Result<AccountPage> {
+    let (mut matched, mut returned, mut more) = (0, 0, false);
+    let header = visit_snapshot_accounts(data, &mut |account| {
+        if !filter.matches(&account) {
+            return Ok(true);
+        }
+        matched += 1;
+        if matched <= offset {
+            return Ok(true);
+        }
+        if limit.is_some_and(|limit| returned >= limit) {
+            more = true;
+            return Ok(false);
+        }
+        visit(&account)?;
+        returned += 1;
+        Ok(true)
+    })?;
+    Ok(AccountPage { header, returned, next_offset: more.then_some(offset + returned) })
+}
+
+pub fn iter_accounts(
+    fln: &Path,
+    filter: &AccountFilter,
+    offset: u64,
+    limit: Option<u64>,
+    visit: impl FnMut(&AccountSummary) -> Result<()>,
+) -> Result<AccountPage> {
+    let data = std::fs::read(fln)
+        .with_context(|| format!("failed to read {}", fln.display()))
+        .context(ErrorKind::MissingFile)?;
+    iter_accounts_in(&open_snapshot(data)?, filter, offset, limit, visit)
+        .with_context(|| format!("failed to read the accounts of {}", fln.display()))
+        .context(ErrorKind::CorruptData)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        accounts::{iter_accounts_in, AccountFilter},
+        snapshot::{encode_snapshot, SnapshotFormat},
+        state::State,
+        types::EvmState,
+    };
+    use alloy::primitives::{Address, Bytes, U256};
+    use revm::{primitives::AccountInfo, InMemoryDB};
+
+    #[test]
+    fn test_iter_accounts() {
+        let mut db = InMemoryDB::default();
+        for i in 1..=5u8 {
+            let info = AccountInfo { balance: U256::from(i), ..AccountInfo::default() };
+            db.insert_account_info(Address::repeat_byte(i), info);
+        }
+        db.inject_contract(Address::repeat_byte(9), Bytes::from_static(&[0x60, 0x00, 0xf3]));
+        db.insert_storage(Address::repeat_byte(9), U256::from(1), U256::from(2));
+        let state: EvmState = db.into();
+        for format in [SnapshotFormat::Rmp, SnapshotFormat::Flat] {
+            let encoded = encode_snapshot(10, &state, format, None, None).unwrap();
+            let page = |filter: &AccountFilter, offset, limit| {
+                let mut addresses = Vec::new();
+                let page = iter_accounts_in(&encoded, filter, offset, limit, |account| {
+                    addresses.push(account.address);
+                    Ok(())
+                })
+                .unwrap();
+                (addresses, page.next_offset)
+            };
+            let all = AccountFilter::default();
+            assert_eq!(page(&all, 1, Some(2)), (vec![Address::repeat_byte(2), Address::repeat_byte(3)], Some(3)));
+            assert_eq!(page(&all, 4, Some(2)), (vec![Address::repeat_byte(5), Address::repeat_byte(9)], None));
+            let contracts = AccountFilter { has_code: true, min_balance: None };
+            assert_eq!(page(&contracts, 0, None), (vec![Address::repeat_byte(9)], None));
+            let rich = AccountFilter { has_code: false, min_balance: Some(U256::from(4)) };
+            assert_eq!(page(&rich, 0, None).0, [Address::repeat_byte(4), Address::repeat_byte(5)]);
+        }
+    }
+}
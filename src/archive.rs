@@ -0,0 +1,77 @@
+use crate::{
+    chainspec::ChainSpec,
+    diff::StateDiff,
+    fs::{read_evm_state, ShardLayout},
+    run::deploy_system_contracts,
+    state::State,
+    validate::collect_snapshots,
+};
+use alloy::primitives::B256;
+use anyhow::{anyhow, Context, Result};
+use revm::InMemoryDB;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::create_dir_all,
+    path::{Path, PathBuf},
+};
+
+const ARCHIVE_EXTENSION: &str = "diff.rmp";
+
+// Everything a block changed, so the state after any archived block can be rebuilt from an earlier snapshot
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArchiveEntry {
+    pub block_number: u64,
+    pub block_hash: B256,
+    pub state_diff: StateDiff,
+}
+
+pub(crate) fn archive_file_number(path: &Path) -> Option<u64> {
+    path.file_name()?.to_str()?.strip_suffix(".diff.rmp")?.parse().ok()
+}
+
+fn entry_path(dir: &Path, block_number: u64) -> PathBuf {
+    ShardLayout::default().local_path(dir, block_number, ARCHIVE_EXTENSION)
+}
+
+pub fn write_archive_entry(dir: &Path, entry: &ArchiveEntry) -> Result<()> {
+    let path = entry_path(dir, entry.block_number);
+    create_dir_all(path.parent().unwrap())?;
+    std::fs::write(path, rmp_serde::to_vec(entry)?)?;
+    Ok(())
+}
+
+pub fn read_archive_entry(dir: &Path, block_number: u64) -> Result<ArchiveEntry> {
+    let path = entry_path(dir, block_number);
+    let data = std::fs::read(&path)
+        .with_context(|| format!("no archive entry for block {block_number} at {}", path.display()))?;
+    Ok(rmp_serde::from_slice(&data)?)
+}
+
+// System contract deployments and block hashes are applied outside of transactions, so they are not in the diff
+pub(crate) fn apply_entry(state: &mut InMemoryDB, spec: &ChainSpec, entry: &ArchiveEntry) {
+    deploy_system_contracts(state, spec, entry.block_number);
+    entry.state_diff.apply(state);
+    if entry.block_number >= spec.non_placeholder_block_hash_height {
+        state.insert_block_hash(entry.block_number, entry.block_hash);
+    }
+}
+
+// The latest of the snapshots (files or directories of them) that `block` can be rebuilt from, with its next block
+pub fn snapshot_before(snapshots: &[PathBuf], block: u64) -> Result<(u64, PathBuf)> {
+    collect_snapshots(snapshots)?
+        .into_iter()
+        .rev()
+        .find(|(next_block_num, _)| *next_block_num <= block + 1)
+        .ok_or_else(|| anyhow!("no snapshot at or before block {block}"))
+}
+
+// Rebuilds the state after `block` from the latest snapshot at or before it and the archived diffs since then
+pub fn state_at(spec: &ChainSpec, snapshots: &[PathBuf], archive_dir: &Path, block: u64) -> Result<InMemoryDB> {
+    let (next_block_num, fln) = snapshot_before(snapshots, block)?;
+    let (_, mut state) = read_evm_state(&fln)?;
+    for block_number in next_block_num..=block {
+        let entry = read_archive_entry(archive_dir, block_number)?;
+        apply_entry(&mut state, spec, &entry);
+    }
+    Ok(state)
+}
@@ -0,0 +1,85 @@
+use crate::{chainspec::ChainSpec, diff::StateDiff, jsonl::JsonLinesLog, query::erc20_balance};
+use alloy::primitives::{Address, U256};
+use anyhow::{anyhow, Result};
+use revm::DatabaseRef;
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::Path,
+    sync::Mutex,
+};
+
+// Written whenever a tracked balance differs from its previous record; `token` is None for the native balance
+#[derive(Debug, Serialize)]
+pub struct BalanceRecord {
+    pub block: u64,
+    pub address: Address,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<Address>,
+    pub balance: U256,
+}
+
+#[derive(Default)]
+struct Tracked {
+    last: BTreeMap<(Address, Option<Address>), U256>,
+    // Token contracts changed since the last sample, whose balances have to be queried again
+    dirty_tokens: BTreeSet<Address>,
+    sampled: bool,
+}
+
+// Samples the native and ERC20 balances of a few addresses every `every` blocks into a JSON lines log. Only changes
+// are written, after a full record at the first sample.
+pub struct BalanceTracker {
+    spec: ChainSpec,
+    addresses: Vec<Address>,
+    tokens: Vec<Address>,
+    every: u64,
+    log: JsonLinesLog,
+    tracked: Mutex<Tracked>,
+}
+
+impl BalanceTracker {
+    pub fn create(
+        spec: ChainSpec,
+        fln: &Path,
+        addresses: Vec<Address>,
+        tokens: Vec<Address>,
+        every: u64,
+    ) -> Result<Self> {
+        Ok(Self { spec, addresses, tokens, every, log: JsonLinesLog::create(fln)?, tracked: Mutex::default() })
+    }
+
+    pub fn record<S>(&self, state: &S, block: u64, diff: &StateDiff) -> Result<()>
+    where
+        S: DatabaseRef,
+        <S as DatabaseRef>::Error: std::fmt::Debug,
+    {
+        let mut tracked = self.tracked.lock().unwrap();
+        tracked.dirty_tokens.extend(self.tokens.iter().filter(|token| diff.accounts.contains_key(token)));
+        if block % self.every != 0 {
+            return Ok(());
+        }
+        let mut records = Vec::new();
+        for &address in &self.addresses {
+            let native = state.basic_ref(address).map_err(|e| anyhow!("{e:?}"))?.unwrap_or_default().balance;
+            records.push(BalanceRecord { block, address, token: None, balance: native });
+            for &token in &self.tokens {
+                if tracked.sampled && !tracked.dirty_tokens.contains(&token) {
+                    continue;
+                }
+                let deployed = state.basic_ref(token).map_err(|e| anyhow!("{e:?}"))?.is_some();
+                let balance =
+                    if deployed { erc20_balance(&self.spec, state, block + 1, token, address)? } else { U256::ZERO };
+                records.push(BalanceRecord { block, address, token: Some(token), balance });
+            }
+        }
+        let first = !tracked.sampled;
+        records.retain(|record| {
+            let previous = tracked.last.insert((record.address, record.token), record.balance);
+            first || previous != Some(record.balance)
+        });
+        tracked.dirty_tokens.clear();
+        tracked.sampled = true;
+        self.log.write(&records)
+    }
+}
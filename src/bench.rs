@@ -0,0 +1,179 @@
+use crate::{
+    fs::{read_blocks, BlockSource},
+    inspector::ReplayInspector,
+    run::{run_blocks, RunConfig},
+    types::{BlockAndReceipts, EvmBlock},
+};
+use anyhow::{anyhow, Result};
+use revm::InMemoryDB;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::Display,
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+pub struct StageTimings {
+    pub read: Duration,
+    pub sender_recovery: Duration,
+    pub execution: Duration,
+    pub commit: Duration,
+    pub snapshot: Duration,
+    pub hashing: Duration,
+}
+
+impl StageTimings {
+    pub const fn stages(&self) -> [(&'static str, Duration); 6] {
+        [
+            ("read", self.read),
+            ("sender_recovery", self.sender_recovery),
+            ("execution", self.execution),
+            ("commit", self.commit),
+            ("snapshot", self.snapshot),
+            ("hashing", self.hashing),
+        ]
+    }
+
+    pub fn total(&self) -> Duration {
+        self.stages().into_iter().map(|(_, duration)| duration).sum()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BenchReport {
+    pub start_block: u64,
+    pub end_block: u64,
+    pub gas_used: u64,
+    pub elapsed: Duration,
+    pub timings: StageTimings,
+}
+
+impl BenchReport {
+    pub const fn blocks(&self) -> u64 {
+        self.end_block - self.start_block + 1
+    }
+
+    pub fn blocks_per_sec(&self) -> f64 {
+        self.blocks() as f64 / self.elapsed.as_secs_f64()
+    }
+
+    pub fn gas_per_sec(&self) -> f64 {
+        self.gas_used as f64 / self.elapsed.as_secs_f64()
+    }
+}
+
+impl Display for BenchReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let elapsed = self.elapsed.as_secs_f64();
+        writeln!(
+            f,
+            "Replayed blocks {}-{} (n={}) in {:?}",
+            self.start_block,
+            self.end_block,
+            self.blocks(),
+            self.elapsed
+        )?;
+        let other = self.elapsed.saturating_sub(self.timings.total());
+        for (stage, duration) in self.timings.stages().into_iter().chain([("other", other)]) {
+            writeln!(f, "{stage:>16}: {:>12.3?} ({:>5.1}%)", duration, 100.0 * duration.as_secs_f64() / elapsed)?;
+        }
+        writeln!(f, "blocks/sec: {:.1}", self.blocks_per_sec())?;
+        write!(f, "gas/sec: {:.0}", self.gas_per_sec())
+    }
+}
+
+// Replays start_block..=end_block synchronously so stage timings are not skewed by the reader/processor overlap
+pub fn bench(
+    config: &RunConfig,
+    source: &BlockSource,
+    state: &mut InMemoryDB,
+    start_block: u64,
+    end_block: u64,
+    read_limit: u64,
+    inspector: &mut ReplayInspector,
+) -> Result<BenchReport> {
+    let mut timings = StageTimings::default();
+    let mut gas_used = 0;
+    let start = Instant::now();
+    let mut cur_block = start_block;
+    while cur_block <= end_block {
+        let last_block_in_chunk = end_block.min(cur_block + read_limit - 1);
+        let blocks = read_blocks(source, cur_block, last_block_in_chunk, config.chunk_size, &mut timings)?;
+        for block in blocks.iter().flat_map(|(_, chunk)| chunk) {
+            let BlockAndReceipts { block: EvmBlock::Reth115(block), .. } = &block.block_and_receipts;
+            gas_used += block.header().gas_used;
+        }
+        run_blocks(None, config, state, blocks, &mut timings, inspector)?;
+        cur_block = last_block_in_chunk + 1;
+    }
+    Ok(BenchReport { start_block, end_block, gas_used, elapsed: start.elapsed(), timings })
+}
+
+#[derive(Debug, Serialize)]
+pub struct StageComparison {
+    pub stage: &'static str,
+    pub baseline: Duration,
+    pub current: Duration,
+    pub change_pct: f64,
+}
+
+impl StageComparison {
+    fn new(stage: &'static str, baseline: Duration, current: Duration) -> Self {
+        let change_pct =
+            if baseline.is_zero() { 0.0 } else { 100.0 * (current.as_secs_f64() / baseline.as_secs_f64() - 1.0) };
+        Self { stage, baseline, current, change_pct }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct BenchComparison {
+    pub threshold_pct: f64,
+    pub stages: Vec<StageComparison>,
+    pub total: StageComparison,
+    pub passed: bool,
+}
+
+impl Display for BenchComparison {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for stage in self.stages.iter().chain([&self.total]) {
+            writeln!(
+                f,
+                "{:>16}: {:>12.3?} -> {:>12.3?} ({:+.1}%)",
+                stage.stage, stage.baseline, stage.current, stage.change_pct
+            )?;
+        }
+        let verdict = if self.passed { "PASS" } else { "FAIL" };
+        write!(
+            f,
+            "{verdict}: total time changed by {:+.1}% (threshold {:.1}%)",
+            self.total.change_pct, self.threshold_pct
+        )
+    }
+}
+
+// Only the total is held to the threshold, since individual stages are too short on small ranges to be stable
+pub fn compare_to_baseline(
+    baseline: &BenchReport,
+    current: &BenchReport,
+    threshold_pct: f64,
+) -> Result<BenchComparison> {
+    if (baseline.start_block, baseline.end_block) != (current.start_block, current.end_block) {
+        return Err(anyhow!(
+            "baseline covers blocks {}-{} but this run replayed {}-{}",
+            baseline.start_block,
+            baseline.end_block,
+            current.start_block,
+            current.end_block
+        ));
+    }
+    let stages = baseline
+        .timings
+        .stages()
+        .into_iter()
+        .zip(current.timings.stages())
+        .map(|((stage, baseline), (_, current))| StageComparison::new(stage, baseline, current))
+        .collect();
+    let total = StageComparison::new("total", baseline.elapsed, current.elapsed);
+    let passed = total.change_pct <= threshold_pct;
+    Ok(BenchComparison { threshold_pct, stages, total, passed })
+}
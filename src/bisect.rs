@@ -0,0 +1,209 @@
+use crate::{
+    archive::read_archive_entry,
+    bench::StageTimings,
+    cli::Chain,
+    compare::{compare_states, StateComparison},
+    diff::StateDiff,
+    error::ErrorKind,
+    fs::{find_snapshot, read_blocks, read_evm_state, BlockSource},
+    inspector::ReplayInspector,
+    run::{run_blocks, RunConfig},
+    state::{State, StateHash},
+};
+use anyhow::{anyhow, Context, Result};
+use clap::ValueEnum;
+use revm::InMemoryDB;
+use serde::Serialize;
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+// only store this many blocks in memory while replaying
+const READ_LIMIT: u64 = 100000;
+
+// Another build of this tool, run as `{bin} sync-from-state ...` over the same blocks
+pub struct OtherVersion {
+    pub bin: PathBuf,
+    // The block source flags, and any others the build should replay with
+    pub args: Vec<String>,
+}
+
+// Where the other build starts from: a snapshot it wrote (or the one both sides started from), or genesis
+struct OtherStart {
+    fln: Option<PathBuf>,
+    is_abci: bool,
+}
+
+impl OtherVersion {
+    // Replays through `end_block` into a fresh `out_dir`, returning the snapshot it wrote there
+    fn replay(
+        &self,
+        chain: Chain,
+        start: &OtherStart,
+        end_block: u64,
+        out_dir: &Path,
+        archive_dir: Option<&Path>,
+    ) -> Result<PathBuf> {
+        std::fs::create_dir_all(out_dir)?;
+        let mut command = Command::new(&self.bin);
+        command.arg("sync-from-state").arg("--chain").arg(chain.to_possible_value().unwrap().get_name());
+        if let Some(fln) = &start.fln {
+            command.arg("--fln").arg(fln);
+        }
+        if start.is_abci {
+            command.arg("--is-abci");
+        }
+        command.arg("--end-block").arg(end_block.to_string()).arg("--snapshot-dir").arg(out_dir);
+        if let Some(archive_dir) = archive_dir {
+            command.arg("--archive-dir").arg(archive_dir);
+        }
+        let status = command
+            .args(&self.args)
+            .status()
+            .with_context(|| format!("failed to run {}", self.bin.display()))
+            .context(ErrorKind::Usage)?;
+        if !status.success() {
+            return Err(anyhow!("{} exited with {status} replaying to block {end_block}", self.bin.display()));
+        }
+        match find_snapshot(out_dir, chain, Some(end_block + 1))? {
+            Some((header, path)) if header.next_block_num == end_block + 1 => Ok(path),
+            _ => Err(anyhow!(
+                "{} wrote no snapshot after block {end_block} to {}",
+                self.bin.display(),
+                out_dir.display()
+            )),
+        }
+    }
+}
+
+fn replay(
+    config: &RunConfig,
+    source: &BlockSource,
+    state: &mut InMemoryDB,
+    start_block: u64,
+    end_block: u64,
+) -> Result<()> {
+    let mut timings = StageTimings::default();
+    let mut inspector = ReplayInspector::default();
+    let mut cur_block = start_block;
+    while cur_block <= end_block {
+        let last_block_in_chunk = end_block.min(cur_block + READ_LIMIT - 1);
+        let blocks = read_blocks(source, cur_block, last_block_in_chunk, config.chunk_size, &mut timings)?;
+        run_blocks(None, config, state, blocks, &mut timings, &mut inspector)?;
+        cur_block = last_block_in_chunk + 1;
+    }
+    Ok(())
+}
+
+#[derive(Debug, Serialize)]
+pub struct BlockDiffs {
+    pub block: u64,
+    pub ours: StateDiff,
+    pub theirs: StateDiff,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Divergence {
+    // The last block both sides agree on, and the first one after it they do not
+    pub last_agreeing_block: u64,
+    pub first_divergent_block: u64,
+    pub ours: StateHash,
+    pub theirs: StateHash,
+    // Replays of each side, halving the range every time
+    pub steps: usize,
+    pub comparison: StateComparison,
+    #[serde(skip)]
+    pub diffs: Option<BlockDiffs>,
+}
+
+impl Display for Divergence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "First divergent block {} (agreeing up to block {}), found in {} steps",
+            self.first_divergent_block, self.last_agreeing_block, self.steps
+        )?;
+        writeln!(f, "ours:   {}", self.ours.digest())?;
+        writeln!(f, "theirs: {}", self.theirs.digest())?;
+        write!(f, "{}", self.comparison)
+    }
+}
+
+// Binary-searches the first block after which this build's state differs from the other build's, both starting
+// from `state` at `start_block`. The range is known to diverge by block `end_block`, which is checked first. Each
+// step replays the rest of the range on both sides and compares state hashes, so the search needs about twice the
+// replay time of the range and memory for three states. The other build's snapshots go under `work_dir`.
+#[allow(clippy::too_many_arguments)]
+pub fn bisect_divergence(
+    config: &mut RunConfig,
+    source: &BlockSource,
+    state: InMemoryDB,
+    start_fln: Option<PathBuf>,
+    is_abci: bool,
+    start_block: u64,
+    end_block: u64,
+    other: &OtherVersion,
+    work_dir: &Path,
+    dump_diffs: bool,
+    limit: usize,
+) -> Result<Divergence> {
+    if end_block < start_block {
+        return Err(anyhow!("the state is already past block {end_block}")).context(ErrorKind::Usage);
+    }
+    let chain = config.chain;
+    let (mut lo, mut hi) = (start_block - 1, end_block);
+    let (mut lo_state, mut lo_other) = (state, OtherStart { fln: start_fln, is_abci });
+    let mut steps = 0;
+    let mut step = |lo_state: &InMemoryDB, lo_other: &OtherStart, lo: u64, block: u64| -> Result<_> {
+        steps += 1;
+        println!("Step {steps}: replaying blocks {}-{block} on both sides", lo + 1);
+        let mut ours = lo_state.clone();
+        replay(config, source, &mut ours, lo + 1, block)?;
+        let theirs_fln = other.replay(chain, lo_other, block, &work_dir.join(format!("other-{block}")), None)?;
+        let (_, theirs) = read_evm_state(&theirs_fln)?;
+        let (ours_hash, theirs_hash) = (ours.blake3_hash_slow(), theirs.blake3_hash_slow());
+        Ok((ours, theirs, ours_hash, theirs_hash, theirs_fln))
+    };
+
+    let (mut ours_hi, mut theirs_hi, mut ours_hash, mut theirs_hash, _) = step(&lo_state, &lo_other, lo, hi)?;
+    if ours_hash == theirs_hash {
+        return Err(anyhow!("both builds agree on the state after block {hi}")).context(ErrorKind::Usage);
+    }
+    while hi - lo > 1 {
+        let mid = lo + (hi - lo) / 2;
+        let (ours, theirs, ours_mid, theirs_mid, theirs_fln) = step(&lo_state, &lo_other, lo, mid)?;
+        if ours_mid == theirs_mid {
+            (lo, lo_state) = (mid, ours);
+            lo_other = OtherStart { fln: Some(theirs_fln), is_abci: false };
+        } else {
+            (hi, ours_hi, theirs_hi, ours_hash, theirs_hash) = (mid, ours, theirs, ours_mid, theirs_mid);
+        }
+    }
+
+    // The archived diffs of the divergent block, from one more replay of it on each side
+    let diffs = if dump_diffs {
+        let (ours_dir, theirs_dir) = (work_dir.join("archive-ours"), work_dir.join("archive-theirs"));
+        let previous_archive_dir = config.archive_dir.replace(ours_dir.clone());
+        replay(config, source, &mut lo_state, hi, hi)?;
+        config.archive_dir = previous_archive_dir;
+        other.replay(chain, &lo_other, hi, &work_dir.join(format!("other-{hi}-diff")), Some(&theirs_dir))?;
+        Some(BlockDiffs {
+            block: hi,
+            ours: read_archive_entry(&ours_dir, hi)?.state_diff,
+            theirs: read_archive_entry(&theirs_dir, hi)?.state_diff,
+        })
+    } else {
+        None
+    };
+    Ok(Divergence {
+        last_agreeing_block: lo,
+        first_divergent_block: hi,
+        ours: ours_hash,
+        theirs: theirs_hash,
+        steps,
+        comparison: compare_states(hi + 1, &ours_hi, &theirs_hi, limit),
+        diffs,
+    })
+}
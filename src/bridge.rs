@@ -0,0 +1,105 @@
+use crate::run::NATIVE_TOKEN_SYSTEM_ADDRESS;
+use alloy::{
+    consensus::Transaction as _,
+    primitives::{b256, Address, Log, B256, U256},
+};
+use reth_primitives::Transaction;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+const ERC20_TRANSFER_SELECTOR: [u8; 4] = [0xa9, 0x05, 0x9c, 0xbb];
+// keccak256("Transfer(address,address,uint256)")
+const TRANSFER_TOPIC: B256 = b256!("0xddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef");
+// keccak256("Received(address,uint256)"), emitted by the native token system contract when it is sent HYPE
+const RECEIVED_TOPIC: B256 = b256!("0x88a5966d370b9919b20f3e2c13ff65706f196a4e32cc2c12bf57088f88525874");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BridgeDirection {
+    CoreToEvm,
+    EvmToCore,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BridgeTransfer {
+    pub block: u64,
+    // Index into the block's system transactions if `system_tx`, otherwise into its regular transactions
+    pub tx_index: usize,
+    pub system_tx: bool,
+    pub direction: BridgeDirection,
+    // None for native HYPE
+    pub token: Option<Address>,
+    pub system_address: Address,
+    pub account: Address,
+    pub amount: U256,
+    pub success: bool,
+}
+
+// System transactions credit Core balances on the EVM side: native HYPE is sent as a plain value transfer and ERC20
+// tokens as a transfer(address,uint256) call from the token's system address
+pub fn system_tx_transfer(
+    block: u64,
+    tx_index: usize,
+    tx: &Transaction,
+    sender: Address,
+    success: bool,
+) -> BridgeTransfer {
+    let input = tx.input();
+    let (token, account, amount) = if input.is_empty() {
+        (None, tx.to().unwrap_or_default(), tx.value())
+    } else if input.len() >= 68 && input[..4] == ERC20_TRANSFER_SELECTOR {
+        (tx.to(), Address::from_slice(&input[16..36]), U256::from_be_slice(&input[36..68]))
+    } else {
+        (tx.to(), Address::ZERO, U256::ZERO)
+    };
+    BridgeTransfer {
+        block,
+        tx_index,
+        system_tx: true,
+        direction: BridgeDirection::CoreToEvm,
+        token,
+        system_address: sender,
+        account,
+        amount,
+        success,
+    }
+}
+
+// Transfers back to Core are sends to a system address: HYPE to the native token system contract, or ERC20 tokens to
+// the token's system address
+pub fn user_tx_transfers(
+    block: u64,
+    tx_index: usize,
+    logs: &[Log],
+    erc20_contract_to_system_address: &BTreeMap<Address, Address>,
+) -> Vec<BridgeTransfer> {
+    let mut transfers = Vec::new();
+    for log in logs {
+        let topics = log.topics();
+        let transfer = |token, system_address, account, amount| BridgeTransfer {
+            block,
+            tx_index,
+            system_tx: false,
+            direction: BridgeDirection::EvmToCore,
+            token,
+            system_address,
+            account,
+            amount,
+            success: true,
+        };
+        let amount = || log.data.data.get(..32).map_or(U256::ZERO, U256::from_be_slice);
+        match topics {
+            [topic, user] if *topic == RECEIVED_TOPIC && log.address == NATIVE_TOKEN_SYSTEM_ADDRESS => {
+                transfers.push(transfer(None, NATIVE_TOKEN_SYSTEM_ADDRESS, Address::from_word(*user), amount()));
+            }
+            [topic, from, to] if *topic == TRANSFER_TOPIC => {
+                let to = Address::from_word(*to);
+                if erc20_contract_to_system_address.get(&log.address) == Some(&to) {
+                    transfers.push(transfer(Some(log.address), to, Address::from_word(*from), amount()));
+                }
+            }
+            _ => {}
+        }
+    }
+    transfers
+}
@@ -0,0 +1,168 @@
+use crate::sink::{AccountDiffRow, Batch, LogRow, SinkWriter, StorageDiffRow, TransactionRow};
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use futures::future::try_join_all;
+use rskafka::{
+    client::{
+        partition::{Compression, PartitionClient, UnknownTopicHandling},
+        ClientBuilder,
+    },
+    record::Record,
+};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    future::IntoFuture,
+    path::{Path, PathBuf},
+};
+use tokio::runtime::Runtime;
+
+// Every topic gets a message per block, keyed by the block number
+const TOPICS: [&str; 3] = ["blocks", "receipts", "state_diffs"];
+
+#[derive(Serialize)]
+struct Receipts<'a> {
+    block: u64,
+    transactions: Vec<&'a TransactionRow>,
+    logs: Vec<&'a LogRow>,
+}
+
+#[derive(Serialize)]
+struct StateDiffs<'a> {
+    block: u64,
+    accounts: Vec<&'a AccountDiffRow>,
+    storage: Vec<&'a StorageDiffRow>,
+}
+
+// The messages of each topic, in block order
+fn messages(batch: &Batch) -> Result<Vec<(&'static str, u64, Vec<u8>)>> {
+    let mut receipts: BTreeMap<u64, Receipts<'_>> = BTreeMap::new();
+    let mut state_diffs: BTreeMap<u64, StateDiffs<'_>> = BTreeMap::new();
+    for block in &batch.blocks {
+        let block = block.number;
+        receipts.insert(block, Receipts { block, transactions: Vec::new(), logs: Vec::new() });
+        state_diffs.insert(block, StateDiffs { block, accounts: Vec::new(), storage: Vec::new() });
+    }
+    for tx in &batch.transactions {
+        receipts.get_mut(&tx.block).unwrap().transactions.push(tx);
+    }
+    for log in &batch.logs {
+        receipts.get_mut(&log.block).unwrap().logs.push(log);
+    }
+    for account in &batch.account_diffs {
+        state_diffs.get_mut(&account.block).unwrap().accounts.push(account);
+    }
+    for slot in &batch.storage_diffs {
+        state_diffs.get_mut(&slot.block).unwrap().storage.push(slot);
+    }
+    let mut messages = Vec::new();
+    for block in &batch.blocks {
+        messages.push(("blocks", block.number, serde_json::to_vec(block)?));
+    }
+    for (block, receipts) in receipts {
+        messages.push(("receipts", block, serde_json::to_vec(&receipts)?));
+    }
+    for (block, state_diffs) in state_diffs {
+        messages.push(("state_diffs", block, serde_json::to_vec(&state_diffs)?));
+    }
+    Ok(messages)
+}
+
+enum Publisher {
+    // A single partition per topic keeps every topic in block order
+    Kafka(HashMap<&'static str, PartitionClient>),
+    Nats(async_nats::jetstream::Context),
+}
+
+// Publishes to kafka://broker[,broker...][/prefix] topics or nats://host[:port][/prefix] JetStream subjects, named
+// `{prefix}.blocks`, `{prefix}.receipts`, and `{prefix}.state_diffs`. Delivery is at least once: the offset file only
+// advances after the broker acknowledged a batch, so blocks after it are published again after a crash.
+pub struct MessageBus {
+    runtime: Runtime,
+    publisher: Publisher,
+    prefix: String,
+    offset_file: PathBuf,
+}
+
+impl MessageBus {
+    pub fn connect(url: &str, default_prefix: &str, offset_dir: &Path) -> Result<Self> {
+        let (scheme, rest) = url.split_once("://").ok_or_else(|| anyhow!("invalid message bus URL {url}"))?;
+        let (servers, prefix) = rest.split_once('/').unwrap_or((rest, ""));
+        let prefix = if prefix.is_empty() { default_prefix } else { prefix }.to_owned();
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+        let publisher = runtime.block_on(async {
+            match scheme {
+                "kafka" => {
+                    let brokers = servers.split(',').map(ToOwned::to_owned).collect();
+                    let client = ClientBuilder::new(brokers).build().await?;
+                    let mut partitions = HashMap::new();
+                    for topic in TOPICS {
+                        let partition = client
+                            .partition_client(format!("{prefix}.{topic}"), 0, UnknownTopicHandling::Retry)
+                            .await?;
+                        partitions.insert(topic, partition);
+                    }
+                    Ok(Publisher::Kafka(partitions))
+                }
+                "nats" => {
+                    let client = async_nats::connect(servers).await?;
+                    Ok(Publisher::Nats(async_nats::jetstream::new(client)))
+                }
+                _ => Err(anyhow!("unsupported message bus {url}: expected a kafka:// or nats:// URL")),
+            }
+        })?;
+        let offset_file = offset_dir.join(format!("{prefix}.offset"));
+        Ok(Self { runtime, publisher, prefix, offset_file })
+    }
+
+    async fn publish(&self, messages: Vec<(&'static str, u64, Vec<u8>)>) -> Result<()> {
+        match &self.publisher {
+            Publisher::Kafka(partitions) => {
+                let mut records: HashMap<&str, Vec<Record>> = HashMap::new();
+                for (topic, block, payload) in messages {
+                    records.entry(topic).or_default().push(Record {
+                        key: Some(block.to_string().into_bytes()),
+                        value: Some(payload),
+                        headers: BTreeMap::new(),
+                        timestamp: Utc::now(),
+                    });
+                }
+                try_join_all(
+                    records
+                        .into_iter()
+                        .map(|(topic, records)| partitions[topic].produce(records, Compression::NoCompression)),
+                )
+                .await?;
+            }
+            Publisher::Nats(jetstream) => {
+                let mut acks = Vec::new();
+                for (topic, block, payload) in messages {
+                    let mut headers = async_nats::HeaderMap::new();
+                    headers.insert("block", block.to_string().as_str());
+                    let subject = format!("{}.{topic}", self.prefix);
+                    acks.push(jetstream.publish_with_headers(subject, headers, payload.into()).await?);
+                }
+                try_join_all(acks.into_iter().map(IntoFuture::into_future)).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl SinkWriter for MessageBus {
+    fn offset(&mut self, _chain: &str) -> Result<Option<u64>> {
+        match std::fs::read_to_string(&self.offset_file) {
+            Ok(offset) => Ok(Some(offset.trim().parse()?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn write(&mut self, _chain: &str, batch: &Batch) -> Result<()> {
+        self.runtime.block_on(self.publish(messages(batch)?))?;
+        let partial = self.offset_file.with_extension("offset.partial");
+        std::fs::write(&partial, batch.last_block.to_string())?;
+        std::fs::rename(partial, &self.offset_file)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,180 @@
+use crate::{
+    archive::{apply_entry, ArchiveEntry},
+    chainspec::ChainSpec,
+    fs::BlockSource,
+    state::StateHash,
+    types::EvmBlock,
+};
+use alloy::primitives::{hex, B256};
+use anyhow::{anyhow, Result};
+use reth_primitives::{proofs::calculate_receipt_root, Receipt};
+use revm::InMemoryDB;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::create_dir_all,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+const CACHE_EXTENSION: &str = "rmp";
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedBlock {
+    entry: ArchiveEntry,
+    receipts_root: B256,
+    // Only for blocks the state was hashed after
+    state_hash: Option<StateHash>,
+}
+
+fn hash_pair(a: &[u8], b: &[u8]) -> B256 {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(a);
+    hasher.update(b);
+    B256::from(*hasher.finalize().as_bytes())
+}
+
+// The key of a block's result: its pre-state and the block itself
+fn result_key(pre_state: B256, block_hash: B256) -> B256 {
+    hash_pair(pre_state.as_slice(), block_hash.as_slice())
+}
+
+// A block's post-state is its pre-state with the block's entry applied, so it is identified by the two of them
+fn post_state(key: B256, entry: &ArchiveEntry) -> Result<B256> {
+    Ok(hash_pair(key.as_slice(), &rmp_serde::to_vec(entry)?))
+}
+
+// Results of executed blocks keyed by (pre-state, block hash), so replaying the same range again applies the cached
+// diffs instead of executing. Rather than hashing the state before every block, pre-states are identified by chaining
+// from the hash of the loaded state through the diffs applied since, so a block whose diff changes gets new keys for
+// every block after it. The build is not part of the key: results from before a code change stay valid unless
+// `execute_from` is set to the first block the change may affect.
+pub struct ResultCache {
+    dir: PathBuf,
+    execute_from: u64,
+    // None once the state was rolled back, after which no keys are known
+    pre_state: Mutex<Option<B256>>,
+}
+
+impl ResultCache {
+    pub fn open(dir: &Path, state_hash: &StateHash, execute_from: Option<u64>) -> Result<Self> {
+        create_dir_all(dir)?;
+        Ok(Self {
+            dir: dir.to_owned(),
+            execute_from: execute_from.unwrap_or(u64::MAX),
+            pre_state: Mutex::new(Some(state_hash.digest())),
+        })
+    }
+
+    // `{dir}/{first key byte}/{key}.rmp`
+    fn path(&self, key: B256) -> PathBuf {
+        let key = hex::encode(key);
+        self.dir.join(&key[..2]).join(format!("{key}.{CACHE_EXTENSION}"))
+    }
+
+    fn read(&self, key: B256) -> Option<CachedBlock> {
+        let data = std::fs::read(self.path(key)).ok()?;
+        match rmp_serde::from_slice(&data) {
+            Ok(cached) => Some(cached),
+            Err(e) => {
+                eprintln!("Warning: ignoring unreadable cached result {key}: {e}");
+                None
+            }
+        }
+    }
+
+    // Applies the cached results of the blocks from `start_block` on, returning the first block that has to be
+    // executed. Applied blocks are not hashed, snapshotted, or logged.
+    pub fn skip_cached(
+        &self,
+        spec: &ChainSpec,
+        source: &BlockSource,
+        state: &mut InMemoryDB,
+        start_block: u64,
+        end_block: u64,
+        verify_receipts_root: bool,
+    ) -> Result<u64> {
+        let mut pre_state = self.pre_state.lock().unwrap();
+        let Some(mut key) = *pre_state else {
+            return Ok(start_block);
+        };
+        let mut next = start_block;
+        while next <= end_block && next < self.execute_from {
+            // Missing blocks are the reader's to report
+            let Ok(block_and_receipts) = source.read_block(next) else {
+                break;
+            };
+            let EvmBlock::Reth115(block) = &block_and_receipts.block;
+            let Some(cached) = self.read(result_key(key, block.hash())) else {
+                break;
+            };
+            if verify_receipts_root && cached.receipts_root != block.header().receipts_root {
+                return Err(anyhow!(
+                    "Receipts root mismatch in cached block {next}: cached {}, header {}",
+                    cached.receipts_root,
+                    block.header().receipts_root
+                ));
+            }
+            apply_entry(state, spec, &cached.entry);
+            key = post_state(result_key(key, block.hash()), &cached.entry)?;
+            next += 1;
+        }
+        *pre_state = Some(key);
+        Ok(next)
+    }
+
+    // Records an executed block, replacing any earlier result for it
+    pub(crate) fn record(
+        &self,
+        entry: ArchiveEntry,
+        receipts: &[Receipt],
+        state_hash: Option<StateHash>,
+    ) -> Result<()> {
+        let mut pre_state = self.pre_state.lock().unwrap();
+        let Some(key) = *pre_state else {
+            return Ok(());
+        };
+        let key = result_key(key, entry.block_hash);
+        let receipts_with_bloom: Vec<_> = receipts.iter().cloned().map(Receipt::with_bloom).collect();
+        let next_pre_state = post_state(key, &entry)?;
+        let cached = CachedBlock { entry, receipts_root: calculate_receipt_root(&receipts_with_bloom), state_hash };
+        let path = self.path(key);
+        create_dir_all(path.parent().unwrap())?;
+        // Renamed into place so that an interrupted write is never read back
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, rmp_serde::to_vec(&cached)?)?;
+        std::fs::rename(&tmp, &path)?;
+        *pre_state = Some(next_pre_state);
+        Ok(())
+    }
+
+    // The rolled-back state is not the one the keys chain from
+    pub(crate) fn stop(&self) {
+        *self.pre_state.lock().unwrap() = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        archive::ArchiveEntry,
+        cache::{post_state, result_key},
+        diff::{AccountDiff, StateDiff},
+    };
+    use alloy::primitives::{Address, B256};
+
+    #[test]
+    fn test_result_keys() {
+        let entry = |accounts| ArchiveEntry {
+            block_number: 1,
+            block_hash: B256::repeat_byte(1),
+            state_diff: StateDiff { accounts, ..StateDiff::default() },
+        };
+        let unchanged = entry([].into());
+        let changed = entry([(Address::repeat_byte(2), AccountDiff::default())].into());
+        let key = result_key(B256::ZERO, B256::repeat_byte(1));
+        assert_ne!(key, result_key(B256::repeat_byte(3), B256::repeat_byte(1)));
+        // A block whose changes differ leaves a different state for the next block to be keyed by
+        assert_eq!(post_state(key, &unchanged).unwrap(), post_state(key, &entry([].into())).unwrap());
+        assert_ne!(post_state(key, &unchanged).unwrap(), post_state(key, &changed).unwrap());
+    }
+}
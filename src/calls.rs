@@ -0,0 +1,217 @@
+use crate::{
+    bench::StageTimings,
+    fs::{read_blocks, BlockSource},
+    inspector::ReplayInspector,
+    report::RankedAccount,
+    run::{run_blocks, RunConfig, CORE_WRITER_ADDRESS, NATIVE_TOKEN_SYSTEM_ADDRESS},
+};
+use alloy::primitives::Address;
+use anyhow::Result;
+use itertools::Itertools;
+use revm::{interpreter::CallOutcome, InMemoryDB};
+use serde::Serialize;
+use std::{collections::BTreeMap, fmt::Display};
+
+// Read precompiles (L1 state the block files carry results for) start at 0x800
+const FIRST_READ_PRECOMPILE: u16 = 0x800;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CallTargetKind {
+    Precompile,
+    ReadPrecompile,
+    CoreWriter,
+    // 0x2222..., which bridges HYPE to the L1
+    NativeBridge,
+    // 0x20...{token index}, which bridges a linked token to the L1
+    TokenBridge,
+}
+
+impl Display for CallTargetKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Precompile => "precompile",
+            Self::ReadPrecompile => "read precompile",
+            Self::CoreWriter => "CoreWriter",
+            Self::NativeBridge => "native bridge",
+            Self::TokenBridge => "token bridge",
+        })
+    }
+}
+
+fn target_kind(address: Address, is_precompile: bool) -> Option<CallTargetKind> {
+    if is_precompile {
+        let read = address[..18].iter().all(|&byte| byte == 0)
+            && u16::from_be_bytes([address[18], address[19]]) >= FIRST_READ_PRECOMPILE;
+        return Some(if read { CallTargetKind::ReadPrecompile } else { CallTargetKind::Precompile });
+    }
+    if address == CORE_WRITER_ADDRESS {
+        Some(CallTargetKind::CoreWriter)
+    } else if address == NATIVE_TOKEN_SYSTEM_ADDRESS {
+        Some(CallTargetKind::NativeBridge)
+    } else if address[0] == 0x20 && address[1..12].iter().all(|&byte| byte == 0) {
+        Some(CallTargetKind::TokenBridge)
+    } else {
+        None
+    }
+}
+
+#[derive(Debug)]
+struct TargetStats {
+    kind: CallTargetKind,
+    calls: u64,
+    failed: u64,
+    gas: u64,
+    callers: BTreeMap<Address, u64>,
+}
+
+// Calls to precompiles and Hyperliquid system contracts, including those made by other contracts. Gas is what the
+// call frame spent.
+#[derive(Debug, Default)]
+pub struct CallStats {
+    targets: BTreeMap<Address, TargetStats>,
+    // The tracked target of each open call frame
+    frames: Vec<Option<Address>>,
+}
+
+impl CallStats {
+    pub(crate) fn call(&mut self, caller: Address, target: Address, is_precompile: bool) {
+        let Some(kind) = target_kind(target, is_precompile) else {
+            self.frames.push(None);
+            return;
+        };
+        let stats = self.targets.entry(target).or_insert_with(|| TargetStats {
+            kind,
+            calls: 0,
+            failed: 0,
+            gas: 0,
+            callers: BTreeMap::new(),
+        });
+        stats.calls += 1;
+        *stats.callers.entry(caller).or_default() += 1;
+        self.frames.push(Some(target));
+    }
+
+    pub(crate) fn call_end(&mut self, outcome: &CallOutcome) {
+        if let Some(Some(target)) = self.frames.pop() {
+            let stats = self.targets.get_mut(&target).unwrap();
+            stats.gas += outcome.result.gas.spent();
+            if !outcome.result.is_ok() {
+                stats.failed += 1;
+            }
+        }
+    }
+
+    pub fn report(&self, start_block: u64, end_block: u64, callers: usize) -> CallReport {
+        let targets = self
+            .targets
+            .iter()
+            .map(|(&address, stats)| CallTargetReport {
+                address,
+                kind: stats.kind,
+                calls: stats.calls,
+                failed: stats.failed,
+                gas: stats.gas,
+                distinct_callers: stats.callers.len(),
+                top_callers: stats
+                    .callers
+                    .iter()
+                    .map(|(&address, &value)| RankedAccount { address, value })
+                    .sorted_by(|a, b| b.value.cmp(&a.value))
+                    .take(callers)
+                    .collect(),
+            })
+            .sorted_by(|a, b| b.calls.cmp(&a.calls))
+            .collect();
+        CallReport { start_block, end_block, targets }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct CallTargetReport {
+    pub address: Address,
+    pub kind: CallTargetKind,
+    pub calls: u64,
+    pub failed: u64,
+    pub gas: u64,
+    pub distinct_callers: usize,
+    // Immediate callers by number of calls
+    pub top_callers: Vec<RankedAccount<u64>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CallReport {
+    pub start_block: u64,
+    pub end_block: u64,
+    // By number of calls
+    pub targets: Vec<CallTargetReport>,
+}
+
+impl Display for CallReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Calls to precompiles and system contracts in blocks {}-{}", self.start_block, self.end_block)?;
+        if self.targets.is_empty() {
+            return write!(f, "\nnone");
+        }
+        for target in &self.targets {
+            write!(
+                f,
+                "\n{} ({}): {} calls ({} failed), {} gas, {} callers",
+                target.address, target.kind, target.calls, target.failed, target.gas, target.distinct_callers
+            )?;
+            for RankedAccount { address, value } in &target.top_callers {
+                write!(f, "\n    {address} {value}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Replays the range with call statistics collected by the inspector
+pub fn call_stats(
+    config: &RunConfig,
+    source: &BlockSource,
+    state: &mut InMemoryDB,
+    start_block: u64,
+    end_block: u64,
+    read_limit: u64,
+    callers: usize,
+) -> Result<CallReport> {
+    let mut timings = StageTimings::default();
+    let mut inspector = ReplayInspector { calls: Some(CallStats::default()), ..ReplayInspector::default() };
+    let mut cur_block = start_block;
+    while cur_block <= end_block {
+        let last_block_in_chunk = end_block.min(cur_block + read_limit - 1);
+        let blocks = read_blocks(source, cur_block, last_block_in_chunk, config.chunk_size, &mut timings)?;
+        run_blocks(None, config, state, blocks, &mut timings, &mut inspector)?;
+        cur_block = last_block_in_chunk + 1;
+    }
+    Ok(inspector.calls.unwrap().report(start_block, end_block, callers))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::calls::{target_kind, CallTargetKind};
+    use alloy::primitives::address;
+
+    #[test]
+    fn test_target_kind() {
+        assert_eq!(
+            target_kind(address!("0x0000000000000000000000000000000000000001"), true),
+            Some(CallTargetKind::Precompile)
+        );
+        assert_eq!(
+            target_kind(address!("0x0000000000000000000000000000000000000801"), true),
+            Some(CallTargetKind::ReadPrecompile)
+        );
+        assert_eq!(
+            target_kind(address!("0x3333333333333333333333333333333333333333"), false),
+            Some(CallTargetKind::CoreWriter)
+        );
+        assert_eq!(
+            target_kind(address!("0x20000000000000000000000000000000000000c5"), false),
+            Some(CallTargetKind::TokenBridge)
+        );
+        assert_eq!(target_kind(address!("0x5555555555555555555555555555555555555555"), false), None);
+    }
+}
@@ -0,0 +1,235 @@
+use crate::{cli::Chain, error::ErrorKind, run::chain_id};
+use alloy::primitives::Address;
+use anyhow::{anyhow, Context, Result};
+use revm::{interpreter::opcode::OpCode, primitives::SpecId};
+use serde::Deserialize;
+use std::{collections::BTreeSet, path::Path, str::FromStr};
+
+// How a spot token's index maps to the system address its bridged balance moves through
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SystemAddressScheme {
+    // 0x20, then zeros, then the index as 8 big-endian bytes
+    PrefixedIndexV1,
+}
+
+impl SystemAddressScheme {
+    pub fn derive(self, token_index: u64) -> Address {
+        match self {
+            Self::PrefixedIndexV1 => {
+                let mut addr = [0u8; 20];
+                addr[0] = 0x20;
+                addr[12..20].copy_from_slice(&token_index.to_be_bytes());
+                Address::from(addr)
+            }
+        }
+    }
+}
+
+// An opcode by name (SELFDESTRUCT) or by value (0xff)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Opcode(pub u8);
+
+impl FromStr for Opcode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if let Some(hex) = s.strip_prefix("0x") {
+            return Ok(Self(u8::from_str_radix(hex, 16).with_context(|| format!("invalid opcode {s}"))?));
+        }
+        (0..=u8::MAX)
+            .find(|&opcode| OpCode::new(opcode).is_some_and(|op| op.as_str().eq_ignore_ascii_case(s)))
+            .map(Self)
+            .ok_or_else(|| anyhow!("unknown opcode {s}"))
+    }
+}
+
+impl TryFrom<String> for Opcode {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+// Who the block env's coinbase is, and so who priority fees are paid to
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Beneficiary {
+    // The fees leave circulation
+    #[default]
+    Zero,
+    Header,
+    Address(Address),
+}
+
+// Execution parameters from `from_block` on, as far as the block header does not already fix them
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EvmRules {
+    pub from_block: u64,
+    // Execute with this gas limit instead of the header's
+    pub block_gas_limit: Option<u64>,
+    // Halt like undefined opcodes
+    pub disabled_opcodes: BTreeSet<Opcode>,
+    pub beneficiary: Beneficiary,
+}
+
+static DEFAULT_EVM_RULES: EvmRules = EvmRules {
+    from_block: 0,
+    block_gas_limit: None,
+    disabled_opcodes: BTreeSet::new(),
+    beneficiary: Beneficiary::Zero,
+};
+
+impl EvmRules {
+    pub fn block_gas_limit(&self, header_gas_limit: u64) -> u64 {
+        self.block_gas_limit.unwrap_or(header_gas_limit)
+    }
+
+    pub fn coinbase(&self, header_beneficiary: Address) -> Address {
+        match self.beneficiary {
+            Beneficiary::Zero => Address::ZERO,
+            Beneficiary::Header => header_beneficiary,
+            Beneficiary::Address(address) => address,
+        }
+    }
+}
+
+// A JSON file of `evm_rules` in ascending order of `from_block`, each replacing the previous from its block on, so a
+// parameter change can be adopted without a release. `chain` guards against the file of another chain.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ChainSpecFile {
+    chain: Option<Chain>,
+    evm_rules: Vec<EvmRules>,
+}
+
+// HyperEVM's upgrade history. Heights are the first block a rule applies to.
+#[derive(Debug, Clone)]
+pub struct ChainSpec {
+    pub chain: Chain,
+    pub chain_id: u64,
+    // (first block, spec) in ascending order of block
+    pub hardforks: Vec<(u64, SpecId)>,
+    // Before this height the blockhash opcode returns keccak256(number.to_string().as_bytes())
+    pub non_placeholder_block_hash_height: u64,
+    // The first block in which the behavior diverges with the hl node due to warm addresses
+    pub warm_precompiles_block: u64,
+    pub core_writer_deploy_block: Option<u64>,
+    // (first token index, scheme) in ascending order of index. A token keeps its system address for good, so a revised
+    // scheme takes effect from the first token listed after its activation rather than from a block.
+    pub system_address_schemes: Vec<(u64, SystemAddressScheme)>,
+    // In ascending order of `from_block`; blocks before the first use the defaults
+    pub evm_rules: Vec<EvmRules>,
+}
+
+impl ChainSpec {
+    pub fn for_chain(chain: Chain) -> Self {
+        let core_writer_deploy_block = match chain {
+            Chain::Mainnet => Some(7_578_299),
+            Chain::Testnet => None,
+        };
+        Self {
+            chain,
+            chain_id: chain_id(chain),
+            hardforks: vec![(0, SpecId::CANCUN)],
+            non_placeholder_block_hash_height: 243_538,
+            warm_precompiles_block: 8_197_684,
+            core_writer_deploy_block,
+            system_address_schemes: vec![(0, SystemAddressScheme::PrefixedIndexV1)],
+            evm_rules: Vec::new(),
+        }
+    }
+
+    // The built-in spec, with the EVM rules of a chain spec file if one is given
+    pub fn load(chain: Chain, file: Option<&Path>) -> Result<Self> {
+        let mut spec = Self::for_chain(chain);
+        if let Some(path) = file {
+            let data = std::fs::read(path)
+                .with_context(|| format!("failed to read the chain spec {}", path.display()))
+                .context(ErrorKind::MissingFile)?;
+            let parsed: ChainSpecFile = serde_json::from_slice(&data)
+                .with_context(|| format!("invalid chain spec {}", path.display()))
+                .context(ErrorKind::Usage)?;
+            spec.set_evm_rules(chain, parsed).with_context(|| format!("invalid chain spec {}", path.display()))?;
+        }
+        Ok(spec)
+    }
+
+    fn set_evm_rules(&mut self, chain: Chain, file: ChainSpecFile) -> Result<()> {
+        if let Some(file_chain) = file.chain.filter(|&file_chain| file_chain != chain) {
+            return Err(anyhow!("it is for {file_chain}, not {chain}")).context(ErrorKind::Usage);
+        }
+        if let Some(pair) = file.evm_rules.windows(2).find(|pair| pair[0].from_block >= pair[1].from_block) {
+            return Err(anyhow!(
+                "evm_rules from block {} follow those from block {}",
+                pair[1].from_block,
+                pair[0].from_block
+            ))
+            .context(ErrorKind::Usage);
+        }
+        self.evm_rules = file.evm_rules;
+        Ok(())
+    }
+
+    pub fn spec_id(&self, block_number: u64) -> SpecId {
+        self.hardforks
+            .iter()
+            .rev()
+            .find(|(activation, _)| *activation <= block_number)
+            .map_or(SpecId::CANCUN, |(_, spec_id)| *spec_id)
+    }
+
+    pub fn evm_rules(&self, block_number: u64) -> &EvmRules {
+        self.evm_rules.iter().rev().find(|rules| rules.from_block <= block_number).unwrap_or(&DEFAULT_EVM_RULES)
+    }
+
+    pub fn system_address(&self, token_index: u64) -> Address {
+        self.system_address_schemes
+            .iter()
+            .rev()
+            .find(|(first_index, _)| *first_index <= token_index)
+            .map_or(SystemAddressScheme::PrefixedIndexV1, |(_, scheme)| *scheme)
+            .derive(token_index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        chainspec::{ChainSpec, ChainSpecFile, Opcode},
+        cli::Chain,
+    };
+    use alloy::primitives::{address, Address};
+
+    #[test]
+    fn test_system_address() {
+        let spec = ChainSpec::for_chain(Chain::Mainnet);
+        assert_eq!(spec.system_address(0), address!("0x2000000000000000000000000000000000000000"));
+        assert_eq!(spec.system_address(0x0102), address!("0x2000000000000000000000000000000000000102"));
+    }
+
+    #[test]
+    fn test_evm_rules() {
+        let file = |json: &str| serde_json::from_str::<ChainSpecFile>(json).unwrap();
+        let mut spec = ChainSpec::for_chain(Chain::Mainnet);
+        let rules = r#"{"chain": "mainnet", "evm_rules": [
+            {"from_block": 100, "disabled_opcodes": ["SELFDESTRUCT", "0x5c"], "beneficiary": "header"},
+            {"from_block": 200, "block_gas_limit": 30000000}
+        ]}"#;
+        spec.set_evm_rules(Chain::Mainnet, file(rules)).unwrap();
+        let beneficiary = Address::repeat_byte(1);
+        assert_eq!(spec.evm_rules(99).coinbase(beneficiary), Address::ZERO);
+        assert_eq!(spec.evm_rules(150).coinbase(beneficiary), beneficiary);
+        assert_eq!(spec.evm_rules(150).disabled_opcodes, [Opcode(0x5c), Opcode(0xff)].into());
+        // Later rules replace earlier ones rather than adding to them
+        assert!(spec.evm_rules(200).disabled_opcodes.is_empty());
+        assert_eq!(spec.evm_rules(250).block_gas_limit(2000000), 30000000);
+
+        assert!(spec.set_evm_rules(Chain::Testnet, file(rules)).is_err());
+        let unordered = r#"{"evm_rules": [{"from_block": 5}, {"from_block": 5}]}"#;
+        assert!(spec.set_evm_rules(Chain::Mainnet, file(unordered)).is_err());
+        assert!(serde_json::from_str::<ChainSpecFile>(r#"{"evm_rules": [{"disabled_opcodes": ["NOPE"]}]}"#).is_err());
+    }
+}
@@ -0,0 +1,174 @@
+use crate::diff::AccountFields;
+use alloy::primitives::{Address, U256};
+use revm::{
+    db::{AccountState, DbAccount},
+    primitives::HashMap,
+    InMemoryDB,
+};
+use serde::Serialize;
+use std::{collections::BTreeSet, fmt::Display};
+
+// Mismatched slots listed per account; all of them are counted
+const MAX_REPORTED_SLOTS: usize = 10;
+
+#[derive(Debug, Serialize)]
+pub struct SlotMismatch {
+    pub slot: U256,
+    pub replayed: U256,
+    pub node: U256,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AccountMismatch {
+    pub address: Address,
+    // None when the account does not exist on that side
+    pub replayed: Option<AccountFields>,
+    pub node: Option<AccountFields>,
+    pub mismatched_slots: usize,
+    pub slots: Vec<SlotMismatch>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StateComparison {
+    pub next_block_num: u64,
+    pub accounts_compared: usize,
+    pub mismatched_accounts: usize,
+    pub mismatched_slots: usize,
+    // The first mismatched accounts by address
+    pub mismatches: Vec<AccountMismatch>,
+}
+
+impl StateComparison {
+    pub const fn matches(&self) -> bool {
+        self.mismatched_accounts == 0
+    }
+}
+
+impl Display for StateComparison {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Compared {} accounts before block {}: ", self.accounts_compared, self.next_block_num)?;
+        if self.matches() {
+            return write!(f, "the replayed state matches the node's");
+        }
+        write!(f, "{} accounts and {} storage slots differ", self.mismatched_accounts, self.mismatched_slots)?;
+        let fields = |fields: Option<AccountFields>| {
+            fields.map_or_else(
+                || "missing".to_owned(),
+                |AccountFields { balance, nonce, code_hash }| {
+                    format!("balance {balance}, nonce {nonce}, code {code_hash}")
+                },
+            )
+        };
+        for mismatch in &self.mismatches {
+            write!(f, "\n{}", mismatch.address)?;
+            if mismatch.replayed != mismatch.node {
+                write!(f, "\n  replayed: {}\n  node:     {}", fields(mismatch.replayed), fields(mismatch.node))?;
+            }
+            for SlotMismatch { slot, replayed, node } in &mismatch.slots {
+                write!(f, "\n  slot {slot:#x}: replayed {replayed:#x}, node {node:#x}")?;
+            }
+            if mismatch.mismatched_slots > mismatch.slots.len() {
+                write!(f, "\n  and {} more slots", mismatch.mismatched_slots - mismatch.slots.len())?;
+            }
+        }
+        if self.mismatched_accounts > self.mismatches.len() {
+            write!(f, "\nand {} more accounts", self.mismatched_accounts - self.mismatches.len())?;
+        }
+        Ok(())
+    }
+}
+
+// Fields and storage of an account, None for destroyed accounts and empty ones without storage, which the node may
+// or may not keep
+fn existing_account(account: &DbAccount) -> Option<(AccountFields, &HashMap<U256, U256>)> {
+    let empty = account.info.is_empty() && account.storage.values().all(U256::is_zero);
+    (account.account_state != AccountState::NotExisting && !empty).then(|| ((&account.info).into(), &account.storage))
+}
+
+fn compare_accounts(
+    address: Address,
+    replayed: Option<(AccountFields, &HashMap<U256, U256>)>,
+    node: Option<(AccountFields, &HashMap<U256, U256>)>,
+) -> Option<AccountMismatch> {
+    let empty = HashMap::default();
+    let replayed_storage = replayed.map_or(&empty, |(_, storage)| storage);
+    let node_storage = node.map_or(&empty, |(_, storage)| storage);
+    let slots: BTreeSet<U256> = replayed_storage.keys().chain(node_storage.keys()).copied().collect();
+    let mut mismatched_slots = 0;
+    let mut reported = Vec::new();
+    for slot in slots {
+        let replayed = replayed_storage.get(&slot).copied().unwrap_or_default();
+        let node = node_storage.get(&slot).copied().unwrap_or_default();
+        if replayed != node {
+            mismatched_slots += 1;
+            if reported.len() < MAX_REPORTED_SLOTS {
+                reported.push(SlotMismatch { slot, replayed, node });
+            }
+        }
+    }
+    let (replayed, node) = (replayed.map(|(fields, _)| fields), node.map(|(fields, _)| fields));
+    (replayed != node || mismatched_slots > 0).then_some(AccountMismatch {
+        address,
+        replayed,
+        node,
+        mismatched_slots,
+        slots: reported,
+    })
+}
+
+// Compares accounts and storage; block hashes are not, since the node keeps a different window of them
+pub fn compare_states(next_block_num: u64, replayed: &InMemoryDB, node: &InMemoryDB, limit: usize) -> StateComparison {
+    let addresses: BTreeSet<Address> = replayed.accounts.keys().chain(node.accounts.keys()).copied().collect();
+    let mut comparison = StateComparison {
+        next_block_num,
+        accounts_compared: addresses.len(),
+        mismatched_accounts: 0,
+        mismatched_slots: 0,
+        mismatches: Vec::new(),
+    };
+    for address in addresses {
+        let replayed = replayed.accounts.get(&address).and_then(existing_account);
+        let node = node.accounts.get(&address).and_then(existing_account);
+        if let Some(mismatch) = compare_accounts(address, replayed, node) {
+            comparison.mismatched_accounts += 1;
+            comparison.mismatched_slots += mismatch.mismatched_slots;
+            if comparison.mismatches.len() < limit {
+                comparison.mismatches.push(mismatch);
+            }
+        }
+    }
+    comparison
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compare::compare_states;
+    use alloy::primitives::{address, U256};
+    use revm::{primitives::AccountInfo, InMemoryDB};
+
+    #[test]
+    fn test_compare_states() {
+        let (a, b, c) = (
+            address!("0x000000000000000000000000000000000000000a"),
+            address!("0x000000000000000000000000000000000000000b"),
+            address!("0x000000000000000000000000000000000000000c"),
+        );
+        let info = |balance: u64| AccountInfo { balance: U256::from(balance), ..AccountInfo::default() };
+        let mut replayed = InMemoryDB::default();
+        replayed.insert_account_info(a, info(1));
+        replayed.insert_account_storage(a, U256::from(1), U256::from(5)).unwrap();
+        replayed.insert_account_storage(a, U256::from(2), U256::ZERO).unwrap();
+        replayed.insert_account_info(b, info(2));
+        // Empty accounts count as missing
+        replayed.insert_account_info(c, info(0));
+        let mut node = replayed.clone();
+        node.accounts.remove(&c);
+        assert!(compare_states(10, &replayed, &node, 10).matches());
+
+        node.insert_account_storage(a, U256::from(1), U256::from(6)).unwrap();
+        node.insert_account_info(b, info(3));
+        let comparison = compare_states(10, &replayed, &node, 10);
+        assert_eq!((comparison.mismatched_accounts, comparison.mismatched_slots), (2, 1));
+        assert_eq!(comparison.mismatches[0].slots[0].node, U256::from(6));
+    }
+}
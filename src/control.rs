@@ -0,0 +1,120 @@
+use crate::status::SyncStatus;
+use alloy::primitives::{Address, U256};
+use anyhow::{anyhow, Result};
+use revm::primitives::AccountInfo;
+use std::{
+    sync::{
+        mpsc::{channel, Receiver, RecvTimeoutError, Sender, TryRecvError},
+        Arc,
+    },
+    time::Duration,
+};
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::oneshot;
+
+const PAUSE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+pub enum ControlRequest {
+    Snapshot { reply: oneshot::Sender<Result<(u64, String)>> },
+    Account { address: Address, reply: oneshot::Sender<(u64, Option<AccountInfo>)> },
+    Storage { address: Address, slot: U256, reply: oneshot::Sender<(u64, U256)> },
+}
+
+// Requests are answered by the processor between blocks, so they see a consistent state
+#[derive(Clone)]
+pub struct ControlHandle {
+    tx: Sender<ControlRequest>,
+    pub status: Arc<SyncStatus>,
+}
+
+pub struct Controller {
+    rx: Receiver<ControlRequest>,
+}
+
+pub fn control_channel(status: Arc<SyncStatus>) -> (ControlHandle, Controller) {
+    let (tx, rx) = channel();
+    (ControlHandle { tx, status }, Controller { rx })
+}
+
+impl ControlHandle {
+    async fn request<T>(&self, request: impl FnOnce(oneshot::Sender<T>) -> ControlRequest) -> Result<T> {
+        let (reply, rx) = oneshot::channel();
+        self.tx.send(request(reply)).map_err(|_| anyhow!("Sync is no longer running"))?;
+        rx.await.map_err(|_| anyhow!("Sync stopped before answering"))
+    }
+
+    pub fn pause(&self) {
+        self.status.set_paused(true);
+    }
+
+    pub fn resume(&self) {
+        self.status.set_paused(false);
+    }
+
+    pub async fn snapshot(&self) -> Result<(u64, String)> {
+        self.request(|reply| ControlRequest::Snapshot { reply }).await?
+    }
+
+    pub async fn account(&self, address: Address) -> Result<(u64, Option<AccountInfo>)> {
+        self.request(|reply| ControlRequest::Account { address, reply }).await
+    }
+
+    pub async fn storage(&self, address: Address, slot: U256) -> Result<(u64, U256)> {
+        self.request(|reply| ControlRequest::Storage { address, slot, reply }).await
+    }
+}
+
+// SIGUSR1 pauses the sync after the current block, then takes a snapshot there if `snapshot` is set; SIGUSR2 resumes
+// it. The reader keeps its place, so nothing is read again on resuming.
+#[cfg(unix)]
+pub async fn handle_pause_signals(handle: ControlHandle, snapshot: bool) -> Result<()> {
+    let mut pause = signal(SignalKind::user_defined1())?;
+    let mut resume = signal(SignalKind::user_defined2())?;
+    loop {
+        tokio::select! {
+            Some(()) = pause.recv() => {
+                println!("Received SIGUSR1, pausing after the current block");
+                handle.pause();
+                if snapshot {
+                    match handle.snapshot().await {
+                        Ok((block, path)) => println!("Wrote snapshot {path} of paused block {block}"),
+                        Err(e) => eprintln!("Snapshot on pause failed: {e}"),
+                    }
+                }
+            }
+            Some(()) = resume.recv() => {
+                println!("Received SIGUSR2, resuming");
+                handle.resume();
+            }
+            else => return Ok(()),
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub async fn handle_pause_signals(_handle: ControlHandle, _snapshot: bool) -> Result<()> {
+    Err(anyhow!("pause signals need SIGUSR1 and SIGUSR2, which only exist on Unix"))
+}
+
+impl Controller {
+    // Returns the next pending request, blocking for as long as the sync is paused and calling `keepalive` meanwhile
+    pub(crate) fn next_request(&self, status: &SyncStatus, keepalive: impl Fn()) -> Option<ControlRequest> {
+        loop {
+            match self.rx.try_recv() {
+                Ok(request) => return Some(request),
+                Err(TryRecvError::Disconnected) => return None,
+                Err(TryRecvError::Empty) => {}
+            }
+            if !status.is_paused() {
+                return None;
+            }
+            keepalive();
+            match self.rx.recv_timeout(PAUSE_POLL_INTERVAL) {
+                Ok(request) => return Some(request),
+                Err(RecvTimeoutError::Disconnected) => return None,
+                Err(RecvTimeoutError::Timeout) => {}
+            }
+        }
+    }
+}
@@ -0,0 +1,169 @@
+use crate::run::CORE_WRITER_ADDRESS;
+use alloy::primitives::{b256, Address, Bytes, Log, B256, U256};
+use reth_primitives::SealedBlock;
+use serde::Serialize;
+
+// keccak256("RawAction(address,bytes)")
+const RAW_ACTION_TOPIC: B256 = b256!("0x8c7f585fb295f7eb1e6aeb8fba61b23a4fe60beda405f0045073b185c74412e3");
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum CoreWriterAction {
+    LimitOrder { asset: u32, is_buy: bool, limit_px: u64, sz: u64, reduce_only: bool, tif: u8, cloid: u128 },
+    VaultTransfer { vault: Address, is_deposit: bool, usd: u64 },
+    TokenDelegate { validator: Address, wei: u64, is_undelegate: bool },
+    StakingDeposit { wei: u64 },
+    StakingWithdraw { wei: u64 },
+    SpotSend { destination: Address, token: u64, wei: u64 },
+    UsdClassTransfer { ntl: u64, to_perp: bool },
+    FinalizeEvmContract { token: u64, variant: u8, create_nonce: u64 },
+    AddApiWallet { wallet: Address, name: String },
+    CancelOrderByOid { asset: u32, oid: u64 },
+    CancelOrderByCloid { asset: u32, cloid: u128 },
+    // Unsupported versions and action ids, and payloads that fail to decode
+    Unknown { version: u8, id: u32, data: Bytes },
+}
+
+// ABI words of an action's parameters
+struct Words<'a>(&'a [u8]);
+
+impl Words<'_> {
+    fn word(&self, i: usize) -> Option<U256> {
+        self.0.get(32 * i..32 * (i + 1)).map(U256::from_be_slice)
+    }
+
+    fn uint<T: TryFrom<U256>>(&self, i: usize) -> Option<T> {
+        T::try_from(self.word(i)?).ok()
+    }
+
+    fn bool(&self, i: usize) -> Option<bool> {
+        match self.uint::<u8>(i)? {
+            0 => Some(false),
+            1 => Some(true),
+            _ => None,
+        }
+    }
+
+    fn address(&self, i: usize) -> Option<Address> {
+        let word = self.word(i)?;
+        (word >> 160).is_zero().then(|| Address::from_word(word.into()))
+    }
+
+    fn bytes(&self, i: usize) -> Option<&[u8]> {
+        let offset: usize = self.uint(i)?;
+        let len: usize = Words(self.0.get(offset..)?).uint(0)?;
+        self.0.get(offset.checked_add(32)?..offset.checked_add(32)?.checked_add(len)?)
+    }
+}
+
+fn decode_params(id: u32, words: &Words<'_>) -> Option<CoreWriterAction> {
+    let action = match id {
+        1 => CoreWriterAction::LimitOrder {
+            asset: words.uint(0)?,
+            is_buy: words.bool(1)?,
+            limit_px: words.uint(2)?,
+            sz: words.uint(3)?,
+            reduce_only: words.bool(4)?,
+            tif: words.uint(5)?,
+            cloid: words.uint(6)?,
+        },
+        2 => CoreWriterAction::VaultTransfer {
+            vault: words.address(0)?,
+            is_deposit: words.bool(1)?,
+            usd: words.uint(2)?,
+        },
+        3 => CoreWriterAction::TokenDelegate {
+            validator: words.address(0)?,
+            wei: words.uint(1)?,
+            is_undelegate: words.bool(2)?,
+        },
+        4 => CoreWriterAction::StakingDeposit { wei: words.uint(0)? },
+        5 => CoreWriterAction::StakingWithdraw { wei: words.uint(0)? },
+        6 => CoreWriterAction::SpotSend { destination: words.address(0)?, token: words.uint(1)?, wei: words.uint(2)? },
+        7 => CoreWriterAction::UsdClassTransfer { ntl: words.uint(0)?, to_perp: words.bool(1)? },
+        8 => CoreWriterAction::FinalizeEvmContract {
+            token: words.uint(0)?,
+            variant: words.uint(1)?,
+            create_nonce: words.uint(2)?,
+        },
+        9 => CoreWriterAction::AddApiWallet {
+            wallet: words.address(0)?,
+            name: String::from_utf8(words.bytes(1)?.to_vec()).ok()?,
+        },
+        10 => CoreWriterAction::CancelOrderByOid { asset: words.uint(0)?, oid: words.uint(1)? },
+        11 => CoreWriterAction::CancelOrderByCloid { asset: words.uint(0)?, cloid: words.uint(1)? },
+        _ => return None,
+    };
+    Some(action)
+}
+
+// Actions are encoded as a version byte, a big-endian 3 byte action id, and the ABI encoded parameters
+pub fn decode_action(data: &[u8]) -> CoreWriterAction {
+    let version = data.first().copied().unwrap_or_default();
+    let id = data.get(1..4).map_or(0, |id| u32::from_be_bytes([0, id[0], id[1], id[2]]));
+    let params = data.get(4..).unwrap_or_default();
+    (version == 1).then(|| decode_params(id, &Words(params))).flatten().unwrap_or_else(|| CoreWriterAction::Unknown {
+        version,
+        id,
+        data: Bytes::copy_from_slice(params),
+    })
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CoreWriterRecord {
+    pub block: u64,
+    pub tx_index: usize,
+    pub tx_hash: B256,
+    pub user: Address,
+    #[serde(flatten)]
+    pub action: CoreWriterAction,
+}
+
+// Returns the sender and raw action of a CoreWriter RawAction(address indexed user, bytes data) log
+fn raw_action(log: &Log) -> Option<(Address, &[u8])> {
+    if log.address != CORE_WRITER_ADDRESS || log.topics().first() != Some(&RAW_ACTION_TOPIC) {
+        return None;
+    }
+    let user = Address::from_word(*log.topics().get(1)?);
+    Some((user, Words(&log.data.data).bytes(0)?))
+}
+
+// `logs` holds the index in the block of each executed transaction along with its logs, since overrides and partial
+// replays skip some
+pub fn block_actions<'a>(
+    block: &SealedBlock,
+    logs: impl IntoIterator<Item = (usize, &'a [Log])>,
+) -> Vec<CoreWriterRecord> {
+    let mut records = Vec::new();
+    for (tx_index, logs) in logs {
+        let tx_hash = block.body().transactions[tx_index].hash();
+        for (user, data) in logs.iter().filter_map(raw_action) {
+            records.push(CoreWriterRecord {
+                block: block.number,
+                tx_index,
+                tx_hash,
+                user,
+                action: decode_action(data),
+            });
+        }
+    }
+    records
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::corewriter::{decode_action, CoreWriterAction};
+    use alloy::primitives::{address, U256};
+
+    #[test]
+    fn test_decode_spot_send() {
+        let destination = address!("0x1111111111111111111111111111111111111111");
+        let mut data = vec![1, 0, 0, 6];
+        data.extend_from_slice(destination.into_word().as_slice());
+        data.extend_from_slice(&U256::from(150).to_be_bytes::<32>());
+        data.extend_from_slice(&U256::from(1_000_000).to_be_bytes::<32>());
+        assert_eq!(decode_action(&data), CoreWriterAction::SpotSend { destination, token: 150, wei: 1_000_000 });
+        data[0] = 2;
+        assert!(matches!(decode_action(&data), CoreWriterAction::Unknown { version: 2, id: 6, .. }));
+    }
+}
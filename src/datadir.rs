@@ -0,0 +1,144 @@
+use crate::{cli::Chain, error::ErrorKind, fs::find_snapshot};
+use anyhow::{anyhow, Context, Result};
+use clap::{parser::ValueSource, ArgMatches, Command, ValueEnum};
+use serde::{Deserialize, Serialize};
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const MANIFEST_FILE: &str = "data-dir.json";
+const BLOCKS_DIR: &str = "blocks";
+pub(crate) const SNAPSHOTS_DIR: &str = "snapshots";
+// The per-block diffs of --archive-dir, which historical queries and rebuild-state read
+const INDEX_DIR: &str = "index";
+
+#[derive(Debug, Serialize, Deserialize)]
+struct DataDirManifest {
+    chain: Chain,
+    created_at: u64,
+    // Version of this crate that ran init
+    writer: String,
+}
+
+// `{root}/blocks`, `{root}/snapshots`, and `{root}/index` for one chain, as set up by init
+#[derive(Debug)]
+pub struct DataDir {
+    pub root: PathBuf,
+    pub chain: Chain,
+}
+
+impl DataDir {
+    // Running init again on the same chain only creates what is missing
+    pub fn init(root: &Path, chain: Chain) -> Result<Self> {
+        if root.join(MANIFEST_FILE).exists() {
+            let existing = Self::open(root)?;
+            if existing.chain != chain {
+                return Err(anyhow!("{} is already a {} data dir", root.display(), existing.chain))
+                    .context(ErrorKind::Usage);
+            }
+        }
+        for dir in [BLOCKS_DIR, SNAPSHOTS_DIR, INDEX_DIR] {
+            std::fs::create_dir_all(root.join(dir))?;
+        }
+        let path = root.join(MANIFEST_FILE);
+        if !path.exists() {
+            let manifest = DataDirManifest {
+                chain,
+                created_at: SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs(),
+                writer: env!("CARGO_PKG_VERSION").to_owned(),
+            };
+            std::fs::write(path, serde_json::to_vec_pretty(&manifest)?)?;
+        }
+        Ok(Self { root: root.to_owned(), chain })
+    }
+
+    pub fn open(root: &Path) -> Result<Self> {
+        let path = root.join(MANIFEST_FILE);
+        let data = std::fs::read(&path)
+            .with_context(|| format!("{} is not a data dir; set one up with init", root.display()))
+            .context(ErrorKind::MissingFile)?;
+        let manifest: DataDirManifest = serde_json::from_slice(&data)
+            .with_context(|| format!("{} is not a data dir manifest", path.display()))
+            .context(ErrorKind::CorruptData)?;
+        Ok(Self { root: root.to_owned(), chain: manifest.chain })
+    }
+
+    pub fn blocks_dir(&self) -> PathBuf {
+        self.root.join(BLOCKS_DIR)
+    }
+
+    pub fn snapshot_dir(&self) -> PathBuf {
+        self.root.join(SNAPSHOTS_DIR)
+    }
+
+    pub fn index_dir(&self) -> PathBuf {
+        self.root.join(INDEX_DIR)
+    }
+
+    fn latest_snapshot(&self) -> Result<Option<PathBuf>> {
+        Ok(find_snapshot(&self.snapshot_dir(), self.chain, None)?.map(|(_, path)| path))
+    }
+
+    // The value of the flag whose id is `id`, if it is one the data dir stands in for. `-f` defaults to the newest
+    // snapshot where a state is required or where snapshots are written, so that a sync resumes where it stopped.
+    // The index is only passed where it is read, as writing it slows a sync down.
+    fn value_of(&self, id: &str, command: &Command, matches: &ArgMatches) -> Result<Option<OsString>> {
+        let has = |id: &str| command.get_arguments().any(|arg| arg.get_id() == id);
+        let given = |id: &str| has(id) && matches.value_source(id) == Some(ValueSource::CommandLine);
+        let required = command.get_arguments().any(|arg| arg.get_id() == id && arg.is_required_set());
+        let path = match id {
+            "chain" => return Ok(Some(self.chain.to_possible_value().unwrap().get_name().into())),
+            // `dir` is the blocks dir of the download commands
+            "blocks_dir" | "dir" => self.blocks_dir(),
+            "snapshot_dir" => self.snapshot_dir(),
+            "archive_dir" if required || given("block") || command.get_name() == "prune" => self.index_dir(),
+            "fln" if required || has("snapshot_dir") => match self.latest_snapshot()? {
+                Some(path) => path,
+                None => return Ok(None),
+            },
+            _ => return Ok(None),
+        };
+        Ok(Some(path.into_os_string()))
+    }
+}
+
+// Adds the flags the leaf subcommand takes but was not given, derived from the --data-dir among `args`
+pub fn expand_data_dir_args(root: Command, args: Vec<OsString>) -> Result<Vec<OsString>> {
+    let Ok(matches) = root.clone().ignore_errors(true).try_get_matches_from(&args) else {
+        return Ok(args);
+    };
+    let mut command = root;
+    let mut leaf = &matches;
+    while let Some((name, sub_matches)) = leaf.subcommand() {
+        command = command.find_subcommand(name).unwrap().clone();
+        leaf = sub_matches;
+    }
+    let Some(data_dir) = matches.get_one::<PathBuf>("data_dir") else {
+        return Ok(args);
+    };
+    // init is what creates the manifest
+    if command.get_name() == "init" {
+        return Ok(args);
+    }
+    let data_dir = DataDir::open(data_dir)?;
+    let mut derived = Vec::new();
+    for arg in command.get_arguments() {
+        let (id, Some(long)) = (arg.get_id().as_str(), arg.get_long()) else {
+            continue;
+        };
+        if leaf.value_source(id) == Some(ValueSource::CommandLine) {
+            continue;
+        }
+        if let Some(value) = data_dir.value_of(id, &command, leaf)? {
+            derived.push(OsString::from(format!("--{long}")));
+            derived.push(value);
+        }
+    }
+    // Before any `--`, after which everything is positional
+    let end = args.iter().position(|arg| arg == "--").unwrap_or(args.len());
+    let mut args = args;
+    args.splice(end..end, derived);
+    Ok(args)
+}
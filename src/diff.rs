@@ -0,0 +1,130 @@
+use alloy::primitives::{Address, Bytes, B256, U256};
+use revm::{
+    db::AccountState,
+    primitives::{Account, AccountInfo, Bytecode, HashMap},
+    DatabaseRef, InMemoryDB,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccountFields {
+    pub balance: U256,
+    pub nonce: u64,
+    pub code_hash: B256,
+}
+
+impl From<&AccountInfo> for AccountFields {
+    fn from(info: &AccountInfo) -> Self {
+        Self { balance: info.balance, nonce: info.nonce, code_hash: info.code_hash }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SlotDiff {
+    pub before: U256,
+    pub after: U256,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccountDiff {
+    // None when the account did not exist before the block, or was destroyed by it
+    pub before: Option<AccountFields>,
+    pub after: Option<AccountFields>,
+    // Set when the block wiped the account's storage (selfdestruct or creation), in which case slots that are not
+    // listed in `storage` are zero afterwards
+    pub storage_cleared: bool,
+    pub storage: BTreeMap<U256, SlotDiff>,
+}
+
+// All state changes made by the transactions of one block
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateDiff {
+    pub accounts: BTreeMap<Address, AccountDiff>,
+    pub contracts: BTreeMap<B256, Bytes>,
+}
+
+impl StateDiff {
+    pub fn is_empty(&self) -> bool {
+        self.accounts.is_empty() && self.contracts.is_empty()
+    }
+
+    pub fn storage_slots(&self) -> usize {
+        self.accounts.values().map(|account| account.storage.len()).sum()
+    }
+
+    pub fn blake3_hash(&self) -> B256 {
+        blake3::hash(&rmp_serde::to_vec(self).unwrap()).as_bytes().into()
+    }
+
+    // Replays the changes onto the pre-block state, matching what committing the block's transactions does
+    pub fn apply(&self, state: &mut InMemoryDB) {
+        for (&code_hash, code) in &self.contracts {
+            state.contracts.insert(code_hash, Bytecode::new_raw(code.clone()));
+        }
+        for (&address, diff) in &self.accounts {
+            let account = state.accounts.entry(address).or_default();
+            if diff.storage_cleared {
+                account.storage.clear();
+                account.account_state = AccountState::StorageCleared;
+            }
+            match diff.after {
+                Some(AccountFields { balance, nonce, code_hash }) => {
+                    let code = state.contracts.get(&code_hash).cloned();
+                    account.info = AccountInfo { balance, nonce, code_hash, code };
+                    if !diff.storage_cleared {
+                        account.account_state = AccountState::Touched;
+                    }
+                }
+                None => {
+                    account.info = AccountInfo::default();
+                    account.storage.clear();
+                    account.account_state = AccountState::NotExisting;
+                }
+            }
+            for (&slot, value) in &diff.storage {
+                account.storage.insert(slot, value.after);
+            }
+        }
+    }
+
+    // Must be called with the changes of each transaction before they are committed to `state`
+    pub fn record<S: DatabaseRef>(&mut self, state: &S, changes: &HashMap<Address, Account>) {
+        for (&address, account) in changes {
+            if !account.is_touched() {
+                continue;
+            }
+            let diff = self.accounts.entry(address).or_insert_with(|| AccountDiff {
+                before: state
+                    .basic_ref(address)
+                    .ok()
+                    .flatten()
+                    .filter(|info| !info.is_empty())
+                    .map(|info| (&info).into()),
+                ..AccountDiff::default()
+            });
+            if account.is_selfdestructed() || account.is_created() {
+                diff.storage_cleared = true;
+                for slot in diff.storage.values_mut() {
+                    slot.after = U256::ZERO;
+                }
+            }
+            if account.is_selfdestructed() {
+                diff.after = None;
+                continue;
+            }
+            diff.after = Some((&account.info).into());
+            if account.is_created() {
+                if let Some(code) = account.info.code.as_ref().filter(|code| !code.is_empty()) {
+                    self.contracts.insert(account.info.code_hash, code.original_bytes());
+                }
+            }
+            for (&slot, value) in account.changed_storage_slots() {
+                diff.storage
+                    .entry(slot)
+                    .and_modify(|slot| slot.after = value.present_value)
+                    .or_insert(SlotDiff { before: value.original_value, after: value.present_value });
+            }
+        }
+    }
+}
@@ -0,0 +1,162 @@
+use crate::{cli::Chain, fs::BlockSource};
+use indicatif::HumanBytes;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use serde::Serialize;
+use std::{
+    collections::BTreeSet,
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug, Serialize)]
+pub struct BlockCoverage {
+    pub present: u64,
+    pub missing: u64,
+    pub first_missing: Option<u64>,
+}
+
+// Only checks that a file exists for each block; their contents are read when they execute
+pub fn block_coverage(source: &BlockSource, start_block: u64, end_block: u64) -> BlockCoverage {
+    let missing: BTreeSet<u64> =
+        (start_block..=end_block).into_par_iter().filter(|&block_num| !source.has_block(block_num)).collect();
+    BlockCoverage {
+        present: (end_block + 1).saturating_sub(start_block) - missing.len() as u64,
+        missing: missing.len() as u64,
+        first_missing: missing.first().copied(),
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SnapshotDirCheck {
+    pub dir: PathBuf,
+    pub writable: bool,
+    pub available_bytes: Option<u64>,
+    // Room for one more snapshot the size of the starting state, as the previous one may be uploaded or pruned
+    // before the next is written
+    pub needed_bytes: u64,
+}
+
+// Writes and removes a probe file, creating the directory if needed, as the sync would
+pub fn check_snapshot_dir(dir: &Path, needed_bytes: u64) -> SnapshotDirCheck {
+    let probe = dir.join(".dry-run-probe");
+    let writable = std::fs::create_dir_all(dir)
+        .and_then(|()| std::fs::write(&probe, b""))
+        .and_then(|()| std::fs::remove_file(&probe));
+    SnapshotDirCheck {
+        dir: dir.to_owned(),
+        writable: writable.is_ok(),
+        available_bytes: fs2::available_space(dir).ok(),
+        needed_bytes,
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct DryRunReport {
+    pub chain: Chain,
+    pub state: Option<PathBuf>,
+    // The chain recorded in the snapshot, if it records one
+    pub state_chain: Option<Chain>,
+    pub start_block: u64,
+    pub end_block: u64,
+    pub checkpoints: u64,
+    pub blocks: BlockCoverage,
+    pub snapshot_dir: Option<SnapshotDirCheck>,
+    pub token_map_version: String,
+    pub tokens: usize,
+    pub token_map_source: String,
+    pub problems: Vec<String>,
+}
+
+impl DryRunReport {
+    // Collects what would stop the sync or make it fail partway
+    pub fn find_problems(&mut self) {
+        let mut problems = Vec::new();
+        if self.start_block > self.end_block {
+            problems.push(format!("the state is already past the end block {}", self.end_block));
+        }
+        if let Some(state_chain) = self.state_chain.filter(|&state_chain| state_chain != self.chain) {
+            problems.push(format!("the state was written by a {state_chain} sync"));
+        }
+        if let Some(first_missing) = self.blocks.first_missing {
+            problems
+                .push(format!("{} block files are missing, starting at block {first_missing}", self.blocks.missing));
+        }
+        if let Some(check) = &self.snapshot_dir {
+            if !check.writable {
+                problems.push(format!("cannot write to the snapshot dir {}", check.dir.display()));
+            }
+            if check.available_bytes.is_some_and(|available| available < check.needed_bytes) {
+                problems.push(format!(
+                    "{} free in {}, but a snapshot takes about {}",
+                    HumanBytes(check.available_bytes.unwrap_or_default()),
+                    check.dir.display(),
+                    HumanBytes(check.needed_bytes)
+                ));
+            }
+        }
+        self.problems = problems;
+    }
+}
+
+impl Display for DryRunReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "Would sync {} blocks {}-{} from {}, with {} checkpoints",
+            self.chain,
+            self.start_block,
+            self.end_block,
+            self.state.as_deref().map_or_else(|| "genesis".into(), Path::to_string_lossy),
+            self.checkpoints
+        )?;
+        writeln!(f, "block files: {} present, {} missing", self.blocks.present, self.blocks.missing)?;
+        if let Some(check) = &self.snapshot_dir {
+            let available =
+                check.available_bytes.map_or_else(|| "unknown".to_owned(), |bytes| HumanBytes(bytes).to_string());
+            writeln!(
+                f,
+                "snapshot dir {}: {}, {available} free, about {} per snapshot",
+                check.dir.display(),
+                if check.writable { "writable" } else { "NOT WRITABLE" },
+                HumanBytes(check.needed_bytes)
+            )?;
+        }
+        write!(f, "token map {} ({} tokens) from {}", self.token_map_version, self.tokens, self.token_map_source)?;
+        if self.problems.is_empty() {
+            return write!(f, "\nno problems found");
+        }
+        for problem in &self.problems {
+            write!(f, "\nproblem: {problem}")?;
+        }
+        Ok(())
+    }
+}
+
+// Blocks after which the state is hashed and snapshotted
+pub fn count_checkpoints(start_block: u64, end_block: u64, chunk_size: u64, snapshot_at: &[u64]) -> u64 {
+    if start_block > end_block {
+        return 0;
+    }
+    let periodic = end_block / chunk_size - start_block.saturating_sub(1) / chunk_size;
+    let extra = snapshot_at
+        .iter()
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .filter(|&&block| block >= start_block && block < end_block && block % chunk_size != 0)
+        .count() as u64;
+    periodic + extra + u64::from(end_block % chunk_size != 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::dryrun::count_checkpoints;
+
+    #[test]
+    fn test_count_checkpoints() {
+        // 1000, 2000, and the end block
+        assert_eq!(count_checkpoints(1, 2500, 1000, &[]), 3);
+        assert_eq!(count_checkpoints(1001, 2000, 1000, &[]), 1);
+        assert_eq!(count_checkpoints(1, 2500, 1000, &[1500, 1500, 2000, 2500]), 4);
+        assert_eq!(count_checkpoints(10, 5, 1000, &[]), 0);
+    }
+}
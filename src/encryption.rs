@@ -0,0 +1,77 @@
+use crate::error::ErrorKind;
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    Aes256Gcm, Key, Nonce,
+};
+use alloy::primitives::hex;
+use anyhow::{anyhow, Result};
+
+// Hex-encoded 32-byte AES-256-GCM key, given directly or as a file holding it
+const KEY_ENV: &str = "HYPER_EVM_SYNC_SNAPSHOT_KEY";
+const KEY_FILE_ENV: &str = "HYPER_EVM_SYNC_SNAPSHOT_KEY_FILE";
+// Encrypted snapshots are MAGIC, then the nonce, then the ciphertext of the plain snapshot
+const MAGIC: &[u8; 8] = b"HEVMENC1";
+const NONCE_LEN: usize = 12;
+
+fn snapshot_key() -> Result<Option<Key<Aes256Gcm>>> {
+    let encoded = match (std::env::var(KEY_ENV), std::env::var(KEY_FILE_ENV)) {
+        (Ok(key), _) => key,
+        (_, Ok(fln)) => std::fs::read_to_string(&fln).map_err(|e| anyhow!("failed to read key file {fln}: {e}"))?,
+        _ => return Ok(None),
+    };
+    let bytes = hex::decode(encoded.trim())?;
+    if bytes.len() != 32 {
+        return Err(anyhow!("snapshot key must be 32 bytes, got {}", bytes.len()));
+    }
+    Ok(Some(*Key::<Aes256Gcm>::from_slice(&bytes)))
+}
+
+fn encrypt(key: &Key<Aes256Gcm>, data: &[u8]) -> Result<Vec<u8>> {
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = Aes256Gcm::new(key).encrypt(&nonce, data).map_err(|_| anyhow!("snapshot encryption failed"))?;
+    Ok([MAGIC.as_slice(), nonce.as_slice(), &ciphertext].concat())
+}
+
+fn decrypt(key: &Key<Aes256Gcm>, data: &[u8]) -> Result<Vec<u8>> {
+    let data = &data[MAGIC.len()..];
+    if data.len() < NONCE_LEN {
+        return Err(anyhow!("encrypted snapshot is truncated"));
+    }
+    let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+    Aes256Gcm::new(key)
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| anyhow!("snapshot decryption failed: wrong key or corrupted file").context(ErrorKind::CorruptData))
+}
+
+// Encrypts when a key is configured, otherwise leaves the snapshot as is
+pub fn seal_snapshot(data: Vec<u8>) -> Result<Vec<u8>> {
+    match snapshot_key()? {
+        Some(key) => encrypt(&key, &data),
+        None => Ok(data),
+    }
+}
+
+// Plain snapshots are read whether or not a key is configured
+pub fn open_snapshot(data: Vec<u8>) -> Result<Vec<u8>> {
+    if !data.starts_with(MAGIC) {
+        return Ok(data);
+    }
+    let key = snapshot_key()?
+        .ok_or_else(|| anyhow!("snapshot is encrypted but neither {KEY_ENV} nor {KEY_FILE_ENV} is set"))?;
+    decrypt(&key, &data)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::encryption::{decrypt, encrypt};
+    use aes_gcm::{Aes256Gcm, Key};
+
+    #[test]
+    fn test_encryption_round_trip() {
+        let key = Key::<Aes256Gcm>::from([7; 32]);
+        let sealed = encrypt(&key, b"snapshot").unwrap();
+        assert_ne!(&sealed[8 + 12..], b"snapshot");
+        assert_eq!(decrypt(&key, &sealed).unwrap(), b"snapshot");
+        assert!(decrypt(&Key::<Aes256Gcm>::from([8; 32]), &sealed).is_err());
+    }
+}
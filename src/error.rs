@@ -0,0 +1,117 @@
+use serde::Serialize;
+use std::{fmt::Display, io::ErrorKind as IoErrorKind};
+
+// Failure classes with their own process exit codes, so automation can tell retryable failures from bad data.
+// Errors are tagged with `.context(ErrorKind::..)` where they arise; untagged errors are classified by their cause.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ErrorKind {
+    Internal,
+    Usage,
+    MissingFile,
+    CorruptData,
+    HashMismatch,
+    Network,
+    // A bench run slower than its baseline allows
+    Regression,
+}
+
+impl ErrorKind {
+    pub const fn exit_code(self) -> u8 {
+        match self {
+            Self::Internal => 1,
+            Self::Usage => 2,
+            Self::MissingFile => 3,
+            Self::CorruptData => 4,
+            Self::HashMismatch => 5,
+            Self::Network => 6,
+            Self::Regression => 7,
+        }
+    }
+
+    pub const fn is_retryable(self) -> bool {
+        matches!(self, Self::Network)
+    }
+
+    pub(crate) fn of(error: &anyhow::Error) -> Self {
+        if let Some(&kind) = error.downcast_ref::<Self>() {
+            return kind;
+        }
+        for cause in error.chain() {
+            if let Some(e) = cause.downcast_ref::<std::io::Error>() {
+                if e.kind() == IoErrorKind::NotFound {
+                    return Self::MissingFile;
+                }
+            } else if cause.is::<rmp_serde::decode::Error>()
+                || cause.is::<bincode::Error>()
+                || cause.is::<lz4_flex::frame::Error>()
+            {
+                return Self::CorruptData;
+            } else if cause.is::<reqwest::Error>() {
+                return Self::Network;
+            }
+        }
+        Self::Internal
+    }
+}
+
+impl Display for ErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let description = match self {
+            Self::Internal => "internal error",
+            Self::Usage => "invalid usage",
+            Self::MissingFile => "missing file",
+            Self::CorruptData => "corrupt data",
+            Self::HashMismatch => "hash mismatch",
+            Self::Network => "network failure",
+            Self::Regression => "performance regression",
+        };
+        f.write_str(description)
+    }
+}
+
+#[derive(Debug)]
+pub struct SyncError {
+    pub kind: ErrorKind,
+    pub error: anyhow::Error,
+}
+
+impl From<anyhow::Error> for SyncError {
+    fn from(error: anyhow::Error) -> Self {
+        Self { kind: ErrorKind::of(&error), error }
+    }
+}
+
+impl Display for SyncError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:#}", self.error)
+    }
+}
+
+impl std::error::Error for SyncError {}
+
+#[derive(Serialize)]
+struct ErrorObject<'a> {
+    kind: ErrorKind,
+    exit_code: u8,
+    retryable: bool,
+    message: &'a str,
+}
+
+impl SyncError {
+    // Prints to stderr, as a single JSON object when `json` is set
+    pub fn report(&self, json: bool) {
+        if json {
+            let message = self.to_string();
+            let object = ErrorObject {
+                kind: self.kind,
+                exit_code: self.kind.exit_code(),
+                retryable: self.kind.is_retryable(),
+                message: &message,
+            };
+            eprintln!("{}", serde_json::to_string(&object).unwrap());
+        } else {
+            eprintln!("Error: {self}");
+        }
+    }
+}
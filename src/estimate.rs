@@ -0,0 +1,89 @@
+use crate::{
+    cli::Chain,
+    fs::{read_evm_state, sample_block_sizes},
+    mirrors::Mirrors,
+    report::state_stats,
+};
+use anyhow::{anyhow, Result};
+use indicatif::HumanBytes;
+use serde::Serialize;
+use std::{fmt::Display, path::PathBuf};
+
+#[derive(Debug, Serialize)]
+pub struct Estimate {
+    pub chain: String,
+    pub start_block: u64,
+    pub end_block: u64,
+    pub sampled_blocks: usize,
+    pub download_bytes: u64,
+    // Decompressed blocks held in memory while reading ahead of execution
+    pub block_buffer_bytes: u64,
+    // Only available when starting from a snapshot, by extrapolating its growth since genesis to the end block
+    pub snapshot_bytes: Option<u64>,
+    pub state_memory_bytes: Option<u64>,
+    pub peak_memory_bytes: Option<u64>,
+}
+
+impl Display for Estimate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let optional =
+            |bytes: Option<u64>| bytes.map_or("unknown (pass a snapshot)".to_owned(), |b| HumanBytes(b).to_string());
+        writeln!(f, "{} blocks {}-{} ({} sampled)", self.chain, self.start_block, self.end_block, self.sampled_blocks)?;
+        writeln!(f, "download: {}", HumanBytes(self.download_bytes))?;
+        writeln!(f, "block buffer: {}", HumanBytes(self.block_buffer_bytes))?;
+        writeln!(f, "snapshot: {}", optional(self.snapshot_bytes))?;
+        writeln!(f, "state in memory: {}", optional(self.state_memory_bytes))?;
+        write!(f, "peak memory: {}", optional(self.peak_memory_bytes))
+    }
+}
+
+fn sample_points(start_block: u64, end_block: u64, samples: u64) -> Vec<u64> {
+    let blocks = end_block - start_block + 1;
+    let samples = samples.clamp(1, blocks);
+    (0..samples).map(|i| start_block + i * blocks / samples).collect()
+}
+
+// Block sizes come from downloading a sample of blocks spread over the range. The state is assumed to keep growing
+// at its average rate so far, and to be held twice at the peak while a snapshot is written.
+pub async fn estimate(
+    chain: Chain,
+    mirrors: &Mirrors,
+    start_block: u64,
+    end_block: u64,
+    snapshot: Option<PathBuf>,
+    samples: u64,
+    read_limit: u64,
+) -> Result<Estimate> {
+    let sizes = sample_block_sizes(mirrors, &sample_points(start_block, end_block, samples)).await?;
+    if sizes.is_empty() {
+        return Err(anyhow!("none of the sampled blocks between {start_block} and {end_block} are published"));
+    }
+    let blocks = end_block - start_block + 1;
+    let mean = |total: u64| total / sizes.len() as u64;
+    let compressed = mean(sizes.iter().map(|(compressed, _)| compressed).sum());
+    let decompressed = mean(sizes.iter().map(|(_, decompressed)| decompressed).sum());
+    // The reader can have one batch queued while another is being executed
+    let block_buffer_bytes = 2 * read_limit.min(blocks) * decompressed;
+
+    let (snapshot_bytes, state_memory_bytes) = match snapshot {
+        Some(fln) => {
+            let file_size = std::fs::metadata(&fln)?.len();
+            let (next_block_num, state) = read_evm_state(&fln)?;
+            let stats = state_stats(next_block_num, &state);
+            let growth = end_block.max(stats.block) as f64 / stats.block.max(1) as f64;
+            (Some((file_size as f64 * growth) as u64), Some((stats.estimated_size as f64 * growth) as u64))
+        }
+        None => (None, None),
+    };
+    Ok(Estimate {
+        chain: chain.to_string(),
+        start_block,
+        end_block,
+        sampled_blocks: sizes.len(),
+        download_bytes: compressed * blocks,
+        block_buffer_bytes,
+        snapshot_bytes,
+        state_memory_bytes,
+        peak_memory_bytes: state_memory_bytes.map(|state| 2 * state + block_buffer_bytes),
+    })
+}
@@ -0,0 +1,73 @@
+use crate::{diff::StateDiff, state::StateHash, watch::WatchMatch};
+use alloy::primitives::B256;
+use futures::{stream, Stream};
+use reth_primitives::SealedBlock;
+use serde::Serialize;
+use std::sync::Arc;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BlockSummary {
+    pub number: u64,
+    pub hash: B256,
+    pub timestamp: u64,
+    pub gas_used: u64,
+    pub transactions: usize,
+    pub system_transactions: usize,
+}
+
+impl BlockSummary {
+    pub fn new(block: &SealedBlock, system_transactions: usize) -> Self {
+        Self {
+            number: block.number,
+            hash: block.hash(),
+            timestamp: block.header().timestamp,
+            gas_used: block.header().gas_used,
+            transactions: block.body().transactions.len(),
+            system_transactions,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ExecutedBlock {
+    #[serde(flatten)]
+    pub summary: BlockSummary,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state_diff: Option<StateDiff>,
+}
+
+// Everything the pipeline reports, for embedding applications that would otherwise scrape stdout
+#[derive(Debug, Clone)]
+pub enum SyncEvent {
+    BlockExecuted(Arc<ExecutedBlock>),
+    HashComputed { block: u64, state_hash: StateHash },
+    SnapshotWritten { block: u64, path: String },
+    // Blocks after `fork_block` were replaced upstream; execution restarts after `rolled_back_to`
+    Reorg { fork_block: u64, rolled_back_to: u64 },
+    // Failures that do not stop the sync, such as a snapshot or log that could not be written
+    Error { block: u64, error: String },
+    WatchMatched(Arc<WatchMatch>),
+}
+
+pub type SyncEvents = broadcast::Sender<SyncEvent>;
+
+// Subscribers that fall this many events behind miss events rather than stalling execution
+const SYNC_EVENTS_CAPACITY: usize = 1024;
+
+pub fn sync_events() -> SyncEvents {
+    broadcast::channel(SYNC_EVENTS_CAPACITY).0
+}
+
+// Events sent after the call, skipping any missed by falling behind
+pub fn event_stream(events: &SyncEvents) -> impl Stream<Item = SyncEvent> {
+    stream::unfold(events.subscribe(), |mut rx| async move {
+        loop {
+            match rx.recv().await {
+                Ok(event) => return Some((event, rx)),
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    })
+}
@@ -1,8 +1,34 @@
-use crate::cli::Chain;
+use crate::{
+    chainspec::ChainSpec,
+    cli::Chain,
+    error::ErrorKind,
+    http::{self, Attempt, HttpPolicy},
+};
 use alloy::primitives::Address;
-use anyhow::Result;
+use anyhow::{anyhow, Context, Result};
+use itertools::Itertools;
+use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
-use std::collections::BTreeMap;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Display,
+    path::Path,
+    sync::OnceLock,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+// Overrides of the HTTP policy for the spot metadata request that the token map is built from
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SpotMetaOptions {
+    pub timeout: Option<Duration>,
+    pub retries: Option<u32>,
+}
+
+static OPTIONS: OnceLock<SpotMetaOptions> = OnceLock::new();
+
+pub fn configure_spot_meta(options: SpotMetaOptions) {
+    let _ = OPTIONS.set(options);
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct EvmContract {
@@ -12,6 +38,8 @@ struct EvmContract {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct SpotToken {
     index: u64,
+    #[serde(default)]
+    name: String,
     #[serde(rename = "evmContract")]
     evm_contract: Option<EvmContract>,
 }
@@ -28,25 +56,314 @@ fn info_url(chain: Chain) -> &'static str {
     }
 }
 
+impl SpotMeta {
+    // A changed API could otherwise silently map tokens to the wrong system addresses
+    fn validate(&self) -> Result<()> {
+        if self.tokens.is_empty() {
+            return Err(anyhow!("no tokens"));
+        }
+        let mut indices = BTreeSet::new();
+        let mut contracts = BTreeSet::new();
+        for token in &self.tokens {
+            if !indices.insert(token.index) {
+                return Err(anyhow!("token index {} appears twice", token.index));
+            }
+            if let Some(evm_contract) = &token.evm_contract {
+                if !contracts.insert(evm_contract.address) {
+                    return Err(anyhow!("EVM contract {} is linked to two tokens", evm_contract.address));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+async fn try_fetch_spot_meta(policy: &HttpPolicy, client: &reqwest::Client, url: &str) -> Result<Attempt<SpotMeta>> {
+    let response = match policy.read(client.post(url).json(&serde_json::json!({"type": "spotMeta"})).send()).await {
+        Ok(response) => response,
+        Err(e) => return Ok(Attempt::Retry(e)),
+    };
+    let status = response.status();
+    if status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error() {
+        return Ok(Attempt::Retry(anyhow!("HTTP {status}")));
+    }
+    if !status.is_success() {
+        return Err(anyhow!("HTTP {status}"));
+    }
+    let body = match policy.read(response.bytes()).await {
+        Ok(body) => body,
+        Err(e) => return Ok(Attempt::Retry(e)),
+    };
+    let meta: SpotMeta = serde_json::from_slice(&body).context("unexpected response")?;
+    meta.validate().context("invalid response")?;
+    Ok(Attempt::Done(meta))
+}
+
+// Network failures, throttling, and server errors are retried; other failures mean the API changed, so are not
 async fn fetch_spot_meta(chain: Chain) -> Result<SpotMeta> {
     let url = info_url(chain);
-    let client = reqwest::Client::new();
-    let response = client.post(url).json(&serde_json::json!({"type": "spotMeta"})).send().await?;
-    Ok(response.json().await?)
+    let options = OPTIONS.get().copied().unwrap_or_default();
+    let mut policy = http::policy();
+    policy.read_timeout = options.timeout.unwrap_or(policy.read_timeout);
+    policy.retries = options.retries.unwrap_or(policy.retries);
+    let client = http::client()?;
+    http::retry(&policy, &format!("spotMeta request to {url}"), || try_fetch_spot_meta(&policy, &client, url)).await
 }
 
-pub async fn erc20_contract_to_system_address(chain: Chain) -> Result<BTreeMap<Address, Address>> {
-    let meta = fetch_spot_meta(chain).await?;
+fn system_addresses(chain: Chain, meta: &SpotMeta) -> BTreeMap<Address, Address> {
+    let spec = ChainSpec::for_chain(chain);
     let mut map = BTreeMap::new();
     for token in &meta.tokens {
         if let Some(evm_contract) = &token.evm_contract {
-            let mut addr = [0u8; 20];
-            addr[0] = 0x20;
-            addr[12..20].copy_from_slice(token.index.to_be_bytes().as_ref());
-            let addr = Address::from_slice(&addr);
+            map.insert(evm_contract.address, spec.system_address(token.index));
+        }
+    }
+    map
+}
+
+pub async fn erc20_contract_to_system_address(chain: Chain) -> Result<BTreeMap<Address, Address>> {
+    Ok(system_addresses(chain, &fetch_spot_meta(chain).await?))
+}
+
+pub fn token_map_version(erc20_contract_to_system_address: &BTreeMap<Address, Address>) -> String {
+    let mut hasher = blake3::Hasher::new();
+    for (contract, system_address) in erc20_contract_to_system_address {
+        hasher.update(contract.as_slice());
+        hasher.update(system_address.as_slice());
+    }
+    hasher.finalize().to_hex()[..16].to_owned()
+}
+
+// The token map a sync ran with, embedded in its snapshots so that a replay from one doesn't depend on the API
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenMap {
+    // The spotMeta URL the map was fetched from
+    pub source: String,
+    // Seconds since the Unix epoch
+    pub fetched_at: u64,
+    pub erc20_contract_to_system_address: BTreeMap<Address, Address>,
+}
+
+impl TokenMap {
+    pub fn version(&self) -> String {
+        token_map_version(&self.erc20_contract_to_system_address)
+    }
+
+    // JSON, as written by the token-map command
+    pub fn read(path: &Path) -> Result<Self> {
+        let data = std::fs::read(path)
+            .with_context(|| format!("failed to read the token map {}", path.display()))
+            .context(ErrorKind::MissingFile)?;
+        serde_json::from_slice(&data)
+            .with_context(|| format!("{} is not a token map", path.display()))
+            .context(ErrorKind::CorruptData)
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+}
 
-            map.insert(evm_contract.address, addr);
+fn live_token_map(chain: Chain, meta: &SpotMeta) -> TokenMap {
+    TokenMap {
+        source: info_url(chain).to_owned(),
+        fetched_at: SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs(),
+        erc20_contract_to_system_address: system_addresses(chain, meta),
+    }
+}
+
+pub async fn fetch_token_map(chain: Chain) -> Result<TokenMap> {
+    Ok(live_token_map(chain, &fetch_spot_meta(chain).await?))
+}
+
+// Tokens are linked over time, so the live map normally only adds entries to a snapshot's
+#[derive(Debug, Default, PartialEq, Eq)]
+struct TokenMapChanges {
+    added: usize,
+    // Contracts the live map drops or maps to another system address
+    conflicting: Vec<Address>,
+}
+
+fn merge_token_maps(embedded: &mut TokenMap, live: &TokenMap) -> TokenMapChanges {
+    let embedded_map = &mut embedded.erc20_contract_to_system_address;
+    let live_map = &live.erc20_contract_to_system_address;
+    let mut changes = TokenMapChanges {
+        added: 0,
+        conflicting: embedded_map
+            .iter()
+            .filter(|(contract, system_address)| live_map.get(contract) != Some(system_address))
+            .map(|(contract, _)| *contract)
+            .collect(),
+    };
+    for (contract, system_address) in live_map {
+        if !embedded_map.contains_key(contract) {
+            embedded_map.insert(*contract, *system_address);
+            changes.added += 1;
         }
     }
-    Ok(map)
+    changes
+}
+
+// Prefers the map embedded in the snapshot being resumed from, adding the tokens linked since. Without an embedded
+// map the live one has to be available.
+pub fn resolve_token_map(embedded: Option<TokenMap>, live: Result<TokenMap>) -> Result<TokenMap> {
+    let Some(mut embedded) = embedded else {
+        return live;
+    };
+    let live = match live {
+        Ok(live) => live,
+        Err(e) => {
+            eprintln!(
+                "Warning: using the snapshot's token map from {} at {}, as the live one is unavailable: {e}",
+                embedded.source, embedded.fetched_at
+            );
+            return Ok(embedded);
+        }
+    };
+    let changes = merge_token_maps(&mut embedded, &live);
+    if changes.added > 0 {
+        println!("Added {} tokens linked since the snapshot to its token map", changes.added);
+    }
+    if !changes.conflicting.is_empty() {
+        eprintln!(
+            "Warning: the live token map differs from the snapshot's for {} contracts ({}); using the snapshot's",
+            changes.conflicting.len(),
+            changes.conflicting.iter().take(3).join(", ")
+        );
+    }
+    Ok(embedded)
+}
+
+#[derive(Debug, Serialize)]
+pub struct TokenMapEntry {
+    pub contract: Address,
+    pub system_address: Address,
+    // From the live spot metadata, so unknown for a contract it does not link to a token
+    pub index: Option<u64>,
+    pub name: Option<String>,
+}
+
+// As JSON this is a token map file too, as the fields it adds are ignored when it is read back
+#[derive(Debug, Serialize)]
+pub struct TokenMapListing {
+    #[serde(flatten)]
+    pub token_map: TokenMap,
+    pub version: String,
+    pub tokens: Vec<TokenMapEntry>,
+}
+
+fn list_tokens(token_map: TokenMap, meta: Option<&SpotMeta>) -> TokenMapListing {
+    let linked: BTreeMap<Address, &SpotToken> = meta
+        .iter()
+        .flat_map(|meta| &meta.tokens)
+        .filter_map(|token| Some((token.evm_contract.as_ref()?.address, token)))
+        .collect();
+    let mut tokens: Vec<_> = token_map
+        .erc20_contract_to_system_address
+        .iter()
+        .map(|(&contract, &system_address)| {
+            let token = linked.get(&contract);
+            TokenMapEntry {
+                contract,
+                system_address,
+                index: token.map(|token| token.index),
+                name: token.map(|token| token.name.clone()),
+            }
+        })
+        .collect();
+    tokens.sort_by_key(|entry| (entry.index.is_none(), entry.index, entry.contract));
+    TokenMapListing { version: token_map.version(), token_map, tokens }
+}
+
+// The map a sync would run with: `embedded` (e.g. a snapshot's) with the tokens linked since, or the live map. Names
+// and indices are looked up in the live spot metadata where it is available.
+pub async fn list_token_map(chain: Chain, embedded: Option<TokenMap>) -> Result<TokenMapListing> {
+    let (meta, live) = match fetch_spot_meta(chain).await {
+        Ok(meta) => {
+            let live = live_token_map(chain, &meta);
+            (Some(meta), Ok(live))
+        }
+        Err(e) => (None, Err(e)),
+    };
+    Ok(list_tokens(resolve_token_map(embedded, live)?, meta.as_ref()))
+}
+
+impl Display for TokenMapListing {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{} tokens (version {}) from {} at {}",
+            self.tokens.len(),
+            self.version,
+            self.token_map.source,
+            self.token_map.fetched_at
+        )?;
+        write!(f, "{:>7}  {:<12}  {:<42}  system address", "index", "name", "contract")?;
+        for entry in &self.tokens {
+            let index = entry.index.map_or("-".to_owned(), |index| index.to_string());
+            let name = entry.name.as_deref().unwrap_or("-");
+            write!(f, "\n{index:>7}  {name:<12}  {}  {}", entry.contract, entry.system_address)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::evm_map::{list_tokens, merge_token_maps, SpotMeta, TokenMap, TokenMapChanges};
+    use alloy::primitives::Address;
+
+    #[test]
+    fn test_spot_meta_validation() {
+        let parse = |json: &str| serde_json::from_str::<SpotMeta>(json).unwrap();
+        let contract = r#"{"address": "0x0000000000000000000000000000000000000001"}"#;
+        let valid = format!(
+            r#"{{"tokens": [{{"index": 0, "evmContract": null}}, {{"index": 1, "evmContract": {contract}}}]}}"#
+        );
+        assert!(parse(&valid).validate().is_ok());
+        assert!(parse(r#"{"tokens": []}"#).validate().is_err());
+        assert!(parse(r#"{"tokens": [{"index": 0}, {"index": 0}]}"#).validate().is_err());
+        let shared = format!(
+            r#"{{"tokens": [{{"index": 0, "evmContract": {contract}}}, {{"index": 1, "evmContract": {contract}}}]}}"#
+        );
+        assert!(parse(&shared).validate().is_err());
+    }
+
+    #[test]
+    fn test_merge_token_maps() {
+        let address = |byte| Address::repeat_byte(byte);
+        let map = |entries: &[(u8, u8)]| TokenMap {
+            source: String::new(),
+            fetched_at: 0,
+            erc20_contract_to_system_address: entries.iter().map(|&(a, b)| (address(a), address(b))).collect(),
+        };
+        let mut embedded = map(&[(1, 0x20), (2, 0x21), (3, 0x22)]);
+        let changes = merge_token_maps(&mut embedded, &map(&[(1, 0x20), (2, 0x2f), (4, 0x23)]));
+        assert_eq!(changes, TokenMapChanges { added: 1, conflicting: vec![address(2), address(3)] });
+        assert_eq!(embedded, map(&[(1, 0x20), (2, 0x21), (3, 0x22), (4, 0x23)]));
+    }
+
+    #[test]
+    fn test_list_tokens() {
+        let meta: SpotMeta = serde_json::from_str(
+            r#"{"tokens": [{"index": 0, "name": "USDC", "evmContract": null},
+                {"index": 7, "name": "PURR", "evmContract": {"address": "0x0202020202020202020202020202020202020202"}}]}"#,
+        )
+        .unwrap();
+        let token_map = TokenMap {
+            source: String::new(),
+            fetched_at: 0,
+            erc20_contract_to_system_address: [
+                (Address::repeat_byte(1), Address::repeat_byte(0x21)),
+                (Address::repeat_byte(2), Address::repeat_byte(0x20)),
+            ]
+            .into(),
+        };
+        let listing = list_tokens(token_map, Some(&meta));
+        let tokens: Vec<_> = listing.tokens.iter().map(|entry| (entry.contract, entry.index)).collect();
+        // Contracts no longer listed come last
+        assert_eq!(tokens, [(Address::repeat_byte(2), Some(7)), (Address::repeat_byte(1), None)]);
+        assert_eq!(listing.tokens[0].name.as_deref(), Some("PURR"));
+    }
 }
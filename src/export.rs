@@ -0,0 +1,335 @@
+use crate::{
+    cli::Chain,
+    fs::BlockSource,
+    types::{BlockAndReceipts, EvmBlock},
+};
+use alloy::{
+    consensus::{Eip658Value, ReceiptEnvelope, ReceiptWithBloom, Transaction as _},
+    primitives::{B256, U256},
+    rlp::Encodable,
+};
+use anyhow::{anyhow, Result};
+use clap::ValueEnum;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use reth_primitives::{transaction::SignedTransactionIntoRecoveredExt, Receipt, TxType};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::{
+    fs::{create_dir_all, File},
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum ExportFormat {
+    // e2store archives of up to 8192 blocks, as used by geth, reth, and nimbus
+    Era1,
+    // Concatenated RLP blocks, as read by `geth import` and `reth import`
+    Rlp,
+    // One summary row per block
+    Csv,
+    // One `{block}.json` file per block, shaped like an eth_getBlockReceipts response
+    Receipts,
+}
+
+const ERA1_BLOCKS: u64 = 8192;
+const RLP_READ_CHUNK: u64 = 1000;
+// Depth of the SSZ merkle tree over a full era of header records
+const ACCUMULATOR_DEPTH: usize = 13;
+
+const VERSION: [u8; 2] = [0x65, 0x32];
+const COMPRESSED_HEADER: [u8; 2] = [0x03, 0x00];
+const COMPRESSED_BODY: [u8; 2] = [0x04, 0x00];
+const COMPRESSED_RECEIPTS: [u8; 2] = [0x05, 0x00];
+const TOTAL_DIFFICULTY: [u8; 2] = [0x06, 0x00];
+const ACCUMULATOR: [u8; 2] = [0x07, 0x00];
+const BLOCK_INDEX: [u8; 2] = [0x66, 0x32];
+
+fn write_entry(out: &mut Vec<u8>, entry_type: [u8; 2], data: &[u8]) {
+    out.extend_from_slice(&entry_type);
+    out.extend_from_slice(&u32::try_from(data.len()).unwrap().to_le_bytes());
+    out.extend_from_slice(&[0, 0]);
+    out.extend_from_slice(data);
+}
+
+fn snappy_framed(data: &[u8]) -> Result<Vec<u8>> {
+    let mut encoder = snap::write::FrameEncoder::new(Vec::new());
+    encoder.write_all(data)?;
+    Ok(encoder.into_inner()?)
+}
+
+// Receipts in their network encoding, with the bloom filter that block files do not store
+pub(crate) fn receipt_envelope(receipt: Receipt) -> ReceiptEnvelope {
+    let tx_type = receipt.tx_type;
+    let with_bloom = receipt.with_bloom();
+    let inner = ReceiptWithBloom::new(
+        alloy::consensus::Receipt {
+            status: Eip658Value::Eip658(with_bloom.receipt.success),
+            cumulative_gas_used: with_bloom.receipt.cumulative_gas_used.into(),
+            logs: with_bloom.receipt.logs,
+        },
+        with_bloom.bloom,
+    );
+    match tx_type {
+        TxType::Legacy => ReceiptEnvelope::Legacy(inner),
+        TxType::Eip2930 => ReceiptEnvelope::Eip2930(inner),
+        TxType::Eip1559 => ReceiptEnvelope::Eip1559(inner),
+        TxType::Eip4844 => ReceiptEnvelope::Eip4844(inner),
+        TxType::Eip7702 => ReceiptEnvelope::Eip7702(inner),
+    }
+}
+
+fn sha256_pair(left: &[u8], right: &[u8]) -> [u8; 32] {
+    Sha256::new().chain_update(left).chain_update(right).finalize().into()
+}
+
+// hash_tree_root of the SSZ List[HeaderRecord(block_hash, total_difficulty), 8192]
+fn accumulator_root(records: &[(B256, U256)]) -> B256 {
+    let mut layer: Vec<_> =
+        records.iter().map(|(hash, td)| sha256_pair(hash.as_slice(), &td.to_le_bytes::<32>())).collect();
+    let mut zero = [0; 32];
+    for _ in 0..ACCUMULATOR_DEPTH {
+        if layer.len() % 2 == 1 {
+            layer.push(zero);
+        }
+        layer = layer.chunks(2).map(|pair| sha256_pair(&pair[0], &pair[1])).collect();
+        zero = sha256_pair(&zero, &zero);
+    }
+    let length = U256::from(records.len()).to_le_bytes::<32>();
+    sha256_pair(&layer[0], &length).into()
+}
+
+fn era1_file(blocks: Vec<BlockAndReceipts>, total_difficulty: &mut U256) -> Result<(Vec<u8>, B256)> {
+    let mut out = Vec::new();
+    write_entry(&mut out, VERSION, &[]);
+    let mut offsets = Vec::new();
+    let mut records = Vec::new();
+    let mut starting_number = None;
+    for BlockAndReceipts { block: EvmBlock::Reth115(block), receipts, .. } in blocks {
+        starting_number.get_or_insert(block.number);
+        offsets.push(out.len());
+        *total_difficulty += block.header().difficulty;
+        let receipts: Vec<_> = receipts.into_iter().map(|receipt| receipt_envelope(receipt.into())).collect();
+        write_entry(&mut out, COMPRESSED_HEADER, &snappy_framed(&alloy::rlp::encode(block.header()))?);
+        write_entry(&mut out, COMPRESSED_BODY, &snappy_framed(&alloy::rlp::encode(block.body()))?);
+        write_entry(&mut out, COMPRESSED_RECEIPTS, &snappy_framed(&alloy::rlp::encode(&receipts))?);
+        write_entry(&mut out, TOTAL_DIFFICULTY, &total_difficulty.to_le_bytes::<32>());
+        records.push((block.hash(), *total_difficulty));
+    }
+    let root = accumulator_root(&records);
+    write_entry(&mut out, ACCUMULATOR, root.as_slice());
+    // Offsets are relative to the start of the index entry
+    let index_start = i64::try_from(out.len())?;
+    let mut index = Vec::new();
+    index.extend_from_slice(&starting_number.unwrap_or_default().to_le_bytes());
+    for offset in offsets {
+        index.extend_from_slice(&(i64::try_from(offset)? - index_start).to_le_bytes());
+    }
+    index.extend_from_slice(&u64::try_from(records.len())?.to_le_bytes());
+    write_entry(&mut out, BLOCK_INDEX, &index);
+    Ok((out, root))
+}
+
+// Writes `{out_dir}/hyperevm-{chain}-{era}-{root}.era1` for every era overlapping the range. Total difficulty is
+// accumulated from the first exported block, so it only matches the chain's when exporting from genesis.
+pub fn export_era1(
+    chain: Chain,
+    source: &BlockSource,
+    out_dir: &Path,
+    start_block: u64,
+    end_block: u64,
+) -> Result<Vec<String>> {
+    create_dir_all(out_dir)?;
+    let network = format!("hyperevm-{}", chain.to_string().to_lowercase());
+    let mut total_difficulty = U256::ZERO;
+    let mut written = Vec::new();
+    for era in start_block / ERA1_BLOCKS..=end_block / ERA1_BLOCKS {
+        let era_start = start_block.max(era * ERA1_BLOCKS);
+        let era_end = end_block.min((era + 1) * ERA1_BLOCKS - 1);
+        let blocks = (era_start..=era_end)
+            .into_par_iter()
+            .map(|block_num| source.read_block(block_num))
+            .collect::<Result<Vec<_>>>()?;
+        let (data, root) = era1_file(blocks, &mut total_difficulty)?;
+        let fln = out_dir.join(format!("{network}-{era:05}-{}.era1", hex_prefix(root)));
+        std::fs::write(&fln, data)?;
+        println!("Wrote blocks {era_start}-{era_end} to {}", fln.display());
+        written.push(fln.display().to_string());
+    }
+    Ok(written)
+}
+
+// Writes `{out_dir}/hyperevm-{chain}-{start}-{end}.rlp`
+pub fn export_rlp(
+    chain: Chain,
+    source: &BlockSource,
+    out_dir: &Path,
+    start_block: u64,
+    end_block: u64,
+) -> Result<String> {
+    create_dir_all(out_dir)?;
+    let fln = out_dir.join(format!("hyperevm-{}-{start_block}-{end_block}.rlp", chain.to_string().to_lowercase()));
+    let mut writer = BufWriter::new(File::create(&fln)?);
+    for chunk_start in (start_block..=end_block).step_by(usize::try_from(RLP_READ_CHUNK).unwrap()) {
+        let chunk_end = end_block.min(chunk_start + RLP_READ_CHUNK - 1);
+        let encoded = (chunk_start..=chunk_end)
+            .into_par_iter()
+            .map(|block_num| {
+                let BlockAndReceipts { block: EvmBlock::Reth115(block), .. } = source.read_block(block_num)?;
+                Ok(alloy::rlp::encode(block.unseal()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        for block in encoded {
+            writer.write_all(&block)?;
+        }
+    }
+    writer.flush()?;
+    Ok(fln.display().to_string())
+}
+
+// Writes `{out_dir}/hyperevm-{chain}-{start}-{end}.csv`. The size is that of the block's RLP encoding, and the
+// transaction count excludes system transactions, which have their own column.
+pub fn export_csv(
+    chain: Chain,
+    source: &BlockSource,
+    out_dir: &Path,
+    start_block: u64,
+    end_block: u64,
+) -> Result<String> {
+    create_dir_all(out_dir)?;
+    let fln = out_dir.join(format!("hyperevm-{}-{start_block}-{end_block}.csv", chain.to_string().to_lowercase()));
+    let mut writer = BufWriter::new(File::create(&fln)?);
+    writeln!(writer, "number,hash,timestamp,tx_count,system_tx_count,gas_used,gas_limit,base_fee_per_gas,size")?;
+    for chunk_start in (start_block..=end_block).step_by(usize::try_from(RLP_READ_CHUNK).unwrap()) {
+        let chunk_end = end_block.min(chunk_start + RLP_READ_CHUNK - 1);
+        let rows = (chunk_start..=chunk_end)
+            .into_par_iter()
+            .map(|block_num| {
+                let BlockAndReceipts { block: EvmBlock::Reth115(block), system_txs, .. } =
+                    source.read_block(block_num)?;
+                let header = block.header();
+                let row = format!(
+                    "{},{},{},{},{},{},{},{}",
+                    header.number,
+                    block.hash(),
+                    header.timestamp,
+                    block.body().transactions.len(),
+                    system_txs.len(),
+                    header.gas_used,
+                    header.gas_limit,
+                    header.base_fee_per_gas.map(|fee| fee.to_string()).unwrap_or_default(),
+                );
+                Ok(format!("{row},{}", block.unseal().length()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        for row in rows {
+            writeln!(writer, "{row}")?;
+        }
+    }
+    writer.flush()?;
+    Ok(fln.display().to_string())
+}
+
+fn quantity(value: impl std::fmt::LowerHex) -> String {
+    format!("{value:#x}")
+}
+
+// The receipts of a block's transactions in the RPC's JSON shape. System transactions are left out, as the RPC does by
+// default.
+fn block_receipts(block_and_receipts: BlockAndReceipts) -> Result<Value> {
+    let BlockAndReceipts { block: EvmBlock::Reth115(block), receipts, .. } = block_and_receipts;
+    let (block_hash, header) = (block.hash(), block.header());
+    let mut previous_gas_used = 0;
+    let mut log_index = 0u64;
+    let mut rpc_receipts = Vec::with_capacity(receipts.len());
+    for (index, (tx_signed, receipt)) in block.body().transactions.iter().zip(receipts).enumerate() {
+        let tx_hash = tx_signed.hash();
+        let transaction = &tx_signed.transaction;
+        let sender = tx_signed
+            .clone()
+            .try_into_ecrecovered()
+            .map_err(|_| anyhow!("Cannot recover the sender of transaction {tx_hash} in block {}", header.number))?
+            .into_parts()
+            .1;
+        let envelope = receipt_envelope(receipt.into());
+        let cumulative_gas_used = envelope.cumulative_gas_used();
+        let logs: Vec<Value> = envelope
+            .logs()
+            .iter()
+            .map(|log| {
+                let rpc_log = json!({
+                    "address": log.address,
+                    "topics": log.data.topics(),
+                    "data": log.data.data,
+                    "blockNumber": quantity(header.number),
+                    "blockHash": block_hash,
+                    "transactionHash": tx_hash,
+                    "transactionIndex": quantity(index),
+                    "logIndex": quantity(log_index),
+                    "removed": false,
+                });
+                log_index += 1;
+                rpc_log
+            })
+            .collect();
+        rpc_receipts.push(json!({
+            "transactionHash": tx_hash,
+            "transactionIndex": quantity(index),
+            "blockHash": block_hash,
+            "blockNumber": quantity(header.number),
+            "from": sender,
+            "to": transaction.to(),
+            "cumulativeGasUsed": quantity(cumulative_gas_used),
+            "gasUsed": quantity(cumulative_gas_used.saturating_sub(previous_gas_used)),
+            "effectiveGasPrice": quantity(transaction.effective_gas_price(header.base_fee_per_gas)),
+            "contractAddress": transaction.to().is_none().then(|| sender.create(transaction.nonce())),
+            "logs": logs,
+            "logsBloom": envelope.logs_bloom(),
+            "type": quantity(u8::from(envelope.tx_type())),
+            "status": quantity(u8::from(envelope.status())),
+        }));
+        previous_gas_used = cumulative_gas_used;
+    }
+    Ok(Value::Array(rpc_receipts))
+}
+
+// Writes `{out_dir}/{block}.json` for every block in the range, returning the number of files
+pub fn export_receipts(source: &BlockSource, out_dir: &Path, start_block: u64, end_block: u64) -> Result<u64> {
+    create_dir_all(out_dir)?;
+    for chunk_start in (start_block..=end_block).step_by(usize::try_from(RLP_READ_CHUNK).unwrap()) {
+        let chunk_end = end_block.min(chunk_start + RLP_READ_CHUNK - 1);
+        (chunk_start..=chunk_end).into_par_iter().try_for_each(|block_num| {
+            let receipts = block_receipts(source.read_block(block_num)?)?;
+            std::fs::write(out_dir.join(format!("{block_num}.json")), serde_json::to_vec(&receipts)?)?;
+            anyhow::Ok(())
+        })?;
+    }
+    Ok(end_block - start_block + 1)
+}
+
+fn hex_prefix(root: B256) -> String {
+    root[..4].iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::export::accumulator_root;
+    use alloy::primitives::{B256, U256};
+    use sha2::{Digest, Sha256};
+
+    #[test]
+    fn test_accumulator_root_single_record() {
+        let hash = B256::repeat_byte(0xab);
+        let td = U256::from(7);
+        let mut node: [u8; 32] =
+            Sha256::new().chain_update(hash.as_slice()).chain_update(td.to_le_bytes::<32>()).finalize().into();
+        let mut zero = [0; 32];
+        for _ in 0..13 {
+            node = Sha256::new().chain_update(node).chain_update(zero).finalize().into();
+            zero = Sha256::new().chain_update(zero).chain_update(zero).finalize().into();
+        }
+        let expected: [u8; 32] =
+            Sha256::new().chain_update(node).chain_update(U256::from(1).to_le_bytes::<32>()).finalize().into();
+        assert_eq!(accumulator_root(&[(hash, td)]), B256::from(expected));
+    }
+}
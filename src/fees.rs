@@ -0,0 +1,205 @@
+use crate::{
+    bench::StageTimings,
+    fs::{read_blocks, BlockSource},
+    inspector::ReplayInspector,
+    run::{run_blocks, RunConfig},
+};
+use alloy::primitives::U256;
+use anyhow::Result;
+use revm::InMemoryDB;
+use serde::Serialize;
+use std::{collections::BTreeMap, fmt::Display, io::Write, sync::Mutex};
+
+// Fees as charged by execution. The base fee is burned; the priority fee goes to the block's beneficiary, which
+// replay sets to the zero address, so it leaves circulation as well.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct FeeTotals {
+    pub blocks: u64,
+    pub transactions: u64,
+    // Executed without a base fee; their fees, if any, are counted with the others
+    pub system_transactions: u64,
+    pub gas_used: u64,
+    pub base_fees_burned: U256,
+    pub priority_fees: U256,
+}
+
+impl FeeTotals {
+    pub(crate) fn record_tx(&mut self, is_system_tx: bool, basefee: u64, effective_gas_price: u128, gas_used: u64) {
+        if is_system_tx {
+            self.system_transactions += 1;
+        } else {
+            self.transactions += 1;
+        }
+        self.gas_used += gas_used;
+        let gas_used = U256::from(gas_used);
+        self.base_fees_burned += U256::from(basefee) * gas_used;
+        self.priority_fees += U256::from(effective_gas_price.saturating_sub(u128::from(basefee))) * gas_used;
+    }
+
+    fn add(&mut self, other: &Self) {
+        self.blocks += other.blocks;
+        self.transactions += other.transactions;
+        self.system_transactions += other.system_transactions;
+        self.gas_used += other.gas_used;
+        self.base_fees_burned += other.base_fees_burned;
+        self.priority_fees += other.priority_fees;
+    }
+
+    pub fn total_fees(&self) -> U256 {
+        self.base_fees_burned + self.priority_fees
+    }
+}
+
+// Sums the fees of every executed block into buckets of `bucket_size` blocks
+pub struct FeeLedger {
+    bucket_size: u64,
+    buckets: Mutex<BTreeMap<u64, FeeTotals>>,
+}
+
+impl FeeLedger {
+    pub fn new(bucket_size: u64) -> Self {
+        Self { bucket_size, buckets: Mutex::default() }
+    }
+
+    pub(crate) fn record_block(&self, block: u64, mut fees: FeeTotals) {
+        fees.blocks = 1;
+        let bucket = block - block % self.bucket_size;
+        self.buckets.lock().unwrap().entry(bucket).or_default().add(&fees);
+    }
+
+    pub fn report(&self, start_block: u64, end_block: u64) -> FeeReport {
+        let buckets: Vec<_> = self
+            .buckets
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(&bucket, &totals)| FeeBucket {
+                start_block: bucket.max(start_block),
+                end_block: (bucket + self.bucket_size - 1).min(end_block),
+                totals,
+            })
+            .collect();
+        let mut total = FeeTotals::default();
+        for bucket in &buckets {
+            total.add(&bucket.totals);
+        }
+        FeeReport { start_block, end_block, bucket_size: self.bucket_size, buckets, total }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct FeeBucket {
+    pub start_block: u64,
+    pub end_block: u64,
+    #[serde(flatten)]
+    pub totals: FeeTotals,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FeeReport {
+    pub start_block: u64,
+    pub end_block: u64,
+    pub bucket_size: u64,
+    pub buckets: Vec<FeeBucket>,
+    pub total: FeeTotals,
+}
+
+impl FeeReport {
+    // Amounts in wei
+    pub fn write_csv(&self, mut writer: impl Write) -> Result<()> {
+        writeln!(
+            writer,
+            "start_block,end_block,blocks,transactions,system_transactions,gas_used,base_fees_burned,priority_fees,\
+             total_fees"
+        )?;
+        for FeeBucket { start_block, end_block, totals } in &self.buckets {
+            writeln!(
+                writer,
+                "{start_block},{end_block},{},{},{},{},{},{},{}",
+                totals.blocks,
+                totals.transactions,
+                totals.system_transactions,
+                totals.gas_used,
+                totals.base_fees_burned,
+                totals.priority_fees,
+                totals.total_fees()
+            )?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+impl Display for FeeReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let line = |f: &mut std::fmt::Formatter<'_>, totals: &FeeTotals| {
+            write!(
+                f,
+                "{} blocks, {} transactions, {} system transactions, {} gas: {} wei base fees burned, {} wei priority \
+                 fees, {} wei in total",
+                totals.blocks,
+                totals.transactions,
+                totals.system_transactions,
+                totals.gas_used,
+                totals.base_fees_burned,
+                totals.priority_fees,
+                totals.total_fees()
+            )
+        };
+        write!(f, "Fees in blocks {}-{}: ", self.start_block, self.end_block)?;
+        line(f, &self.total)?;
+        if self.buckets.len() > 1 {
+            for bucket in &self.buckets {
+                write!(f, "\n{}-{}: ", bucket.start_block, bucket.end_block)?;
+                line(f, &bucket.totals)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Replays the range with `config.fee_ledger` collecting the fees
+pub fn fee_report(
+    config: &RunConfig,
+    source: &BlockSource,
+    state: &mut InMemoryDB,
+    start_block: u64,
+    end_block: u64,
+    read_limit: u64,
+) -> Result<FeeReport> {
+    let mut timings = StageTimings::default();
+    let mut inspector = ReplayInspector::default();
+    let mut cur_block = start_block;
+    while cur_block <= end_block {
+        let last_block_in_chunk = end_block.min(cur_block + read_limit - 1);
+        let blocks = read_blocks(source, cur_block, last_block_in_chunk, config.chunk_size, &mut timings)?;
+        run_blocks(None, config, state, blocks, &mut timings, &mut inspector)?;
+        cur_block = last_block_in_chunk + 1;
+    }
+    Ok(config.fee_ledger.as_ref().unwrap().report(start_block, end_block))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fees::{FeeLedger, FeeTotals};
+    use alloy::primitives::U256;
+
+    #[test]
+    fn test_fee_ledger() {
+        let ledger = FeeLedger::new(100);
+        let mut fees = FeeTotals::default();
+        // Paying 15 per gas on a base fee of 10
+        fees.record_tx(false, 10, 15, 21000);
+        fees.record_tx(true, 0, 0, 50000);
+        ledger.record_block(99, fees);
+        ledger.record_block(150, fees);
+        ledger.record_block(160, FeeTotals::default());
+        let report = ledger.report(50, 180);
+        let bounds: Vec<_> = report.buckets.iter().map(|bucket| (bucket.start_block, bucket.end_block)).collect();
+        assert_eq!(bounds, [(50, 99), (100, 180)]);
+        assert_eq!(report.buckets[1].totals.blocks, 2);
+        assert_eq!(report.total.base_fees_burned, U256::from(2 * 210000));
+        assert_eq!(report.total.priority_fees, U256::from(2 * 105000));
+        assert_eq!(report.total.gas_used, 2 * 71000);
+    }
+}
@@ -0,0 +1,104 @@
+use anyhow::{anyhow, Result};
+
+// A run of key-value entries in ascending key order. Each key is stored as the length of the prefix it shares with
+// the previous key and the rest, so consecutive keys of the same account, or small slot numbers, take a few bytes.
+#[derive(Default)]
+pub struct RunWriter {
+    buffer: Vec<u8>,
+    last_key: Vec<u8>,
+}
+
+impl RunWriter {
+    pub fn push(&mut self, key: &[u8], value: &[u8]) {
+        debug_assert!(self.last_key.is_empty() || key > self.last_key.as_slice());
+        let shared = key.iter().zip(&self.last_key).take_while(|(a, b)| a == b).count();
+        write_varint(&mut self.buffer, shared as u64);
+        write_varint(&mut self.buffer, (key.len() - shared) as u64);
+        self.buffer.extend_from_slice(&key[shared..]);
+        write_varint(&mut self.buffer, value.len() as u64);
+        self.buffer.extend_from_slice(value);
+        self.last_key.truncate(shared);
+        self.last_key.extend_from_slice(&key[shared..]);
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.buffer
+    }
+}
+
+pub struct RunReader<'a> {
+    data: &'a [u8],
+    key: Vec<u8>,
+}
+
+impl<'a> RunReader<'a> {
+    pub const fn new(data: &'a [u8]) -> Self {
+        Self { data, key: Vec::new() }
+    }
+
+    pub fn next_entry(&mut self) -> Result<Option<(&[u8], &'a [u8])>> {
+        if self.data.is_empty() {
+            return Ok(None);
+        }
+        let shared = usize::try_from(read_varint(&mut self.data)?)?;
+        let suffix = take(&mut self.data, usize::try_from(read_varint(&mut self.data)?)?)?;
+        if shared > self.key.len() {
+            return Err(anyhow!("flat entry shares {shared} bytes with a {}-byte key", self.key.len()));
+        }
+        self.key.truncate(shared);
+        self.key.extend_from_slice(suffix);
+        let value = take(&mut self.data, usize::try_from(read_varint(&mut self.data)?)?)?;
+        Ok(Some((&self.key, value)))
+    }
+}
+
+fn take<'a>(data: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+    if len > data.len() {
+        return Err(anyhow!("flat entry of {len} bytes is truncated"));
+    }
+    let (bytes, rest) = data.split_at(len);
+    *data = rest;
+    Ok(bytes)
+}
+
+pub fn write_varint(buffer: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buffer.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+pub fn read_varint(data: &mut &[u8]) -> Result<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = data.split_first().ok_or_else(|| anyhow!("truncated varint"))?;
+        *data = rest;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(anyhow!("varint longer than 64 bits"))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::flat::{RunReader, RunWriter};
+
+    #[test]
+    fn test_prefix_compressed_run() {
+        let entries: [(&[u8], &[u8]); 4] = [(b"a1", b"x"), (b"a1b", b""), (b"a2", b"yz"), (b"b", &[0; 200])];
+        let mut writer = RunWriter::default();
+        for (key, value) in entries {
+            writer.push(key, value);
+        }
+        let run = writer.finish();
+        let mut reader = RunReader::new(&run);
+        for (key, value) in entries {
+            let (read_key, read_value) = reader.next_entry().unwrap().unwrap();
+            assert_eq!((read_key, read_value), (key, value));
+        }
+        assert!(reader.next_entry().unwrap().is_none());
+    }
+}
@@ -1,23 +1,37 @@
 use crate::{
+    bench::StageTimings,
     cli::Chain,
+    datadir::SNAPSHOTS_DIR,
+    encryption::{open_snapshot, seal_snapshot},
+    error::ErrorKind,
+    evm_map::TokenMap,
+    integrity::{check_block_file, check_parent_link},
+    mirrors::Mirrors,
+    snapshot::{decode_snapshot, decode_snapshot_header, encode_snapshot, SnapshotFormat, SnapshotHeader},
+    telemetry,
     types::{AbciState, BlockAndReceipts, EvmBlock, EvmState, PreprocessedBlock},
 };
-use anyhow::Result;
-use aws_config::{BehaviorVersion, Region};
-use aws_sdk_s3::{types::RequestPayer, Client};
-use futures::{stream, StreamExt, TryStreamExt};
+use anyhow::{anyhow, Context, Result};
+use clap::ValueEnum;
+use futures::{future::BoxFuture, stream, StreamExt, TryStreamExt};
 use indicatif::{ProgressBar, ProgressStyle};
 use itertools::Itertools;
 use rayon::iter::{IntoParallelIterator, ParallelIterator};
 use reth_primitives::transaction::SignedTransactionIntoRecoveredExt;
 use revm::InMemoryDB;
+use serde::Deserialize;
 use std::{
+    collections::BTreeMap,
+    fmt::Display,
     fs::{create_dir_all, File},
-    io::{Read, Write},
+    future::Future,
+    io::{BufReader, Read, Write},
     path::{Path, PathBuf},
-    sync::Arc,
+    str::FromStr,
+    sync::RwLock,
     time::Instant,
 };
+use tokio::{io::AsyncWriteExt, runtime::Handle};
 
 const DOWNLOAD_CHUNK_SIZE: u64 = 10000;
 const CONCURRENCY_LIMIT: usize = 1000;
@@ -29,61 +43,622 @@ fn decompress(data: &[u8]) -> Result<Vec<u8>, lz4_flex::frame::Error> {
     Ok(decompressed)
 }
 
-fn bucket(chain: Chain) -> &'static str {
-    match chain {
-        Chain::Mainnet => "hl-mainnet-evm-blocks",
-        Chain::Testnet => "hl-testnet-evm-blocks",
+// Windows limits paths to 260 characters unless they are verbatim (`\\?\C:\...`), which files deep in a shard
+// layout under a long data dir can exceed. Verbatim paths are not normalized by Windows, so this makes the path
+// absolute first and paths under it have to be built component by component, as `ShardLayout::local_path` does.
+#[cfg(windows)]
+pub fn long_path(path: &Path) -> PathBuf {
+    use std::path::{Component, Prefix};
+    let Ok(absolute) = std::path::absolute(path) else {
+        return path.to_owned();
+    };
+    let Some(Component::Prefix(prefix)) = absolute.components().next() else {
+        return absolute;
+    };
+    let rest = absolute.strip_prefix(prefix.as_os_str()).unwrap_or(&absolute);
+    let mut verbatim = match prefix.kind() {
+        Prefix::Disk(_) => PathBuf::from(format!(r"\\?\{}", prefix.as_os_str().to_string_lossy())),
+        Prefix::UNC(server, share) => {
+            PathBuf::from(format!(r"\\?\UNC\{}\{}", server.to_string_lossy(), share.to_string_lossy()))
+        }
+        _ => return absolute,
+    };
+    verbatim.push(rest);
+    verbatim
+}
+
+#[cfg(not(windows))]
+pub fn long_path(path: &Path) -> PathBuf {
+    path.to_owned()
+}
+
+// Reads at an offset without moving the file's cursor, so concurrent readers can share one handle
+#[cfg(unix)]
+fn read_exact_at(file: &File, buffer: &mut [u8], offset: u64) -> std::io::Result<()> {
+    std::os::unix::fs::FileExt::read_exact_at(file, buffer, offset)
+}
+
+// Windows' positional reads do move the cursor, which nothing else here relies on
+#[cfg(windows)]
+fn read_exact_at(file: &File, mut buffer: &mut [u8], mut offset: u64) -> std::io::Result<()> {
+    use std::{io::ErrorKind as IoErrorKind, os::windows::fs::FileExt};
+    while !buffer.is_empty() {
+        match file.seek_read(buffer, offset) {
+            Ok(0) => return Err(IoErrorKind::UnexpectedEof.into()),
+            Ok(n) => {
+                buffer = &mut buffer[n..];
+                offset += n as u64;
+            }
+            Err(e) if e.kind() == IoErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
     }
+    Ok(())
 }
 
-fn read_block_and_receipts(file_path: &Path) -> Result<BlockAndReceipts> {
-    let mut file = File::open(file_path)?;
-    let mut buffer = Vec::new();
-    file.read_to_end(&mut buffer)?;
+// An advisory lock on `{dir}/.lock`, held until dropped, so two processes never write into the same directory. The
+// lock is taken through the OS (flock, LockFileEx), so it is released when the process dies and needs no cleanup.
+pub struct DirLock {
+    _file: File,
+}
 
-    let buffer = decompress(&buffer)?;
+impl DirLock {
+    pub fn acquire(dir: &Path) -> Result<Self> {
+        create_dir_all(dir)?;
+        let file = File::options().create(true).truncate(false).write(true).open(dir.join(".lock"))?;
+        fs2::FileExt::try_lock_exclusive(&file)
+            .with_context(|| format!("{} is in use by another process", dir.display()))
+            .context(ErrorKind::Usage)?;
+        Ok(Self { _file: file })
+    }
+}
+
+// How a block file is stored. Mirrors publish lz4, hl-node may leave its files uncompressed, and download-blocks can
+// recompress them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BlockCompression {
+    None,
+    #[default]
+    Lz4,
+    Gzip,
+    Zstd,
+}
+
+// Slow enough to matter only when recompressing a whole archive, and close to the best ratio on block files
+const ZSTD_LEVEL: i32 = 9;
+
+impl BlockCompression {
+    // In the order a mirror's files are looked for
+    const ALL: [Self; 4] = [Self::Lz4, Self::Zstd, Self::Gzip, Self::None];
+
+    pub const fn extension(self) -> &'static str {
+        match self {
+            Self::None => "rmp",
+            Self::Lz4 => "rmp.lz4",
+            Self::Gzip => "rmp.gz",
+            Self::Zstd => "rmp.zst",
+        }
+    }
+
+    fn of_path(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?;
+        Self::ALL.into_iter().find(|compression| name.ends_with(&format!(".{}", compression.extension())))
+    }
+
+    // By the magic bytes, so that a file is read whatever its name says
+    fn detect(data: &[u8]) -> Self {
+        match data {
+            [0x04, 0x22, 0x4d, 0x18, ..] => Self::Lz4,
+            [0x1f, 0x8b, ..] => Self::Gzip,
+            [0x28, 0xb5, 0x2f, 0xfd, ..] => Self::Zstd,
+            _ => Self::None,
+        }
+    }
 
+    fn decompress(self, data: Vec<u8>) -> Result<Vec<u8>> {
+        let decompressed = match self {
+            Self::None => return Ok(data),
+            Self::Lz4 => decompress(&data)?,
+            Self::Gzip => {
+                let mut decompressed = Vec::new();
+                flate2::read::GzDecoder::new(data.as_slice()).read_to_end(&mut decompressed)?;
+                decompressed
+            }
+            Self::Zstd => zstd::decode_all(data.as_slice())?,
+        };
+        Ok(decompressed)
+    }
+
+    fn compress(self, data: &[u8]) -> Result<Vec<u8>> {
+        let compressed = match self {
+            Self::None => data.to_vec(),
+            Self::Lz4 => {
+                let mut encoder = lz4_flex::frame::FrameEncoder::new(Vec::new());
+                encoder.write_all(data)?;
+                encoder.finish()?
+            }
+            Self::Gzip => {
+                let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()?
+            }
+            Self::Zstd => zstd::encode_all(data, ZSTD_LEVEL)?,
+        };
+        Ok(compressed)
+    }
+}
+
+fn parse_block_file(buffer: Vec<u8>) -> Result<BlockAndReceipts> {
+    let buffer = BlockCompression::detect(&buffer).decompress(buffer).context(ErrorKind::CorruptData)?;
     let mut input: Vec<_> = rmp_serde::from_slice(&buffer)?;
     assert_eq!(input.len(), 1);
     Ok(input.pop().unwrap())
 }
 
-pub fn read_blocks(dir: &str, start_block: u64, end_block: u64, chunk_size: u64) -> Vec<(u64, Vec<PreprocessedBlock>)> {
+// A single block file, which need not be in a blocks dir
+pub fn read_block_file(path: &Path) -> Result<BlockAndReceipts> {
+    parse_block_file(std::fs::read(path)?)
+}
+
+// The node-data buckets' replica_cmds and explorer_blocks archives are not usable sources: they hold L1 actions and
+// blocks, and EVM blocks with receipts only come out of executing those on the L1 state machine.
+#[derive(Debug, Clone, Copy, Default, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BlockSourceKind {
+    // `{dir}/.../{block}.rmp.lz4` (or .rmp.zst, .rmp.gz, .rmp) in a shard layout, as written by download-blocks
+    #[default]
+    Mirror,
+    // The data directory of a local hl-node: `{block}.rmp.lz4` or `{block}.rmp` files in any subdirectory
+    HlNode,
+}
+
+pub struct BlockSource {
+    store: Box<dyn BlockStore>,
+    // Check every block file against its header as it is read
+    validate: bool,
+}
+
+// Where block files come from. A store only finds and fetches the files, in whatever compression it holds them;
+// decoding and validation are shared. Files that are not there yet are None, so that following the tip can wait.
+trait BlockStore: Send + Sync {
+    fn read(&self, block_num: u64) -> Result<Option<Vec<u8>>>;
+
+    // For read_blocks_async, which keeps many reads in flight
+    fn read_async(&self, block_num: u64) -> BoxFuture<'_, Result<Option<Vec<u8>>>>;
+
+    fn has_block(&self, block_num: u64) -> bool {
+        self.read(block_num).is_ok_and(|file| file.is_some())
+    }
+
+    // Whether the block is available now, downloading it first if the store keeps a copy of the mirrors
+    fn poll<'a>(&'a self, _mirrors: &'a Mirrors, block_num: u64) -> BoxFuture<'a, Result<bool>> {
+        Box::pin(async move { Ok(self.read_async(block_num).await?.is_some()) })
+    }
+
+    // Drops the store's own copy of a block, if it keeps one
+    fn evict(&self, _block_num: u64) -> Result<()> {
+        Ok(())
+    }
+}
+
+// Runs a future from synchronous code, such as a rayon thread or a caller that is itself on the runtime
+fn block_on<F: Future>(handle: &Handle, future: F) -> F::Output {
+    if Handle::try_current().is_ok() {
+        tokio::task::block_in_place(|| handle.block_on(future))
+    } else {
+        handle.block_on(future)
+    }
+}
+
+struct MirrorDir {
+    dir: PathBuf,
+    layout: ShardLayout,
+}
+
+impl BlockStore for MirrorDir {
+    fn read(&self, block_num: u64) -> Result<Option<Vec<u8>>> {
+        Ok(existing_block_file(&self.dir, &self.layout, block_num).map(std::fs::read).transpose()?)
+    }
+
+    fn read_async(&self, block_num: u64) -> BoxFuture<'_, Result<Option<Vec<u8>>>> {
+        Box::pin(async move {
+            match existing_block_file(&self.dir, &self.layout, block_num) {
+                Some(path) => Ok(Some(tokio::fs::read(path).await?)),
+                None => Ok(None),
+            }
+        })
+    }
+
+    fn has_block(&self, block_num: u64) -> bool {
+        existing_block_file(&self.dir, &self.layout, block_num).is_some()
+    }
+
+    fn poll<'a>(&'a self, mirrors: &'a Mirrors, block_num: u64) -> BoxFuture<'a, Result<bool>> {
+        Box::pin(fetch_block_if_available(&self.dir, &self.layout, BlockCompression::Lz4, mirrors, block_num))
+    }
+
+    fn evict(&self, block_num: u64) -> Result<()> {
+        if let Some(local_path) = existing_block_file(&self.dir, &self.layout, block_num) {
+            std::fs::remove_file(local_path)?;
+        }
+        Ok(())
+    }
+}
+
+pub struct HlNodeBlocks {
+    dir: PathBuf,
+    index: RwLock<BTreeMap<u64, PathBuf>>,
+}
+
+pub(crate) fn block_file_number(path: &Path) -> Option<u64> {
+    let name = path.file_name()?.to_str()?;
+    let extension = BlockCompression::of_path(path)?.extension();
+    name.strip_suffix(extension)?.strip_suffix('.')?.parse().ok()
+}
+
+// The first of the block's possible files that exists
+fn existing_block_file(dir: &Path, layout: &ShardLayout, block_num: u64) -> Option<PathBuf> {
+    BlockCompression::ALL
+        .into_iter()
+        .map(|compression| layout.local_path(dir, block_num, compression.extension()))
+        .find(|path| path.is_file())
+}
+
+pub(crate) fn index_files(
+    dir: &Path,
+    file_number: fn(&Path) -> Option<u64>,
+    index: &mut BTreeMap<u64, PathBuf>,
+) -> Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            index_files(&path, file_number, index)?;
+        } else if let Some(block_num) = file_number(&path) {
+            index.insert(block_num, path);
+        }
+    }
+    Ok(())
+}
+
+impl HlNodeBlocks {
+    fn open(dir: PathBuf) -> Result<Self> {
+        let mut index = BTreeMap::new();
+        index_files(&dir, block_file_number, &mut index)?;
+        println!("Indexed n={} blocks in {}", index.len(), dir.display());
+        Ok(Self { dir, index: RwLock::new(index) })
+    }
+
+    // The node only writes into its newest directories, so rescanning follows the most recently modified
+    // subdirectory at each level instead of walking the whole tree again
+    fn refresh(&self) -> Result<()> {
+        let mut new_files = BTreeMap::new();
+        let mut dir = self.dir.clone();
+        loop {
+            let mut newest_subdir = None;
+            for entry in std::fs::read_dir(&dir)? {
+                let entry = entry?;
+                let path = entry.path();
+                if path.is_dir() {
+                    let modified = entry.metadata()?.modified()?;
+                    if newest_subdir.as_ref().is_none_or(|(newest, _)| modified > *newest) {
+                        newest_subdir = Some((modified, path));
+                    }
+                } else if let Some(block_num) = block_file_number(&path) {
+                    new_files.insert(block_num, path);
+                }
+            }
+            match newest_subdir {
+                Some((_, subdir)) => dir = subdir,
+                None => break,
+            }
+        }
+        self.index.write().unwrap().extend(new_files);
+        Ok(())
+    }
+
+    fn path(&self, block_num: u64) -> Option<PathBuf> {
+        self.index.read().unwrap().get(&block_num).cloned()
+    }
+
+    // Rescans for a block the node may have written since
+    fn find(&self, block_num: u64) -> Result<Option<PathBuf>> {
+        if self.path(block_num).is_none() {
+            self.refresh()?;
+        }
+        Ok(self.path(block_num))
+    }
+}
+
+impl BlockStore for HlNodeBlocks {
+    fn read(&self, block_num: u64) -> Result<Option<Vec<u8>>> {
+        Ok(self.find(block_num)?.map(std::fs::read).transpose()?)
+    }
+
+    fn read_async(&self, block_num: u64) -> BoxFuture<'_, Result<Option<Vec<u8>>>> {
+        Box::pin(async move {
+            match self.find(block_num)? {
+                Some(path) => Ok(Some(tokio::fs::read(path).await?)),
+                None => Ok(None),
+            }
+        })
+    }
+
+    fn has_block(&self, block_num: u64) -> bool {
+        self.path(block_num).is_some_and(|path| path.is_file())
+    }
+}
+
+// A single file of records as served by serve-blocks' /range: a little-endian u64 block number, a u32 length, and
+// the block file. Concatenated /range responses make one.
+struct PackedBlocks {
+    file: File,
+    // Offset and length of each block's file
+    index: BTreeMap<u64, (u64, usize)>,
+}
+
+impl PackedBlocks {
+    fn open(path: &Path) -> Result<Self> {
+        let file = File::open(path)
+            .with_context(|| format!("failed to open {}", path.display()))
+            .context(ErrorKind::MissingFile)?;
+        let len = file.metadata()?.len();
+        let mut index = BTreeMap::new();
+        let (mut offset, mut record) = (0, [0; 12]);
+        while offset < len {
+            read_exact_at(&file, &mut record, offset)
+                .with_context(|| format!("{} ends within a record header at offset {offset}", path.display()))
+                .context(ErrorKind::CorruptData)?;
+            let block_num = u64::from_le_bytes(record[..8].try_into().unwrap());
+            let file_len = u32::from_le_bytes(record[8..].try_into().unwrap());
+            index.insert(block_num, (offset + 12, file_len as usize));
+            offset += 12 + u64::from(file_len);
+        }
+        if offset > len {
+            return Err(anyhow!("{} is truncated", path.display())).context(ErrorKind::CorruptData);
+        }
+        println!("Indexed n={} blocks in {}", index.len(), path.display());
+        Ok(Self { file, index })
+    }
+}
+
+impl BlockStore for PackedBlocks {
+    fn read(&self, block_num: u64) -> Result<Option<Vec<u8>>> {
+        let Some(&(offset, len)) = self.index.get(&block_num) else {
+            return Ok(None);
+        };
+        let mut buffer = vec![0; len];
+        read_exact_at(&self.file, &mut buffer, offset)?;
+        Ok(Some(buffer))
+    }
+
+    // Reads at an offset of an open file are quick enough not to need the runtime's file threads
+    fn read_async(&self, block_num: u64) -> BoxFuture<'_, Result<Option<Vec<u8>>>> {
+        Box::pin(async move { self.read(block_num) })
+    }
+
+    fn has_block(&self, block_num: u64) -> bool {
+        self.index.contains_key(&block_num)
+    }
+}
+
+// Blocks read straight from an s3:// or http(s):// upstream under their published keys, e.g. a bucket or another
+// machine's serve-blocks, without a local copy. Nothing is cached, so every read is a download.
+struct RemoteBlocks {
+    mirrors: Mirrors,
+    runtime: Handle,
+}
+
+impl RemoteBlocks {
+    fn connect(url: &str) -> Result<Self> {
+        let runtime = Handle::try_current()
+            .map_err(|_| anyhow!("reading blocks from {url} needs the async runtime"))
+            .context(ErrorKind::Internal)?;
+        let mirrors = block_on(&runtime, Mirrors::connect_to(url))?;
+        Ok(Self { mirrors, runtime })
+    }
+}
+
+impl BlockStore for RemoteBlocks {
+    fn read(&self, block_num: u64) -> Result<Option<Vec<u8>>> {
+        block_on(&self.runtime, self.mirrors.fetch(&block_key(block_num)))
+    }
+
+    fn read_async(&self, block_num: u64) -> BoxFuture<'_, Result<Option<Vec<u8>>>> {
+        Box::pin(async move { self.mirrors.fetch(&block_key(block_num)).await })
+    }
+}
+
+impl BlockSource {
+    // `dir` picks the store: an s3:// or http(s):// URL reads from that upstream, a file is a packed archive, and a
+    // directory is read as `kind` says, also when given as a file:// or hl-node:// URL
+    pub fn open(kind: BlockSourceKind, dir: &str, layout: ShardLayout, validate: bool) -> Result<Self> {
+        let store: Box<dyn BlockStore> = match dir.split_once("://") {
+            Some(("s3" | "http" | "https", _)) => Box::new(RemoteBlocks::connect(dir)?),
+            Some(("hl-node", path)) => Box::new(HlNodeBlocks::open(PathBuf::from(path))?),
+            Some(("file", path)) => Self::open_path(kind, path, layout)?,
+            Some((scheme, _)) => {
+                return Err(anyhow!(
+                    "unsupported block source {scheme}://: expected a path, or a file://, hl-node://, \
+                     s3:// or http(s):// URL"
+                ))
+                .context(ErrorKind::Usage)
+            }
+            None => Self::open_path(kind, dir, layout)?,
+        };
+        Ok(Self { store, validate })
+    }
+
+    fn open_path(kind: BlockSourceKind, path: &str, layout: ShardLayout) -> Result<Box<dyn BlockStore>> {
+        if Path::new(path).is_file() {
+            return Ok(Box::new(PackedBlocks::open(Path::new(path))?));
+        }
+        Ok(match kind {
+            BlockSourceKind::Mirror => Box::new(MirrorDir { dir: long_path(Path::new(path)), layout }),
+            BlockSourceKind::HlNode => Box::new(HlNodeBlocks::open(long_path(Path::new(path)))?),
+        })
+    }
+
+    pub fn has_block(&self, block_num: u64) -> bool {
+        self.store.has_block(block_num)
+    }
+
+    pub fn read_block(&self, block_num: u64) -> Result<BlockAndReceipts> {
+        let buffer = self.store.read(block_num)?.ok_or_else(|| missing_block(block_num))?;
+        self.decode_block(block_num, buffer)
+    }
+
+    fn decode_block(&self, block_num: u64, buffer: Vec<u8>) -> Result<BlockAndReceipts> {
+        let block_and_receipts = parse_block_file(buffer)?;
+        if self.validate {
+            check_block_file(block_num, &block_and_receipts).context(ErrorKind::CorruptData)?;
+        }
+        Ok(block_and_receipts)
+    }
+
+    // The block's file as the mirrors publish it, lz4-compressed; None if there is no file for it
+    pub fn published_block_file(&self, block_num: u64) -> Result<Option<Vec<u8>>> {
+        let Some(buffer) = self.store.read(block_num)? else {
+            return Ok(None);
+        };
+        match BlockCompression::detect(&buffer) {
+            BlockCompression::Lz4 => Ok(Some(buffer)),
+            compression => Ok(Some(BlockCompression::Lz4.compress(&compression.decompress(buffer)?)?)),
+        }
+    }
+
+    // Used when following the tip: downloads into a mirror, or waits for hl-node to write the block
+    pub async fn poll_block(&self, mirrors: &Mirrors, block_num: u64) -> Result<bool> {
+        self.store.poll(mirrors, block_num).await
+    }
+
+    // Reads a block again from upstream after a reorg, replacing a mirror's copy of the abandoned branch
+    pub async fn refetch_block(&self, mirrors: &Mirrors, block_num: u64) -> Result<BlockAndReceipts> {
+        self.store.evict(block_num)?;
+        if !self.poll_block(mirrors, block_num).await? {
+            return Err(anyhow!("block {block_num} is no longer published"));
+        }
+        self.read_block(block_num)
+    }
+}
+
+fn missing_block(block_num: u64) -> anyhow::Error {
+    anyhow!("block {block_num} is not in the block source").context(ErrorKind::MissingFile)
+}
+
+// Checks that the chunk's blocks link up, and recovers their senders
+fn preprocess_chunk(
+    source: &BlockSource,
+    (start_block, end_block): (u64, u64),
+    chunk_start: Instant,
+    blocks: Vec<(u64, BlockAndReceipts)>,
+    timings: &mut StageTimings,
+) -> Result<Vec<PreprocessedBlock>> {
+    if source.validate {
+        for pair in blocks.windows(2) {
+            let ((_, parent), (block_num, block)) = (&pair[0], &pair[1]);
+            check_parent_link(*block_num, parent, block).context(ErrorKind::CorruptData)?;
+        }
+    }
+    timings.read += chunk_start.elapsed();
+    let recovery_start = Instant::now();
+    let blocks: Vec<_> = blocks
+        .into_par_iter()
+        .map(|(block_num, block_and_receipts)| {
+            let BlockAndReceipts { block: EvmBlock::Reth115(block), .. } = &block_and_receipts;
+            let signers = block
+                .body()
+                .transactions
+                .iter()
+                .enumerate()
+                .map(|(tx_index, tx_signed)| {
+                    let recovered = tx_signed.clone().try_into_ecrecovered().map_err(|_| {
+                        anyhow!("cannot recover the sender of transaction {tx_index} in block {block_num}")
+                    });
+                    recovered.context(ErrorKind::CorruptData).map(|tx| tx.into_parts().1)
+                })
+                .collect::<Result<_>>()?;
+            anyhow::Ok(PreprocessedBlock { block_num, block_and_receipts, signers })
+        })
+        .collect::<Result<_>>()?;
+    timings.sender_recovery += recovery_start.elapsed();
+    println!("Deserialized blocks {}-{} in {:?}", start_block, end_block, chunk_start.elapsed());
+    Ok(blocks)
+}
+
+pub fn read_blocks(
+    source: &BlockSource,
+    start_block: u64,
+    end_block: u64,
+    chunk_size: u64,
+    timings: &mut StageTimings,
+) -> Result<Vec<(u64, Vec<PreprocessedBlock>)>> {
     let start = Instant::now();
     let ranges: Vec<_> = (start_block..=end_block).step_by(usize::try_from(chunk_size).unwrap()).collect();
     let mut all_blocks = Vec::new();
     for chunk in ranges {
         let start = Instant::now();
-        let start_block = chunk;
-        let end_block = (chunk + chunk_size - 1).min(end_block);
-        let blocks: Vec<_> = (start_block..=end_block)
+        let chunk_end = (chunk + chunk_size - 1).min(end_block);
+        telemetry::start_chunk(chunk, chunk_end);
+        let _span = telemetry::stage_span("read", chunk, None);
+        let blocks: Vec<_> = (chunk..=chunk_end)
             .into_par_iter()
             .map(|block_num| {
-                let f = ((block_num - 1) / 1_000_000) * 1_000_000;
-                let s = ((block_num - 1) / 1_000) * 1_000;
-                let path = format!("{dir}/{f}/{s}/{block_num}.rmp.lz4");
-                let path = PathBuf::from(path);
-                let block_and_receipts = read_block_and_receipts(&path)
-                    .inspect_err(|_| println!("failed to read block {block_num}"))
-                    .unwrap();
-                let BlockAndReceipts { block: EvmBlock::Reth115(block), .. } = &block_and_receipts;
-                let signers = block
-                    .body()
-                    .transactions
-                    .iter()
-                    .map(|tx_signed| tx_signed.clone().try_into_ecrecovered().unwrap().into_parts().1)
-                    .collect_vec();
-                PreprocessedBlock { block_num, block_and_receipts, signers }
+                let block_and_receipts =
+                    source.read_block(block_num).with_context(|| format!("failed to read block {block_num}"))?;
+                anyhow::Ok((block_num, block_and_receipts))
+            })
+            .collect::<Result<_>>()?;
+        all_blocks.push((chunk, preprocess_chunk(source, (chunk, chunk_end), start, blocks, timings)?));
+    }
+    println!("Deserialized n={} blocks in {:?}", end_block - start_block + 1, start.elapsed());
+    Ok(all_blocks)
+}
+
+// Like read_blocks, but with up to `queue_depth` files read concurrently through tokio, so the reader overlaps disk
+// latency instead of blocking a runtime thread on each read. Decoding runs on the thread pool.
+pub async fn read_blocks_async(
+    source: &BlockSource,
+    start_block: u64,
+    end_block: u64,
+    chunk_size: u64,
+    queue_depth: usize,
+    timings: &mut StageTimings,
+) -> Result<Vec<(u64, Vec<PreprocessedBlock>)>> {
+    let start = Instant::now();
+    let mut all_blocks = Vec::new();
+    for chunk in (start_block..=end_block).step_by(usize::try_from(chunk_size).unwrap()) {
+        let start = Instant::now();
+        let chunk_end = (chunk + chunk_size - 1).min(end_block);
+        telemetry::start_chunk(chunk, chunk_end);
+        let _span = telemetry::stage_span("read", chunk, None);
+        let files: Vec<_> = stream::iter(chunk..=chunk_end)
+            .map(|block_num| async move {
+                let buffer = source.store.read_async(block_num).await?.ok_or_else(|| missing_block(block_num))?;
+                anyhow::Ok((block_num, buffer))
             })
-            .collect();
-        println!("Deserialized blocks {}-{} in {:?}", start_block, end_block, start.elapsed());
-        all_blocks.push((chunk, blocks));
+            .buffered(queue_depth)
+            .try_collect()
+            .await?;
+        tokio::task::block_in_place(|| -> Result<()> {
+            let blocks: Vec<_> = files
+                .into_par_iter()
+                .map(|(block_num, buffer)| {
+                    let block_and_receipts = source
+                        .decode_block(block_num, buffer)
+                        .with_context(|| format!("failed to read block {block_num}"))?;
+                    anyhow::Ok((block_num, block_and_receipts))
+                })
+                .collect::<Result<_>>()?;
+            all_blocks.push((chunk, preprocess_chunk(source, (chunk, chunk_end), start, blocks, timings)?));
+            Ok(())
+        })?;
     }
     println!("Deserialized n={} blocks in {:?}", end_block - start_block + 1, start.elapsed());
-    all_blocks
+    Ok(all_blocks)
 }
 
-pub fn read_abci_state(fln: String) -> Result<(u64, InMemoryDB)> {
+pub fn read_abci_state(fln: &Path) -> Result<(u64, InMemoryDB)> {
     let mut file = File::open(fln)?;
     let mut buffer = Vec::new();
     file.read_to_end(&mut buffer)?;
@@ -91,12 +666,57 @@ pub fn read_abci_state(fln: String) -> Result<(u64, InMemoryDB)> {
     Ok(state.into_next_block_num_and_in_memory_db())
 }
 
-pub fn read_evm_state(fln: String) -> Result<(u64, InMemoryDB)> {
+pub fn read_snapshot(fln: &Path) -> Result<(SnapshotHeader, EvmState)> {
     let mut file = File::open(fln)?;
     let mut buffer = Vec::new();
     file.read_to_end(&mut buffer)?;
-    let (next_block_num, evm_state): (u64, EvmState) = rmp_serde::from_slice(&buffer)?;
-    Ok((next_block_num, evm_state.into()))
+    decode_snapshot(&open_snapshot(buffer)?)
+}
+
+// Only plain snapshots can be read up to the end of their header; encrypted ones are read in full
+pub fn read_snapshot_header(fln: &Path) -> Result<SnapshotHeader> {
+    match decode_snapshot_header(BufReader::new(File::open(fln)?))? {
+        Some(header) => Ok(header),
+        None => Ok(read_snapshot(fln)?.0),
+    }
+}
+
+// The newest snapshot in `dir` for `chain` that resumes at or before `max_next_block_num`. Snapshots are found by
+// their headers rather than their file names, so the chunk size they were taken at does not matter. Snapshots that do
+// not record their chain are taken to be for any chain.
+pub fn find_snapshot(
+    dir: &Path,
+    chain: Chain,
+    max_next_block_num: Option<u64>,
+) -> Result<Option<(SnapshotHeader, PathBuf)>> {
+    let mut newest: Option<(SnapshotHeader, PathBuf)> = None;
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if !path.is_file() || path.extension().is_none_or(|ext| ext != "rmp") {
+            continue;
+        }
+        let header = match read_snapshot_header(&path) {
+            Ok(header) => header,
+            Err(e) => {
+                eprintln!("Warning: skipping {}: {e}", path.display());
+                continue;
+            }
+        };
+        if header.chain.is_some_and(|snapshot_chain| snapshot_chain != chain)
+            || max_next_block_num.is_some_and(|max| header.next_block_num > max)
+        {
+            continue;
+        }
+        if newest.as_ref().is_none_or(|(newest, _)| header.next_block_num > newest.next_block_num) {
+            newest = Some((header, path));
+        }
+    }
+    Ok(newest)
+}
+
+pub fn read_evm_state(fln: &Path) -> Result<(u64, InMemoryDB)> {
+    let (header, evm_state) = read_snapshot(fln)?;
+    Ok((header.next_block_num, evm_state.into()))
 }
 
 fn create_file_with_dirs(path: &Path) -> Result<File> {
@@ -106,41 +726,206 @@ fn create_file_with_dirs(path: &Path) -> Result<File> {
     Ok(File::create(path)?)
 }
 
-pub fn snapshot_evm_state(next_block_num: u64, state: &EvmState, fln: String) -> Result<()> {
-    let mut file = create_file_with_dirs(Path::new(&fln))?;
-    let buffer = rmp_serde::to_vec(&(next_block_num, state))?;
+pub fn snapshot_evm_state(next_block_num: u64, state: &EvmState, fln: &Path) -> Result<()> {
+    write_snapshot_as(next_block_num, state, fln, SnapshotFormat::default(), None, None)
+}
+
+pub fn write_snapshot_as(
+    next_block_num: u64,
+    state: &EvmState,
+    fln: &Path,
+    format: SnapshotFormat,
+    chain: Option<Chain>,
+    token_map: Option<&TokenMap>,
+) -> Result<()> {
+    let mut file = create_file_with_dirs(fln)?;
+    let buffer = seal_snapshot(encode_snapshot(next_block_num, state, format, chain, token_map)?)?;
     file.write_all(&buffer)?;
     Ok(())
 }
 
+// Directory levels of a local block mirror, each grouping blocks into multiples of its size, e.g. `1000000/1000` for
+// `{dir}/4000000/4000000/4000001.rmp.lz4` (the S3 bucket's own keys) or `flat` for no subdirectories
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct ShardLayout(Vec<u64>);
+
+impl Default for ShardLayout {
+    fn default() -> Self {
+        Self(vec![1_000_000, 1_000])
+    }
+}
+
+impl FromStr for ShardLayout {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s == "flat" {
+            return Ok(Self(Vec::new()));
+        }
+        let levels: Vec<u64> = s.split('/').map(str::parse).collect::<Result<_, _>>()?;
+        if levels.contains(&0) {
+            return Err(anyhow!("shard sizes must be positive"));
+        }
+        Ok(Self(levels))
+    }
+}
+
+impl TryFrom<String> for ShardLayout {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl Display for ShardLayout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.0.is_empty() {
+            return write!(f, "flat");
+        }
+        write!(f, "{}", self.0.iter().join("/"))
+    }
+}
+
+impl ShardLayout {
+    pub fn block_path(&self, block_num: u64) -> String {
+        self.file_path(block_num, "rmp.lz4")
+    }
+
+    // The file's path under a local `dir`, with the platform's separators
+    pub fn local_path(&self, dir: &Path, block_num: u64, extension: &str) -> PathBuf {
+        let mut path = dir.to_owned();
+        for level in &self.0 {
+            path.push(format!("{}", ((block_num - 1) / level) * level));
+        }
+        path.push(format!("{block_num}.{extension}"));
+        path
+    }
+
+    // The file's key in a mirror, or its path relative to the mirror's root
+    pub fn file_path(&self, block_num: u64, extension: &str) -> String {
+        let mut path = String::new();
+        for level in &self.0 {
+            path += &format!("{}/", ((block_num - 1) / level) * level);
+        }
+        path + &format!("{block_num}.{extension}")
+    }
+}
+
 fn block_key(block_num: u64) -> String {
-    let f = ((block_num - 1) / 1_000_000) * 1_000_000;
-    let s = ((block_num - 1) / 1_000) * 1_000;
-    format!("{f}/{s}/{block_num}.rmp.lz4")
+    ShardLayout::default().block_path(block_num)
 }
 
-async fn fetch_block(block_num: u64, dir: PathBuf, s3: Arc<Client>, pb: ProgressBar, bucket: &str) -> Result<()> {
-    let key = block_key(block_num);
-    let local_path: PathBuf = dir.join(&key);
-    if local_path.is_file() {
-        pb.inc(1);
-        return Ok(());
+// Moves every block file under dir, whatever its current layout, to its path in the given layout. Snapshots share
+// the uncompressed blocks' .rmp extension, so a snapshots dir is left alone and .rmp files only move if they parse
+// as blocks.
+pub fn migrate_blocks(dir: &Path, layout: &ShardLayout) -> Result<usize> {
+    let dir = &long_path(dir);
+    let mut index = BTreeMap::new();
+    index_files(dir, block_file_number, &mut index)?;
+    let mut moved = 0;
+    for (block_num, path) in index {
+        let in_snapshot_dir = path
+            .strip_prefix(dir)
+            .is_ok_and(|relative| relative.components().any(|component| component.as_os_str() == SNAPSHOTS_DIR));
+        let Some(compression) = BlockCompression::of_path(&path).filter(|_| !in_snapshot_dir) else {
+            continue;
+        };
+        if compression == BlockCompression::None && read_block_file(&path).is_err() {
+            continue;
+        }
+        let target = layout.local_path(dir, block_num, compression.extension());
+        if path != target {
+            if let Some(parent) = target.parent() {
+                create_dir_all(parent)?;
+            }
+            std::fs::rename(&path, &target)?;
+            moved += 1;
+        }
     }
+    remove_empty_dirs(dir)?;
+    Ok(moved)
+}
 
-    let obj = s3.get_object().bucket(bucket).key(key).request_payer(RequestPayer::Requester).send().await?;
+pub(crate) fn remove_empty_dirs(dir: &Path) -> Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            remove_empty_dirs(&path)?;
+            if std::fs::read_dir(&path)?.next().is_none() {
+                std::fs::remove_dir(&path)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn fetch_block(
+    block_num: u64,
+    dir: &Path,
+    layout: &ShardLayout,
+    compression: BlockCompression,
+    mirrors: &Mirrors,
+    pb: ProgressBar,
+) -> Result<()> {
+    if !fetch_block_if_available(dir, layout, compression, mirrors, block_num).await? {
+        return Err(anyhow!("block {block_num} is not published on any mirror"));
+    }
+    pb.inc(1);
+    Ok(())
+}
 
-    let mut body = obj.body.into_async_read();
+// Returns false if the block has not been published yet
+async fn fetch_block_if_available(
+    dir: &Path,
+    layout: &ShardLayout,
+    compression: BlockCompression,
+    mirrors: &Mirrors,
+    block_num: u64,
+) -> Result<bool> {
+    if existing_block_file(dir, layout, block_num).is_some() {
+        return Ok(true);
+    }
+    let Some(mut bytes) = mirrors.fetch(&block_key(block_num)).await? else {
+        return Ok(false);
+    };
+    if compression != BlockCompression::Lz4 {
+        bytes = compression.compress(&decompress(&bytes)?)?;
+    }
+    let local_path = layout.local_path(dir, block_num, compression.extension());
     if let Some(parent) = local_path.parent() {
         create_dir_all(parent)?;
     }
-    let mut file = tokio::fs::File::create(&local_path).await?;
-    tokio::io::copy(&mut body, &mut file).await?;
+    // Write to a temporary file first so an interrupted download is never mistaken for a complete block
+    let mut partial_path = local_path.clone().into_os_string();
+    partial_path.push(".partial");
+    let mut file = tokio::fs::File::create(&partial_path).await?;
+    file.write_all(&bytes).await?;
+    file.flush().await?;
+    std::fs::rename(partial_path, local_path)?;
+    Ok(true)
+}
 
-    pb.inc(1);
-    Ok(())
+// Compressed and decompressed sizes of the published blocks among `block_nums`
+pub async fn sample_block_sizes(mirrors: &Mirrors, block_nums: &[u64]) -> Result<Vec<(u64, u64)>> {
+    let mut sizes = Vec::new();
+    for &block_num in block_nums {
+        if let Some(compressed) = mirrors.fetch(&block_key(block_num)).await? {
+            sizes.push((compressed.len() as u64, decompress(&compressed)?.len() as u64));
+        }
+    }
+    Ok(sizes)
 }
 
-pub async fn download_blocks(chain: Chain, dir: &str, start_block: u64, end_block: u64) -> Result<()> {
+pub async fn download_blocks(
+    mirrors: &Mirrors,
+    dir: &Path,
+    layout: &ShardLayout,
+    compression: BlockCompression,
+    start_block: u64,
+    end_block: u64,
+) -> Result<()> {
     let pb = ProgressBar::new(end_block - start_block + 1);
     pb.set_style(
         ProgressStyle::default_bar()
@@ -148,22 +933,13 @@ pub async fn download_blocks(chain: Chain, dir: &str, start_block: u64, end_bloc
             .unwrap()
             .progress_chars("##-"),
     );
-    let region = Region::new("ap-northeast-1".to_string());
-    let config = aws_config::defaults(BehaviorVersion::latest()).region(region).load().await;
-    let s3 = Arc::new(Client::new(&config));
-
-    let bucket = bucket(chain);
+    let dir = &long_path(dir);
 
     let mut cur_block = start_block;
     while cur_block <= end_block {
         let next_block = (end_block + 1).min(cur_block + DOWNLOAD_CHUNK_SIZE);
-        let mut futures = Vec::with_capacity((next_block - cur_block).try_into().unwrap());
-        for block_num in cur_block..next_block {
-            let local_path = PathBuf::from(dir);
-            let s3 = s3.clone();
-            let pb = pb.clone();
-            futures.push(fetch_block(block_num, local_path, s3, pb, bucket));
-        }
+        let futures = (cur_block..next_block)
+            .map(|block_num| fetch_block(block_num, dir, layout, compression, mirrors, pb.clone()));
         stream::iter(futures).buffer_unordered(CONCURRENCY_LIMIT).try_collect::<Vec<()>>().await?;
         cur_block = next_block;
     }
@@ -174,28 +950,68 @@ pub async fn download_blocks(chain: Chain, dir: &str, start_block: u64, end_bloc
 mod tests {
     use crate::{
         cli::Chain,
-        fs::{download_blocks, read_abci_state, read_evm_state, snapshot_evm_state},
+        fs::{
+            block_file_number, download_blocks, read_abci_state, read_evm_state, snapshot_evm_state, BlockCompression,
+            ShardLayout,
+        },
+        mirrors::Mirrors,
         state::State,
+        throttle::DownloadLimits,
     };
     use anyhow::Result;
-    use std::time::Instant;
+    use std::{path::Path, time::Instant};
 
     #[tokio::test]
     async fn test_block_download() -> Result<()> {
         let time = Instant::now();
-        download_blocks(Chain::Mainnet, "hl-mainnet-evm-blocks", 4000000, 4001000).await?;
+        let mirrors = Mirrors::connect(Chain::Mainnet, &[], DownloadLimits::default()).await?;
+        download_blocks(
+            &mirrors,
+            Path::new("hl-mainnet-evm-blocks"),
+            &ShardLayout::default(),
+            BlockCompression::Lz4,
+            4000000,
+            4001000,
+        )
+        .await?;
         println!("downloaded in {:?}", time.elapsed());
         Ok(())
     }
 
+    #[test]
+    fn test_shard_layout_paths() -> Result<()> {
+        assert_eq!(ShardLayout::default().block_path(4000001), "4000000/4000000/4000001.rmp.lz4");
+        assert_eq!("flat".parse::<ShardLayout>()?.block_path(12345678), "12345678.rmp.lz4");
+        assert_eq!("100/10".parse::<ShardLayout>()?.block_path(12345), "12300/12340/12345.rmp.lz4");
+        let dir = Path::new("blocks");
+        assert_eq!(
+            ShardLayout::default().local_path(dir, 4000001, "rmp.lz4"),
+            dir.join("4000000/4000000/4000001.rmp.lz4")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_block_compression_roundtrip() -> Result<()> {
+        let data = [0x91, 0x01, 0x02, 0x03].repeat(100);
+        for compression in BlockCompression::ALL {
+            let compressed = compression.compress(&data)?;
+            assert_eq!(BlockCompression::detect(&compressed), compression);
+            assert_eq!(compression.decompress(compressed)?, data);
+        }
+        assert_eq!(block_file_number(Path::new("1000/1234.rmp.zst")), Some(1234));
+        assert_eq!(block_file_number(Path::new("1234.rmp.lz4.partial")), None);
+        Ok(())
+    }
+
     #[test]
     fn test_evm_state_serde() -> Result<()> {
-        let abci_state_path = "tmp/abci_state.rmp";
-        let state = read_abci_state(abci_state_path.to_owned())?;
-        let snapshot_path = "tmp/snapshot.rmp";
+        let abci_state_path = Path::new("tmp/abci_state.rmp");
+        let state = read_abci_state(abci_state_path)?;
+        let snapshot_path = Path::new("tmp/snapshot.rmp");
         let hash1 = state.1.blake3_hash_slow();
-        snapshot_evm_state(state.0, &state.1.into(), snapshot_path.to_owned())?;
-        let state = read_evm_state(snapshot_path.to_owned())?;
+        snapshot_evm_state(state.0, &state.1.into(), snapshot_path)?;
+        let state = read_evm_state(snapshot_path)?;
         let hash2 = state.1.blake3_hash_slow();
         assert_eq!(hash1, hash2);
         Ok(())
@@ -0,0 +1,233 @@
+use crate::{
+    cli::Chain,
+    control::ControlHandle,
+    fs::BlockSource,
+    types::{BlockAndReceipts, EvmBlock},
+};
+use alloy::{
+    consensus::Transaction as _,
+    primitives::{Address, U256},
+};
+use anyhow::Result;
+use async_graphql::{http::GraphiQLSource, Context, EmptyMutation, EmptySubscription, Object, Schema, SimpleObject};
+use async_graphql_axum::GraphQL;
+use axum::{response::Html, routing::get, Router};
+use reth_primitives::{transaction::SignedTransactionIntoRecoveredExt, Receipt};
+use revm::primitives::AccountInfo;
+use std::{net::SocketAddr, sync::Arc};
+use tokio::net::TcpListener;
+
+const MAX_BLOCKS_PER_QUERY: u64 = 100;
+
+struct GraphqlContext {
+    chain: Chain,
+    handle: ControlHandle,
+    source: Arc<BlockSource>,
+}
+
+#[derive(SimpleObject)]
+struct SyncStatusObject {
+    chain: String,
+    start_block: u64,
+    end_block: u64,
+    current_block: u64,
+    last_snapshot_block: u64,
+    paused: bool,
+}
+
+#[derive(SimpleObject)]
+struct BlockObject {
+    number: u64,
+    hash: String,
+    parent_hash: String,
+    timestamp: u64,
+    gas_used: u64,
+    gas_limit: u64,
+    base_fee_per_gas: Option<u64>,
+    system_transaction_count: usize,
+    transactions: Vec<TransactionObject>,
+}
+
+#[derive(SimpleObject)]
+struct TransactionObject {
+    index: usize,
+    hash: String,
+    from: String,
+    to: Option<String>,
+    value: String,
+    nonce: u64,
+    gas_limit: u64,
+    input: String,
+    receipt: ReceiptObject,
+}
+
+#[derive(SimpleObject)]
+struct ReceiptObject {
+    success: bool,
+    cumulative_gas_used: u64,
+    logs: Vec<LogObject>,
+}
+
+#[derive(SimpleObject)]
+struct LogObject {
+    address: String,
+    topics: Vec<String>,
+    data: String,
+}
+
+struct AccountObject {
+    block: u64,
+    address: Address,
+    info: Option<AccountInfo>,
+}
+
+fn block_object(block_and_receipts: BlockAndReceipts) -> BlockObject {
+    let BlockAndReceipts { block: EvmBlock::Reth115(block), receipts, system_txs, .. } = block_and_receipts;
+    let transactions = block
+        .body()
+        .transactions
+        .iter()
+        .zip(receipts)
+        .enumerate()
+        .map(|(index, (tx_signed, receipt))| {
+            let from =
+                tx_signed.clone().try_into_ecrecovered().map(|tx| tx.into_parts().1.to_string()).unwrap_or_default();
+            let tx = &tx_signed.transaction;
+            let Receipt { success, cumulative_gas_used, logs, .. } = receipt.into();
+            TransactionObject {
+                index,
+                hash: tx_signed.hash().to_string(),
+                from,
+                to: tx.to().map(|to| to.to_string()),
+                value: tx.value().to_string(),
+                nonce: tx.nonce(),
+                gas_limit: tx.gas_limit(),
+                input: tx.input().to_string(),
+                receipt: ReceiptObject {
+                    success,
+                    cumulative_gas_used,
+                    logs: logs
+                        .into_iter()
+                        .map(|log| LogObject {
+                            address: log.address.to_string(),
+                            topics: log.topics().iter().map(ToString::to_string).collect(),
+                            data: log.data.data.to_string(),
+                        })
+                        .collect(),
+                },
+            }
+        })
+        .collect();
+    let header = block.header();
+    BlockObject {
+        number: header.number,
+        hash: block.hash().to_string(),
+        parent_hash: header.parent_hash.to_string(),
+        timestamp: header.timestamp,
+        gas_used: header.gas_used,
+        gas_limit: header.gas_limit,
+        base_fee_per_gas: header.base_fee_per_gas,
+        system_transaction_count: system_txs.len(),
+        transactions,
+    }
+}
+
+#[Object]
+impl AccountObject {
+    // Block the account was read at
+    async fn block(&self) -> u64 {
+        self.block
+    }
+
+    async fn address(&self) -> String {
+        self.address.to_string()
+    }
+
+    async fn exists(&self) -> bool {
+        self.info.is_some()
+    }
+
+    async fn balance(&self) -> String {
+        self.info.as_ref().map(|info| info.balance).unwrap_or_default().to_string()
+    }
+
+    async fn nonce(&self) -> u64 {
+        self.info.as_ref().map(|info| info.nonce).unwrap_or_default()
+    }
+
+    async fn code_hash(&self) -> Option<String> {
+        self.info.as_ref().map(|info| info.code_hash.to_string())
+    }
+
+    // Read from the live state, so it may be at a later block than the account itself
+    async fn storage(&self, ctx: &Context<'_>, slot: String) -> async_graphql::Result<String> {
+        let slot: U256 = slot.parse()?;
+        let (_, value) = ctx.data::<GraphqlContext>()?.handle.storage(self.address, slot).await?;
+        Ok(value.to_string())
+    }
+}
+
+struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    async fn status(&self, ctx: &Context<'_>) -> async_graphql::Result<SyncStatusObject> {
+        let context = ctx.data::<GraphqlContext>()?;
+        let status = &context.handle.status;
+        Ok(SyncStatusObject {
+            chain: context.chain.to_string(),
+            start_block: status.start_block(),
+            end_block: status.end_block(),
+            current_block: status.current_block(),
+            last_snapshot_block: status.last_snapshot_block(),
+            paused: status.is_paused(),
+        })
+    }
+
+    // Any block in the blocks dir, whether or not it has been executed yet
+    async fn block(&self, ctx: &Context<'_>, number: u64) -> async_graphql::Result<BlockObject> {
+        let source = ctx.data::<GraphqlContext>()?.source.clone();
+        let block_and_receipts = tokio::task::spawn_blocking(move || source.read_block(number)).await??;
+        Ok(block_object(block_and_receipts))
+    }
+
+    async fn blocks(&self, ctx: &Context<'_>, from: u64, to: u64) -> async_graphql::Result<Vec<BlockObject>> {
+        if to < from || to - from >= MAX_BLOCKS_PER_QUERY {
+            return Err(format!("at most {MAX_BLOCKS_PER_QUERY} blocks can be queried at once").into());
+        }
+        let source = ctx.data::<GraphqlContext>()?.source.clone();
+        let blocks = tokio::task::spawn_blocking(move || {
+            (from..=to).map(|number| source.read_block(number)).collect::<Result<Vec<_>>>()
+        })
+        .await??;
+        Ok(blocks.into_iter().map(block_object).collect())
+    }
+
+    async fn account(&self, ctx: &Context<'_>, address: String) -> async_graphql::Result<AccountObject> {
+        let address: Address = address.parse()?;
+        let (block, info) = ctx.data::<GraphqlContext>()?.handle.account(address).await?;
+        Ok(AccountObject { block, address, info })
+    }
+}
+
+async fn graphiql() -> Html<String> {
+    Html(GraphiQLSource::build().endpoint("/graphql").finish())
+}
+
+// Blocks, transactions, and receipts come from the block files and accounts from the live state. GET /graphql serves
+// an in-browser query editor.
+pub async fn serve_graphql(
+    addr: SocketAddr,
+    chain: Chain,
+    handle: ControlHandle,
+    source: Arc<BlockSource>,
+) -> Result<()> {
+    println!("Serving GraphQL on {addr}/graphql");
+    let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+        .data(GraphqlContext { chain, handle, source })
+        .finish();
+    let app = Router::new().route("/graphql", get(graphiql).post_service(GraphQL::new(schema)));
+    let listener = TcpListener::bind(addr).await?;
+    axum::serve(listener, app).await?;
+    Ok(())
+}
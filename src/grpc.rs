@@ -0,0 +1,100 @@
+use crate::{cli::Chain, control::ControlHandle};
+use alloy::primitives::{Address, U256};
+use anyhow::Result;
+use revm::primitives::AccountInfo;
+use std::net::SocketAddr;
+use tonic::{transport::Server, Request, Response, Status};
+
+#[allow(clippy::all)]
+mod pb {
+    tonic::include_proto!("hyper_evm_sync.control");
+}
+
+use pb::{
+    control_server::{Control, ControlServer},
+    GetAccountRequest, GetAccountResponse, GetStorageRequest, GetStorageResponse, PauseRequest, ResumeRequest,
+    StatusRequest, StatusResponse, TriggerSnapshotRequest, TriggerSnapshotResponse,
+};
+
+struct ControlService {
+    chain: Chain,
+    handle: ControlHandle,
+}
+
+impl ControlService {
+    fn status_response(&self) -> StatusResponse {
+        let status = &self.handle.status;
+        StatusResponse {
+            chain: self.chain.to_string(),
+            start_block: status.start_block(),
+            end_block: status.end_block(),
+            current_block: status.current_block(),
+            last_snapshot_block: status.last_snapshot_block(),
+            paused: status.is_paused(),
+        }
+    }
+}
+
+fn parse<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, Status>
+where
+    T::Err: std::fmt::Display,
+{
+    value.parse().map_err(|e| Status::invalid_argument(format!("Invalid {name} {value}: {e}")))
+}
+
+fn unavailable(e: anyhow::Error) -> Status {
+    Status::unavailable(e.to_string())
+}
+
+#[tonic::async_trait]
+impl Control for ControlService {
+    async fn status(&self, _request: Request<StatusRequest>) -> Result<Response<StatusResponse>, Status> {
+        Ok(Response::new(self.status_response()))
+    }
+
+    async fn pause(&self, _request: Request<PauseRequest>) -> Result<Response<StatusResponse>, Status> {
+        self.handle.pause();
+        Ok(Response::new(self.status_response()))
+    }
+
+    async fn resume(&self, _request: Request<ResumeRequest>) -> Result<Response<StatusResponse>, Status> {
+        self.handle.resume();
+        Ok(Response::new(self.status_response()))
+    }
+
+    async fn trigger_snapshot(
+        &self,
+        _request: Request<TriggerSnapshotRequest>,
+    ) -> Result<Response<TriggerSnapshotResponse>, Status> {
+        let (block, path) = self.handle.snapshot().await.map_err(|e| Status::internal(e.to_string()))?;
+        Ok(Response::new(TriggerSnapshotResponse { block, path }))
+    }
+
+    async fn get_account(&self, request: Request<GetAccountRequest>) -> Result<Response<GetAccountResponse>, Status> {
+        let address: Address = parse("address", &request.into_inner().address)?;
+        let (block, info) = self.handle.account(address).await.map_err(unavailable)?;
+        let exists = info.is_some();
+        let AccountInfo { balance, nonce, code_hash, .. } = info.unwrap_or_default();
+        Ok(Response::new(GetAccountResponse {
+            block,
+            exists,
+            balance: format!("{balance:#x}"),
+            nonce,
+            code_hash: code_hash.to_string(),
+        }))
+    }
+
+    async fn get_storage(&self, request: Request<GetStorageRequest>) -> Result<Response<GetStorageResponse>, Status> {
+        let GetStorageRequest { address, slot } = request.into_inner();
+        let address: Address = parse("address", &address)?;
+        let slot: U256 = parse("slot", &slot)?;
+        let (block, value) = self.handle.storage(address, slot).await.map_err(unavailable)?;
+        Ok(Response::new(GetStorageResponse { block, value: format!("{value:#x}") }))
+    }
+}
+
+pub async fn serve_control(addr: SocketAddr, chain: Chain, handle: ControlHandle) -> Result<()> {
+    println!("Serving gRPC control interface on {addr}");
+    Server::builder().add_service(ControlServer::new(ControlService { chain, handle })).serve(addr).await?;
+    Ok(())
+}
@@ -0,0 +1,80 @@
+use crate::status::SyncStatus;
+use anyhow::Result;
+use axum::{extract::State, http::StatusCode, routing::get, Json, Router};
+use serde::Serialize;
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::net::TcpListener;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum SyncState {
+    Syncing,
+    CaughtUp,
+    Stalled,
+}
+
+#[derive(Serialize)]
+struct HealthBody {
+    state: SyncState,
+    current_block: u64,
+    seconds_since_progress: u64,
+}
+
+#[derive(Clone)]
+struct HealthState {
+    status: Arc<SyncStatus>,
+    stall_timeout: Duration,
+    // Last block seen to change, and when
+    progress: Arc<Mutex<(u64, Instant)>>,
+}
+
+impl HealthState {
+    fn check(&self) -> HealthBody {
+        let current_block = self.status.current_block();
+        let mut progress = self.progress.lock().unwrap();
+        if progress.0 != current_block {
+            *progress = (current_block, Instant::now());
+        }
+        let since_progress = progress.1.elapsed();
+        let state = if self.status.is_caught_up() {
+            SyncState::CaughtUp
+        } else if since_progress > self.stall_timeout && !self.status.is_paused() {
+            SyncState::Stalled
+        } else {
+            SyncState::Syncing
+        };
+        HealthBody { state, current_block, seconds_since_progress: since_progress.as_secs() }
+    }
+}
+
+// Liveness: fails only once the pipeline has stopped making progress without being caught up or paused
+async fn healthz(State(state): State<HealthState>) -> (StatusCode, Json<HealthBody>) {
+    let body = state.check();
+    let code = if body.state == SyncState::Stalled { StatusCode::SERVICE_UNAVAILABLE } else { StatusCode::OK };
+    (code, Json(body))
+}
+
+// Readiness: only succeeds once every published block has been executed, so queries see the chain tip
+async fn readyz(State(state): State<HealthState>) -> (StatusCode, Json<HealthBody>) {
+    let body = state.check();
+    let code = if body.state == SyncState::CaughtUp { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    (code, Json(body))
+}
+
+// Progress is sampled when probed, so a probe interval well below `stall_timeout` is assumed
+pub async fn serve_health(addr: SocketAddr, status: Arc<SyncStatus>, stall_timeout: Duration) -> Result<()> {
+    println!("Serving health checks on {addr}");
+    let progress = Arc::new(Mutex::new((status.current_block(), Instant::now())));
+    let app = Router::new().route("/healthz", get(healthz)).route("/readyz", get(readyz)).with_state(HealthState {
+        status,
+        stall_timeout,
+        progress,
+    });
+    let listener = TcpListener::bind(addr).await?;
+    axum::serve(listener, app).await?;
+    Ok(())
+}
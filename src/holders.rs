@@ -0,0 +1,168 @@
+use crate::{
+    chainspec::ChainSpec,
+    error::ErrorKind,
+    query::snapshot_block,
+    run::{call, CallRequest},
+};
+use alloy::primitives::{b256, keccak256, Address, B256, U256};
+use anyhow::{anyhow, Context, Result};
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use revm::{primitives::HashMap, InMemoryDB};
+use serde::Serialize;
+use std::{
+    collections::BTreeSet,
+    fmt::Display,
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+// Slots tried for the balances mapping without a hint: the first declared variables, where a Solidity ERC20 usually
+// keeps `mapping(address => uint256)` of balances, and OpenZeppelin 5's namespaced ERC20 storage
+const SEARCHED_SLOTS: u64 = 16;
+const OZ_ERC20_STORAGE: B256 = b256!("0x52c63247e1f47db19d5ce0460030c497f067ca4cebf71ba98eeadabe20bace00");
+const TOTAL_SUPPLY_SELECTOR: [u8; 4] = [0x18, 0x16, 0x0d, 0xdd];
+const TOTAL_SUPPLY_GAS_LIMIT: u64 = 1_000_000;
+
+// Where Solidity stores `mapping[key]` of a mapping declared at `slot`
+fn mapping_slot(key: Address, slot: U256) -> U256 {
+    let mut preimage = [0; 64];
+    preimage[12..32].copy_from_slice(key.as_slice());
+    preimage[32..].copy_from_slice(&slot.to_be_bytes::<32>());
+    U256::from_be_bytes(keccak256(preimage).0)
+}
+
+fn matches(storage: &HashMap<U256, U256>, candidates: &[Address], slot: U256) -> Vec<(Address, U256)> {
+    candidates
+        .par_iter()
+        .filter_map(|&holder| storage.get(&mapping_slot(holder, slot)).map(|&balance| (holder, balance)))
+        .filter(|(_, balance)| !balance.is_zero())
+        .collect()
+}
+
+fn total_supply(spec: &ChainSpec, state: &InMemoryDB, next_block_num: u64, token: Address) -> Option<U256> {
+    let request = CallRequest {
+        from: Address::ZERO,
+        to: token,
+        data: TOTAL_SUPPLY_SELECTOR.to_vec().into(),
+        value: U256::ZERO,
+        gas_limit: TOTAL_SUPPLY_GAS_LIMIT,
+    };
+    let result = call(spec, state, next_block_num, request).ok()?;
+    let output = result.output().filter(|output| result.is_success() && output.len() >= 32)?;
+    Some(U256::from_be_slice(&output[..32]))
+}
+
+#[derive(Debug, Serialize)]
+pub struct HolderExport {
+    pub token: Address,
+    pub block: u64,
+    pub balance_slot: U256,
+    pub out: PathBuf,
+    pub holders: usize,
+    pub total_balance: U256,
+    // None if the token has no working totalSupply()
+    pub total_supply: Option<U256>,
+}
+
+impl Display for HolderExport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Exported n={} holders of {} at block {} (balances mapping at slot {}) to {}",
+            self.holders,
+            self.token,
+            self.block,
+            self.balance_slot,
+            self.out.display()
+        )?;
+        match self.total_supply {
+            Some(total_supply) if total_supply != self.total_balance => write!(
+                f,
+                "\nWarning: the balances add up to {} but totalSupply() is {total_supply}; holders without an \
+                 account in the state are missing (see --holders)",
+                self.total_balance
+            ),
+            _ => Ok(()),
+        }
+    }
+}
+
+// One address per line
+pub fn read_holder_list(fln: &Path) -> Result<Vec<Address>> {
+    let text = std::fs::read_to_string(fln).with_context(|| format!("failed to read {}", fln.display()))?;
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            line.parse()
+                .with_context(|| format!("invalid address {line} in {}", fln.display()))
+                .context(ErrorKind::Usage)
+        })
+        .collect()
+}
+
+// Holders are found by looking up candidate addresses in the balances mapping, as storage only has the hashed keys.
+// Candidates are every account in the state, addresses stored in the token's own storage, and `extra_holders`.
+pub fn export_holders(
+    spec: &ChainSpec,
+    next_block_num: u64,
+    state: &InMemoryDB,
+    token: Address,
+    balance_slot: Option<U256>,
+    extra_holders: &[Address],
+    out: &Path,
+) -> Result<HolderExport> {
+    let storage =
+        &state.accounts.get(&token).ok_or_else(|| anyhow!("no account {token}")).context(ErrorKind::Usage)?.storage;
+    let mut candidates: BTreeSet<Address> = state.accounts.keys().copied().collect();
+    candidates.extend(extra_holders);
+    for value in storage.values() {
+        let word = B256::from(*value);
+        if word[..12].iter().all(|&byte| byte == 0) && !value.is_zero() {
+            candidates.insert(Address::from_word(word));
+        }
+    }
+    let candidates: Vec<_> = candidates.into_iter().collect();
+    let (balance_slot, mut holders) = match balance_slot {
+        Some(slot) => (slot, matches(storage, &candidates, slot)),
+        None => (0..SEARCHED_SLOTS)
+            .map(U256::from)
+            .chain([U256::from_be_bytes(OZ_ERC20_STORAGE.0)])
+            .map(|slot| (slot, matches(storage, &candidates, slot)))
+            .max_by_key(|(_, holders)| holders.len())
+            .filter(|(_, holders)| !holders.is_empty())
+            .ok_or_else(|| anyhow!("no balances mapping found in {token}; pass --balance-slot"))?,
+    };
+    holders.sort_by(|(a, a_balance), (b, b_balance)| b_balance.cmp(a_balance).then(a.cmp(b)));
+    let mut writer = BufWriter::new(File::create(out)?);
+    writeln!(writer, "holder,balance")?;
+    for (holder, balance) in &holders {
+        writeln!(writer, "{holder},{balance}")?;
+    }
+    writer.flush()?;
+    Ok(HolderExport {
+        token,
+        block: snapshot_block(next_block_num),
+        balance_slot,
+        out: out.to_path_buf(),
+        holders: holders.len(),
+        total_balance: holders.iter().fold(U256::ZERO, |total, (_, balance)| total.saturating_add(*balance)),
+        total_supply: total_supply(spec, state, next_block_num, token),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::holders::mapping_slot;
+    use alloy::primitives::{address, b256, U256};
+
+    #[test]
+    fn test_mapping_slot() {
+        let holder = address!("0x0000000000000000000000000000000000000001");
+        assert_eq!(
+            mapping_slot(holder, U256::ZERO),
+            U256::from_be_bytes(b256!("0xada5013122d395ba3c54772283fb069b10426056ef8ca54750cb9bb552a59e7d").0)
+        );
+    }
+}
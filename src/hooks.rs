@@ -0,0 +1,25 @@
+use crate::diff::StateDiff;
+use alloy::primitives::Address;
+use reth_primitives::{SealedBlock, Transaction};
+use revm::primitives::ExecutionResult;
+
+pub struct ExecutedTx<'a> {
+    pub block: &'a SealedBlock,
+    pub tx_index: usize,
+    pub is_system_tx: bool,
+    pub sender: Address,
+    pub transaction: &'a Transaction,
+    pub result: &'a ExecutionResult,
+}
+
+// Callbacks for library users building indexers or monitors on top of replay, registered in `RunConfig::hooks`.
+// They run on the processing thread between transactions, so slow hooks slow down the sync.
+pub trait SyncHooks: Send + Sync {
+    fn on_block_start(&self, _block: &SealedBlock) {}
+
+    // System transactions come first, with their own tx_index sequence
+    fn on_tx_executed(&self, _tx: &ExecutedTx<'_>) {}
+
+    // Called once all of the block's changes are in the state, with the accounts and storage slots it changed
+    fn on_block_committed(&self, _block: &SealedBlock, _diff: &StateDiff) {}
+}
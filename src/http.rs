@@ -0,0 +1,193 @@
+use crate::error::ErrorKind;
+use anyhow::{anyhow, Context, Result};
+use aws_config::timeout::TimeoutConfig;
+use rand::Rng;
+use reqwest::{blocking, Certificate, Proxy};
+use std::{future::Future, path::PathBuf, sync::OnceLock, time::Duration};
+
+// Timeouts and retries of every network call. The read timeout bounds each wait for response data rather than the
+// whole request, so large downloads that keep making progress are never cut off.
+#[derive(Debug, Clone)]
+pub struct HttpPolicy {
+    pub connect_timeout: Duration,
+    pub read_timeout: Duration,
+    // After the first attempt
+    pub retries: u32,
+    // The wait before the first retry; it doubles with each one, up to `max_backoff`, less a random jitter of up to
+    // half so that workers failing together do not retry together
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub user_agent: String,
+}
+
+impl Default for HttpPolicy {
+    fn default() -> Self {
+        Self {
+            connect_timeout: Duration::from_secs(10),
+            read_timeout: Duration::from_secs(30),
+            retries: 5,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(30),
+            user_agent: default_user_agent(),
+        }
+    }
+}
+
+pub fn default_user_agent() -> String {
+    format!("hyper-evm-sync/{}", env!("CARGO_PKG_VERSION"))
+}
+
+impl HttpPolicy {
+    // Before retry number `retry`, counting from 0
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let backoff = self.initial_backoff.saturating_mul(2u32.saturating_pow(retry)).min(self.max_backoff);
+        backoff.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+    }
+
+    // Fails with a Network error if `future` (sending a request, or reading a chunk of its response) takes longer
+    // than the read timeout
+    pub async fn read<T, E>(&self, future: impl Future<Output = Result<T, E>>) -> Result<T>
+    where
+        E: Into<anyhow::Error>,
+    {
+        match tokio::time::timeout(self.read_timeout, future).await {
+            Ok(result) => result.map_err(Into::into),
+            Err(_) => Err(anyhow!("no response within {:?}", self.read_timeout).context(ErrorKind::Network)),
+        }
+    }
+
+    pub fn aws_timeout_config(&self) -> TimeoutConfig {
+        TimeoutConfig::builder().connect_timeout(self.connect_timeout).read_timeout(self.read_timeout).build()
+    }
+}
+
+pub enum Attempt<T> {
+    Done(T),
+    Retry(anyhow::Error),
+}
+
+// Runs `attempt` until it is done, fails, or has asked for a retry more often than the policy allows. Callers decide
+// what is retryable; typically network failures, throttling, and server errors.
+pub async fn retry<T, F, Fut>(policy: &HttpPolicy, what: &str, mut attempt: F) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<Attempt<T>>>,
+{
+    let mut retries = 0;
+    loop {
+        let error = match attempt().await {
+            Ok(Attempt::Done(result)) => return Ok(result),
+            Ok(Attempt::Retry(e)) if retries < policy.retries => e,
+            Ok(Attempt::Retry(e)) => {
+                return Err(e
+                    .context(format!("{what} failed after {} attempts", retries + 1))
+                    .context(ErrorKind::Network))
+            }
+            Err(e) => return Err(e.context(format!("{what} failed"))),
+        };
+        let backoff = policy.backoff(retries);
+        eprintln!("{what} failed, retrying in {backoff:?}: {error}");
+        tokio::time::sleep(backoff).await;
+        retries += 1;
+    }
+}
+
+// Applies to every HTTP client the tool creates. Without a proxy, reqwest already follows HTTP_PROXY, HTTPS_PROXY,
+// ALL_PROXY, and NO_PROXY.
+#[derive(Debug, Clone, Default)]
+pub struct HttpOptions {
+    pub proxy: Option<String>,
+    // PEM files of CA certificates trusted in addition to the built-in roots, e.g. of a TLS-intercepting proxy
+    pub ca_certs: Vec<PathBuf>,
+    pub accept_invalid_certs: bool,
+    pub policy: HttpPolicy,
+}
+
+struct ClientSettings {
+    proxy: Option<Proxy>,
+    ca_certs: Vec<Certificate>,
+    accept_invalid_certs: bool,
+    policy: HttpPolicy,
+}
+
+static SETTINGS: OnceLock<ClientSettings> = OnceLock::new();
+
+// Called once on startup; clients built before it, or without it, use reqwest's defaults and the default policy
+pub fn configure(options: &HttpOptions) -> Result<()> {
+    let proxy = options.proxy.as_deref().map(Proxy::all).transpose()?;
+    let mut ca_certs = Vec::new();
+    for path in &options.ca_certs {
+        let pem = std::fs::read(path).with_context(|| format!("failed to read CA certificate {}", path.display()))?;
+        let certs = Certificate::from_pem_bundle(&pem)
+            .with_context(|| format!("invalid CA certificate {}", path.display()))
+            .context(ErrorKind::Usage)?;
+        if certs.is_empty() {
+            return Err(anyhow!("no certificates in {}", path.display()).context(ErrorKind::Usage));
+        }
+        ca_certs.extend(certs);
+    }
+    if options.accept_invalid_certs {
+        eprintln!("Warning: TLS certificates are not verified");
+    }
+    let settings = ClientSettings {
+        proxy,
+        ca_certs,
+        accept_invalid_certs: options.accept_invalid_certs,
+        policy: options.policy.clone(),
+    };
+    SETTINGS.set(settings).map_err(|_| anyhow!("HTTP options configured twice"))
+}
+
+pub fn policy() -> HttpPolicy {
+    SETTINGS.get().map(|settings| settings.policy.clone()).unwrap_or_default()
+}
+
+pub fn client_builder() -> reqwest::ClientBuilder {
+    let policy = policy();
+    let mut builder = reqwest::Client::builder().connect_timeout(policy.connect_timeout).user_agent(policy.user_agent);
+    if let Some(settings) = SETTINGS.get() {
+        if let Some(proxy) = &settings.proxy {
+            builder = builder.proxy(proxy.clone());
+        }
+        for cert in &settings.ca_certs {
+            builder = builder.add_root_certificate(cert.clone());
+        }
+        builder = builder.danger_accept_invalid_certs(settings.accept_invalid_certs);
+    }
+    builder
+}
+
+pub fn blocking_client_builder() -> blocking::ClientBuilder {
+    let policy = policy();
+    let mut builder = blocking::Client::builder().connect_timeout(policy.connect_timeout).user_agent(policy.user_agent);
+    if let Some(settings) = SETTINGS.get() {
+        if let Some(proxy) = &settings.proxy {
+            builder = builder.proxy(proxy.clone());
+        }
+        for cert in &settings.ca_certs {
+            builder = builder.add_root_certificate(cert.clone());
+        }
+        builder = builder.danger_accept_invalid_certs(settings.accept_invalid_certs);
+    }
+    builder
+}
+
+pub fn client() -> Result<reqwest::Client> {
+    Ok(client_builder().build()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::http::HttpPolicy;
+    use std::time::Duration;
+
+    #[test]
+    fn test_backoff() {
+        let policy = HttpPolicy::default();
+        for (retry, full) in [(0, 1), (1, 2), (4, 16), (5, 30), (40, 30)] {
+            let backoff = policy.backoff(retry);
+            let full = Duration::from_secs(full);
+            assert!(backoff >= full / 2 && backoff <= full, "{backoff:?} before retry {retry}");
+        }
+    }
+}
@@ -0,0 +1,260 @@
+use crate::{
+    fs::read_snapshot,
+    query::snapshot_block,
+    report::{state_stats, top_accounts, RankedAccount, StateStats},
+    snapshot::SnapshotHeader,
+    state::{State, StateHash},
+    types::{BlockAndReceipts, EvmBlock},
+};
+use alloy::{
+    consensus::Transaction as _,
+    primitives::{hex, Address, B256, U256},
+};
+use anyhow::Result;
+use reth_primitives::{transaction::SignedTransactionIntoRecoveredExt, Receipt, Transaction};
+use revm::InMemoryDB;
+use serde::Serialize;
+use std::{fmt::Display, path::Path};
+
+fn selector(transaction: &Transaction) -> Option<String> {
+    transaction.input().get(..4).map(hex::encode_prefixed)
+}
+
+#[derive(Debug, Serialize)]
+pub struct TxSummary {
+    pub index: usize,
+    pub hash: B256,
+    // None if the signature does not recover, which is worth knowing about a bad block
+    pub sender: Option<Address>,
+    pub to: Option<Address>,
+    pub value: U256,
+    pub nonce: u64,
+    pub gas_limit: u64,
+    pub selector: Option<String>,
+    pub success: bool,
+    pub gas_used: u64,
+    pub logs: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SystemTxSummary {
+    pub index: usize,
+    // Native transfers have no input; the others are calls to the token contract
+    pub native: bool,
+    pub to: Option<Address>,
+    pub value: U256,
+    pub selector: Option<String>,
+    pub success: Option<bool>,
+    pub logs: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BlockInspection {
+    pub number: u64,
+    pub hash: B256,
+    pub parent_hash: B256,
+    pub timestamp: u64,
+    pub beneficiary: Address,
+    pub gas_used: u64,
+    pub gas_limit: u64,
+    pub base_fee_per_gas: Option<u64>,
+    pub transactions: Vec<TxSummary>,
+    pub system_transactions: Vec<SystemTxSummary>,
+    pub read_precompile_calls: usize,
+    pub highest_precompile_address: Option<Address>,
+}
+
+pub fn inspect_block(block_and_receipts: &BlockAndReceipts) -> BlockInspection {
+    let BlockAndReceipts {
+        block: EvmBlock::Reth115(block),
+        receipts,
+        system_txs,
+        read_precompile_calls,
+        highest_precompile_address,
+    } = block_and_receipts;
+    let mut previous_gas_used = 0;
+    let transactions = block
+        .body()
+        .transactions
+        .iter()
+        .enumerate()
+        .map(|(index, tx_signed)| {
+            let transaction = &tx_signed.transaction;
+            let receipt = receipts.get(index).cloned().map(Receipt::from).unwrap_or_default();
+            let gas_used = receipt.cumulative_gas_used.saturating_sub(previous_gas_used);
+            previous_gas_used = receipt.cumulative_gas_used;
+            TxSummary {
+                index,
+                hash: tx_signed.hash(),
+                sender: tx_signed.clone().try_into_ecrecovered().ok().map(|tx| tx.into_parts().1),
+                to: transaction.to(),
+                value: transaction.value(),
+                nonce: transaction.nonce(),
+                gas_limit: transaction.gas_limit(),
+                selector: selector(transaction),
+                success: receipt.success,
+                gas_used,
+                logs: receipt.logs.len(),
+            }
+        })
+        .collect();
+    let system_transactions = system_txs
+        .iter()
+        .enumerate()
+        .map(|(index, system_tx)| {
+            let receipt = system_tx.receipt.clone().map(Receipt::from);
+            SystemTxSummary {
+                index,
+                native: system_tx.tx.input().is_empty(),
+                to: system_tx.tx.to(),
+                value: system_tx.tx.value(),
+                selector: selector(&system_tx.tx),
+                success: receipt.as_ref().map(|receipt| receipt.success),
+                logs: receipt.as_ref().map(|receipt| receipt.logs.len()),
+            }
+        })
+        .collect();
+    let header = block.header();
+    BlockInspection {
+        number: header.number,
+        hash: block.hash(),
+        parent_hash: header.parent_hash,
+        timestamp: header.timestamp,
+        beneficiary: header.beneficiary,
+        gas_used: header.gas_used,
+        gas_limit: header.gas_limit,
+        base_fee_per_gas: header.base_fee_per_gas,
+        transactions,
+        system_transactions,
+        read_precompile_calls: read_precompile_calls.iter().map(|(_, calls)| calls.len()).sum(),
+        highest_precompile_address: *highest_precompile_address,
+    }
+}
+
+fn or_dash<T: Display>(value: Option<T>) -> String {
+    value.map_or_else(|| "-".to_owned(), |value| value.to_string())
+}
+
+impl Display for BlockInspection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Block {} {}", self.number, self.hash)?;
+        writeln!(f, "  parent     {}", self.parent_hash)?;
+        writeln!(f, "  timestamp  {}", self.timestamp)?;
+        writeln!(f, "  miner      {}", self.beneficiary)?;
+        writeln!(f, "  gas        {} / {}", self.gas_used, self.gas_limit)?;
+        writeln!(f, "  base fee   {}", or_dash(self.base_fee_per_gas))?;
+        writeln!(
+            f,
+            "  precompile calls {} (highest precompile {})",
+            self.read_precompile_calls,
+            or_dash(self.highest_precompile_address)
+        )?;
+        write!(f, "{} system transactions", self.system_transactions.len())?;
+        for tx in &self.system_transactions {
+            let kind = if tx.native { "native" } else { "token" };
+            let status = match tx.success {
+                Some(true) => "ok",
+                Some(false) => "FAILED",
+                None => "no receipt",
+            };
+            write!(
+                f,
+                "\n  #{} {kind} to {} value {} selector {} {status}",
+                tx.index,
+                or_dash(tx.to),
+                tx.value,
+                or_dash(tx.selector.as_ref())
+            )?;
+        }
+        write!(f, "\n{} transactions", self.transactions.len())?;
+        for tx in &self.transactions {
+            write!(
+                f,
+                "\n  #{} {} from {} to {} value {} nonce {} selector {} gas {}/{} logs {}{}",
+                tx.index,
+                tx.hash,
+                tx.sender.map_or_else(|| "UNRECOVERABLE".to_owned(), |sender| sender.to_string()),
+                tx.to.map_or_else(|| "CREATE".to_owned(), |to| to.to_string()),
+                tx.value,
+                tx.nonce,
+                or_dash(tx.selector.as_ref()),
+                tx.gas_used,
+                tx.gas_limit,
+                tx.logs,
+                if tx.success { "" } else { " REVERTED" }
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct StateInspection {
+    pub header: SnapshotHeader,
+    pub file_size: u64,
+    // Of the last block, if the snapshot kept it
+    pub block_hash: Option<B256>,
+    pub stats: StateStats,
+    // By storage slots
+    pub largest_contracts: Vec<RankedAccount<usize>>,
+    pub state_hash: Option<StateHash>,
+}
+
+impl Display for StateInspection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let SnapshotHeader { version, next_block_num, created_at, writer, format, chain, token_map } = &self.header;
+        writeln!(
+            f,
+            "{} snapshot after block {} ({})",
+            chain.map_or_else(|| "Unknown chain".to_owned(), |chain| chain.to_string()),
+            snapshot_block(*next_block_num),
+            or_dash(self.block_hash)
+        )?;
+        writeln!(
+            f,
+            "version {version}, format {format:?}, {} bytes, written by {writer} at {created_at}",
+            self.file_size
+        )?;
+        match token_map {
+            Some(token_map) => writeln!(
+                f,
+                "token map {} ({} tokens) from {} at {}",
+                token_map.version(),
+                token_map.erc20_contract_to_system_address.len(),
+                token_map.source,
+                token_map.fetched_at
+            )?,
+            None => writeln!(f, "no token map")?,
+        }
+        let StateStats { accounts, contracts, storage_slots, code_blobs, code_bytes, block_hashes, .. } = self.stats;
+        writeln!(
+            f,
+            "{accounts} accounts, {contracts} contracts, {storage_slots} storage slots, {code_blobs} code blobs \
+             ({code_bytes} bytes), {block_hashes} block hashes"
+        )?;
+        if let Some(state_hash) = &self.state_hash {
+            writeln!(f, "state hash {} ({state_hash:?})", state_hash.digest())?;
+        }
+        write!(f, "largest contracts by storage slots:")?;
+        for (i, RankedAccount { address, value }) in self.largest_contracts.iter().enumerate() {
+            write!(f, "\n{:>4}. {address} {value}", i + 1)?;
+        }
+        Ok(())
+    }
+}
+
+// Reads a snapshot without executing anything; hashing the state is optional as it takes a while on large states
+pub fn inspect_state(fln: &Path, limit: usize, hash: bool) -> Result<StateInspection> {
+    let file_size = std::fs::metadata(fln)?.len();
+    let (header, state) = read_snapshot(fln)?;
+    let state = InMemoryDB::from(state);
+    let block = snapshot_block(header.next_block_num);
+    Ok(StateInspection {
+        file_size,
+        block_hash: state.block_hashes.get(&U256::from(block)).copied(),
+        stats: state_stats(header.next_block_num, &state),
+        largest_contracts: top_accounts(header.next_block_num, &state, limit).by_storage_slots,
+        state_hash: hash.then(|| state.blake3_hash_slow()),
+        header,
+    })
+}
@@ -0,0 +1,54 @@
+use crate::{calls::CallStats, profile::OpcodeProfile};
+use revm::{
+    interpreter::{CallInputs, CallOutcome, Interpreter},
+    Database, EvmContext, Inspector,
+};
+
+// Optional analyses run during replay. Transactions only go through the (slower) inspector path when one is enabled.
+#[derive(Default)]
+pub struct ReplayInspector {
+    pub opcodes: Option<OpcodeProfile>,
+    pub calls: Option<CallStats>,
+}
+
+impl ReplayInspector {
+    pub const fn is_active(&self) -> bool {
+        self.opcodes.is_some() || self.calls.is_some()
+    }
+}
+
+impl<DB: Database> Inspector<DB> for ReplayInspector {
+    fn step(&mut self, interp: &mut Interpreter, _context: &mut EvmContext<DB>) {
+        if let Some(opcodes) = &mut self.opcodes {
+            opcodes.step(interp);
+        }
+    }
+
+    fn step_end(&mut self, interp: &mut Interpreter, _context: &mut EvmContext<DB>) {
+        if let Some(opcodes) = &mut self.opcodes {
+            opcodes.step_end(interp);
+        }
+    }
+
+    fn call(&mut self, context: &mut EvmContext<DB>, inputs: &mut CallInputs) -> Option<CallOutcome> {
+        let address = inputs.bytecode_address;
+        let precompile = context.precompiles.contains(&address).then_some(address);
+        if let Some(opcodes) = &mut self.opcodes {
+            opcodes.call(precompile);
+        }
+        if let Some(calls) = &mut self.calls {
+            calls.call(inputs.caller, inputs.target_address, precompile.is_some());
+        }
+        None
+    }
+
+    fn call_end(&mut self, _context: &mut EvmContext<DB>, _inputs: &CallInputs, outcome: CallOutcome) -> CallOutcome {
+        if let Some(opcodes) = &mut self.opcodes {
+            opcodes.call_end(&outcome);
+        }
+        if let Some(calls) = &mut self.calls {
+            calls.call_end(&outcome);
+        }
+        outcome
+    }
+}
@@ -0,0 +1,151 @@
+use crate::{
+    fs::BlockSource,
+    types::{BlockAndReceipts, EvmBlock},
+};
+use alloy::primitives::B256;
+use anyhow::{anyhow, Result};
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use reth_primitives::proofs::calculate_transaction_root;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const VERIFY_CHUNK_SIZE: u64 = 10000;
+// How far past the local clock a block's timestamp may be
+const MAX_CLOCK_DRIFT_SECS: u64 = 60;
+
+fn check_seal(block_num: u64, block_and_receipts: &BlockAndReceipts) -> Result<()> {
+    let EvmBlock::Reth115(block) = &block_and_receipts.block;
+    let header = block.header();
+    if header.number != block_num {
+        return Err(anyhow!("block file {block_num} contains block {}", header.number));
+    }
+    let header_hash = header.hash_slow();
+    if header_hash != block.hash() {
+        return Err(anyhow!(
+            "block {block_num} is sealed with {} but its header hashes to {header_hash}",
+            block.hash()
+        ));
+    }
+    Ok(())
+}
+
+// Checks that a block file holds the block it is named after, that its seal is the hash of its header, and that its
+// transactions hash to the header's transactions root
+pub fn check_block_file(block_num: u64, block_and_receipts: &BlockAndReceipts) -> Result<()> {
+    check_seal(block_num, block_and_receipts)?;
+    let EvmBlock::Reth115(block) = &block_and_receipts.block;
+    let header = block.header();
+    let transactions_root = calculate_transaction_root(&block.body().transactions);
+    if transactions_root != header.transactions_root {
+        return Err(anyhow!(
+            "block {block_num} transactions root mismatch: computed {transactions_root}, header {}",
+            header.transactions_root
+        ));
+    }
+    Ok(())
+}
+
+pub fn check_parent_link(block_num: u64, parent: &BlockAndReceipts, block: &BlockAndReceipts) -> Result<()> {
+    let (EvmBlock::Reth115(parent), EvmBlock::Reth115(block)) = (&parent.block, &block.block);
+    check_parent_hash(block_num, parent.hash(), block.header().parent_hash)
+}
+
+fn check_parent_hash(block_num: u64, parent_hash: B256, recorded: B256) -> Result<()> {
+    if parent_hash != recorded {
+        return Err(anyhow!(
+            "block {block_num} has parent hash {recorded} but block {} hashes to {parent_hash}",
+            block_num - 1
+        ));
+    }
+    Ok(())
+}
+
+// Checks a block file's header alone: its seal as for check_block_file, and that its gas fields and timestamp are
+// plausible. Unlike check_block_file, nothing is hashed but the header.
+fn check_header(block_num: u64, block_and_receipts: &BlockAndReceipts) -> Result<()> {
+    check_seal(block_num, block_and_receipts)?;
+    let EvmBlock::Reth115(block) = &block_and_receipts.block;
+    let header = block.header();
+    if header.gas_used > header.gas_limit {
+        return Err(anyhow!("block {block_num} used {} gas over its limit of {}", header.gas_used, header.gas_limit));
+    }
+    if header.gas_used > 0 && block.body().transactions.is_empty() {
+        return Err(anyhow!("block {block_num} has no transactions but used {} gas", header.gas_used));
+    }
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    if header.timestamp > now + MAX_CLOCK_DRIFT_SECS {
+        return Err(anyhow!("block {block_num} has timestamp {} in the future", header.timestamp));
+    }
+    Ok(())
+}
+
+// What each block contributes to the checks between consecutive blocks
+struct Link {
+    hash: B256,
+    parent_hash: B256,
+    // Only compared when given
+    timestamp: Option<u64>,
+}
+
+fn verify_range(
+    source: &BlockSource,
+    start_block: u64,
+    end_block: u64,
+    check: impl Fn(u64, &BlockAndReceipts) -> Result<Link> + Sync,
+) -> Vec<(u64, String)> {
+    let mut failures = Vec::new();
+    let mut parent: Option<Link> = None;
+    let mut cur_block = start_block;
+    while cur_block <= end_block {
+        let last_block_in_chunk = end_block.min(cur_block + VERIFY_CHUNK_SIZE - 1);
+        let results: Vec<Result<Link>> = (cur_block..=last_block_in_chunk)
+            .into_par_iter()
+            .map(|block_num| check(block_num, &source.read_block(block_num)?))
+            .collect();
+        for (block_num, result) in (cur_block..=last_block_in_chunk).zip(results) {
+            match result {
+                Ok(link) => {
+                    if let Some(parent) = &parent {
+                        if let Err(e) = check_parent_hash(block_num, parent.hash, link.parent_hash) {
+                            failures.push((block_num, e.to_string()));
+                        }
+                        if let (Some(parent_timestamp), Some(timestamp)) = (parent.timestamp, link.timestamp) {
+                            if timestamp < parent_timestamp {
+                                failures.push((
+                                    block_num,
+                                    format!("block {block_num} has timestamp {timestamp} before its parent's {parent_timestamp}"),
+                                ));
+                            }
+                        }
+                    }
+                    parent = Some(link);
+                }
+                Err(e) => {
+                    failures.push((block_num, e.to_string()));
+                    parent = None;
+                }
+            }
+        }
+        println!("Verified blocks {cur_block}-{last_block_in_chunk}");
+        cur_block = last_block_in_chunk + 1;
+    }
+    failures
+}
+
+// Checks every block file in the range and the hash links between consecutive blocks, returning all failures
+pub fn verify_blocks(source: &BlockSource, start_block: u64, end_block: u64) -> Vec<(u64, String)> {
+    verify_range(source, start_block, end_block, |block_num, block_and_receipts| {
+        check_block_file(block_num, block_and_receipts)?;
+        let EvmBlock::Reth115(block) = &block_and_receipts.block;
+        Ok(Link { hash: block.hash(), parent_hash: block.header().parent_hash, timestamp: None })
+    })
+}
+
+// A quicker pass than verify_blocks that also checks that timestamps never go backwards
+pub fn verify_headers(source: &BlockSource, start_block: u64, end_block: u64) -> Vec<(u64, String)> {
+    verify_range(source, start_block, end_block, |block_num, block_and_receipts| {
+        check_header(block_num, block_and_receipts)?;
+        let EvmBlock::Reth115(block) = &block_and_receipts.block;
+        let header = block.header();
+        Ok(Link { hash: block.hash(), parent_hash: header.parent_hash, timestamp: Some(header.timestamp) })
+    })
+}
@@ -0,0 +1,64 @@
+use crate::{jsonl::JsonLinesLog, state::StateHash};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{BufRead, BufReader},
+    path::Path,
+    sync::Mutex,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+#[derive(Debug, Serialize)]
+pub struct JournalEntry<'a> {
+    pub block: u64,
+    pub state_hash: &'a StateHash,
+    pub timestamp: u64,
+    // Wall-clock time since the previous checkpoint, or since the journal was opened for the first one
+    pub duration_secs: f64,
+}
+
+// Append-only record of the state hash at every checkpoint, for comparing runs without keeping their snapshots
+pub struct HashJournal {
+    log: JsonLinesLog,
+    last_checkpoint: Mutex<Instant>,
+}
+
+impl HashJournal {
+    pub fn create(fln: &Path) -> Result<Self> {
+        Ok(Self { log: JsonLinesLog::create(fln)?, last_checkpoint: Mutex::new(Instant::now()) })
+    }
+
+    pub fn record(&self, block: u64, state_hash: &StateHash) -> Result<()> {
+        let duration_secs = {
+            let mut last_checkpoint = self.last_checkpoint.lock().unwrap();
+            let elapsed = last_checkpoint.elapsed();
+            *last_checkpoint = Instant::now();
+            elapsed.as_secs_f64()
+        };
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        self.log.write(&[JournalEntry { block, state_hash, timestamp, duration_secs }])
+    }
+}
+
+#[derive(Deserialize)]
+struct ExpectedHash {
+    block: u64,
+    state_hash: StateHash,
+}
+
+// Reads JSON lines with `block` and `state_hash` fields, so a hash journal from another run can be used directly
+pub fn read_expected_hashes(fln: &Path) -> Result<BTreeMap<u64, StateHash>> {
+    let mut hashes = BTreeMap::new();
+    for (i, line) in BufReader::new(File::open(fln)?).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let ExpectedHash { block, state_hash } =
+            serde_json::from_str(&line).map_err(|e| anyhow!("{}:{}: {e}", fln.display(), i + 1))?;
+        hashes.insert(block, state_hash);
+    }
+    Ok(hashes)
+}
@@ -0,0 +1,29 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::{
+    fs::File,
+    io::{LineWriter, Write},
+    path::Path,
+    sync::Mutex,
+};
+
+// Appends records to a file as one JSON object per line
+pub struct JsonLinesLog {
+    writer: Mutex<LineWriter<File>>,
+}
+
+impl JsonLinesLog {
+    pub fn create(fln: &Path) -> Result<Self> {
+        let file = File::options().create(true).append(true).open(fln)?;
+        Ok(Self { writer: Mutex::new(LineWriter::new(file)) })
+    }
+
+    pub fn write<T: Serialize>(&self, records: &[T]) -> Result<()> {
+        let mut writer = self.writer.lock().unwrap();
+        for record in records {
+            serde_json::to_writer(&mut *writer, record)?;
+            writer.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+}
@@ -1,7 +1,76 @@
+pub mod accounts;
+pub mod archive;
+pub mod balances;
+pub mod bench;
+pub mod bisect;
+pub mod bridge;
+pub mod bus;
+pub mod cache;
+pub mod calls;
+pub mod chainspec;
 pub mod cli;
+pub mod compare;
+pub mod control;
+pub mod corewriter;
+pub mod datadir;
+pub mod diff;
+pub mod dryrun;
+pub mod encryption;
+pub mod error;
+pub mod estimate;
+pub mod events;
 pub mod evm_map;
+pub mod export;
+pub mod fees;
+pub mod flat;
 pub mod fs;
+pub mod graphql;
+pub mod grpc;
+pub mod health;
+pub mod holders;
+pub mod hooks;
+pub mod http;
+pub mod inspect;
+pub mod inspector;
+pub mod integrity;
+pub mod journal;
+pub mod jsonl;
+pub mod manifest;
+pub mod mirrors;
+pub mod notify;
+pub mod partial;
+pub mod plan;
 pub mod precompile;
+pub mod profile;
+pub mod progress;
+pub mod proving;
+pub mod prune;
+pub mod query;
+pub mod recovery;
+pub mod remote;
+pub mod reorg;
+pub mod replicate;
+pub mod report;
+pub mod rest;
+pub mod revert;
 pub mod run;
+pub mod serve;
+pub mod simulate;
+pub mod sink;
+pub mod slow;
+pub mod snapshot;
 pub mod state;
+pub mod status;
+pub mod stream;
+#[cfg(unix)]
+pub mod systemd;
+pub mod telemetry;
+pub mod threads;
+pub mod throttle;
+pub mod tui;
 pub mod types;
+pub mod validate;
+pub mod warehouse;
+pub mod watch;
+pub mod witness;
+pub mod ws;
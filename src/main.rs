@@ -1,10 +1,20 @@
-use anyhow::Result;
-use clap::Parser;
 use hyper_evm_sync::cli::Cli;
+use std::process::ExitCode;
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    let cli = Cli::try_parse()?;
-    cli.execute().await?;
-    Ok(())
+fn main() -> ExitCode {
+    let cli = match Cli::parse_args() {
+        Ok(cli) => cli,
+        Err(e) => {
+            e.report(std::env::args().any(|arg| arg == "--json" || arg == "--json-errors"));
+            return ExitCode::from(e.kind.exit_code());
+        }
+    };
+    let json_errors = cli.json_errors || cli.json;
+    match cli.runtime().and_then(|runtime| runtime.block_on(cli.execute())) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            e.report(json_errors);
+            ExitCode::from(e.kind.exit_code())
+        }
+    }
 }
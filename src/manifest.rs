@@ -0,0 +1,155 @@
+use crate::{cli::Chain, error::ErrorKind, evm_map::token_map_version};
+use alloy::primitives::Address;
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs::create_dir_all,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+const MANIFEST_FILE: &str = "manifest.json";
+
+// What the snapshots in a directory depend on; resuming with other values mixes incompatible snapshots
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct ManifestSettings {
+    chain: String,
+}
+
+impl ManifestSettings {
+    fn fingerprint(&self) -> String {
+        blake3::hash(&serde_json::to_vec(self).unwrap()).to_hex()[..16].to_owned()
+    }
+
+    fn differences(&self, other: &Self) -> Vec<String> {
+        let mut differences = Vec::new();
+        if self.chain != other.chain {
+            differences.push(format!("chain {} (this run: {})", self.chain, other.chain));
+        }
+        differences
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Manifest {
+    #[serde(flatten)]
+    settings: ManifestSettings,
+    fingerprint: String,
+    // Of the last run, for information only: snapshots are found by their headers, whatever chunk size wrote them
+    chunk_size: u64,
+    token_map_version: String,
+    // The last executed block
+    current_block: u64,
+    last_snapshot: Option<u64>,
+    // Version of this crate that last wrote the manifest
+    writer: String,
+}
+
+// `{snapshot dir}/manifest.json`, rewritten as the sync progresses
+pub struct SyncManifest {
+    path: PathBuf,
+    manifest: Mutex<Manifest>,
+}
+
+impl SyncManifest {
+    // Refuses a directory synced with other settings unless `force` is set, in which case the manifest is rewritten.
+    // A changed token map is only reported, since tokens are added to it over time.
+    pub fn open(
+        snapshot_dir: &Path,
+        chain: Chain,
+        chunk_size: u64,
+        erc20_contract_to_system_address: &BTreeMap<Address, Address>,
+        next_block_num: u64,
+        force: bool,
+    ) -> Result<Self> {
+        let path = snapshot_dir.join(MANIFEST_FILE);
+        let settings = ManifestSettings { chain: chain.to_string() };
+        let token_map_version = token_map_version(erc20_contract_to_system_address);
+        let mut last_snapshot = None;
+        match std::fs::read(&path) {
+            Ok(data) => {
+                let previous: Manifest = match serde_json::from_slice(&data) {
+                    Ok(previous) => previous,
+                    Err(e) if force => {
+                        println!("Replacing the unreadable manifest {}: {e}", path.display());
+                        return Self::create(path, settings, chunk_size, token_map_version, next_block_num, None);
+                    }
+                    Err(e) => {
+                        return Err(anyhow!("unreadable manifest {}: {e}; pass --force to replace it", path.display()))
+                            .context(ErrorKind::CorruptData)
+                    }
+                };
+                // Compared setting by setting, as older manifests' fingerprints also covered the chunk size
+                let differences = previous.settings.differences(&settings);
+                if !differences.is_empty() {
+                    let differences = differences.join(", ");
+                    if !force {
+                        return Err(anyhow!(
+                            "{} was synced with {differences}; pass --force to resume with this run's settings",
+                            snapshot_dir.display()
+                        ))
+                        .context(ErrorKind::Usage);
+                    }
+                    println!(
+                        "Resuming {}, synced with {differences}, with this run's settings",
+                        snapshot_dir.display()
+                    );
+                }
+                if previous.token_map_version != token_map_version {
+                    println!(
+                        "The token map changed since {} was last synced (version {} -> {token_map_version})",
+                        snapshot_dir.display(),
+                        previous.token_map_version
+                    );
+                }
+                last_snapshot = previous.last_snapshot;
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => create_dir_all(snapshot_dir)?,
+            Err(e) => return Err(e).with_context(|| format!("failed to read {}", path.display())),
+        }
+        Self::create(path, settings, chunk_size, token_map_version, next_block_num, last_snapshot)
+    }
+
+    fn create(
+        path: PathBuf,
+        settings: ManifestSettings,
+        chunk_size: u64,
+        token_map_version: String,
+        next_block_num: u64,
+        last_snapshot: Option<u64>,
+    ) -> Result<Self> {
+        let manifest = Manifest {
+            fingerprint: settings.fingerprint(),
+            settings,
+            chunk_size,
+            token_map_version,
+            current_block: next_block_num.saturating_sub(1),
+            last_snapshot,
+            writer: env!("CARGO_PKG_VERSION").to_owned(),
+        };
+        let manifest = Self { path, manifest: Mutex::new(manifest) };
+        manifest.save(&manifest.manifest.lock().unwrap())?;
+        Ok(manifest)
+    }
+
+    fn save(&self, manifest: &Manifest) -> Result<()> {
+        let partial = self.path.with_extension("json.partial");
+        std::fs::write(&partial, serde_json::to_vec_pretty(manifest)?)?;
+        std::fs::rename(partial, &self.path)?;
+        Ok(())
+    }
+
+    pub fn record_progress(&self, current_block: u64) -> Result<()> {
+        let mut manifest = self.manifest.lock().unwrap();
+        manifest.current_block = current_block;
+        self.save(&manifest)
+    }
+
+    pub fn record_snapshot(&self, block: u64) -> Result<()> {
+        let mut manifest = self.manifest.lock().unwrap();
+        manifest.current_block = block;
+        manifest.last_snapshot = Some(block);
+        self.save(&manifest)
+    }
+}
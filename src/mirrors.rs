@@ -0,0 +1,205 @@
+use crate::{
+    cli::Chain,
+    error::ErrorKind,
+    http::{self, HttpPolicy},
+    remote::S3Location,
+    throttle::{DownloadLimits, Throttle},
+};
+use anyhow::{anyhow, Context, Result};
+use aws_config::{BehaviorVersion, Region};
+use aws_sdk_s3::{operation::get_object::GetObjectError, types::RequestPayer, Client};
+use reqwest::{StatusCode, Url};
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+// A mirror that failed is tried last for this long, so one that is down does not slow every download
+const FAILED_MIRROR_BACKOFF: Duration = Duration::from_secs(30);
+
+fn official_bucket(chain: Chain) -> &'static str {
+    match chain {
+        Chain::Mainnet => "hl-mainnet-evm-blocks",
+        Chain::Testnet => "hl-testnet-evm-blocks",
+    }
+}
+
+pub async fn blocks_s3_client() -> Client {
+    let region = Region::new("ap-northeast-1".to_string());
+    let timeouts = http::policy().aws_timeout_config();
+    let config = aws_config::defaults(BehaviorVersion::latest()).region(region).timeout_config(timeouts).load().await;
+    Client::new(&config)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Upstream {
+    S3(S3Location),
+    // Blocks are fetched from `{url}/{key}`
+    Http(String),
+}
+
+impl Upstream {
+    fn parse(url: &str) -> Result<Self> {
+        if url.starts_with("s3://") {
+            return Ok(Self::S3(S3Location::parse(url)?));
+        }
+        match Url::parse(url)?.scheme() {
+            "http" | "https" => Ok(Self::Http(url.trim_end_matches('/').to_owned())),
+            _ => Err(anyhow!("unsupported mirror {url}: expected an s3:// or http(s):// URL")),
+        }
+    }
+}
+
+enum Fetch {
+    Block(Vec<u8>),
+    Missing,
+    Throttled,
+}
+
+// Upstreams holding the published block files under their S3 keys. Requests rotate between them, and a request that
+// fails on one is retried on the next, so a block is only missing once every mirror agrees. A block that every mirror
+// failed on is retried under the HTTP policy.
+pub struct Mirrors {
+    upstreams: Vec<Upstream>,
+    s3: Client,
+    http: reqwest::Client,
+    policy: HttpPolicy,
+    next: AtomicUsize,
+    failed_until: Mutex<Vec<Option<Instant>>>,
+    throttle: Throttle,
+}
+
+impl Mirrors {
+    // Without any URLs, the chain's official bucket is the only upstream
+    pub async fn connect(chain: Chain, urls: &[String], limits: DownloadLimits) -> Result<Self> {
+        let upstreams = if urls.is_empty() {
+            vec![Upstream::S3(S3Location { bucket: official_bucket(chain).to_owned(), prefix: String::new() })]
+        } else {
+            urls.iter().map(|url| Upstream::parse(url)).collect::<Result<_>>()?
+        };
+        Self::with_upstreams(upstreams, limits).await
+    }
+
+    // A single upstream, read without download limits, as a block source
+    pub async fn connect_to(url: &str) -> Result<Self> {
+        Self::with_upstreams(vec![Upstream::parse(url).context(ErrorKind::Usage)?], DownloadLimits::default()).await
+    }
+
+    async fn with_upstreams(upstreams: Vec<Upstream>, limits: DownloadLimits) -> Result<Self> {
+        Ok(Self {
+            failed_until: Mutex::new(vec![None; upstreams.len()]),
+            upstreams,
+            s3: blocks_s3_client().await,
+            http: http::client()?,
+            policy: http::policy(),
+            next: AtomicUsize::new(0),
+            throttle: Throttle::new(limits),
+        })
+    }
+
+    // Upstreams in the order to try them: starting from the next in turn, with recently failed ones last
+    fn order(&self) -> Vec<usize> {
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let now = Instant::now();
+        let failed_until = self.failed_until.lock().unwrap();
+        let mut order: Vec<_> = (0..self.upstreams.len()).map(|i| (start + i) % self.upstreams.len()).collect();
+        order.sort_by_key(|&i| failed_until[i].is_some_and(|until| until > now));
+        order
+    }
+
+    fn set_failed(&self, i: usize, failed: bool) {
+        self.failed_until.lock().unwrap()[i] = failed.then(|| Instant::now() + FAILED_MIRROR_BACKOFF);
+    }
+
+    async fn fetch_from(&self, upstream: &Upstream, key: &str) -> Result<Fetch> {
+        match upstream {
+            Upstream::S3(location) => {
+                let request = self.s3.get_object().bucket(&location.bucket).key(location.key(key));
+                match request.request_payer(RequestPayer::Requester).send().await {
+                    Ok(obj) => {
+                        Ok(Fetch::Block(obj.body.collect().await.context(ErrorKind::Network)?.into_bytes().to_vec()))
+                    }
+                    Err(e) if e.as_service_error().is_some_and(GetObjectError::is_no_such_key) => Ok(Fetch::Missing),
+                    Err(e)
+                        if e.raw_response().is_some_and(|response| matches!(response.status().as_u16(), 429 | 503)) =>
+                    {
+                        Ok(Fetch::Throttled)
+                    }
+                    Err(e) => Err(anyhow::Error::new(e)),
+                }
+            }
+            Upstream::Http(url) => {
+                let response = self.policy.read(self.http.get(format!("{url}/{key}")).send()).await?;
+                match response.status() {
+                    StatusCode::NOT_FOUND => Ok(Fetch::Missing),
+                    StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE => Ok(Fetch::Throttled),
+                    status if status.is_success() => {
+                        Ok(Fetch::Block(self.policy.read(response.bytes()).await?.to_vec()))
+                    }
+                    status => Err(anyhow!("{url} returned {status} for {key}")),
+                }
+            }
+        }
+    }
+
+    // Returns None if no mirror has published the block yet. Only waits out throttling once every mirror throttled.
+    pub async fn fetch(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let mut retries = 0;
+        loop {
+            let (mut throttled, mut error) = (false, None);
+            for i in self.order() {
+                self.throttle.before_request().await;
+                match self.fetch_from(&self.upstreams[i], key).await {
+                    Ok(Fetch::Block(bytes)) => {
+                        self.set_failed(i, false);
+                        self.throttle.after_response(bytes.len() as u64).await;
+                        return Ok(Some(bytes));
+                    }
+                    // A mirror may lag behind the others
+                    Ok(Fetch::Missing) => {}
+                    Ok(Fetch::Throttled) => throttled = true,
+                    Err(e) => {
+                        self.set_failed(i, true);
+                        error = Some(e);
+                    }
+                }
+            }
+            if throttled {
+                self.throttle.throttled().await;
+                continue;
+            }
+            return match error {
+                Some(e) if retries < self.policy.retries => {
+                    let backoff = self.policy.backoff(retries);
+                    eprintln!("No mirror returned {key}, retrying in {backoff:?}: {e}");
+                    tokio::time::sleep(backoff).await;
+                    retries += 1;
+                    continue;
+                }
+                Some(e) => Err(e.context(format!("no mirror returned {key}")).context(ErrorKind::Network)),
+                None => Ok(None),
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{mirrors::Upstream, remote::S3Location};
+
+    #[test]
+    fn test_parse_mirrors() {
+        assert_eq!(
+            Upstream::parse("s3://blocks/evm").unwrap(),
+            Upstream::S3(S3Location { bucket: "blocks".to_owned(), prefix: "evm".to_owned() })
+        );
+        assert_eq!(
+            Upstream::parse("https://mirror.example/blocks/").unwrap(),
+            Upstream::Http("https://mirror.example/blocks".to_owned())
+        );
+        assert!(Upstream::parse("ftp://mirror.example").is_err());
+    }
+}
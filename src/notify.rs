@@ -0,0 +1,57 @@
+use crate::{cli::Chain, http, state::StateHash, watch::WatchMatch};
+use serde::Serialize;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::{
+    sync::mpsc::{unbounded_channel, UnboundedSender},
+    task::JoinHandle,
+};
+
+const NOTIFY_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Notification {
+    SnapshotWritten { block: u64, path: String },
+    SnapshotUploaded { block: u64, url: String },
+    Reorg { fork_block: u64, rolled_back_to: u64 },
+    Completed { block: u64, state_hash: Option<StateHash> },
+    Failed { task: String, error: String },
+    WatchMatched(WatchMatch),
+}
+
+#[derive(Serialize)]
+struct Payload<'a> {
+    chain: String,
+    timestamp: u64,
+    #[serde(flatten)]
+    notification: &'a Notification,
+}
+
+#[derive(Clone)]
+pub struct Notifier {
+    tx: UnboundedSender<Notification>,
+}
+
+impl Notifier {
+    // Never blocks, so this is safe to call from the synchronous replay loop
+    pub fn notify(&self, notification: Notification) {
+        let _ = self.tx.send(notification);
+    }
+}
+
+// Notifications are delivered in order by a background task, which exits once every `Notifier` is dropped
+pub fn spawn_notifier(chain: Chain, url: String) -> (Notifier, JoinHandle<()>) {
+    let (tx, mut rx) = unbounded_channel::<Notification>();
+    let handle = tokio::spawn(async move {
+        let client = http::client_builder().timeout(NOTIFY_TIMEOUT).build().unwrap();
+        while let Some(notification) = rx.recv().await {
+            let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+            let payload = Payload { chain: chain.to_string(), timestamp, notification: &notification };
+            match client.post(&url).json(&payload).send().await.and_then(|response| response.error_for_status()) {
+                Ok(_) => {}
+                Err(e) => eprintln!("Failed to deliver {notification:?} to {url}: {e}"),
+            }
+        }
+    });
+    (Notifier { tx }, handle)
+}
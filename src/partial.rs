@@ -0,0 +1,191 @@
+use crate::{
+    bench::StageTimings,
+    fs::{read_blocks, BlockSource},
+    inspector::ReplayInspector,
+    run::{run_blocks, RunConfig},
+    state::StateHash,
+    status::SyncStatus,
+    types::LegacyReceipt,
+};
+use alloy::primitives::Address;
+use anyhow::Result;
+use itertools::Itertools;
+use reth_primitives::{Receipt, Transaction};
+use revm::{
+    primitives::{Account, HashMap},
+    InMemoryDB,
+};
+use serde::Serialize;
+use std::{
+    collections::BTreeSet,
+    fmt::Display,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+// At most this many contaminated transactions are kept for the report; all of them are counted
+const MAX_REPORTED_CONTAMINATIONS: usize = 100;
+
+// A transaction whose execution read or wrote accounts outside the tracked set. Their state is not kept, so its
+// outcome (and everything depending on it) may differ from the chain's.
+#[derive(Debug, Clone, Serialize)]
+pub struct Contamination {
+    pub block: u64,
+    pub tx_index: usize,
+    pub is_system_tx: bool,
+    pub untracked: Vec<Address>,
+}
+
+// An approximate replay that only executes transactions involving a set of contracts, against a state holding just
+// those contracts. Senders are not tracked: their nonces and balances are not checked.
+pub struct PartialScope {
+    contracts: BTreeSet<Address>,
+    executed: AtomicU64,
+    contaminated: AtomicU64,
+    contaminations: Mutex<Vec<Contamination>>,
+}
+
+// Precompiles, including the read precompiles at 0x800 and up, hold no state to track
+fn is_precompile(address: &Address) -> bool {
+    address[..18].iter().all(|&byte| byte == 0)
+}
+
+impl PartialScope {
+    pub fn new(contracts: BTreeSet<Address>) -> Self {
+        Self {
+            contracts,
+            executed: AtomicU64::new(0),
+            contaminated: AtomicU64::new(0),
+            contaminations: Mutex::default(),
+        }
+    }
+
+    pub fn tracks(&self, address: &Address) -> bool {
+        self.contracts.contains(address)
+    }
+
+    // Calls made through another contract are found by the logs the tracked contracts emitted in the recorded receipt
+    pub fn involves(&self, sender: Address, transaction: &Transaction, receipt: Option<&LegacyReceipt>) -> bool {
+        let logged = || {
+            receipt
+                .is_some_and(|receipt| Receipt::from(receipt.clone()).logs.iter().any(|log| self.tracks(&log.address)))
+        };
+        self.tracks(&sender) || transaction.to().is_some_and(|to| self.tracks(&to)) || logged()
+    }
+
+    // Keeps only the tracked accounts' changes, flagging the transaction if it touched any other account
+    pub fn restrict(
+        &self,
+        block: u64,
+        tx_index: usize,
+        is_system_tx: bool,
+        sender: Address,
+        changes: &mut HashMap<Address, Account>,
+    ) {
+        self.executed.fetch_add(1, Ordering::Relaxed);
+        let untracked: Vec<Address> = changes
+            .keys()
+            .filter(|address| **address != sender && !is_precompile(address) && !self.tracks(address))
+            .copied()
+            .collect();
+        changes.retain(|address, _| self.tracks(address));
+        if untracked.is_empty() {
+            return;
+        }
+        self.contaminated.fetch_add(1, Ordering::Relaxed);
+        let mut contaminations = self.contaminations.lock().unwrap();
+        if contaminations.len() < MAX_REPORTED_CONTAMINATIONS {
+            contaminations.push(Contamination { block, tx_index, is_system_tx, untracked });
+        }
+    }
+
+    // The tracked accounts of a full state (or a partial one written before), with their code and the block hashes
+    pub fn extract(&self, state: &InMemoryDB) -> InMemoryDB {
+        let mut partial = InMemoryDB::default();
+        for (address, account) in state.accounts.iter().filter(|(address, _)| self.tracks(address)) {
+            if let Some(code) = state.contracts.get(&account.info.code_hash) {
+                partial.contracts.insert(account.info.code_hash, code.clone());
+            }
+            partial.accounts.insert(*address, account.clone());
+        }
+        partial.block_hashes = state.block_hashes.clone();
+        partial
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct PartialReport {
+    pub start_block: u64,
+    pub end_block: u64,
+    pub contracts: Vec<Address>,
+    pub executed_transactions: u64,
+    pub contaminated_transactions: u64,
+    pub state_hash: Option<StateHash>,
+    // The first contaminated transactions
+    pub contaminations: Vec<Contamination>,
+}
+
+impl Display for PartialReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "Approximate replay of {} contracts over blocks {}-{}: executed {} transactions",
+            self.contracts.len(),
+            self.start_block,
+            self.end_block,
+            self.executed_transactions
+        )?;
+        if self.contaminated_transactions == 0 {
+            return write!(f, "No transaction touched untracked accounts");
+        }
+        write!(
+            f,
+            "{} transactions touched untracked accounts, so the tracked state may have diverged from the chain's:",
+            self.contaminated_transactions
+        )?;
+        for Contamination { block, tx_index, is_system_tx, untracked } in &self.contaminations {
+            let kind = if *is_system_tx { "system tx" } else { "tx" };
+            write!(f, "\n  block {block} {kind} {tx_index}: {}", untracked.iter().take(3).join(", "))?;
+            if untracked.len() > 3 {
+                write!(f, " and {} more", untracked.len() - 3)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Replays the range from `state`, which is expected to hold only the scope's contracts (see PartialScope::extract)
+pub fn replay_partial(
+    mut config: RunConfig,
+    scope: Arc<PartialScope>,
+    source: &BlockSource,
+    state: &mut InMemoryDB,
+    start_block: u64,
+    end_block: u64,
+    read_limit: u64,
+) -> Result<PartialReport> {
+    config.partial = Some(scope.clone());
+    config.snapshot_dir = None;
+    config.status = Arc::new(SyncStatus::new(start_block, end_block));
+    let mut state_hash = None;
+    let mut timings = StageTimings::default();
+    let mut inspector = ReplayInspector::default();
+    let mut cur_block = start_block;
+    while cur_block <= end_block {
+        let last_block_in_chunk = end_block.min(cur_block + read_limit - 1);
+        let blocks = read_blocks(source, cur_block, last_block_in_chunk, config.chunk_size, &mut timings)?;
+        state_hash = run_blocks(None, &config, state, blocks, &mut timings, &mut inspector)?.or(state_hash);
+        cur_block = last_block_in_chunk + 1;
+    }
+    Ok(PartialReport {
+        start_block,
+        end_block,
+        contracts: scope.contracts.iter().copied().collect(),
+        executed_transactions: scope.executed.load(Ordering::Relaxed),
+        contaminated_transactions: scope.contaminated.load(Ordering::Relaxed),
+        state_hash,
+        contaminations: scope.contaminations.lock().unwrap().clone(),
+    })
+}
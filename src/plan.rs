@@ -0,0 +1,224 @@
+use crate::{cli::Chain, error::ErrorKind};
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::Display,
+    fs::OpenOptions,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(100);
+// A lock older than this was left behind by a crashed worker; updates under it take milliseconds
+const STALE_LOCK: Duration = Duration::from_secs(60);
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "kebab-case")]
+pub enum UnitStatus {
+    Pending,
+    // Seconds since the Unix epoch
+    Claimed { worker: String, at: u64 },
+    Done { worker: String, at: u64 },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkUnit {
+    pub start_block: u64,
+    pub end_block: u64,
+    #[serde(flatten)]
+    pub status: UnitStatus,
+}
+
+// A range split into units that workers claim one at a time, kept in a JSON file they all can reach
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DownloadPlan {
+    pub chain: Chain,
+    pub start_block: u64,
+    pub end_block: u64,
+    pub unit_size: u64,
+    pub units: Vec<WorkUnit>,
+}
+
+impl DownloadPlan {
+    pub fn new(chain: Chain, start_block: u64, end_block: u64, unit_size: u64) -> Self {
+        let units = (start_block..=end_block)
+            .step_by(usize::try_from(unit_size).unwrap_or(usize::MAX))
+            .map(|start| WorkUnit {
+                start_block: start,
+                end_block: end_block.min(start.saturating_add(unit_size - 1)),
+                status: UnitStatus::Pending,
+            })
+            .collect();
+        Self { chain, start_block, end_block, unit_size, units }
+    }
+
+    // Takes the first pending unit, or failing that one whose claim is older than `claim_timeout` seconds
+    fn claim(&mut self, worker: &str, at: u64, claim_timeout: u64) -> Option<WorkUnit> {
+        let expired = |status: &UnitStatus| match status {
+            UnitStatus::Claimed { at: claimed_at, .. } => at.saturating_sub(*claimed_at) >= claim_timeout,
+            _ => false,
+        };
+        let unit = match self.units.iter().position(|unit| unit.status == UnitStatus::Pending) {
+            Some(i) => &mut self.units[i],
+            None => self.units.iter_mut().find(|unit| expired(&unit.status))?,
+        };
+        unit.status = UnitStatus::Claimed { worker: worker.to_owned(), at };
+        Some(unit.clone())
+    }
+
+    fn finish(&mut self, start_block: u64, status: UnitStatus) -> Result<()> {
+        let unit = self
+            .units
+            .iter_mut()
+            .find(|unit| unit.start_block == start_block)
+            .ok_or_else(|| anyhow!("the plan has no unit starting at block {start_block}"))?;
+        if !matches!(unit.status, UnitStatus::Done { .. }) {
+            unit.status = status;
+        }
+        Ok(())
+    }
+
+    fn counts(&self) -> (usize, usize, usize) {
+        let (mut pending, mut claimed, mut done) = (0, 0, 0);
+        for unit in &self.units {
+            match unit.status {
+                UnitStatus::Pending => pending += 1,
+                UnitStatus::Claimed { .. } => claimed += 1,
+                UnitStatus::Done { .. } => done += 1,
+            }
+        }
+        (pending, claimed, done)
+    }
+
+    pub fn is_complete(&self) -> bool {
+        let (pending, claimed, _) = self.counts();
+        pending + claimed == 0
+    }
+}
+
+impl Display for DownloadPlan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (pending, claimed, done) = self.counts();
+        write!(
+            f,
+            "{} blocks {}-{} in {} units of {} blocks: {done} done, {claimed} claimed, {pending} pending",
+            self.chain,
+            self.start_block,
+            self.end_block,
+            self.units.len(),
+            self.unit_size
+        )
+    }
+}
+
+// `{plan}.lock`, created exclusively so that workers on other machines sharing the file wait for each other
+struct PlanLock(PathBuf);
+
+impl PlanLock {
+    fn acquire(plan: &Path) -> Result<Self> {
+        let path = plan.with_extension("json.lock");
+        loop {
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(_) => return Ok(Self(path)),
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                    let age = std::fs::metadata(&path).and_then(|metadata| metadata.modified()).ok();
+                    if age.and_then(|modified| modified.elapsed().ok()).is_some_and(|age| age > STALE_LOCK) {
+                        eprintln!("Warning: removing the stale lock {}", path.display());
+                        let _ = std::fs::remove_file(&path);
+                    }
+                    std::thread::sleep(LOCK_POLL_INTERVAL);
+                }
+                Err(e) => return Err(e).with_context(|| format!("failed to lock {}", plan.display())),
+            }
+        }
+    }
+}
+
+impl Drop for PlanLock {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+fn save_plan(path: &Path, plan: &DownloadPlan) -> Result<()> {
+    let partial = path.with_extension("json.partial");
+    std::fs::write(&partial, serde_json::to_vec_pretty(plan)?)?;
+    std::fs::rename(partial, path)?;
+    Ok(())
+}
+
+pub fn read_plan(path: &Path) -> Result<DownloadPlan> {
+    let data = std::fs::read(path)
+        .with_context(|| format!("failed to read {}", path.display()))
+        .context(ErrorKind::MissingFile)?;
+    serde_json::from_slice(&data)
+        .with_context(|| format!("{} is not a download plan", path.display()))
+        .context(ErrorKind::CorruptData)
+}
+
+fn update_plan<T>(path: &Path, update: impl FnOnce(&mut DownloadPlan) -> Result<T>) -> Result<(T, DownloadPlan)> {
+    let _lock = PlanLock::acquire(path)?;
+    let mut plan = read_plan(path)?;
+    let result = update(&mut plan)?;
+    save_plan(path, &plan)?;
+    Ok((result, plan))
+}
+
+pub fn create_plan(path: &Path, plan: &DownloadPlan) -> Result<()> {
+    if path.exists() {
+        return Err(anyhow!("{} already exists", path.display())).context(ErrorKind::Usage);
+    }
+    save_plan(path, plan)
+}
+
+pub fn claim_unit(path: &Path, worker: &str, claim_timeout: u64) -> Result<Option<WorkUnit>> {
+    Ok(update_plan(path, |plan| Ok(plan.claim(worker, now(), claim_timeout)))?.0)
+}
+
+// Returns the plan as it stands after the update
+pub fn complete_unit(path: &Path, start_block: u64, worker: &str) -> Result<DownloadPlan> {
+    let status = UnitStatus::Done { worker: worker.to_owned(), at: now() };
+    Ok(update_plan(path, |plan| plan.finish(start_block, status))?.1)
+}
+
+// Hands a unit the worker failed on back to the others
+pub fn release_unit(path: &Path, start_block: u64) -> Result<()> {
+    update_plan(path, |plan| plan.finish(start_block, UnitStatus::Pending))?;
+    Ok(())
+}
+
+// The host name and process id, unique among the workers of a plan
+pub fn default_worker_name() -> String {
+    let host = std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .map(|host| host.trim().to_owned())
+        .unwrap_or_else(|_| "unknown".to_owned());
+    format!("{host}-{}", std::process::id())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        cli::Chain,
+        plan::{DownloadPlan, UnitStatus},
+    };
+
+    #[test]
+    fn test_claim_units() {
+        let mut plan = DownloadPlan::new(Chain::Mainnet, 1, 250, 100);
+        let bounds: Vec<_> = plan.units.iter().map(|unit| (unit.start_block, unit.end_block)).collect();
+        assert_eq!(bounds, [(1, 100), (101, 200), (201, 250)]);
+
+        let first = plan.claim("a", 1000, 60).unwrap();
+        assert_eq!(plan.claim("b", 1000, 60).unwrap().start_block, 101);
+        plan.finish(101, UnitStatus::Done { worker: "b".to_owned(), at: 1010 }).unwrap();
+        assert_eq!(plan.claim("b", 1010, 60).unwrap().start_block, 201);
+        // Only an expired claim is taken over
+        assert!(plan.claim("b", 1020, 60).is_none());
+        assert_eq!(plan.claim("b", 1060, 60).unwrap().start_block, first.start_block);
+        assert!(!plan.is_complete());
+    }
+}
@@ -1,12 +1,46 @@
 use crate::types::{ReadPrecompileInput, ReadPrecompileResult};
 use alloy::primitives::{Address, Bytes};
+use anyhow::Result;
 use revm::{
     handler::register::EvmHandler,
     precompile::{PrecompileError, PrecompileSpecId},
     primitives::{Env, HashMap, Precompile, PrecompileOutput, PrecompileResult, StatefulPrecompile},
     ContextPrecompile, ContextPrecompiles,
 };
-use std::sync::Arc;
+use std::{path::PathBuf, sync::Arc};
+
+pub type PrecompileCalls = Vec<(Address, Vec<(ReadPrecompileInput, ReadPrecompileResult)>)>;
+
+// Supplies the results of the read precompile calls a block made, which depend on Hyperliquid core state and so
+// cannot be computed during replay
+pub trait PrecompileProvider: Send + Sync {
+    fn block_calls(&self, block_number: u64, recorded: PrecompileCalls) -> Result<PrecompileCalls>;
+}
+
+// Uses the results recorded in the block files
+pub struct BlockFilePrecompiles;
+
+impl PrecompileProvider for BlockFilePrecompiles {
+    fn block_calls(&self, _block_number: u64, recorded: PrecompileCalls) -> Result<PrecompileCalls> {
+        Ok(recorded)
+    }
+}
+
+// Reads results from `{dir}/{block_number}.rmp` when present, in the same format as the block files' own record, and
+// falls back to the block file otherwise
+pub struct SidecarPrecompiles {
+    pub dir: PathBuf,
+}
+
+impl PrecompileProvider for SidecarPrecompiles {
+    fn block_calls(&self, block_number: u64, recorded: PrecompileCalls) -> Result<PrecompileCalls> {
+        let path = self.dir.join(format!("{block_number}.rmp"));
+        if !path.is_file() {
+            return Ok(recorded);
+        }
+        Ok(rmp_serde::from_slice(&std::fs::read(path)?)?)
+    }
+}
 
 struct ReplayPrecompile(Arc<HashMap<ReadPrecompileInput, ReadPrecompileResult>>);
 
@@ -0,0 +1,85 @@
+use alloy::primitives::Address;
+use anyhow::Result;
+use revm::interpreter::{opcode::OpCode, CallOutcome, Interpreter};
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct GasStats {
+    pub count: u64,
+    pub gas: u64,
+}
+
+impl GasStats {
+    fn record(&mut self, gas: u64) {
+        self.count += 1;
+        self.gas += gas;
+    }
+}
+
+// Gas attributed to CALL-family opcodes includes the gas forwarded to the callee
+pub struct OpcodeProfile {
+    opcodes: [GasStats; 256],
+    precompiles: BTreeMap<Address, GasStats>,
+    current: Option<(u8, u64)>,
+    calls: Vec<Option<Address>>,
+}
+
+impl Default for OpcodeProfile {
+    fn default() -> Self {
+        Self {
+            opcodes: [GasStats { count: 0, gas: 0 }; 256],
+            precompiles: BTreeMap::new(),
+            current: None,
+            calls: Vec::new(),
+        }
+    }
+}
+
+impl OpcodeProfile {
+    pub(crate) fn step(&mut self, interp: &Interpreter) {
+        self.current = Some((interp.current_opcode(), interp.gas.remaining()));
+    }
+
+    pub(crate) fn step_end(&mut self, interp: &Interpreter) {
+        if let Some((opcode, gas_before)) = self.current.take() {
+            self.opcodes[usize::from(opcode)].record(gas_before.saturating_sub(interp.gas.remaining()));
+        }
+    }
+
+    pub(crate) fn call(&mut self, precompile: Option<Address>) {
+        self.calls.push(precompile);
+    }
+
+    pub(crate) fn call_end(&mut self, outcome: &CallOutcome) {
+        if let Some(Some(address)) = self.calls.pop() {
+            self.precompiles.entry(address).or_default().record(outcome.result.gas.spent());
+        }
+    }
+
+    pub fn opcodes(&self) -> impl Iterator<Item = (u8, GasStats)> + '_ {
+        (0..=u8::MAX).map(|opcode| (opcode, self.opcodes[usize::from(opcode)])).filter(|(_, stats)| stats.count > 0)
+    }
+
+    pub const fn precompiles(&self) -> &BTreeMap<Address, GasStats> {
+        &self.precompiles
+    }
+
+    pub fn write_csv(&self, fln: &Path) -> Result<()> {
+        let mut file = BufWriter::new(File::create(fln)?);
+        writeln!(file, "kind,id,name,count,gas")?;
+        for (opcode, GasStats { count, gas }) in self.opcodes() {
+            let name = OpCode::new(opcode).map_or("UNKNOWN", OpCode::as_str);
+            writeln!(file, "opcode,{opcode:#04x},{name},{count},{gas}")?;
+        }
+        for (address, GasStats { count, gas }) in &self.precompiles {
+            writeln!(file, "precompile,{address},,{count},{gas}")?;
+        }
+        file.flush()?;
+        Ok(())
+    }
+}
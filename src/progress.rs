@@ -0,0 +1,42 @@
+use indicatif::{HumanDuration, ProgressBar};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// Rates are averaged over windows of this length, so they track slowdowns instead of the whole run
+const WINDOW: Duration = Duration::from_secs(2);
+
+// A sync's progress bar, with throughput and lag behind the chain shown as its message
+pub struct SyncProgress {
+    pub pb: ProgressBar,
+    window_start: Instant,
+    blocks: u64,
+    transactions: u64,
+    gas: u64,
+}
+
+impl SyncProgress {
+    pub fn new(pb: ProgressBar) -> Self {
+        Self { pb, window_start: Instant::now(), blocks: 0, transactions: 0, gas: 0 }
+    }
+
+    pub fn record_block(&mut self, transactions: usize, gas_used: u64, timestamp: u64) {
+        self.pb.inc(1);
+        self.blocks += 1;
+        self.transactions += transactions as u64;
+        self.gas += gas_used;
+        let elapsed = self.window_start.elapsed();
+        if elapsed < WINDOW {
+            return;
+        }
+        let secs = elapsed.as_secs_f64();
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let lag = Duration::from_secs(now.saturating_sub(timestamp));
+        self.pb.set_message(format!(
+            "{:.0} blocks/s, {:.0} tx/s, {:.2} Mgas/s, {} behind",
+            self.blocks as f64 / secs,
+            self.transactions as f64 / secs,
+            self.gas as f64 / secs / 1e6,
+            HumanDuration(lag)
+        ));
+        *self = Self::new(self.pb.clone());
+    }
+}
@@ -0,0 +1,60 @@
+use crate::{
+    run::{chain_id, verify_block, RunConfig},
+    types::{BlockAndReceipts, PreprocessedBlock},
+    witness::BlockWitness,
+};
+use alloy::{consensus::Transaction as _, primitives::Address};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, path::Path};
+
+const PROVING_INPUT_VERSION: u32 = 1;
+
+// Everything a guest program needs to re-execute one block without access to this crate's data sources
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProvingInput {
+    pub version: u32,
+    pub chain_id: u64,
+    // Only the tokens bridged by this block's system transactions
+    pub erc20_contract_to_system_address: BTreeMap<Address, Address>,
+    pub block_and_receipts: BlockAndReceipts,
+    pub signers: Vec<Address>,
+    pub witness: BlockWitness,
+}
+
+// The witness is checked against the block before it is packaged, so a bundle never carries a witness that fails to
+// re-execute
+pub fn build_proving_input(
+    config: &RunConfig,
+    block: PreprocessedBlock,
+    witness: BlockWitness,
+) -> Result<ProvingInput> {
+    verify_block(config, witness.clone(), block.clone())?;
+    let PreprocessedBlock { block_and_receipts, signers, .. } = block;
+    let mut erc20_contract_to_system_address = BTreeMap::new();
+    for system_tx in &block_and_receipts.system_txs {
+        if system_tx.tx.input().is_empty() {
+            continue;
+        }
+        let contract = system_tx.tx.to().ok_or_else(|| anyhow!("System transaction without a recipient"))?;
+        let system_address = config
+            .erc20_contract_to_system_address
+            .get(&contract)
+            .ok_or_else(|| anyhow!("No system address for token contract {contract}"))?;
+        erc20_contract_to_system_address.insert(contract, *system_address);
+    }
+    Ok(ProvingInput {
+        version: PROVING_INPUT_VERSION,
+        chain_id: chain_id(config.chain),
+        erc20_contract_to_system_address,
+        block_and_receipts,
+        signers,
+        witness,
+    })
+}
+
+pub fn write_proving_input(input: &ProvingInput, fln: &Path) -> Result<usize> {
+    let buffer = rmp_serde::to_vec(input)?;
+    std::fs::write(fln, &buffer)?;
+    Ok(buffer.len())
+}
@@ -0,0 +1,103 @@
+use crate::{
+    archive::archive_file_number,
+    fs::{block_file_number, index_files, remove_empty_dirs},
+};
+use anyhow::Result;
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+pub struct PrunePolicy {
+    pub before: u64,
+    // The newest snapshots are kept whatever their block, so a sync can always be resumed
+    pub keep_snapshots: usize,
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct PruneReport {
+    // Block files and archived diffs are removed below this block
+    pub cutoff: u64,
+    pub block_files: usize,
+    pub archive_entries: usize,
+    pub snapshots: usize,
+    pub bytes: u64,
+    pub dry_run: bool,
+}
+
+impl Display for PruneReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let verb = if self.dry_run { "Would remove" } else { "Removed" };
+        write!(
+            f,
+            "{verb} {} block files and {} archive entries before block {}, and {} snapshots ({} bytes)",
+            self.block_files, self.archive_entries, self.cutoff, self.snapshots, self.bytes
+        )
+    }
+}
+
+// Snapshots written by the sync are named after the last block they include
+pub(crate) fn snapshot_file_number(path: &Path) -> Option<u64> {
+    path.file_name()?.to_str()?.strip_suffix(".rmp")?.parse().ok()
+}
+
+fn numbered_files(dir: Option<&Path>, file_number: fn(&Path) -> Option<u64>) -> Result<BTreeMap<u64, PathBuf>> {
+    let mut index = BTreeMap::new();
+    if let Some(dir) = dir {
+        index_files(dir, file_number, &mut index)?;
+    }
+    Ok(index)
+}
+
+fn remove(paths: &[&PathBuf], dry_run: bool) -> Result<u64> {
+    let mut bytes = 0;
+    for path in paths {
+        bytes += std::fs::metadata(path)?.len();
+        if !dry_run {
+            std::fs::remove_file(path)?;
+        }
+    }
+    Ok(bytes)
+}
+
+// Snapshots before the cutoff are removed except the newest `keep_snapshots`. The cutoff for blocks and diffs is then
+// lowered to the oldest remaining snapshot, so every remaining snapshot can still be resumed or rebuilt from.
+pub fn prune(
+    blocks_dir: Option<&Path>,
+    archive_dir: Option<&Path>,
+    snapshot_dir: Option<&Path>,
+    policy: &PrunePolicy,
+) -> Result<PruneReport> {
+    let snapshots = numbered_files(snapshot_dir, snapshot_file_number)?;
+    let kept: Vec<u64> = snapshots.keys().rev().take(policy.keep_snapshots).copied().collect();
+    let is_kept = |block: u64| block >= policy.before || kept.contains(&block);
+    let old_snapshots: Vec<_> = snapshots.iter().filter(|(&block, _)| !is_kept(block)).map(|(_, path)| path).collect();
+    let oldest_remaining = snapshots.keys().copied().find(|&block| is_kept(block));
+    let cutoff = oldest_remaining.map_or(policy.before, |block| policy.before.min(block + 1));
+
+    let blocks = numbered_files(blocks_dir, block_file_number)?;
+    let old_blocks: Vec<_> = blocks.range(..cutoff).map(|(_, path)| path).collect();
+    let entries = numbered_files(archive_dir, archive_file_number)?;
+    let old_entries: Vec<_> = entries.range(..cutoff).map(|(_, path)| path).collect();
+
+    let mut bytes = 0;
+    for paths in [&old_blocks, &old_entries, &old_snapshots] {
+        bytes += remove(paths, policy.dry_run)?;
+    }
+    if !policy.dry_run {
+        for dir in [blocks_dir, archive_dir].into_iter().flatten() {
+            remove_empty_dirs(dir)?;
+        }
+    }
+    Ok(PruneReport {
+        cutoff,
+        block_files: old_blocks.len(),
+        archive_entries: old_entries.len(),
+        snapshots: old_snapshots.len(),
+        bytes,
+        dry_run: policy.dry_run,
+    })
+}
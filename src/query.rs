@@ -0,0 +1,247 @@
+use crate::{
+    chainspec::ChainSpec,
+    revert::RevertDecoder,
+    run::{call, CallRequest},
+};
+use alloy::{
+    consensus::constants::KECCAK_EMPTY,
+    primitives::{Address, Bytes, B256, U256},
+};
+use anyhow::{anyhow, Result};
+use itertools::Itertools;
+use revm::{
+    primitives::{AccountInfo, ExecutionResult},
+    DatabaseRef, InMemoryDB,
+};
+use serde::Serialize;
+use std::{collections::BTreeMap, fmt::Display, fs::create_dir_all, path::Path};
+
+#[derive(Debug, Serialize)]
+pub struct StorageSlot {
+    pub slot: B256,
+    pub value: B256,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AccountDump {
+    pub block: u64,
+    pub address: Address,
+    pub balance: U256,
+    pub nonce: u64,
+    pub code_hash: B256,
+    pub storage: Vec<StorageSlot>,
+}
+
+impl Display for AccountDump {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "block: {}", self.block)?;
+        writeln!(f, "address: {}", self.address)?;
+        writeln!(f, "balance: {}", self.balance)?;
+        writeln!(f, "nonce: {}", self.nonce)?;
+        writeln!(f, "code_hash: {}", self.code_hash)?;
+        write!(f, "storage: {} slots", self.storage.len())?;
+        for StorageSlot { slot, value } in &self.storage {
+            write!(f, "\n  {slot} => {value}")?;
+        }
+        Ok(())
+    }
+}
+
+// Snapshots store the next block to execute, so the state reflects the block before it
+pub const fn snapshot_block(next_block_num: u64) -> u64 {
+    next_block_num.saturating_sub(1)
+}
+
+pub fn dump_account(next_block_num: u64, state: &InMemoryDB, address: Address) -> Result<AccountDump> {
+    let account = state.accounts.get(&address).ok_or_else(|| anyhow!("Account {address} not found in snapshot"))?;
+    let AccountInfo { balance, nonce, code_hash, .. } = account.info;
+    let storage = account
+        .storage
+        .iter()
+        .filter(|(_, value)| !value.is_zero())
+        .sorted_by_key(|(slot, _)| **slot)
+        .map(|(&slot, &value)| StorageSlot { slot: slot.into(), value: value.into() })
+        .collect();
+    Ok(AccountDump { block: snapshot_block(next_block_num), address, balance, nonce, code_hash, storage })
+}
+
+// Missing accounts and slots read as zero, matching `eth_getStorageAt`
+pub fn storage_at(state: &InMemoryDB, address: Address, slot: U256) -> B256 {
+    state
+        .accounts
+        .get(&address)
+        .and_then(|account| account.storage.get(&slot))
+        .map_or(B256::ZERO, |&value| value.into())
+}
+
+pub fn account_code(state: &InMemoryDB, address: Address) -> Result<Bytes> {
+    let account = state.accounts.get(&address).ok_or_else(|| anyhow!("Account {address} not found in snapshot"))?;
+    let code_hash = account.info.code_hash;
+    if code_hash == KECCAK_EMPTY || code_hash == B256::ZERO {
+        return Err(anyhow!("Account {address} has no code"));
+    }
+    let bytecode = state
+        .contracts
+        .get(&code_hash)
+        .or(account.info.code.as_ref())
+        .ok_or_else(|| anyhow!("Code {code_hash} for account {address} not found in snapshot"))?;
+    Ok(bytecode.original_bytes())
+}
+
+// Writes every distinct deployed bytecode to `{dir}/{code_hash}.bin`, returning the number of files written
+pub fn export_all_code(state: &InMemoryDB, dir: &Path) -> Result<usize> {
+    create_dir_all(dir)?;
+    let mut n = 0;
+    for (code_hash, bytecode) in state.contracts.iter().sorted_by_key(|(code_hash, _)| **code_hash) {
+        if *code_hash == KECCAK_EMPTY {
+            continue;
+        }
+        std::fs::write(dir.join(format!("{code_hash}.bin")), bytecode.original_bytes())?;
+        n += 1;
+    }
+    Ok(n)
+}
+
+const ERC20_BALANCE_OF_SELECTOR: [u8; 4] = [0x70, 0xa0, 0x82, 0x31];
+const BALANCE_OF_GAS_LIMIT: u64 = 1_000_000;
+
+pub fn erc20_balance<S>(spec: &ChainSpec, state: &S, block: u64, token: Address, holder: Address) -> Result<U256>
+where
+    S: DatabaseRef,
+    <S as DatabaseRef>::Error: std::fmt::Debug,
+{
+    let mut data = ERC20_BALANCE_OF_SELECTOR.to_vec();
+    data.extend_from_slice(holder.into_word().as_slice());
+    let request = CallRequest {
+        from: Address::ZERO,
+        to: token,
+        data: data.into(),
+        value: U256::ZERO,
+        gas_limit: BALANCE_OF_GAS_LIMIT,
+    };
+    let result = call(spec, state, block, request)?;
+    match result.output() {
+        Some(output) if result.is_success() && output.len() >= 32 => Ok(U256::from_be_slice(&output[..32])),
+        _ => Err(anyhow!("balanceOf({holder}) on {token} failed: {result:?}")),
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct TokenBalance {
+    pub contract: Address,
+    pub system_address: Address,
+    pub balance: U256,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BalanceReport {
+    pub block: u64,
+    pub address: Address,
+    pub native: U256,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub erc20: Option<Vec<TokenBalance>>,
+}
+
+impl Display for BalanceReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "native: {}", self.native)?;
+        for TokenBalance { contract, system_address, balance } in self.erc20.iter().flatten() {
+            write!(f, "\n{contract} (system {system_address}): {balance}")?;
+        }
+        Ok(())
+    }
+}
+
+fn erc20_balances(
+    spec: &ChainSpec,
+    next_block_num: u64,
+    state: &InMemoryDB,
+    address: Address,
+    erc20_contract_to_system_address: &BTreeMap<Address, Address>,
+) -> Result<Vec<TokenBalance>> {
+    let mut balances = Vec::new();
+    for (&contract, &system_address) in erc20_contract_to_system_address {
+        if !state.accounts.contains_key(&contract) {
+            continue;
+        }
+        let balance = erc20_balance(spec, state, next_block_num, contract, address)?;
+        if !balance.is_zero() {
+            balances.push(TokenBalance { contract, system_address, balance });
+        }
+    }
+    Ok(balances)
+}
+
+// With a token map, also reports the holder's nonzero balance of every spot-linked ERC20 contract
+pub fn balance_report(
+    spec: &ChainSpec,
+    next_block_num: u64,
+    state: &InMemoryDB,
+    address: Address,
+    erc20_contract_to_system_address: Option<&BTreeMap<Address, Address>>,
+) -> Result<BalanceReport> {
+    let block = snapshot_block(next_block_num);
+    let native = state.accounts.get(&address).map_or(U256::ZERO, |account| account.info.balance);
+    let erc20 = match erc20_contract_to_system_address {
+        Some(map) => Some(erc20_balances(spec, next_block_num, state, address, map)?),
+        None => None,
+    };
+    Ok(BalanceReport { block, address, native, erc20 })
+}
+
+#[derive(Debug, Serialize)]
+pub struct CallOutcome {
+    pub block: u64,
+    pub success: bool,
+    pub gas_used: u64,
+    pub output: Bytes,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub halt_reason: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub revert_reason: Option<String>,
+}
+
+impl Display for CallOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (&self.halt_reason, self.success) {
+            (Some(reason), _) => writeln!(f, "halted: {reason}")?,
+            (None, true) => writeln!(f, "success")?,
+            (None, false) => match &self.revert_reason {
+                Some(reason) => writeln!(f, "reverted: {reason}")?,
+                None => writeln!(f, "reverted")?,
+            },
+        }
+        writeln!(f, "gas_used: {}", self.gas_used)?;
+        write!(f, "output: {}", self.output)
+    }
+}
+
+// Runs `request` on top of the snapshot, as if it were sent in the snapshot's next block
+pub fn simulate_call(
+    spec: &ChainSpec,
+    next_block_num: u64,
+    state: &InMemoryDB,
+    request: CallRequest,
+    revert_decoder: &RevertDecoder,
+) -> Result<CallOutcome> {
+    let block = snapshot_block(next_block_num);
+    let outcome = match call(spec, state, next_block_num, request)? {
+        ExecutionResult::Success { gas_used, output, .. } => {
+            let output = output.into_data();
+            CallOutcome { block, success: true, gas_used, output, halt_reason: None, revert_reason: None }
+        }
+        ExecutionResult::Revert { gas_used, output } => {
+            let revert_reason = revert_decoder.decode(&output);
+            CallOutcome { block, success: false, gas_used, output, halt_reason: None, revert_reason }
+        }
+        ExecutionResult::Halt { reason, gas_used } => CallOutcome {
+            block,
+            success: false,
+            gas_used,
+            output: Bytes::new(),
+            halt_reason: Some(format!("{reason:?}")),
+            revert_reason: None,
+        },
+    };
+    Ok(outcome)
+}
@@ -0,0 +1,121 @@
+use crate::{
+    archive::{apply_entry, ArchiveEntry},
+    chainspec::ChainSpec,
+};
+use anyhow::Result;
+use revm::InMemoryDB;
+use std::{
+    collections::BTreeMap,
+    fs::{create_dir_all, File},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+fn segment_start(path: &Path) -> Option<u64> {
+    path.file_name()?.to_str()?.strip_suffix(".wal")?.parse().ok()
+}
+
+// None if the record is cut short or unreadable
+fn read_record(data: &mut &[u8]) -> Option<ArchiveEntry> {
+    let len = u32::from_le_bytes(data.get(..4)?.try_into().unwrap()) as usize;
+    let entry = rmp_serde::from_slice(data.get(4..4 + len)?).ok()?;
+    *data = &data[4 + len..];
+    Some(entry)
+}
+
+#[derive(Default)]
+struct Segment {
+    writer: Option<BufWriter<File>>,
+    last_block: Option<u64>,
+}
+
+// Write-ahead journal of the diffs of the blocks executed since the last snapshot, so that a restart from that
+// snapshot catches up without executing them again. Records are a little-endian u32 length and an rmp ArchiveEntry,
+// in `{dir}/{first block}.wal` files; a record cut short by a crash is ignored.
+pub struct RecoveryJournal {
+    dir: PathBuf,
+    segment: Mutex<Segment>,
+}
+
+impl RecoveryJournal {
+    pub fn open(dir: &Path) -> Result<Self> {
+        create_dir_all(dir)?;
+        Ok(Self { dir: dir.to_owned(), segment: Mutex::default() })
+    }
+
+    fn segments(&self) -> Result<BTreeMap<u64, PathBuf>> {
+        let mut segments = BTreeMap::new();
+        for entry in std::fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if let Some(start) = segment_start(&path) {
+                segments.insert(start, path);
+            }
+        }
+        Ok(segments)
+    }
+
+    fn clear(&self, segment: &mut Segment) -> Result<()> {
+        *segment = Segment::default();
+        for path in self.segments()?.into_values() {
+            std::fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    pub fn append(&self, entry: &ArchiveEntry) -> Result<()> {
+        let mut segment = self.segment.lock().unwrap();
+        // After a rollback, or without a recovered journal, the records on disk do not lead up to this block
+        if segment.last_block.is_none_or(|last_block| last_block + 1 != entry.block_number) {
+            self.clear(&mut segment)?;
+        }
+        if segment.writer.is_none() {
+            let path = self.dir.join(format!("{}.wal", entry.block_number));
+            segment.writer = Some(BufWriter::new(File::options().create(true).append(true).open(path)?));
+        }
+        let writer = segment.writer.as_mut().unwrap();
+        let record = rmp_serde::to_vec(entry)?;
+        writer.write_all(&u32::try_from(record.len())?.to_le_bytes())?;
+        writer.write_all(&record)?;
+        writer.flush()?;
+        segment.last_block = Some(entry.block_number);
+        Ok(())
+    }
+
+    // Called after a snapshot of the last appended block
+    pub fn truncate(&self) -> Result<()> {
+        let mut segment = self.segment.lock().unwrap();
+        self.clear(&mut segment)
+    }
+
+    // Applies the journaled blocks from `next_block_num` on, up to `end_block`, returning the next block to execute
+    pub fn recover(
+        &self,
+        spec: &ChainSpec,
+        state: &mut InMemoryDB,
+        next_block_num: u64,
+        end_block: u64,
+    ) -> Result<u64> {
+        let mut next = next_block_num;
+        // Whether every record was applied or older, so that new ones can be appended after them
+        let mut complete = true;
+        'segments: for path in self.segments()?.into_values() {
+            let data = std::fs::read(path)?;
+            let mut data = data.as_slice();
+            while !data.is_empty() {
+                let entry = read_record(&mut data);
+                let Some(entry) = entry.filter(|entry| entry.block_number <= next.min(end_block)) else {
+                    complete = false;
+                    break 'segments;
+                };
+                if entry.block_number == next {
+                    apply_entry(state, spec, &entry);
+                    next += 1;
+                }
+            }
+        }
+        let mut segment = self.segment.lock().unwrap();
+        segment.last_block = (complete && next > next_block_num).then(|| next - 1);
+        Ok(next)
+    }
+}
@@ -0,0 +1,257 @@
+use crate::{
+    cli::Chain,
+    error::ErrorKind,
+    fs::read_snapshot_header,
+    http,
+    notify::{Notification, Notifier},
+};
+use anyhow::{anyhow, Context, Result};
+use aws_config::BehaviorVersion;
+use aws_sdk_s3::{
+    primitives::{ByteStream, Length},
+    types::{CompletedMultipartUpload, CompletedPart},
+    Client,
+};
+use std::path::{Path, PathBuf};
+use tokio::{
+    fs::File,
+    io::AsyncWriteExt,
+    sync::mpsc::{unbounded_channel, UnboundedSender},
+    task::JoinHandle,
+};
+
+// S3 rejects single PUTs above 5GiB, so larger snapshots go through multipart upload
+const UPLOAD_PART_SIZE: u64 = 256 * 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct S3Location {
+    pub bucket: String,
+    pub prefix: String,
+}
+
+impl S3Location {
+    pub fn parse(url: &str) -> Result<Self> {
+        let rest = url.strip_prefix("s3://").ok_or_else(|| anyhow!("Expected s3://bucket/prefix, got {url}"))?;
+        let (bucket, prefix) = rest.split_once('/').unwrap_or((rest, ""));
+        if bucket.is_empty() {
+            return Err(anyhow!("Missing bucket in {url}"));
+        }
+        Ok(Self { bucket: bucket.to_owned(), prefix: prefix.trim_matches('/').to_owned() })
+    }
+
+    pub fn key(&self, name: &str) -> String {
+        if self.prefix.is_empty() {
+            name.to_owned()
+        } else {
+            format!("{}/{name}", self.prefix)
+        }
+    }
+}
+
+// Uses the standard AWS environment (credentials, region, AWS_ENDPOINT_URL for S3-compatible stores)
+pub async fn s3_client() -> Client {
+    let timeouts = http::policy().aws_timeout_config();
+    let config = aws_config::defaults(BehaviorVersion::latest()).timeout_config(timeouts).load().await;
+    Client::new(&config)
+}
+
+async fn upload_file(s3: &Client, path: &Path, bucket: &str, key: &str) -> Result<()> {
+    let size = tokio::fs::metadata(path).await?.len();
+    if size <= UPLOAD_PART_SIZE {
+        let body = ByteStream::from_path(path).await?;
+        s3.put_object().bucket(bucket).key(key).body(body).send().await?;
+        return Ok(());
+    }
+
+    let upload = s3.create_multipart_upload().bucket(bucket).key(key).send().await?;
+    let upload_id = upload.upload_id().ok_or_else(|| anyhow!("No upload id for {key}"))?;
+    // S3 keeps (and bills for) the parts of an unfinished upload until it is aborted
+    if let Err(e) = upload_parts(s3, path, size, bucket, key, upload_id).await {
+        if let Err(abort_error) = s3.abort_multipart_upload().bucket(bucket).key(key).upload_id(upload_id).send().await
+        {
+            eprintln!("Warning: failed to abort the upload of {key}: {abort_error}");
+        }
+        return Err(e);
+    }
+    Ok(())
+}
+
+async fn upload_parts(s3: &Client, path: &Path, size: u64, bucket: &str, key: &str, upload_id: &str) -> Result<()> {
+    let mut parts = Vec::new();
+    let mut offset = 0;
+    while offset < size {
+        let part_number = i32::try_from(parts.len() + 1)?;
+        let length = UPLOAD_PART_SIZE.min(size - offset);
+        let body = ByteStream::read_from().path(path).offset(offset).length(Length::Exact(length)).build().await?;
+        let part = s3
+            .upload_part()
+            .bucket(bucket)
+            .key(key)
+            .upload_id(upload_id)
+            .part_number(part_number)
+            .body(body)
+            .send()
+            .await?;
+        parts
+            .push(CompletedPart::builder().set_e_tag(part.e_tag().map(str::to_owned)).part_number(part_number).build());
+        offset += length;
+    }
+    s3.complete_multipart_upload()
+        .bucket(bucket)
+        .key(key)
+        .upload_id(upload_id)
+        .multipart_upload(CompletedMultipartUpload::builder().set_parts(Some(parts)).build())
+        .send()
+        .await?;
+    Ok(())
+}
+
+#[derive(Clone)]
+pub struct SnapshotUploader {
+    tx: UnboundedSender<(u64, PathBuf)>,
+}
+
+impl SnapshotUploader {
+    pub fn upload(&self, block: u64, path: PathBuf) {
+        let _ = self.tx.send((block, path));
+    }
+}
+
+// Uploads run one at a time in the background so a slow link delays uploads rather than execution
+pub async fn spawn_snapshot_uploader(
+    location: S3Location,
+    delete_local: bool,
+    notifier: Option<Notifier>,
+) -> (SnapshotUploader, JoinHandle<()>) {
+    let s3 = s3_client().await;
+    let (tx, mut rx) = unbounded_channel::<(u64, PathBuf)>();
+    let handle = tokio::spawn(async move {
+        while let Some((block, path)) = rx.recv().await {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            let key = location.key(&name);
+            let url = format!("s3://{}/{key}", location.bucket);
+            match upload_file(&s3, &path, &location.bucket, &key).await {
+                Ok(()) => {
+                    println!("Uploaded snapshot {block} to {url}");
+                    if let Some(notifier) = &notifier {
+                        notifier.notify(Notification::SnapshotUploaded { block, url });
+                    }
+                    if delete_local {
+                        if let Err(e) = tokio::fs::remove_file(&path).await {
+                            eprintln!("Failed to delete {}: {e}", path.display());
+                        }
+                    }
+                }
+                Err(e) => eprintln!("Failed to upload snapshot {block} to {url}: {e}"),
+            }
+        }
+    });
+    (SnapshotUploader { tx }, handle)
+}
+
+pub fn is_remote(fln: &str) -> bool {
+    ["s3://", "http://", "https://"].iter().any(|scheme| fln.starts_with(scheme))
+}
+
+fn snapshot_cache_dir() -> PathBuf {
+    dirs::cache_dir().unwrap_or_else(std::env::temp_dir).join("hyper-evm-sync").join("snapshots")
+}
+
+async fn download(url: &str, file: &mut File) -> Result<blake3::Hash> {
+    let mut hasher = blake3::Hasher::new();
+    if url.starts_with("s3://") {
+        let location = S3Location::parse(url)?;
+        let s3 = s3_client().await;
+        let request = s3.get_object().bucket(&location.bucket).key(&location.prefix);
+        let mut body = request.send().await.context(ErrorKind::Network)?.body;
+        while let Some(chunk) = body.try_next().await.context(ErrorKind::Network)? {
+            hasher.update(&chunk);
+            file.write_all(&chunk).await?;
+        }
+    } else {
+        let policy = http::policy();
+        let mut response = policy.read(http::client()?.get(url).send()).await?.error_for_status()?;
+        while let Some(chunk) = policy.read(response.chunk()).await? {
+            hasher.update(&chunk);
+            file.write_all(&chunk).await?;
+        }
+    }
+    file.flush().await?;
+    Ok(hasher.finalize())
+}
+
+fn check_blake3(fln: &str, hash: blake3::Hash, expected: Option<&str>) -> Result<()> {
+    if let Some(expected) = expected {
+        let expected = expected.trim_start_matches("0x");
+        if !hash.to_hex().eq_ignore_ascii_case(expected) {
+            return Err(
+                anyhow!("Snapshot {fln} has blake3 {hash}, expected {expected}").context(ErrorKind::HashMismatch)
+            );
+        }
+    }
+    Ok(())
+}
+
+// Returns a local path for `fln`, downloading remote snapshots into the cache dir first. Previously downloaded
+// snapshots are reused unless their header records another chain. With `expected_blake3`, the file contents must hash
+// to it.
+pub async fn resolve_snapshot(chain: Chain, fln: String, expected_blake3: Option<&str>) -> Result<PathBuf> {
+    if !is_remote(&fln) {
+        if expected_blake3.is_some() {
+            let hash = blake3::hash(&tokio::fs::read(&fln).await?);
+            check_blake3(&fln, hash, expected_blake3)?;
+        }
+        return Ok(PathBuf::from(fln));
+    }
+
+    let name = fln.rsplit('/').next().filter(|name| !name.is_empty()).ok_or_else(|| anyhow!("No file in {fln}"))?;
+    // Keyed by the whole URL, as different buckets or hosts publish snapshots under the same file names
+    let url_hash = blake3::hash(fln.as_bytes()).to_hex();
+    let local_path = snapshot_cache_dir().join(format!("{}-{name}", &url_hash[..16]));
+    if local_path.is_file() {
+        // ABCI states have no header and are taken as they are
+        let other_chain = read_snapshot_header(&local_path)
+            .ok()
+            .and_then(|header| header.chain)
+            .filter(|&cached_chain| cached_chain != chain);
+        let cached = match (other_chain, expected_blake3) {
+            (Some(cached_chain), _) => {
+                eprintln!("Warning: cached {} is a {cached_chain} snapshot, downloading again", local_path.display());
+                false
+            }
+            (None, Some(_)) => {
+                check_blake3(&fln, blake3::hash(&tokio::fs::read(&local_path).await?), expected_blake3).is_ok()
+            }
+            (None, None) => true,
+        };
+        if cached {
+            println!("Using cached {} for {fln}", local_path.display());
+            return Ok(local_path);
+        }
+    }
+
+    tokio::fs::create_dir_all(snapshot_cache_dir()).await?;
+    let partial_path = local_path.with_extension("partial");
+    println!("Downloading {fln} to {}", local_path.display());
+    let mut file = File::create(&partial_path).await?;
+    let hash = download(&fln, &mut file).await?;
+    check_blake3(&fln, hash, expected_blake3)?;
+    tokio::fs::rename(&partial_path, &local_path).await?;
+    println!("Downloaded {fln} (blake3 {hash})");
+    Ok(local_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::remote::S3Location;
+
+    #[test]
+    fn test_s3_location_parse() {
+        let location = S3Location::parse("s3://bucket/some/prefix/").unwrap();
+        assert_eq!(location, S3Location { bucket: "bucket".to_owned(), prefix: "some/prefix".to_owned() });
+        assert_eq!(location.key("100.rmp"), "some/prefix/100.rmp");
+        assert_eq!(S3Location::parse("s3://bucket").unwrap().key("100.rmp"), "100.rmp");
+        assert!(S3Location::parse("https://bucket/prefix").is_err());
+        assert!(S3Location::parse("s3:///prefix").is_err());
+    }
+}
@@ -0,0 +1,82 @@
+use crate::{
+    cli::Chain,
+    fs::{find_snapshot, BlockSource},
+    mirrors::Mirrors,
+    query::snapshot_block,
+    types::{EvmBlock, PreprocessedBlock},
+};
+use alloy::primitives::B256;
+use anyhow::{anyhow, Result};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+// How many blocks back a reorg can be followed, and so how many block hashes the reader remembers
+const REORG_WINDOW: usize = 1024;
+
+// Hashes of the latest blocks sent for execution
+#[derive(Default)]
+pub struct RecentHashes(BTreeMap<u64, B256>);
+
+impl RecentHashes {
+    pub fn record(&mut self, blocks: &[(u64, Vec<PreprocessedBlock>)]) {
+        for block in blocks.iter().flat_map(|(_, chunk)| chunk) {
+            let EvmBlock::Reth115(sealed) = &block.block_and_receipts.block;
+            self.0.insert(block.block_num, sealed.hash());
+        }
+        while self.0.len() > REORG_WINDOW {
+            self.0.pop_first();
+        }
+    }
+
+    // Whether the first of the blocks does not extend the block sent before it
+    pub fn diverges(&self, blocks: &[(u64, Vec<PreprocessedBlock>)]) -> bool {
+        let Some(first) = blocks.first().and_then(|(_, chunk)| chunk.first()) else {
+            return false;
+        };
+        let EvmBlock::Reth115(sealed) = &first.block_and_receipts.block;
+        let parent_hash = sealed.header().parent_hash;
+        self.0.get(&(first.block_num - 1)).is_some_and(|&hash| hash != parent_hash)
+    }
+
+    // The last block the executed and canonical branches share, found by fetching the canonical blocks again from the
+    // tip down
+    pub async fn find_fork(&self, source: &BlockSource, mirrors: &Mirrors, block_num: u64) -> Result<u64> {
+        for (&n, &hash) in self.0.range(..block_num).rev() {
+            let EvmBlock::Reth115(canonical) = source.refetch_block(mirrors, n).await?.block;
+            if canonical.hash() == hash {
+                return Ok(n);
+            }
+        }
+        Err(anyhow!("chain diverged more than {} blocks before block {block_num}", self.0.len()))
+    }
+
+    // Forgets blocks after a rollback point, as they will be sent again
+    pub fn truncate(&mut self, last_block: u64) {
+        self.0.split_off(&(last_block + 1));
+    }
+}
+
+// The newest snapshot in `dir` without blocks after `fork_block`, and the block it was taken at
+pub fn rollback_snapshot(dir: &Path, chain: Chain, fork_block: u64) -> Result<(u64, PathBuf)> {
+    find_snapshot(dir, chain, Some(fork_block + 1))?
+        .map(|(header, path)| (snapshot_block(header.next_block_num), path))
+        .ok_or_else(|| anyhow!("no snapshot in {} at or before block {fork_block} to roll back to", dir.display()))
+}
+
+// Where to restart after `block_num` turned out not to extend the executed chain: the fork block, and the block and
+// path of the snapshot to roll back to
+pub async fn rollback_point(
+    recent: &RecentHashes,
+    source: &BlockSource,
+    mirrors: &Mirrors,
+    chain: Chain,
+    snapshot_dir: Option<&Path>,
+    block_num: u64,
+) -> Result<(u64, u64, PathBuf)> {
+    let fork_block = recent.find_fork(source, mirrors, block_num).await?;
+    let dir = snapshot_dir.ok_or_else(|| anyhow!("rolling back needs --snapshot-dir"))?;
+    let (snapshot_block, snapshot) = rollback_snapshot(dir, chain, fork_block)?;
+    Ok((fork_block, snapshot_block, snapshot))
+}
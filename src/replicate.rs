@@ -0,0 +1,297 @@
+use crate::{
+    archive::{apply_entry, ArchiveEntry},
+    bench::StageTimings,
+    cli::Chain,
+    error::ErrorKind,
+    http::{self, Attempt, HttpPolicy},
+    run::{handle_control_requests, write_snapshot, RunConfig},
+    state::{State, StateHash},
+    status::SyncStatus,
+};
+use anyhow::{anyhow, Context, Result};
+use axum::{
+    body::Bytes,
+    extract::State as AxumState,
+    http::StatusCode,
+    routing::{get, post},
+    Json, Router,
+};
+use reqwest::StatusCode as ReqwestStatus;
+use revm::InMemoryDB;
+use serde::{Deserialize, Serialize};
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::Ordering,
+        mpsc::{channel, RecvTimeoutError, Sender},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tokio::{
+    net::TcpListener,
+    sync::{mpsc, oneshot},
+    task::JoinHandle,
+};
+
+// How often the applier answers queries while no batch arrives
+const IDLE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+// Batches the primary may run ahead of the standby before it waits
+const QUEUE_BATCHES: usize = 4;
+
+// The body of POST /replicate, rmp-encoded: the diffs of consecutive blocks, and the primary's state hash after the
+// last of them
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReplicationBatch {
+    pub chain: Chain,
+    pub entries: Vec<ArchiveEntry>,
+    pub state_hash: StateHash,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct StandbyStatus {
+    chain: Chain,
+    next_block: u64,
+}
+
+// Collects the diffs of the blocks since the last checkpoint on the primary, and ships them at the checkpoint
+pub struct Replicator {
+    chain: Chain,
+    pending: Mutex<Vec<ArchiveEntry>>,
+    tx: mpsc::Sender<ReplicationBatch>,
+}
+
+impl Replicator {
+    pub(crate) fn record(&self, entry: ArchiveEntry) {
+        self.pending.lock().unwrap().push(entry);
+    }
+
+    // Blocks the replay loop once the standby falls QUEUE_BATCHES behind
+    pub(crate) fn ship(&self, state_hash: &StateHash) {
+        let entries = std::mem::take(&mut *self.pending.lock().unwrap());
+        if entries.is_empty() {
+            return;
+        }
+        let batch = ReplicationBatch { chain: self.chain, entries, state_hash: state_hash.clone() };
+        // Only fails once replication stopped, which the task reported
+        let _ = self.tx.blocking_send(batch);
+    }
+
+    // The standby cannot roll back, so a reorg on the primary ends replication with the standby's next batch
+    pub(crate) fn discard_pending(&self) {
+        self.pending.lock().unwrap().clear();
+    }
+}
+
+async fn try_post_batch(policy: &HttpPolicy, client: &reqwest::Client, url: &str, body: &[u8]) -> Result<Attempt<()>> {
+    let response = match policy.read(client.post(url).body(body.to_vec()).send()).await {
+        Ok(response) => response,
+        Err(e) => return Ok(Attempt::Retry(e)),
+    };
+    let status = response.status();
+    if status == ReqwestStatus::TOO_MANY_REQUESTS || status.is_server_error() {
+        return Ok(Attempt::Retry(anyhow!("HTTP {status}")));
+    }
+    if !status.is_success() {
+        let message = policy.read(response.text()).await.unwrap_or_default();
+        return Err(anyhow!("HTTP {status}: {message}"));
+    }
+    Ok(Attempt::Done(()))
+}
+
+async fn standby_status(client: &reqwest::Client, target: &str) -> Result<StandbyStatus> {
+    let policy = http::policy();
+    let response = policy.read(client.get(format!("{target}/status")).send()).await?.error_for_status()?;
+    Ok(serde_json::from_slice(&policy.read(response.bytes()).await?)?)
+}
+
+// Batches are posted in order by a background task. Replication stops at the first batch the standby rejects, as
+// every later one would be rejected too; the sync itself carries on.
+pub fn spawn_replicator(chain: Chain, target: String, start_block: u64) -> (Replicator, JoinHandle<()>) {
+    let (tx, mut rx) = mpsc::channel::<ReplicationBatch>(QUEUE_BATCHES);
+    let target = target.trim_end_matches('/').to_owned();
+    let handle = tokio::spawn(async move {
+        let policy = http::policy();
+        let client = http::client().unwrap();
+        match standby_status(&client, &target).await {
+            Ok(status) if status.chain != chain => {
+                eprintln!("Warning: the standby at {target} holds {} state, not {chain}", status.chain);
+            }
+            Ok(status) if status.next_block != start_block => eprintln!(
+                "Warning: the standby at {target} expects block {}, but replication starts at block {start_block}",
+                status.next_block
+            ),
+            Ok(_) => {}
+            Err(e) => eprintln!("Warning: failed to query the standby at {target}: {e}"),
+        }
+        let url = format!("{target}/replicate");
+        while let Some(batch) = rx.recv().await {
+            let (first, last) = (batch.entries[0].block_number, batch.entries.last().unwrap().block_number);
+            let body = match rmp_serde::to_vec(&batch) {
+                Ok(body) => body,
+                Err(e) => {
+                    eprintln!("Failed to encode blocks {first}-{last} for replication: {e}");
+                    return;
+                }
+            };
+            let what = format!("Replicating blocks {first}-{last} to {target}");
+            if let Err(e) = http::retry(&policy, &what, || try_post_batch(&policy, &client, &url, &body)).await {
+                eprintln!("Replication to {target} stopped: {e:#}");
+                return;
+            }
+        }
+    });
+    (Replicator { chain, pending: Mutex::default(), tx }, handle)
+}
+
+type Reply = oneshot::Sender<Result<u64, (StatusCode, String)>>;
+
+// Applies a batch on top of the standby's state, which has to be at the batch's first block. A state that does not
+// hash like the primary's cannot be trusted any more, so this fails the standby.
+fn apply_batch(config: &RunConfig, state: &mut InMemoryDB, next_block: u64, batch: ReplicationBatch) -> Result<u64> {
+    if batch.chain != config.chain {
+        return Err(anyhow!("this standby holds {} state, not {}", config.chain, batch.chain))
+            .context(ErrorKind::Usage);
+    }
+    let first = batch.entries.first().map_or(next_block, |entry| entry.block_number);
+    if first != next_block {
+        return Err(anyhow!("expected block {next_block}, got a batch starting at block {first}"))
+            .context(ErrorKind::Usage);
+    }
+    if let Some(pair) = batch.entries.windows(2).find(|pair| pair[1].block_number != pair[0].block_number + 1) {
+        return Err(anyhow!("block {} follows block {} in the batch", pair[1].block_number, pair[0].block_number))
+            .context(ErrorKind::CorruptData);
+    }
+    let last = batch.entries.last().map_or(next_block - 1, |entry| entry.block_number);
+    for entry in &batch.entries {
+        apply_entry(state, &config.spec, entry);
+    }
+    let hash = state.blake3_hash_slow();
+    if hash != batch.state_hash {
+        return Err(anyhow!(
+            "State hash mismatch after applying blocks {first}-{last}: {} on the standby, {} on the primary",
+            hash.digest(),
+            batch.state_hash.digest()
+        ))
+        .context(ErrorKind::HashMismatch);
+    }
+    Ok(last)
+}
+
+#[derive(Clone)]
+struct StandbyState {
+    chain: Chain,
+    status: Arc<SyncStatus>,
+    batches: Sender<(ReplicationBatch, Reply)>,
+}
+
+async fn status(AxumState(state): AxumState<StandbyState>) -> Json<StandbyStatus> {
+    Json(StandbyStatus { chain: state.chain, next_block: state.status.current_block() + 1 })
+}
+
+async fn replicate(AxumState(state): AxumState<StandbyState>, body: Bytes) -> Result<String, (StatusCode, String)> {
+    let batch: ReplicationBatch =
+        rmp_serde::from_slice(&body).map_err(|e| (StatusCode::BAD_REQUEST, format!("not a batch: {e}")))?;
+    let (reply, rx) = oneshot::channel();
+    state
+        .batches
+        .send((batch, reply))
+        .map_err(|_| (StatusCode::SERVICE_UNAVAILABLE, "the standby stopped".to_owned()))?;
+    let last = rx.await.map_err(|_| (StatusCode::SERVICE_UNAVAILABLE, "the standby stopped".to_owned()))??;
+    Ok(format!("applied through block {last}"))
+}
+
+// Keeps `state` in step with a primary syncing with --replicate-target, without executing blocks. The applier
+// thread owns the state and answers `config.controller` queries between batches. It snapshots after every batch if
+// `config.snapshot_dir` is set, so a failover can start syncing from the latest one.
+pub async fn run_standby(config: RunConfig, mut state: InMemoryDB, start_block: u64, addr: SocketAddr) -> Result<()> {
+    let (batches, rx) = channel::<(ReplicationBatch, Reply)>();
+    let standby = StandbyState { chain: config.chain, status: config.status.clone(), batches };
+    let applier = tokio::task::spawn_blocking(move || -> Result<()> {
+        let mut timings = StageTimings::default();
+        let mut next_block = start_block;
+        loop {
+            handle_control_requests(&config, &state, next_block - 1, &mut timings);
+            let (batch, reply) = match rx.recv_timeout(IDLE_POLL_INTERVAL) {
+                Ok(received) => received,
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => return Ok(()),
+            };
+            match apply_batch(&config, &mut state, next_block, batch) {
+                Ok(last) => {
+                    println!("Applied blocks {next_block}-{last}");
+                    next_block = last + 1;
+                    config.status.current_block.store(last, Ordering::Relaxed);
+                    if config.snapshot_dir.is_some() {
+                        if let Err(e) = write_snapshot(&config, &state, last) {
+                            eprintln!("Warning: snapshot {last} failed: {e}");
+                        }
+                    }
+                    let _ = reply.send(Ok(last));
+                }
+                Err(e) if e.downcast_ref::<ErrorKind>() == Some(&ErrorKind::HashMismatch) => {
+                    let _ = reply.send(Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())));
+                    return Err(e);
+                }
+                Err(e) => {
+                    let _ = reply.send(Err((StatusCode::CONFLICT, format!("{e:#}"))));
+                }
+            }
+        }
+    });
+    println!("Standby at block {} accepting batches on {addr}", start_block - 1);
+    let app = Router::new().route("/status", get(status)).route("/replicate", post(replicate)).with_state(standby);
+    let listener = TcpListener::bind(addr).await?;
+    tokio::select! {
+        served = axum::serve(listener, app) => served?,
+        applied = applier => applied??,
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        archive::{apply_entry, ArchiveEntry},
+        cli::Chain,
+        diff::{AccountDiff, AccountFields, StateDiff},
+        replicate::{apply_batch, ReplicationBatch},
+        run::RunConfig,
+        state::State,
+    };
+    use alloy::primitives::{Address, B256, U256};
+    use revm::{primitives::KECCAK_EMPTY, InMemoryDB};
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_apply_batch() {
+        let config = RunConfig::new(Chain::Mainnet, BTreeMap::new());
+        let after = AccountFields { balance: U256::from(5), nonce: 1, code_hash: KECCAK_EMPTY };
+        let account = AccountDiff { after: Some(after), ..AccountDiff::default() };
+        let entries: Vec<_> = (1..=2)
+            .map(|block_number| ArchiveEntry {
+                block_number,
+                block_hash: B256::ZERO,
+                state_diff: StateDiff {
+                    accounts: [(Address::repeat_byte(1), account.clone())].into(),
+                    ..StateDiff::default()
+                },
+            })
+            .collect();
+        let mut primary = InMemoryDB::default();
+        for entry in &entries {
+            apply_entry(&mut primary, &config.spec, entry);
+        }
+        let batch = |entries: Vec<ArchiveEntry>| ReplicationBatch {
+            chain: Chain::Mainnet,
+            entries,
+            state_hash: primary.blake3_hash_slow(),
+        };
+
+        let mut standby = InMemoryDB::default();
+        // Out of step with the standby, which is at block 1
+        assert!(apply_batch(&config, &mut standby, 1, batch(entries[1..].to_vec())).is_err());
+        assert_eq!(apply_batch(&config, &mut standby, 1, batch(entries.clone())).unwrap(), 2);
+        assert_eq!(standby.blake3_hash_slow(), primary.blake3_hash_slow());
+    }
+}
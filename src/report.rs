@@ -0,0 +1,309 @@
+use crate::query::snapshot_block;
+use alloy::primitives::{Address, U256};
+use anyhow::Result;
+use itertools::Itertools;
+use revm::{db::DbAccount, InMemoryDB};
+use serde::Serialize;
+use std::{fmt::Display, io::Write};
+
+#[derive(Debug, Serialize)]
+pub struct RankedAccount<T> {
+    pub address: Address,
+    pub value: T,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TopAccounts {
+    pub block: u64,
+    pub by_balance: Vec<RankedAccount<U256>>,
+    pub by_storage_slots: Vec<RankedAccount<usize>>,
+    pub by_code_size: Vec<RankedAccount<usize>>,
+}
+
+fn write_ranking<T: Display>(
+    f: &mut std::fmt::Formatter<'_>,
+    title: &str,
+    ranking: &[RankedAccount<T>],
+) -> std::fmt::Result {
+    write!(f, "{title}:")?;
+    for (i, RankedAccount { address, value }) in ranking.iter().enumerate() {
+        write!(f, "\n{:>4}. {address} {value}", i + 1)?;
+    }
+    Ok(())
+}
+
+impl Display for TopAccounts {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "block: {}", self.block)?;
+        write_ranking(f, "largest native balances", &self.by_balance)?;
+        writeln!(f)?;
+        write_ranking(f, "most storage slots", &self.by_storage_slots)?;
+        writeln!(f)?;
+        write_ranking(f, "largest code", &self.by_code_size)
+    }
+}
+
+fn top_by<T: Ord + Copy>(
+    state: &InMemoryDB,
+    limit: usize,
+    value: impl Fn(&revm::db::DbAccount) -> T,
+) -> Vec<RankedAccount<T>> {
+    state
+        .accounts
+        .iter()
+        .map(|(&address, account)| RankedAccount { address, value: value(account) })
+        .k_largest_by_key(limit, |ranked| (ranked.value, ranked.address))
+        .collect()
+}
+
+pub fn top_accounts(next_block_num: u64, state: &InMemoryDB, limit: usize) -> TopAccounts {
+    let by_balance = top_by(state, limit, |account| account.info.balance);
+    let by_storage_slots = top_by(state, limit, |account| account.storage.values().filter(|v| !v.is_zero()).count());
+    let by_code_size = top_by(state, limit, |account| {
+        state.contracts.get(&account.info.code_hash).map_or(0, |bytecode| bytecode.original_bytes().len())
+    });
+    TopAccounts { block: snapshot_block(next_block_num), by_balance, by_storage_slots, by_code_size }
+}
+
+// Raw payload sizes of the snapshot encoding, ignoring msgpack framing overhead
+const ACCOUNT_SIZE: u64 = 20 + 32 + 8 + 32;
+const STORAGE_SLOT_SIZE: u64 = 32 + 32;
+const BLOCK_HASH_SIZE: u64 = 32 + 32;
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct StateStats {
+    pub block: u64,
+    pub accounts: u64,
+    pub contracts: u64,
+    pub storage_slots: u64,
+    pub code_blobs: u64,
+    pub code_bytes: u64,
+    pub block_hashes: u64,
+    pub estimated_size: u64,
+}
+
+impl StateStats {
+    const fn fields(&self) -> [(&'static str, u64); 7] {
+        [
+            ("accounts", self.accounts),
+            ("contracts", self.contracts),
+            ("storage_slots", self.storage_slots),
+            ("code_blobs", self.code_blobs),
+            ("code_bytes", self.code_bytes),
+            ("block_hashes", self.block_hashes),
+            ("estimated_size", self.estimated_size),
+        ]
+    }
+}
+
+impl Display for StateStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "block: {}", self.block)?;
+        for (name, value) in self.fields() {
+            write!(f, "\n{name}: {value}")?;
+        }
+        Ok(())
+    }
+}
+
+pub fn state_stats(next_block_num: u64, state: &InMemoryDB) -> StateStats {
+    let accounts = state.accounts.len() as u64;
+    let contracts =
+        state.accounts.values().filter(|account| state.contracts.contains_key(&account.info.code_hash)).count();
+    let storage_slots: usize =
+        state.accounts.values().map(|account| account.storage.values().filter(|v| !v.is_zero()).count()).sum();
+    let storage_slots = storage_slots as u64;
+    let code_blobs = state.contracts.len() as u64;
+    let code_bytes: usize = state.contracts.values().map(|bytecode| bytecode.original_bytes().len()).sum();
+    let code_bytes = code_bytes as u64;
+    let block_hashes = state.block_hashes.len() as u64;
+    let estimated_size = accounts * ACCOUNT_SIZE
+        + storage_slots * STORAGE_SLOT_SIZE
+        + code_blobs * 32
+        + code_bytes
+        + block_hashes * BLOCK_HASH_SIZE;
+    StateStats {
+        block: snapshot_block(next_block_num),
+        accounts,
+        contracts: contracts as u64,
+        storage_slots,
+        code_blobs,
+        code_bytes,
+        block_hashes,
+        estimated_size,
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct StateGrowth {
+    pub from: StateStats,
+    pub to: StateStats,
+}
+
+impl Display for StateGrowth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let blocks = self.to.block.saturating_sub(self.from.block);
+        write!(f, "block: {} -> {} ({blocks} blocks)", self.from.block, self.to.block)?;
+        for ((name, from), (_, to)) in self.from.fields().into_iter().zip(self.to.fields()) {
+            let delta = i128::from(to) - i128::from(from);
+            write!(f, "\n{name}: {to} ({delta:+})")?;
+            if blocks > 0 {
+                write!(f, " [{:+.2}/block]", delta as f64 / blocks as f64)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// What one account added to the state between two snapshots
+#[derive(Debug, Clone, Serialize)]
+pub struct AccountGrowth {
+    pub address: Address,
+    pub new_account: bool,
+    pub slots_added: u64,
+    pub slots_cleared: u64,
+    // Size of the code the account got, if its code changed
+    pub code_bytes: u64,
+    // Estimated change in snapshot size, by the sizes `state_stats` estimates with
+    pub bytes: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StorageGrowth {
+    pub from_block: u64,
+    pub to_block: u64,
+    pub accounts: usize,
+    pub bytes: i64,
+    // By estimated growth, largest first
+    pub growth: Vec<AccountGrowth>,
+}
+
+impl StorageGrowth {
+    pub fn write_csv(&self, mut writer: impl Write) -> Result<()> {
+        writeln!(writer, "rank,address,new_account,slots_added,slots_cleared,code_bytes,bytes")?;
+        for (i, growth) in self.growth.iter().enumerate() {
+            writeln!(
+                writer,
+                "{},{},{},{},{},{},{}",
+                i + 1,
+                growth.address,
+                growth.new_account,
+                growth.slots_added,
+                growth.slots_cleared,
+                growth.code_bytes,
+                growth.bytes
+            )?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+impl Display for StorageGrowth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "block: {} -> {}, {} accounts grew the state by {} bytes",
+            self.from_block, self.to_block, self.accounts, self.bytes
+        )?;
+        for (i, growth) in self.growth.iter().enumerate() {
+            write!(
+                f,
+                "\n{:>4}. {} {:+} bytes: {} slots added, {} cleared, {} code bytes{}",
+                i + 1,
+                growth.address,
+                growth.bytes,
+                growth.slots_added,
+                growth.slots_cleared,
+                growth.code_bytes,
+                if growth.new_account { ", new account" } else { "" }
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn account_growth(
+    address: Address,
+    from: Option<&DbAccount>,
+    to: &DbAccount,
+    to_state: &InMemoryDB,
+) -> Option<AccountGrowth> {
+    let zero = U256::ZERO;
+    let was_set = |slot: &U256| from.and_then(|from| from.storage.get(slot)).is_some_and(|value| !value.is_zero());
+    let slots_added = to.storage.iter().filter(|(slot, value)| !value.is_zero() && !was_set(slot)).count() as u64;
+    let slots_cleared = from.map_or(0, |from| {
+        from.storage
+            .iter()
+            .filter(|(slot, value)| !value.is_zero() && to.storage.get(slot).unwrap_or(&zero).is_zero())
+            .count() as u64
+    });
+    let new_account = from.is_none_or(|from| from.info.is_empty()) && !to.info.is_empty();
+    let code_changed = from.is_none_or(|from| from.info.code_hash != to.info.code_hash);
+    let code_bytes = if code_changed {
+        to_state.contracts.get(&to.info.code_hash).map_or(0, |bytecode| bytecode.original_bytes().len() as u64)
+    } else {
+        0
+    };
+    if !new_account && slots_added == 0 && slots_cleared == 0 && code_bytes == 0 {
+        return None;
+    }
+    let slots = i64::try_from(slots_added).unwrap_or(i64::MAX) - i64::try_from(slots_cleared).unwrap_or(i64::MAX);
+    let bytes = i64::from(new_account) * ACCOUNT_SIZE as i64
+        + slots * STORAGE_SLOT_SIZE as i64
+        + i64::try_from(code_bytes).unwrap_or(i64::MAX);
+    Some(AccountGrowth { address, new_account, slots_added, slots_cleared, code_bytes, bytes })
+}
+
+// Attributes the growth between two snapshots to the accounts that caused it. Shrinking accounts are listed too, at
+// the end; accounts removed altogether are not.
+pub fn storage_growth(
+    from_next_block_num: u64,
+    from: &InMemoryDB,
+    to_next_block_num: u64,
+    to: &InMemoryDB,
+) -> StorageGrowth {
+    let growth: Vec<AccountGrowth> = to
+        .accounts
+        .iter()
+        .filter_map(|(&address, account)| account_growth(address, from.accounts.get(&address), account, to))
+        .sorted_by(|a, b| b.bytes.cmp(&a.bytes).then(a.address.cmp(&b.address)))
+        .collect();
+    StorageGrowth {
+        from_block: snapshot_block(from_next_block_num),
+        to_block: snapshot_block(to_next_block_num),
+        accounts: growth.len(),
+        bytes: growth.iter().map(|growth| growth.bytes).sum(),
+        growth,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::report::{storage_growth, ACCOUNT_SIZE, STORAGE_SLOT_SIZE};
+    use alloy::primitives::{address, U256};
+    use revm::{primitives::AccountInfo, InMemoryDB};
+
+    #[test]
+    fn test_storage_growth() {
+        let (a, b) = (
+            address!("0x000000000000000000000000000000000000000a"),
+            address!("0x000000000000000000000000000000000000000b"),
+        );
+        let mut from = InMemoryDB::default();
+        from.insert_account_info(a, AccountInfo { nonce: 1, ..AccountInfo::default() });
+        from.insert_account_storage(a, U256::from(1), U256::from(1)).unwrap();
+        let mut to = from.clone();
+        to.insert_account_storage(a, U256::from(1), U256::ZERO).unwrap();
+        to.insert_account_info(b, AccountInfo { nonce: 1, ..AccountInfo::default() });
+        for slot in 0..3 {
+            to.insert_account_storage(b, U256::from(slot), U256::from(1)).unwrap();
+        }
+        let report = storage_growth(11, &from, 21, &to);
+        assert_eq!((report.from_block, report.to_block, report.accounts), (10, 20, 2));
+        assert_eq!(report.growth[0].address, b);
+        assert!(report.growth[0].new_account);
+        assert_eq!(report.growth[0].bytes, (ACCOUNT_SIZE + 3 * STORAGE_SLOT_SIZE) as i64);
+        assert_eq!(report.growth[1].bytes, -(STORAGE_SLOT_SIZE as i64));
+    }
+}
@@ -0,0 +1,103 @@
+use crate::{cli::Chain, control::ControlHandle};
+use alloy::primitives::{Address, B256, U256};
+use anyhow::Result;
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    routing::get,
+    Json, Router,
+};
+use revm::primitives::AccountInfo;
+use serde::Serialize;
+use std::net::SocketAddr;
+use tokio::net::TcpListener;
+
+#[derive(Clone)]
+struct RestState {
+    chain: Chain,
+    handle: ControlHandle,
+}
+
+type ApiResult<T> = Result<Json<T>, (StatusCode, String)>;
+
+#[derive(Serialize)]
+struct StatusBody {
+    chain: String,
+    start_block: u64,
+    end_block: u64,
+    current_block: u64,
+    last_snapshot_block: u64,
+    paused: bool,
+}
+
+#[derive(Serialize)]
+struct AccountBody {
+    block: u64,
+    address: Address,
+    exists: bool,
+    balance: U256,
+    nonce: u64,
+    code_hash: B256,
+}
+
+#[derive(Serialize)]
+struct StorageBody {
+    block: u64,
+    address: Address,
+    slot: U256,
+    value: U256,
+}
+
+fn parse<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, (StatusCode, String)>
+where
+    T::Err: std::fmt::Display,
+{
+    value.parse().map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid {name} {value}: {e}")))
+}
+
+fn unavailable(e: anyhow::Error) -> (StatusCode, String) {
+    (StatusCode::SERVICE_UNAVAILABLE, e.to_string())
+}
+
+async fn status(State(state): State<RestState>) -> Json<StatusBody> {
+    let status = &state.handle.status;
+    Json(StatusBody {
+        chain: state.chain.to_string(),
+        start_block: status.start_block(),
+        end_block: status.end_block(),
+        current_block: status.current_block(),
+        last_snapshot_block: status.last_snapshot_block(),
+        paused: status.is_paused(),
+    })
+}
+
+async fn account(State(state): State<RestState>, Path(address): Path<String>) -> ApiResult<AccountBody> {
+    let address: Address = parse("address", &address)?;
+    let (block, info) = state.handle.account(address).await.map_err(unavailable)?;
+    let exists = info.is_some();
+    let AccountInfo { balance, nonce, code_hash, .. } = info.unwrap_or_default();
+    Ok(Json(AccountBody { block, address, exists, balance, nonce, code_hash }))
+}
+
+async fn storage(
+    State(state): State<RestState>,
+    Path((address, slot)): Path<(String, String)>,
+) -> ApiResult<StorageBody> {
+    let address: Address = parse("address", &address)?;
+    let slot: U256 = parse("slot", &slot)?;
+    let (block, value) = state.handle.storage(address, slot).await.map_err(unavailable)?;
+    Ok(Json(StorageBody { block, address, slot, value }))
+}
+
+// Plain JSON over HTTP for consumers that do not speak gRPC; answered between blocks like the control interface
+pub async fn serve_rest(addr: SocketAddr, chain: Chain, handle: ControlHandle) -> Result<()> {
+    println!("Serving REST API on {addr}");
+    let app = Router::new()
+        .route("/status", get(status))
+        .route("/account/:address", get(account))
+        .route("/storage/:address/:slot", get(storage))
+        .with_state(RestState { chain, handle });
+    let listener = TcpListener::bind(addr).await?;
+    axum::serve(listener, app).await?;
+    Ok(())
+}
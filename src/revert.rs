@@ -0,0 +1,116 @@
+use alloy::{
+    dyn_abi::{DynSolValue, JsonAbiExt},
+    json_abi::{Error, JsonAbi},
+    primitives::{Address, Bytes, FixedBytes},
+    sol_types::decode_revert_reason,
+};
+use anyhow::{anyhow, Result};
+use revm::primitives::ExecutionResult;
+use serde::Serialize;
+use std::collections::HashMap;
+
+// Custom errors by selector, from the ABIs given with --abi
+#[derive(Debug, Default)]
+pub struct RevertDecoder {
+    errors: HashMap<FixedBytes<4>, Error>,
+}
+
+impl RevertDecoder {
+    // Each file is a JSON ABI, or a compiler artifact with one under "abi"
+    pub fn load(paths: &[String]) -> Result<Self> {
+        let mut errors = HashMap::new();
+        for path in paths {
+            let json: serde_json::Value = serde_json::from_slice(&std::fs::read(path)?)?;
+            let json = json.get("abi").cloned().unwrap_or(json);
+            let abi: JsonAbi = serde_json::from_value(json).map_err(|e| anyhow!("invalid ABI in {path}: {e}"))?;
+            for error in abi.errors() {
+                errors.insert(error.selector(), error.clone());
+            }
+        }
+        Ok(Self { errors })
+    }
+
+    // Error(string) and Panic(uint256) are always decoded, custom errors only when their ABI was loaded
+    pub fn decode(&self, output: &[u8]) -> Option<String> {
+        let selector = output.get(..4).map(FixedBytes::<4>::from_slice);
+        if let Some(error) = selector.and_then(|selector| self.errors.get(&selector)) {
+            if let Ok(values) = error.abi_decode_input(&output[4..], false) {
+                let values: Vec<_> = values.iter().map(format_value).collect();
+                return Some(format!("{}({})", error.name, values.join(", ")));
+            }
+        }
+        decode_revert_reason(output)
+    }
+}
+
+fn format_value(value: &DynSolValue) -> String {
+    match value {
+        DynSolValue::Bool(b) => b.to_string(),
+        DynSolValue::Int(i, _) => i.to_string(),
+        DynSolValue::Uint(u, _) => u.to_string(),
+        DynSolValue::Address(address) => address.to_string(),
+        DynSolValue::FixedBytes(word, size) => Bytes::copy_from_slice(&word[..*size]).to_string(),
+        DynSolValue::Bytes(bytes) => Bytes::copy_from_slice(bytes).to_string(),
+        DynSolValue::String(s) => format!("{s:?}"),
+        DynSolValue::Array(values) | DynSolValue::FixedArray(values) => {
+            format!("[{}]", values.iter().map(format_value).collect::<Vec<_>>().join(", "))
+        }
+        DynSolValue::Tuple(values) => format!("({})", values.iter().map(format_value).collect::<Vec<_>>().join(", ")),
+        other => format!("{other:?}"),
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TxFailure {
+    pub block: u64,
+    pub tx_index: usize,
+    pub is_system_tx: bool,
+    pub sender: Address,
+    pub to: Option<Address>,
+    pub gas_used: u64,
+    // "revert" or "halt"
+    pub kind: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    pub output: Bytes,
+}
+
+impl TxFailure {
+    pub fn new(
+        decoder: &RevertDecoder,
+        block: u64,
+        tx_index: usize,
+        is_system_tx: bool,
+        sender: Address,
+        to: Option<Address>,
+        result: &ExecutionResult,
+    ) -> Option<Self> {
+        let (kind, reason, output) = match result {
+            ExecutionResult::Success { .. } => return None,
+            ExecutionResult::Revert { output, .. } => ("revert", decoder.decode(output), output.clone()),
+            ExecutionResult::Halt { reason, .. } => ("halt", Some(format!("{reason:?}")), Bytes::new()),
+        };
+        Some(Self { block, tx_index, is_system_tx, sender, to, gas_used: result.gas_used(), kind, reason, output })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::revert::RevertDecoder;
+    use alloy::primitives::hex;
+
+    #[test]
+    fn test_decode_builtin_reverts() {
+        let decoder = RevertDecoder::default();
+        let error_string = hex::decode(
+            "08c379a0\
+             0000000000000000000000000000000000000000000000000000000000000020\
+             0000000000000000000000000000000000000000000000000000000000000004\
+             6e6f706500000000000000000000000000000000000000000000000000000000",
+        )
+        .unwrap();
+        assert!(decoder.decode(&error_string).unwrap().contains("nope"));
+        let panic = hex::decode("4e487b710000000000000000000000000000000000000000000000000000000000000011").unwrap();
+        assert!(decoder.decode(&panic).unwrap().contains("overflow"));
+    }
+}
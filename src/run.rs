@@ -1,26 +1,67 @@
+#[cfg(unix)]
+use crate::systemd::SystemdNotifier;
 use crate::{
-    cli::Chain,
-    fs::snapshot_evm_state,
-    precompile::set_replay_precompiles,
+    archive::{write_archive_entry, ArchiveEntry},
+    balances::BalanceTracker,
+    bench::StageTimings,
+    bridge::{system_tx_transfer, user_tx_transfers, BridgeTransfer},
+    cache::ResultCache,
+    chainspec::{ChainSpec, EvmRules, Opcode},
+    cli::{Chain, CHUNK_SIZE},
+    control::{ControlRequest, Controller},
+    corewriter::{block_actions, CoreWriterRecord},
+    diff::StateDiff,
+    error::ErrorKind,
+    events::{BlockSummary, ExecutedBlock, SyncEvent, SyncEvents},
+    evm_map::TokenMap,
+    fees::{FeeLedger, FeeTotals},
+    fs::write_snapshot_as,
+    hooks::{ExecutedTx, SyncHooks},
+    inspector::ReplayInspector,
+    journal::HashJournal,
+    jsonl::JsonLinesLog,
+    manifest::SyncManifest,
+    notify::{Notification, Notifier},
+    partial::PartialScope,
+    precompile::{set_replay_precompiles, BlockFilePrecompiles, PrecompileProvider},
+    progress::SyncProgress,
+    recovery::RecoveryJournal,
+    remote::SnapshotUploader,
+    replicate::Replicator,
+    revert::{RevertDecoder, TxFailure},
+    simulate::ReplayOverrides,
+    slow::{SlowTx, SlowTxLog},
+    snapshot::SnapshotFormat,
     state::{State, StateHash},
+    status::SyncStatus,
+    telemetry,
     types::{
         BlockAndReceipts, EvmBlock, EvmState, PreprocessedBlock, ReadPrecompileInput, ReadPrecompileResult, SystemTx,
     },
+    witness::{write_witness, BlockWitness, WitnessRecorder},
 };
 use alloy::{
     consensus::Transaction as _,
-    primitives::{address, bytes, Address, Bytes, B256, U160, U256},
+    primitives::{address, bytes, Address, Bloom, Bytes, B256, U160, U256},
 };
-use indicatif::ProgressBar;
-use reth_primitives::{Receipt, SealedBlock, Transaction};
+use anyhow::{anyhow, Context, Result};
+use reth_primitives::{proofs::calculate_receipt_root, Receipt, SealedBlock, Transaction};
 use revm::{
+    handler::register::EvmHandler,
+    inspector_handle_register,
+    interpreter::instructions::control,
     primitives::{
-        Account, BlobExcessGasAndPrice, BlockEnv, CfgEnv, CfgEnvWithHandlerCfg, EnvWithHandlerCfg, HandlerCfg, HashMap,
-        ResultAndState, SpecId, TxEnv,
+        Account, BlobExcessGasAndPrice, BlockEnv, CfgEnv, CfgEnvWithHandlerCfg, EnvWithHandlerCfg, ExecutionResult,
+        HandlerCfg, HashMap, ResultAndState, SpecId, TxEnv, TxKind,
     },
-    Database, Evm,
+    Database, DatabaseRef, Evm,
+};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::PathBuf,
+    sync::{atomic::Ordering, Arc},
+    time::{Duration, Instant},
 };
-use std::{collections::BTreeMap, sync::Arc, time::Instant};
 
 fn deploy_system_contract<S: State>(state: &mut S, contract_address: Address, deployed_bytecode: Bytes) {
     state.inject_contract(contract_address, deployed_bytecode);
@@ -36,8 +77,8 @@ fn deploy_system_contract<S: State>(state: &mut S, contract_address: Address, de
     }
 }
 
-fn deploy_system_contracts<S: State>(state: &mut S, chain: Chain, block_number: u64) {
-    match chain {
+pub(crate) fn deploy_system_contracts<S: State>(state: &mut S, spec: &ChainSpec, block_number: u64) {
+    match spec.chain {
         Chain::Mainnet => {
             if block_number == 1 {
                 deploy_system_contract(
@@ -56,7 +97,7 @@ fn deploy_system_contracts<S: State>(state: &mut S, chain: Chain, block_number:
         );
             }
 
-            if block_number == CORE_WRITER_DEPLOY_BLOCK_NUMBER {
+            if Some(block_number) == spec.core_writer_deploy_block {
                 deploy_system_contract(state, CORE_WRITER_ADDRESS, bytes!("0x608060405234801561000f575f5ffd5b5060043610610029575f3560e01c806317938e131461002d575b5f5ffd5b61004760048036038101906100429190610123565b610049565b005b5f5f90505b61019081101561006557808060010191505061004e565b503373ffffffffffffffffffffffffffffffffffffffff167f8c7f585fb295f7eb1e6aeb8fba61b23a4fe60beda405f0045073b185c74412e383836040516100ae9291906101c8565b60405180910390a25050565b5f5ffd5b5f5ffd5b5f5ffd5b5f5ffd5b5f5ffd5b5f5f83601f8401126100e3576100e26100c2565b5b8235905067ffffffffffffffff811115610100576100ff6100c6565b5b60208301915083600182028301111561011c5761011b6100ca565b5b9250929050565b5f5f60208385031215610139576101386100ba565b5b5f83013567ffffffffffffffff811115610156576101556100be565b5b610162858286016100ce565b92509250509250929050565b5f82825260208201905092915050565b828183375f83830152505050565b5f601f19601f8301169050919050565b5f6101a7838561016e565b93506101b483858461017e565b6101bd8361018c565b840190509392505050565b5f6020820190508181035f8301526101e181848661019c565b9050939250505056fea2646970667358221220f01517e1fbaff8af4bd72cb063cccecbacbb00b07354eea7dd52265d355474fb64736f6c634300081c0033"));
             }
         }
@@ -97,46 +138,74 @@ fn fix_mainnet_state_diff(
     }
 }
 
-struct ApplyTxArgs<'a, S> {
+struct ApplyTxArgs<'a, 'r, S> {
     chain_id: u64,
+    spec_id: SpecId,
+    evm_rules: &'a EvmRules,
     block: &'a SealedBlock,
     precompile_results: &'a Arc<HashMap<Address, Arc<HashMap<ReadPrecompileInput, ReadPrecompileResult>>>>,
     sender: Address,
     transaction: &'a Transaction,
+    tx_hash: Option<B256>,
     tx_index: usize,
     is_system_tx: bool,
     cumulative_gas_used: u64,
     db: &'a mut S,
+    timings: &'a mut StageTimings,
+    inspector: &'a mut ReplayInspector,
+    recorders: &'a mut BlockRecorders<'r>,
+    revert_decoder: &'a RevertDecoder,
+    overrides: Option<&'a ReplayOverrides>,
+    partial: Option<&'a PartialScope>,
 }
 
-fn apply_tx<S>(args: ApplyTxArgs<S>) -> Receipt
+fn apply_tx<S>(args: ApplyTxArgs<S>) -> Result<Receipt>
 where
     S: State,
     <S as Database>::Error: std::fmt::Debug,
 {
     let ApplyTxArgs {
         chain_id,
+        spec_id,
+        evm_rules,
         block,
         precompile_results,
         sender,
         transaction,
+        tx_hash,
         tx_index,
         is_system_tx,
         mut cumulative_gas_used,
-        mut db,
+        db,
+        timings,
+        inspector,
+        recorders,
+        revert_decoder,
+        overrides,
+        partial,
     } = args;
-    let mut cfg = CfgEnvWithHandlerCfg::new(CfgEnv::default().with_chain_id(chain_id), HandlerCfg::new(SpecId::CANCUN));
+    let mut cfg = cfg_env(chain_id, spec_id);
+    // A partial state does not hold the senders' accounts
+    cfg.disable_balance_check = partial.is_some();
     let basefee = if is_system_tx {
         cfg.disable_eip3607 = true;
         0
+    } else if overrides.is_some_and(|overrides| overrides.disable_basefee) {
+        0
     } else {
         block.header().base_fee_per_gas.unwrap_or_default()
     };
     let block_env = BlockEnv {
         number: U256::from(block.header().number),
-        coinbase: Address::ZERO,
+        coinbase: overrides
+            .and_then(|overrides| overrides.coinbase)
+            .unwrap_or_else(|| evm_rules.coinbase(block.header().beneficiary)),
         timestamp: U256::from(block.header().timestamp),
-        gas_limit: U256::from(block.header().gas_limit),
+        gas_limit: U256::from(
+            overrides
+                .and_then(|overrides| overrides.gas_limit)
+                .unwrap_or_else(|| evm_rules.block_gas_limit(block.header().gas_limit)),
+        ),
         basefee: U256::from(basefee),
         blob_excess_gas_and_price: Some(BlobExcessGasAndPrice::new(0, false)),
         difficulty: U256::ZERO,
@@ -149,7 +218,7 @@ where
         transact_to: transaction.kind(),
         value: transaction.value(),
         data: transaction.input().clone(),
-        nonce: Some(transaction.nonce()),
+        nonce: partial.is_none().then(|| transaction.nonce()),
         chain_id: transaction.chain_id(),
         access_list: transaction.access_list().map_or_else(Vec::new, |access_list| access_list.0.clone()),
         gas_priority_fee: transaction.max_priority_fee_per_gas().map(U256::from),
@@ -158,50 +227,215 @@ where
         authorization_list: None,
     };
 
-    let ResultAndState { result, mut state } = Evm::builder()
-        .with_db(&mut db)
-        .with_env_with_handler_cfg(EnvWithHandlerCfg::new_with_cfg_env(cfg, block_env, tx_env))
-        .append_handler_register_box(Box::new(move |handler| {
-            set_replay_precompiles(handler, Arc::clone(precompile_results));
-        }))
-        .build()
-        .transact()
-        .unwrap();
+    let env = EnvWithHandlerCfg::new_with_cfg_env(cfg, block_env, tx_env);
+    let start = Instant::now();
+    let recorder = WitnessRecorder { db: &mut *db, witness: recorders.witness.as_mut() };
+    let disabled_opcodes = &evm_rules.disabled_opcodes;
+    let ResultAndState { result, mut state } = if inspector.is_active() {
+        Evm::builder()
+            .with_db(recorder)
+            .with_external_context(inspector)
+            .with_env_with_handler_cfg(env)
+            // Before the inspector wraps the instructions, so it still sees the disabled ones
+            .append_handler_register_box(Box::new(move |handler| disable_opcodes(handler, disabled_opcodes)))
+            .append_handler_register(inspector_handle_register)
+            .append_handler_register_box(Box::new(move |handler| {
+                set_replay_precompiles(handler, Arc::clone(precompile_results));
+            }))
+            .build()
+            .transact()
+    } else {
+        Evm::builder()
+            .with_db(recorder)
+            .with_env_with_handler_cfg(env)
+            .append_handler_register_box(Box::new(move |handler| disable_opcodes(handler, disabled_opcodes)))
+            .append_handler_register_box(Box::new(move |handler| {
+                set_replay_precompiles(handler, Arc::clone(precompile_results));
+            }))
+            .build()
+            .transact()
+    }
+    .map_err(|e| anyhow!("Transaction {tx_index} in block {} could not be executed: {e:?}", block.number))
+    .context(ErrorKind::CorruptData)?;
+    let duration = start.elapsed();
+    timings.execution += duration;
+    if let Some((threshold, slow_txs)) = &mut recorders.slow_txs {
+        if duration >= *threshold {
+            slow_txs.push(SlowTx {
+                block: block.number,
+                tx_index,
+                is_system_tx,
+                hash: tx_hash,
+                sender,
+                to: transaction.to(),
+                gas_used: result.gas_used(),
+                duration_ms: duration.as_secs_f64() * 1000.0,
+            });
+        }
+    }
+    if let Some(fees) = &mut recorders.fees {
+        let effective_gas_price = transaction.effective_gas_price(Some(basefee));
+        fees.record_tx(is_system_tx, basefee, effective_gas_price, result.gas_used());
+    }
+    for hook in recorders.hooks {
+        hook.on_tx_executed(&ExecutedTx { block, tx_index, is_system_tx, sender, transaction, result: &result });
+    }
+    if let Some(failures) = &mut recorders.failures {
+        let to = transaction.to();
+        failures.extend(TxFailure::new(revert_decoder, block.number, tx_index, is_system_tx, sender, to, &result));
+    }
 
+    let start = Instant::now();
+    if let Some(scope) = partial {
+        scope.restrict(block.number, tx_index, is_system_tx, sender, &mut state);
+    }
     if chain_id == MAINNET_CHAIN_ID {
         fix_mainnet_state_diff(block.number, tx_index, is_system_tx, &mut state);
     }
+    if let Some(state_diff) = &mut recorders.state_diff {
+        state_diff.record(&*db, &state);
+    }
     db.commit(state);
+    timings.commit += start.elapsed();
 
     let gas_used = result.gas_used();
     cumulative_gas_used += gas_used;
-    Receipt {
+    Ok(Receipt {
         tx_type: transaction.tx_type(),
         success: result.is_success(),
         cumulative_gas_used,
         logs: result.into_logs().into_iter().collect(),
+    })
+}
+
+fn cfg_env(chain_id: u64, spec_id: SpecId) -> CfgEnvWithHandlerCfg {
+    CfgEnvWithHandlerCfg::new(CfgEnv::default().with_chain_id(chain_id), HandlerCfg::new(spec_id))
+}
+
+fn disable_opcodes<EXT, DB: Database>(handler: &mut EvmHandler<EXT, DB>, opcodes: &BTreeSet<Opcode>) {
+    for &Opcode(opcode) in opcodes {
+        handler.instruction_table.insert(opcode, control::unknown);
     }
 }
 
+pub struct CallRequest {
+    pub from: Address,
+    pub to: Address,
+    pub data: Bytes,
+    pub value: U256,
+    pub gas_limit: u64,
+}
+
+// Executes a read-only call against `state` without committing, under the block's EVM rules. Read precompiles have no
+// recorded results outside of block replay, so calls into them fail as out of gas. There is no header, so a
+// `header` beneficiary falls back to the zero address.
+pub fn call<S>(spec: &ChainSpec, state: &S, block_number: u64, request: CallRequest) -> Result<ExecutionResult>
+where
+    S: DatabaseRef,
+    <S as DatabaseRef>::Error: std::fmt::Debug,
+{
+    let CallRequest { from, to, data, value, gas_limit } = request;
+    let evm_rules = spec.evm_rules(block_number);
+    let disabled_opcodes = &evm_rules.disabled_opcodes;
+    let block_env = BlockEnv {
+        number: U256::from(block_number),
+        coinbase: evm_rules.coinbase(Address::ZERO),
+        timestamp: U256::ZERO,
+        gas_limit: U256::from(gas_limit),
+        basefee: U256::ZERO,
+        blob_excess_gas_and_price: Some(BlobExcessGasAndPrice::new(0, false)),
+        difficulty: U256::ZERO,
+        prevrandao: Some(B256::ZERO),
+    };
+    let tx_env = TxEnv {
+        caller: from,
+        gas_limit,
+        gas_price: U256::ZERO,
+        transact_to: TxKind::Call(to),
+        value,
+        data,
+        nonce: None,
+        chain_id: Some(spec.chain_id),
+        ..TxEnv::default()
+    };
+    let precompile_results = Arc::new(HashMap::default());
+    let ResultAndState { result, .. } = Evm::builder()
+        .with_ref_db(state)
+        .with_env_with_handler_cfg(EnvWithHandlerCfg::new_with_cfg_env(
+            cfg_env(spec.chain_id, spec.spec_id(block_number)),
+            block_env,
+            tx_env,
+        ))
+        .append_handler_register_box(Box::new(move |handler| disable_opcodes(handler, disabled_opcodes)))
+        .append_handler_register_box(Box::new(move |handler| {
+            set_replay_precompiles(handler, Arc::clone(&precompile_results));
+        }))
+        .build()
+        .transact()
+        .map_err(|e| anyhow!("Call to {to} failed: {e:?}"))?;
+    Ok(result)
+}
+
+fn check_receipt(block_num: u64, tx_index: usize, kind: &str, computed: &Receipt, recorded: &Receipt) -> Result<()> {
+    if computed != recorded {
+        return Err(anyhow!(
+            "Receipt mismatch for {kind} {tx_index} in block {block_num}: computed {computed:?}, recorded {recorded:?}"
+        ))
+        .context(ErrorKind::HashMismatch);
+    }
+    Ok(())
+}
+
+// Catches corrupted block files as well as execution bugs that still produce the recorded receipts
+fn check_receipts_root<'a>(block: &SealedBlock, receipts: impl IntoIterator<Item = &'a Receipt>) -> Result<()> {
+    let receipts_with_bloom: Vec<_> = receipts.into_iter().cloned().map(Receipt::with_bloom).collect();
+    let receipts_root = calculate_receipt_root(&receipts_with_bloom);
+    if receipts_root != block.header().receipts_root {
+        return Err(anyhow!(
+            "Receipts root mismatch in block {}: computed {receipts_root}, header {}",
+            block.number,
+            block.header().receipts_root
+        ))
+        .context(ErrorKind::HashMismatch);
+    }
+    let logs_bloom = receipts_with_bloom.iter().fold(Bloom::ZERO, |bloom, receipt| bloom | receipt.bloom);
+    if logs_bloom != block.header().logs_bloom {
+        return Err(anyhow!("Logs bloom mismatch in block {}", block.number)).context(ErrorKind::HashMismatch);
+    }
+    Ok(())
+}
+
 fn process_block<S>(
-    chain: Chain,
+    config: &RunConfig,
     state: &mut S,
-    erc20_contract_to_system_address: &BTreeMap<Address, Address>,
     block_and_receipts: BlockAndReceipts,
     signers: Vec<Address>,
-) where
+    timings: &mut StageTimings,
+    inspector: &mut ReplayInspector,
+    recorders: &mut BlockRecorders<'_>,
+) -> Result<Vec<Receipt>>
+where
     S: State,
     <S as Database>::Error: std::fmt::Debug,
 {
     let BlockAndReceipts { block, receipts, system_txs, read_precompile_calls, highest_precompile_address } =
         block_and_receipts;
     let EvmBlock::Reth115(block) = block;
+    for hook in recorders.hooks {
+        hook.on_block_start(&block);
+    }
+    let spec = &config.spec;
+    let spec_id = spec.spec_id(block.number);
+    let read_precompile_calls = config
+        .precompiles
+        .block_calls(block.number, read_precompile_calls)
+        .with_context(|| format!("No read precompile results for block {}", block.number))?;
     let precompile_results = {
         let mut res: HashMap<_, _> = read_precompile_calls
             .into_iter()
             .map(|(address, calls)| (address, Arc::new(calls.into_iter().collect())))
             .collect();
-        if block.number >= WARM_PRECOMPILES_BLOCK_NUMBER {
+        if block.number >= spec.warm_precompiles_block {
             let highest_precompile_address =
                 highest_precompile_address.unwrap_or(address!("0x000000000000000000000000000000000000080d"));
             let mut i = 0x800;
@@ -217,109 +451,609 @@ fn process_block<S>(
         Arc::new(res)
     };
 
-    deploy_system_contracts(state, chain, block.number);
+    deploy_system_contracts(state, spec, block.number);
+    if let Some(overrides) = &config.overrides {
+        overrides.apply_code(state);
+    }
 
     let mut cumulative_gas_used = 0;
     for (tx_index, system_tx) in system_txs.into_iter().enumerate() {
         let SystemTx { tx, receipt } = system_tx;
+        let sender = if tx.input().is_empty() {
+            NATIVE_TOKEN_SYSTEM_ADDRESS
+        } else {
+            let token = tx
+                .to()
+                .ok_or_else(|| anyhow!("System transaction {tx_index} in block {} has no recipient", block.number))
+                .context(ErrorKind::CorruptData)?;
+            *config
+                .erc20_contract_to_system_address
+                .get(&token)
+                .ok_or_else(|| {
+                    anyhow!(
+                        "System transaction {tx_index} in block {} calls {token}, which is not in the token map",
+                        block.number
+                    )
+                })
+                .context(ErrorKind::CorruptData)?
+        };
+        if config.overrides.as_ref().is_some_and(|overrides| overrides.skips(sender, &tx)) {
+            continue;
+        }
+        if config.partial.as_ref().is_some_and(|scope| !scope.involves(sender, &tx, receipt.as_ref())) {
+            continue;
+        }
         let computed_receipt = apply_tx(ApplyTxArgs {
-            chain_id: chain_id(chain),
+            chain_id: spec.chain_id,
+            spec_id,
+            evm_rules: spec.evm_rules(block.number),
             block: &block,
             precompile_results: &precompile_results,
-            sender: if tx.input().is_empty() {
-                NATIVE_TOKEN_SYSTEM_ADDRESS
-            } else {
-                erc20_contract_to_system_address[&tx.to().unwrap()]
-            },
+            sender,
             transaction: &tx,
+            tx_hash: None,
             tx_index,
             is_system_tx: true,
             cumulative_gas_used,
             db: state,
-        });
+            timings,
+            inspector,
+            recorders,
+            revert_decoder: &config.revert_decoder,
+            overrides: config.overrides.as_ref(),
+            partial: config.partial.as_deref(),
+        })?;
         cumulative_gas_used = computed_receipt.cumulative_gas_used;
-        if let Some(receipt) = receipt {
-            assert_eq!(computed_receipt, receipt.into());
+        if let Some(transfers) = &mut recorders.bridge_transfers {
+            transfers.push(system_tx_transfer(block.number, tx_index, &tx, sender, computed_receipt.success));
+        }
+        if let Some(receipt) = receipt.filter(|_| config.is_canonical()) {
+            check_receipt(block.number, tx_index, "system transaction", &computed_receipt, &receipt.into())?;
         }
     }
 
     let mut cumulative_gas_used = 0;
+    // Paired with the transaction's index in the block, which differs from the position here once any are skipped
     let mut computed_receipts = Vec::new();
     let txs: Vec<_> = block.body().transactions.iter().zip(signers).enumerate().collect();
     for (tx_index, (tx_signed, signer)) in txs {
         let transaction = &tx_signed.transaction;
+        if config.overrides.as_ref().is_some_and(|overrides| overrides.skips(signer, transaction)) {
+            continue;
+        }
+        if config.partial.as_ref().is_some_and(|scope| !scope.involves(signer, transaction, receipts.get(tx_index))) {
+            continue;
+        }
         let receipt = apply_tx(ApplyTxArgs {
-            chain_id: chain_id(chain),
+            chain_id: spec.chain_id,
+            spec_id,
+            evm_rules: spec.evm_rules(block.number),
             block: &block,
             precompile_results: &precompile_results,
             sender: signer,
             transaction,
+            tx_hash: Some(tx_signed.hash()),
             tx_index,
             is_system_tx: false,
             cumulative_gas_used,
             db: state,
-        });
+            timings,
+            inspector,
+            recorders,
+            revert_decoder: &config.revert_decoder,
+            overrides: config.overrides.as_ref(),
+            partial: config.partial.as_deref(),
+        })?;
         cumulative_gas_used = receipt.cumulative_gas_used;
-        computed_receipts.push(receipt);
+        computed_receipts.push((tx_index, receipt));
     }
-    // Before this height threshold, the blockhash opcode would just return keccak256(number.to_string().as_bytes())
-    if block.header().number >= NON_PLACEHOLDER_BLOCK_HASH_HEIGHT {
+    if block.header().number >= spec.non_placeholder_block_hash_height {
         state.insert_block_hash(block.number, block.hash());
     }
-    let expected_receipts: Vec<Receipt> = receipts.into_iter().map(Into::into).collect();
-    assert_eq!(expected_receipts, computed_receipts);
+    if config.is_canonical() {
+        if receipts.len() != computed_receipts.len() {
+            return Err(anyhow!(
+                "Block {} has {} recorded receipts but {} transactions were executed",
+                block.number,
+                receipts.len(),
+                computed_receipts.len()
+            ))
+            .context(ErrorKind::HashMismatch);
+        }
+        for ((tx_index, computed), recorded) in computed_receipts.iter().zip(receipts) {
+            check_receipt(block.number, *tx_index, "transaction", computed, &recorded.into())?;
+        }
+    }
+    if config.verify_receipts_root && config.is_canonical() {
+        check_receipts_root(&block, computed_receipts.iter().map(|(_, receipt)| receipt))?;
+    }
+    if let Some(transfers) = &mut recorders.bridge_transfers {
+        for (tx_index, receipt) in &computed_receipts {
+            transfers.extend(user_tx_transfers(
+                block.number,
+                *tx_index,
+                &receipt.logs,
+                &config.erc20_contract_to_system_address,
+            ));
+        }
+    }
+    if let Some(actions) = &mut recorders.corewriter_actions {
+        let logs = computed_receipts.iter().map(|(tx_index, receipt)| (*tx_index, receipt.logs.as_slice()));
+        actions.extend(block_actions(&block, logs));
+    }
+    if let Some(state_diff) = recorders.state_diff.as_ref().filter(|_| !recorders.hooks.is_empty()) {
+        for hook in recorders.hooks {
+            hook.on_block_committed(&block, state_diff);
+        }
+    }
+    Ok(computed_receipts.into_iter().map(|(_, receipt)| receipt).collect())
+}
+
+// Optional per-block outputs collected while the block executes
+#[derive(Default)]
+struct BlockRecorders<'a> {
+    state_diff: Option<StateDiff>,
+    witness: Option<BlockWitness>,
+    corewriter_actions: Option<Vec<CoreWriterRecord>>,
+    bridge_transfers: Option<Vec<BridgeTransfer>>,
+    failures: Option<Vec<TxFailure>>,
+    slow_txs: Option<(Duration, Vec<SlowTx>)>,
+    fees: Option<FeeTotals>,
+    // Empty when re-executing for checks, so hooks only see each block once
+    hooks: &'a [Arc<dyn SyncHooks>],
+}
+
+pub struct RunConfig {
+    pub chain: Chain,
+    pub spec: ChainSpec,
+    pub erc20_contract_to_system_address: BTreeMap<Address, Address>,
+    pub precompiles: Box<dyn PrecompileProvider>,
+    pub verify_receipts_root: bool,
+    pub snapshot_dir: Option<PathBuf>,
+    pub snapshot_format: SnapshotFormat,
+    pub chunk_size: u64,
+    pub notifier: Option<Notifier>,
+    pub uploader: Option<SnapshotUploader>,
+    pub status: Arc<SyncStatus>,
+    pub controller: Option<Controller>,
+    pub events: Option<SyncEvents>,
+    pub witness_dir: Option<PathBuf>,
+    // Keep every block's changes so historical states can be rebuilt
+    pub archive_dir: Option<PathBuf>,
+    pub corewriter_log: Option<JsonLinesLog>,
+    pub system_tx_log: Option<JsonLinesLog>,
+    pub failure_log: Option<JsonLinesLog>,
+    pub slow_tx_log: Option<SlowTxLog>,
+    pub fee_ledger: Option<FeeLedger>,
+    pub revert_decoder: RevertDecoder,
+    pub hash_journal: Option<HashJournal>,
+    pub expected_hashes: BTreeMap<u64, StateHash>,
+    // Also hash the state every N blocks between checkpoints
+    pub hash_every: Option<u64>,
+    // Extra checkpoints besides every chunk_size blocks
+    pub snapshot_at: BTreeSet<u64>,
+    // Attach each block's state diff to its block event
+    pub record_state_diffs: bool,
+    // Re-execute every Nth chunk on a copy of the state and compare the results
+    pub self_check: Option<u64>,
+    pub hooks: Vec<Arc<dyn SyncHooks>>,
+    // Counterfactual changes for what-if replays, which are never snapshotted
+    pub overrides: Option<ReplayOverrides>,
+    pub balance_tracker: Option<BalanceTracker>,
+    // Approximate replays only execute the transactions involving these contracts
+    pub partial: Option<Arc<PartialScope>>,
+    pub recovery_journal: Option<RecoveryJournal>,
+    pub replicator: Option<Replicator>,
+    pub result_cache: Option<ResultCache>,
+    pub manifest: Option<SyncManifest>,
+    #[cfg(unix)]
+    pub systemd: Option<Arc<SystemdNotifier>>,
+    // Embedded in the snapshots written, along with where the map came from
+    pub token_map: Option<TokenMap>,
+}
+
+impl RunConfig {
+    pub fn new(chain: Chain, erc20_contract_to_system_address: BTreeMap<Address, Address>) -> Self {
+        Self {
+            chain,
+            spec: ChainSpec::for_chain(chain),
+            erc20_contract_to_system_address,
+            precompiles: Box::new(BlockFilePrecompiles),
+            verify_receipts_root: false,
+            snapshot_dir: None,
+            snapshot_format: SnapshotFormat::Rmp,
+            chunk_size: CHUNK_SIZE,
+            notifier: None,
+            uploader: None,
+            status: Arc::new(SyncStatus::default()),
+            controller: None,
+            events: None,
+            witness_dir: None,
+            archive_dir: None,
+            corewriter_log: None,
+            system_tx_log: None,
+            failure_log: None,
+            slow_tx_log: None,
+            fee_ledger: None,
+            revert_decoder: RevertDecoder::default(),
+            hash_journal: None,
+            expected_hashes: BTreeMap::new(),
+            hash_every: None,
+            snapshot_at: BTreeSet::new(),
+            record_state_diffs: false,
+            self_check: None,
+            hooks: Vec::new(),
+            overrides: None,
+            balance_tracker: None,
+            partial: None,
+            recovery_journal: None,
+            replicator: None,
+            result_cache: None,
+            manifest: None,
+            #[cfg(unix)]
+            systemd: None,
+            token_map: None,
+        }
+    }
+
+    // Whether blocks execute as recorded, so their receipts can be checked and the state snapshotted
+    fn is_canonical(&self) -> bool {
+        self.overrides.is_none() && self.partial.is_none()
+    }
+}
+
+pub(crate) fn write_snapshot<S>(config: &RunConfig, state: &S, block_num: u64) -> Result<PathBuf>
+where
+    S: State + Into<EvmState> + Clone,
+{
+    let snapshot_dir = config.snapshot_dir.as_ref().ok_or_else(|| anyhow!("No snapshot dir configured"))?;
+    if !config.is_canonical() {
+        return Err(anyhow!("Not snapshotting the non-canonical state of a replay with overrides or a partial state"));
+    }
+    let path = snapshot_dir.join(format!("{block_num}.rmp"));
+    write_snapshot_as(
+        block_num + 1,
+        &state.clone().into(),
+        &path,
+        config.snapshot_format,
+        Some(config.chain),
+        config.token_map.as_ref(),
+    )?;
+    config.status.last_snapshot_block.store(block_num, Ordering::Relaxed);
+    if let Some(manifest) = &config.manifest {
+        manifest.record_snapshot(block_num)?;
+    }
+    if let Some(notifier) = &config.notifier {
+        notifier.notify(Notification::SnapshotWritten { block: block_num, path: path.display().to_string() });
+    }
+    if let Some(uploader) = &config.uploader {
+        uploader.upload(block_num, path.clone());
+    }
+    send_event(config, SyncEvent::SnapshotWritten { block: block_num, path: path.display().to_string() });
+    Ok(path)
+}
+
+fn send_event(config: &RunConfig, event: SyncEvent) {
+    if let Some(events) = &config.events {
+        // Sending only fails when nobody is subscribed
+        let _ = events.send(event);
+    }
+}
+
+fn report_error(config: &RunConfig, block_num: u64, error: String) {
+    println!("{error}");
+    config.status.record_error(error.clone());
+    send_event(config, SyncEvent::Error { block: block_num, error });
+}
+
+// Called once the state has been rolled back to the snapshot taken at `rolled_back_to`
+pub fn report_reorg(config: &RunConfig, fork_block: u64, rolled_back_to: u64) {
+    println!("Reorg after block {fork_block}: rolled back to the snapshot at block {rolled_back_to}");
+    config.status.current_block.store(rolled_back_to, Ordering::Relaxed);
+    if let Some(replicator) = &config.replicator {
+        replicator.discard_pending();
+    }
+    if let Some(cache) = &config.result_cache {
+        cache.stop();
+    }
+    if let Some(notifier) = &config.notifier {
+        notifier.notify(Notification::Reorg { fork_block, rolled_back_to });
+    }
+    send_event(config, SyncEvent::Reorg { fork_block, rolled_back_to });
+}
+
+pub(crate) fn handle_control_requests<S>(config: &RunConfig, state: &S, block_num: u64, timings: &mut StageTimings)
+where
+    S: State + Into<EvmState> + Clone,
+{
+    let Some(controller) = &config.controller else {
+        return;
+    };
+    // A paused sync is not wedged, so it keeps the systemd watchdog fed
+    let keepalive = || {
+        #[cfg(unix)]
+        if let Some(systemd) = &config.systemd {
+            systemd.heartbeat(block_num);
+        }
+    };
+    while let Some(request) = controller.next_request(&config.status, keepalive) {
+        match request {
+            ControlRequest::Snapshot { reply } => {
+                let start = Instant::now();
+                let res = write_snapshot(config, state, block_num);
+                timings.snapshot += start.elapsed();
+                let _ = reply.send(res.map(|path| (block_num, path.display().to_string())));
+            }
+            ControlRequest::Account { address, reply } => {
+                let _ = reply.send((block_num, state.basic_ref(address).ok().flatten()));
+            }
+            ControlRequest::Storage { address, slot, reply } => {
+                let _ = reply.send((block_num, state.storage_ref(address, slot).unwrap_or_default()));
+            }
+        }
+    }
+}
+
+// Fails if re-executing `blocks` on `replica`, a copy of the state from before the chunk, does not reach `state`
+fn self_check<S>(config: &RunConfig, state: &S, mut replica: S, blocks: Vec<PreprocessedBlock>) -> Result<()>
+where
+    S: State,
+    <S as Database>::Error: std::fmt::Debug,
+{
+    let start = Instant::now();
+    let first_block = blocks.first().unwrap().block_num;
+    let last_block = blocks.last().unwrap().block_num;
+    let mut timings = StageTimings::default();
+    let mut inspector = ReplayInspector::default();
+    for PreprocessedBlock { block_and_receipts, signers, .. } in blocks {
+        let mut recorders = BlockRecorders::default();
+        process_block(config, &mut replica, block_and_receipts, signers, &mut timings, &mut inspector, &mut recorders)?;
+    }
+    if state.blake3_hash_slow() != replica.blake3_hash_slow() {
+        return Err(anyhow!("Self-check failed: re-executing blocks {first_block}-{last_block} diverged"))
+            .context(ErrorKind::HashMismatch);
+    }
+    println!("Self-check of blocks {first_block}-{last_block} passed in {:?}", start.elapsed());
+    Ok(())
+}
+
+// Re-executes a block against only the state in its witness, checking its receipts and that it makes the same state
+// changes as when the witness was recorded
+pub fn verify_block(config: &RunConfig, mut witness: BlockWitness, block: PreprocessedBlock) -> Result<StateDiff> {
+    let block_num = block.block_num;
+    if witness.block_number != block_num {
+        return Err(anyhow!("Witness is for block {}, not {block_num}", witness.block_number));
+    }
+    let expected = std::mem::take(&mut witness.post_state);
+    let mut state = witness.into_state();
+    let mut recorders = BlockRecorders { state_diff: Some(StateDiff::default()), ..BlockRecorders::default() };
+    let PreprocessedBlock { block_and_receipts, signers, .. } = block;
+    process_block(
+        config,
+        &mut state,
+        block_and_receipts,
+        signers,
+        &mut StageTimings::default(),
+        &mut ReplayInspector::default(),
+        &mut recorders,
+    )?;
+    let state_diff = recorders.state_diff.unwrap_or_default();
+    if state_diff != expected {
+        return Err(anyhow!("Block {block_num} state changes do not match the witness"));
+    }
+    Ok(state_diff)
+}
+
+pub(crate) fn check_expected_hash(block_num: u64, expected: &StateHash, computed: &StateHash) -> Result<()> {
+    if expected == computed {
+        println!("State hash after block={block_num} matches the expected hash");
+        return Ok(());
+    }
+    let mismatched: Vec<_> = [
+        ("accounts", expected.accounts_hash, computed.accounts_hash),
+        ("contracts", expected.contracts_hash, computed.contracts_hash),
+        ("storage", expected.storage_hash, computed.storage_hash),
+        ("block_hashes", expected.block_hashes_hash, computed.block_hashes_hash),
+    ]
+    .into_iter()
+    .filter(|(_, expected, computed)| expected != computed)
+    .map(|(name, expected, computed)| format!("{name}: expected {expected}, computed {computed}"))
+    .collect();
+    Err(anyhow!("State hash mismatch after block={block_num}\n{}", mismatched.join("\n")))
+        .context(ErrorKind::HashMismatch)
 }
 
-#[allow(clippy::type_complexity)]
 pub fn run_blocks<S>(
-    pb: Option<ProgressBar>,
-    chain: Chain,
+    mut progress: Option<&mut SyncProgress>,
+    config: &RunConfig,
     state: &mut S,
     blocks: Vec<(u64, Vec<PreprocessedBlock>)>,
-    erc20_contract_to_system_address: &BTreeMap<Address, Address>,
-    snapshot_dir: Option<String>,
-    chunk_size: u64,
-) -> StateHash
+    timings: &mut StageTimings,
+    inspector: &mut ReplayInspector,
+) -> Result<Option<StateHash>>
 where
     S: State + Into<EvmState> + Clone,
     <S as Database>::Error: std::fmt::Debug,
+    <S as DatabaseRef>::Error: std::fmt::Debug,
 {
     let start_block = blocks.first().unwrap().1.first().unwrap().block_num;
     let end_block = blocks.last().unwrap().1.last().unwrap().block_num;
+    let final_block = config.status.end_block();
     let start = Instant::now();
     let mut state_hash = None;
     for (i, chunk) in blocks {
         println!("{i}");
         let start = Instant::now();
         let chunk_len = chunk.len();
+        let execute_span = telemetry::stage_span("execute", i, None);
+        let replica = config
+            .self_check
+            .filter(|every| (i / config.chunk_size) % every == 0)
+            .map(|_| (state.clone(), chunk.clone()));
         for PreprocessedBlock { block_num, block_and_receipts, signers } in chunk {
-            if let Some(pb) = pb.as_ref() {
-                pb.inc(1)
-            }
             let BlockAndReceipts { block: EvmBlock::Reth115(block), .. } = &block_and_receipts;
             assert_eq!(block_num, block.number);
-            process_block(chain, state, erc20_contract_to_system_address, block_and_receipts, signers);
-            if block_num % chunk_size == 0 || block_num == end_block {
+            let transactions = block.body().transactions.len() + block_and_receipts.system_txs.len();
+            config.status.record_block(transactions, block.header().gas_used, block.header().timestamp);
+            if let Some(progress) = progress.as_deref_mut() {
+                progress.record_block(transactions, block.header().gas_used, block.header().timestamp);
+            }
+            let summary =
+                config.events.is_some().then(|| BlockSummary::new(block, block_and_receipts.system_txs.len()));
+            let block_hash = block.hash();
+            let record_state_diff = config.witness_dir.is_some()
+                || config.archive_dir.is_some()
+                || (config.events.is_some() && config.record_state_diffs)
+                || !config.hooks.is_empty()
+                || config.balance_tracker.is_some()
+                || config.recovery_journal.is_some()
+                || config.replicator.is_some()
+                || config.result_cache.is_some();
+            let mut recorders = BlockRecorders {
+                state_diff: record_state_diff.then(StateDiff::default),
+                witness: config.witness_dir.is_some().then(|| BlockWitness::new(block_num)),
+                corewriter_actions: config.corewriter_log.is_some().then(Vec::new),
+                bridge_transfers: config.system_tx_log.is_some().then(Vec::new),
+                failures: config.failure_log.is_some().then(Vec::new),
+                slow_txs: config.slow_tx_log.as_ref().map(|slow_tx_log| (slow_tx_log.threshold, Vec::new())),
+                fees: config.fee_ledger.is_some().then(FeeTotals::default),
+                hooks: &config.hooks,
+            };
+            let receipts =
+                process_block(config, state, block_and_receipts, signers, timings, inspector, &mut recorders)?;
+            config.status.current_block.store(block_num, Ordering::Relaxed);
+            #[cfg(unix)]
+            if let Some(systemd) = &config.systemd {
+                systemd.heartbeat(block_num);
+            }
+            let BlockRecorders {
+                state_diff,
+                witness,
+                corewriter_actions,
+                bridge_transfers,
+                failures,
+                slow_txs,
+                fees,
+                ..
+            } = recorders;
+            if let (Some(ledger), Some(fees)) = (&config.fee_ledger, fees) {
+                ledger.record_block(block_num, fees);
+            }
+            if let (Some(log), Some(actions)) = (&config.corewriter_log, corewriter_actions) {
+                if let Err(e) = log.write(&actions) {
+                    report_error(config, block_num, format!("CoreWriter log {block_num} failed: {e}"));
+                }
+            }
+            if let (Some(log), Some(transfers)) = (&config.system_tx_log, bridge_transfers) {
+                if let Err(e) = log.write(&transfers) {
+                    report_error(config, block_num, format!("System transaction log {block_num} failed: {e}"));
+                }
+            }
+            if let (Some(log), Some(failures)) = (&config.failure_log, failures) {
+                if let Err(e) = log.write(&failures) {
+                    report_error(config, block_num, format!("Failure log {block_num} failed: {e}"));
+                }
+            }
+            if let (Some(slow_tx_log), Some((_, slow_txs))) = (&config.slow_tx_log, slow_txs) {
+                if let Err(e) = slow_tx_log.log.write(&slow_txs) {
+                    report_error(config, block_num, format!("Slow transaction log {block_num} failed: {e}"));
+                }
+            }
+            if let (Some(tracker), Some(state_diff)) = (&config.balance_tracker, &state_diff) {
+                if let Err(e) = tracker.record(state, block_num, state_diff) {
+                    report_error(config, block_num, format!("Balance tracking {block_num} failed: {e}"));
+                }
+            }
+            if let (Some(dir), Some(mut witness)) = (&config.witness_dir, witness) {
+                witness.post_state = state_diff.clone().unwrap_or_default();
+                if let Err(e) = write_witness(dir, &witness) {
+                    report_error(config, block_num, format!("Witness {block_num} failed: {e}"));
+                }
+            }
+            let write_entry = config.archive_dir.is_some()
+                || config.recovery_journal.is_some()
+                || config.replicator.is_some()
+                || config.result_cache.is_some();
+            let mut cache_entry = None;
+            if let Some(state_diff) = state_diff.as_ref().filter(|_| write_entry) {
+                let entry = ArchiveEntry { block_number: block_num, block_hash, state_diff: state_diff.clone() };
+                if let Some(dir) = &config.archive_dir {
+                    if let Err(e) = write_archive_entry(dir, &entry) {
+                        report_error(config, block_num, format!("Archive entry {block_num} failed: {e}"));
+                    }
+                }
+                if let Some(journal) = &config.recovery_journal {
+                    if let Err(e) = journal.append(&entry) {
+                        report_error(config, block_num, format!("Recovery journal {block_num} failed: {e}"));
+                    }
+                }
+                if config.result_cache.is_some() {
+                    cache_entry = Some(entry.clone());
+                }
+                if let Some(replicator) = &config.replicator {
+                    replicator.record(entry);
+                }
+            }
+            if let Some(summary) = summary {
+                let state_diff = state_diff.filter(|_| config.record_state_diffs);
+                send_event(config, SyncEvent::BlockExecuted(Arc::new(ExecutedBlock { summary, state_diff })));
+            }
+            let checkpoint = block_num % config.chunk_size == 0
+                || block_num == final_block
+                || config.snapshot_at.contains(&block_num);
+            let mut block_state_hash = None;
+            if checkpoint || config.hash_every.is_some_and(|every| block_num % every == 0) {
                 let start = Instant::now();
+                let hash_span = telemetry::stage_span("hash", i, Some(block_num));
                 let hash = state.blake3_hash_slow();
+                drop(hash_span);
+                timings.hashing += start.elapsed();
                 println!("Computed state hash after block={block_num}: {hash:?} in {:?}", start.elapsed());
-                if let Some(snapshot_dir) = &snapshot_dir {
-                    match snapshot_evm_state(
-                        block_num + 1,
-                        &state.clone().into(),
-                        format!("{snapshot_dir}/{block_num}.rmp"),
-                    ) {
-                        Ok(()) => println!("Snapshot {block_num} succeeded"),
-                        Err(e) => println!("Snapshot {block_num} failed: {e}"),
+                send_event(config, SyncEvent::HashComputed { block: block_num, state_hash: hash.clone() });
+                if let Some(expected) = config.expected_hashes.get(&block_num) {
+                    check_expected_hash(block_num, expected, &hash)?;
+                }
+                if let Some(journal) = &config.hash_journal {
+                    if let Err(e) = journal.record(block_num, &hash) {
+                        report_error(config, block_num, format!("Hash journal {block_num} failed: {e}"));
                     }
                 }
+                if let Some(replicator) = config.replicator.as_ref().filter(|_| checkpoint) {
+                    replicator.ship(&hash);
+                }
+                if checkpoint && config.snapshot_dir.is_some() {
+                    let start = Instant::now();
+                    let _span = telemetry::stage_span("snapshot", i, Some(block_num));
+                    match write_snapshot(config, state, block_num) {
+                        Ok(_) => {
+                            println!("Snapshot {block_num} succeeded");
+                            if let Some(Err(e)) = config.recovery_journal.as_ref().map(RecoveryJournal::truncate) {
+                                report_error(config, block_num, format!("Recovery journal {block_num} failed: {e}"));
+                            }
+                        }
+                        Err(e) => report_error(config, block_num, format!("Snapshot {block_num} failed: {e}")),
+                    }
+                    timings.snapshot += start.elapsed();
+                }
+                block_state_hash = Some(hash.clone());
                 state_hash = Some(hash);
             }
+            if let (Some(cache), Some(entry)) = (&config.result_cache, cache_entry) {
+                if let Err(e) = cache.record(entry, &receipts, block_state_hash) {
+                    report_error(config, block_num, format!("Result cache {block_num} failed: {e}"));
+                }
+            }
+            handle_control_requests(config, state, block_num, timings);
+        }
+        if let Some((replica, chunk)) = replica {
+            self_check(config, state, replica, chunk)?;
         }
+        drop(execute_span);
+        telemetry::finish_chunk(i);
         println!("Processed blocks {}-{} in {:?}", i, i + (chunk_len as u64 - 1), start.elapsed());
     }
     println!("Processed n={} blocks in {:?}", end_block - start_block + 1, start.elapsed());
-    state_hash.unwrap()
+    Ok(state_hash)
 }
 
 pub const MAINNET_CHAIN_ID: u64 = 999;
@@ -332,13 +1066,9 @@ pub const fn chain_id(chain: Chain) -> u64 {
     }
 }
 
-const NATIVE_TOKEN_SYSTEM_ADDRESS: Address = address!("0x2222222222222222222222222222222222222222");
-const CORE_WRITER_ADDRESS: Address = address!("0x3333333333333333333333333333333333333333");
+pub(crate) const NATIVE_TOKEN_SYSTEM_ADDRESS: Address = address!("0x2222222222222222222222222222222222222222");
+pub(crate) const CORE_WRITER_ADDRESS: Address = address!("0x3333333333333333333333333333333333333333");
 const WHYPE_CONTRACT_ADDRESS: Address = address!("0x5555555555555555555555555555555555555555");
-const NON_PLACEHOLDER_BLOCK_HASH_HEIGHT: u64 = 243_538;
-const CORE_WRITER_DEPLOY_BLOCK_NUMBER: u64 = 7_578_299;
-// This is the first block in which the behavior diverges with the hl node due to warm addresses
-const WARM_PRECOMPILES_BLOCK_NUMBER: u64 = 8_197_684;
 
 #[allow(clippy::cast_possible_truncation)] // len(s) <= 31
 const fn encode_short_string(s: &str) -> U256 {
@@ -0,0 +1,165 @@
+use crate::fs::{block_file_number, BlockSource};
+use anyhow::Result;
+use axum::{
+    body::{Body, Bytes},
+    extract::{Path, Query, State},
+    http::StatusCode,
+    routing::get,
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+use tokio::net::TcpListener;
+use tokio_util::io::ReaderStream;
+
+// Clients are expected to page through longer ranges
+const MAX_RANGE_BLOCKS: u64 = 1000;
+
+type ServeResult = Result<Bytes, (StatusCode, String)>;
+
+fn internal(e: impl std::fmt::Display) -> (StatusCode, String) {
+    (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+}
+
+// `/{key}` with the mirrors' key of a block, e.g. /4000000/4000000/4000001.rmp.lz4, whatever the local layout
+async fn block(State(source): State<Arc<BlockSource>>, Path(key): Path<String>) -> ServeResult {
+    let block_num = block_file_number(std::path::Path::new(&key))
+        .filter(|_| key.ends_with(".rmp.lz4"))
+        .ok_or_else(|| (StatusCode::NOT_FOUND, format!("{key} is not a block file")))?;
+    let file = tokio::task::spawn_blocking(move || source.published_block_file(block_num))
+        .await
+        .map_err(internal)?
+        .map_err(internal)?;
+    file.map(Bytes::from).ok_or_else(|| (StatusCode::NOT_FOUND, format!("no block {block_num}")))
+}
+
+#[derive(Deserialize)]
+struct RangeQuery {
+    start: u64,
+    end: u64,
+}
+
+// Blocks `start..=end` as records of a little-endian u64 block number, a u32 length, and the lz4 block file, up to
+// the first missing block
+async fn range(
+    State(source): State<Arc<BlockSource>>,
+    Query(RangeQuery { start, end }): Query<RangeQuery>,
+) -> ServeResult {
+    if end < start || end - start >= MAX_RANGE_BLOCKS {
+        return Err((StatusCode::BAD_REQUEST, format!("ranges are limited to {MAX_RANGE_BLOCKS} blocks")));
+    }
+    let body = tokio::task::spawn_blocking(move || {
+        let mut body = Vec::new();
+        for block_num in start..=end {
+            let Some(file) = source.published_block_file(block_num)? else {
+                break;
+            };
+            body.extend_from_slice(&block_num.to_le_bytes());
+            body.extend_from_slice(&u32::try_from(file.len())?.to_le_bytes());
+            body.extend_from_slice(&file);
+        }
+        anyhow::Ok(body)
+    })
+    .await
+    .map_err(internal)?
+    .map_err(internal)?;
+    Ok(Bytes::from(body))
+}
+
+// Serves a blocks directory the way the mirrors do, so other instances can pass `--mirror http://{addr}`
+pub async fn serve_blocks(addr: SocketAddr, source: BlockSource) -> Result<()> {
+    println!("Serving blocks on {addr}");
+    let app = Router::new().route("/range", get(range)).route("/*key", get(block)).with_state(Arc::new(source));
+    let listener = TcpListener::bind(addr).await?;
+    axum::serve(listener, app).await?;
+    Ok(())
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct SnapshotEntry {
+    // The snapshot holds the state after this block
+    block: u64,
+    file: String,
+    size: u64,
+    // Of the file contents, for --fln-blake3
+    blake3: String,
+}
+
+#[derive(Clone)]
+struct SnapshotState {
+    dir: PathBuf,
+    // Hashing a snapshot reads all of it, so hashes are kept until the file's size or modification time changes
+    hashes: Arc<Mutex<HashMap<String, (u64, SystemTime, String)>>>,
+}
+
+impl SnapshotState {
+    // The `{block}.rmp` files that the sync writes; anything else in the directory is not served
+    fn snapshot_files(&self) -> Result<Vec<(u64, String)>> {
+        let mut files = Vec::new();
+        for entry in std::fs::read_dir(&self.dir)? {
+            let name = entry?.file_name().to_string_lossy().into_owned();
+            if let Some(block) = name.strip_suffix(".rmp").and_then(|block| block.parse().ok()) {
+                files.push((block, name));
+            }
+        }
+        files.sort();
+        Ok(files)
+    }
+
+    fn entry(&self, block: u64, file: String) -> Result<SnapshotEntry> {
+        let path = self.dir.join(&file);
+        let metadata = std::fs::metadata(&path)?;
+        let (size, modified) = (metadata.len(), metadata.modified()?);
+        let cached = self.hashes.lock().unwrap().get(&file).cloned();
+        let blake3 = match cached {
+            Some((cached_size, cached_modified, hash)) if (cached_size, cached_modified) == (size, modified) => hash,
+            _ => {
+                let mut hasher = blake3::Hasher::new();
+                hasher.update_reader(std::fs::File::open(&path)?)?;
+                let hash = hasher.finalize().to_hex().to_string();
+                self.hashes.lock().unwrap().insert(file.clone(), (size, modified, hash.clone()));
+                hash
+            }
+        };
+        Ok(SnapshotEntry { block, file, size, blake3 })
+    }
+}
+
+async fn snapshot_index(State(state): State<SnapshotState>) -> Result<Json<Vec<SnapshotEntry>>, (StatusCode, String)> {
+    let entries = tokio::task::spawn_blocking(move || {
+        state.snapshot_files()?.into_iter().map(|(block, file)| state.entry(block, file)).collect::<Result<Vec<_>>>()
+    })
+    .await
+    .map_err(internal)?
+    .map_err(internal)?;
+    Ok(Json(entries))
+}
+
+async fn snapshot_file(
+    State(state): State<SnapshotState>,
+    Path(name): Path<String>,
+) -> Result<Body, (StatusCode, String)> {
+    let files = state.snapshot_files().map_err(internal)?;
+    if !files.iter().any(|(_, file)| *file == name) {
+        return Err((StatusCode::NOT_FOUND, format!("no snapshot {name}")));
+    }
+    let file = tokio::fs::File::open(state.dir.join(&name)).await.map_err(internal)?;
+    Ok(Body::from_stream(ReaderStream::new(file)))
+}
+
+// Publishes a snapshot directory for `-f http://{addr}/{block}.rmp`, with GET /index.json listing every snapshot
+pub async fn serve_snapshots(addr: SocketAddr, dir: PathBuf) -> Result<()> {
+    println!("Serving the snapshots in {} on {addr}", dir.display());
+    let state = SnapshotState { dir, hashes: Arc::default() };
+    let app =
+        Router::new().route("/index.json", get(snapshot_index)).route("/:name", get(snapshot_file)).with_state(state);
+    let listener = TcpListener::bind(addr).await?;
+    axum::serve(listener, app).await?;
+    Ok(())
+}
@@ -0,0 +1,328 @@
+use crate::{
+    bench::StageTimings,
+    diff::{AccountFields, StateDiff},
+    fs::{read_blocks, BlockSource},
+    hooks::{ExecutedTx, SyncHooks},
+    inspector::ReplayInspector,
+    run::{run_blocks, RunConfig},
+    state::{State, StateHash},
+    status::SyncStatus,
+};
+use alloy::{
+    consensus::Transaction as _,
+    primitives::{hex, keccak256, Address, Bytes, U256},
+};
+use anyhow::{anyhow, Result};
+use itertools::Itertools;
+use reth_primitives::{SealedBlock, Transaction};
+use revm::{primitives::ExecutionResult, DatabaseRef, InMemoryDB};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Display,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+// Entries of each kind shown in the text report; the JSON report has all of them
+const SHOWN_DIFFERENCES: usize = 20;
+
+// Counterfactual changes to the environment blocks execute in. Receipts are not checked against the recorded ones
+// while any are set.
+#[derive(Debug, Clone, Default)]
+pub struct ReplayOverrides {
+    pub gas_limit: Option<u64>,
+    pub disable_basefee: bool,
+    pub coinbase: Option<Address>,
+    // Bytecode to run at these addresses instead of their own, put back at the start of every block in case the
+    // range redeploys them
+    pub code: BTreeMap<Address, Bytes>,
+    // Transactions matching any of these are left out
+    pub skip: Vec<TxFilter>,
+}
+
+// Matches transactions on every field that is set
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TxFilter {
+    pub sender: Option<Address>,
+    pub to: Option<Address>,
+    pub selector: Option<[u8; 4]>,
+}
+
+impl TxFilter {
+    pub fn matches(&self, sender: Address, transaction: &Transaction) -> bool {
+        self.sender.is_none_or(|filter| filter == sender)
+            && self.to.is_none_or(|filter| transaction.to() == Some(filter))
+            && self.selector.is_none_or(|filter| transaction.input().get(..4) == Some(filter.as_slice()))
+    }
+}
+
+impl FromStr for TxFilter {
+    type Err = anyhow::Error;
+
+    // e.g. sender=0x...,to=0x...,selector=0xa9059cbb
+    fn from_str(s: &str) -> Result<Self> {
+        let mut filter = Self::default();
+        for field in s.split(',') {
+            let (key, value) = field.split_once('=').ok_or_else(|| anyhow!("expected KEY=VALUE, got {field}"))?;
+            match key.trim() {
+                "sender" | "from" => filter.sender = Some(value.trim().parse()?),
+                "to" => filter.to = Some(value.trim().parse()?),
+                "selector" => {
+                    let selector = hex::decode(value.trim())?;
+                    filter.selector =
+                        Some(selector.try_into().map_err(|_| anyhow!("selector {value} is not 4 bytes"))?);
+                }
+                _ => return Err(anyhow!("unknown filter field {key}: expected sender, to, or selector")),
+            }
+        }
+        if filter == Self::default() {
+            return Err(anyhow!("empty transaction filter"));
+        }
+        Ok(filter)
+    }
+}
+
+impl ReplayOverrides {
+    pub(crate) fn skips(&self, sender: Address, transaction: &Transaction) -> bool {
+        self.skip.iter().any(|filter| filter.matches(sender, transaction))
+    }
+
+    pub(crate) fn apply_code<S: State>(&self, state: &mut S) {
+        for (&address, code) in &self.code {
+            let current = state.basic_ref(address).ok().flatten().map(|info| info.code_hash);
+            if current != Some(keccak256(code)) {
+                state.replace_code(address, code.clone());
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct TxOutcome {
+    pub success: bool,
+    pub gas_used: u64,
+    pub logs: usize,
+}
+
+impl From<&ExecutionResult> for TxOutcome {
+    fn from(result: &ExecutionResult) -> Self {
+        Self { success: result.is_success(), gas_used: result.gas_used(), logs: result.logs().len() }
+    }
+}
+
+// Records what a replay did, for comparing one replay against another
+#[derive(Default)]
+struct Outcomes {
+    txs: Mutex<BTreeMap<(u64, bool, usize), TxOutcome>>,
+    touched: Mutex<BTreeMap<Address, BTreeSet<U256>>>,
+}
+
+impl SyncHooks for Outcomes {
+    fn on_tx_executed(&self, tx: &ExecutedTx<'_>) {
+        let key = (tx.block.number, tx.is_system_tx, tx.tx_index);
+        self.txs.lock().unwrap().insert(key, tx.result.into());
+    }
+
+    fn on_block_committed(&self, _block: &SealedBlock, diff: &StateDiff) {
+        let mut touched = self.touched.lock().unwrap();
+        for (&address, account) in &diff.accounts {
+            touched.entry(address).or_default().extend(account.storage.keys());
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct TxDifference {
+    pub block: u64,
+    pub is_system_tx: bool,
+    pub tx_index: usize,
+    // None when the transaction was not executed in that replay
+    pub canonical: Option<TxOutcome>,
+    pub simulated: Option<TxOutcome>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SlotDifference {
+    pub slot: U256,
+    pub canonical: U256,
+    pub simulated: U256,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AccountDifference {
+    pub address: Address,
+    pub canonical: Option<AccountFields>,
+    pub simulated: Option<AccountFields>,
+    pub storage: Vec<SlotDifference>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SimulationReport {
+    pub start_block: u64,
+    pub end_block: u64,
+    // The simulated replay ran patched code at these addresses
+    pub code_overrides: Vec<Address>,
+    pub canonical_state_hash: Option<StateHash>,
+    pub simulated_state_hash: Option<StateHash>,
+    pub transactions: Vec<TxDifference>,
+    pub accounts: Vec<AccountDifference>,
+}
+
+impl Display for SimulationReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Simulated blocks {}-{}", self.start_block, self.end_block)?;
+        if !self.code_overrides.is_empty() {
+            writeln!(f, "Non-canonical: patched code at {}", self.code_overrides.iter().join(", "))?;
+        }
+        if self.transactions.is_empty() && self.accounts.is_empty() {
+            return write!(f, "The simulated replay reached the same state as the canonical one");
+        }
+        writeln!(f, "{} transactions had a different outcome:", self.transactions.len())?;
+        let outcome = |outcome: &Option<TxOutcome>| match outcome {
+            Some(TxOutcome { success, gas_used, logs }) => {
+                format!("{} gas={gas_used} logs={logs}", if *success { "success" } else { "failed" })
+            }
+            None => "skipped".to_owned(),
+        };
+        for tx in self.transactions.iter().take(SHOWN_DIFFERENCES) {
+            let kind = if tx.is_system_tx { "system tx" } else { "tx" };
+            writeln!(
+                f,
+                "  block {} {kind} {}: {} -> {}",
+                tx.block,
+                tx.tx_index,
+                outcome(&tx.canonical),
+                outcome(&tx.simulated)
+            )?;
+        }
+        write!(f, "{} accounts ended up different:", self.accounts.len())?;
+        let fields = |fields: &Option<AccountFields>| match fields {
+            Some(AccountFields { balance, nonce, .. }) => format!("balance={balance} nonce={nonce}"),
+            None => "absent".to_owned(),
+        };
+        for account in self.accounts.iter().take(SHOWN_DIFFERENCES) {
+            write!(f, "\n  {}: {} -> {}", account.address, fields(&account.canonical), fields(&account.simulated))?;
+            if !account.storage.is_empty() {
+                write!(f, ", {} storage slots differ", account.storage.len())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn account_fields(state: &InMemoryDB, address: Address) -> Option<AccountFields> {
+    state.basic_ref(address).ok().flatten().filter(|info| !info.is_empty()).map(|info| (&info).into())
+}
+
+fn compare(
+    canonical: (&InMemoryDB, &Outcomes),
+    simulated: (&InMemoryDB, &Outcomes),
+) -> (Vec<TxDifference>, Vec<AccountDifference>) {
+    let (canonical_txs, simulated_txs) = (canonical.1.txs.lock().unwrap(), simulated.1.txs.lock().unwrap());
+    let keys: BTreeSet<_> = canonical_txs.keys().chain(simulated_txs.keys()).collect();
+    let transactions = keys
+        .into_iter()
+        .filter_map(|key| {
+            let (canonical, simulated) = (canonical_txs.get(key).copied(), simulated_txs.get(key).copied());
+            let &(block, is_system_tx, tx_index) = key;
+            (canonical != simulated).then_some(TxDifference { block, is_system_tx, tx_index, canonical, simulated })
+        })
+        .collect();
+
+    let mut touched = canonical.1.touched.lock().unwrap().clone();
+    for (address, slots) in simulated.1.touched.lock().unwrap().iter() {
+        touched.entry(*address).or_default().extend(slots);
+    }
+    let (canonical, simulated) = (canonical.0, simulated.0);
+    let accounts = touched
+        .into_iter()
+        .filter_map(|(address, slots)| {
+            let storage: Vec<_> = slots
+                .into_iter()
+                .filter_map(|slot| {
+                    let canonical = canonical.storage_ref(address, slot).unwrap_or_default();
+                    let simulated = simulated.storage_ref(address, slot).unwrap_or_default();
+                    (canonical != simulated).then_some(SlotDifference { slot, canonical, simulated })
+                })
+                .collect();
+            let (canonical, simulated) = (account_fields(canonical, address), account_fields(simulated, address));
+            (canonical != simulated || !storage.is_empty()).then_some(AccountDifference {
+                address,
+                canonical,
+                simulated,
+                storage,
+            })
+        })
+        .collect();
+    (transactions, accounts)
+}
+
+// Replays the range twice from `state`, as recorded and with the simulated config's overrides, on copies of the state
+// that are discarded afterwards, and reports every transaction outcome and account that differs between the two
+pub fn simulate_range(
+    mut canonical: RunConfig,
+    mut simulated: RunConfig,
+    source: &BlockSource,
+    state: InMemoryDB,
+    start_block: u64,
+    end_block: u64,
+    read_limit: u64,
+) -> Result<SimulationReport> {
+    let (canonical_outcomes, simulated_outcomes) = (Arc::new(Outcomes::default()), Arc::new(Outcomes::default()));
+    canonical.hooks.push(canonical_outcomes.clone());
+    simulated.hooks.push(simulated_outcomes.clone());
+    for config in [&mut canonical, &mut simulated] {
+        config.snapshot_dir = None;
+        config.status = Arc::new(SyncStatus::new(start_block, end_block));
+    }
+
+    let (mut canonical_state, mut simulated_state) = (state.clone(), state);
+    let (mut canonical_state_hash, mut simulated_state_hash) = (None, None);
+    let mut timings = StageTimings::default();
+    let mut inspector = ReplayInspector::default();
+    let mut cur_block = start_block;
+    while cur_block <= end_block {
+        let last_block_in_chunk = end_block.min(cur_block + read_limit - 1);
+        let blocks = read_blocks(source, cur_block, last_block_in_chunk, canonical.chunk_size, &mut timings)?;
+        let hash = run_blocks(None, &canonical, &mut canonical_state, blocks.clone(), &mut timings, &mut inspector)?;
+        canonical_state_hash = hash.or(canonical_state_hash);
+        let hash = run_blocks(None, &simulated, &mut simulated_state, blocks, &mut timings, &mut inspector)?;
+        simulated_state_hash = hash.or(simulated_state_hash);
+        cur_block = last_block_in_chunk + 1;
+    }
+
+    let (transactions, accounts) =
+        compare((&canonical_state, &canonical_outcomes), (&simulated_state, &simulated_outcomes));
+    let code_overrides = simulated.overrides.iter().flat_map(|overrides| overrides.code.keys().copied()).collect();
+    Ok(SimulationReport {
+        start_block,
+        end_block,
+        code_overrides,
+        canonical_state_hash,
+        simulated_state_hash,
+        transactions,
+        accounts,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::simulate::TxFilter;
+    use alloy::primitives::address;
+
+    #[test]
+    fn test_parse_tx_filter() {
+        let filter: TxFilter = "to=0x5555555555555555555555555555555555555555,selector=0xa9059cbb".parse().unwrap();
+        assert_eq!(
+            filter,
+            TxFilter {
+                sender: None,
+                to: Some(address!("0x5555555555555555555555555555555555555555")),
+                selector: Some([0xa9, 0x05, 0x9c, 0xbb]),
+            }
+        );
+        assert!("selector=0xa9059c".parse::<TxFilter>().is_err());
+        assert!("nonce=1".parse::<TxFilter>().is_err());
+    }
+}
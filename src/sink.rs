@@ -0,0 +1,337 @@
+use crate::{
+    bus::MessageBus,
+    cli::Chain,
+    diff::StateDiff,
+    hooks::{ExecutedTx, SyncHooks},
+    warehouse::{ClickHouse, Postgres},
+};
+use anyhow::{anyhow, Result};
+use reth_primitives::SealedBlock;
+use revm::primitives::ExecutionResult;
+use serde::Serialize;
+use std::{
+    mem::take,
+    path::Path,
+    sync::{
+        mpsc::{sync_channel, Receiver, RecvTimeoutError, SyncSender},
+        Mutex,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+// Blocks per write, and how long a partial batch waits for more blocks when they arrive slowly (e.g. when following)
+const BATCH_BLOCKS: usize = 100;
+const BATCH_TIMEOUT: Duration = Duration::from_secs(1);
+// Blocks queued for the writer before execution waits for it
+const QUEUE_BLOCKS: usize = 1000;
+const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Serialize)]
+pub struct BlockRow {
+    pub chain: String,
+    pub number: u64,
+    pub hash: String,
+    pub timestamp: u64,
+    pub gas_used: u64,
+    pub transactions: usize,
+    pub system_transactions: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TransactionRow {
+    pub chain: String,
+    pub block: u64,
+    pub tx_index: usize,
+    pub is_system_tx: bool,
+    // System transactions are unsigned and have no hash
+    pub hash: Option<String>,
+    pub sender: String,
+    pub to: Option<String>,
+    // "success", "revert", or "halt"
+    pub status: &'static str,
+    pub gas_used: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LogRow {
+    pub chain: String,
+    pub block: u64,
+    pub tx_index: usize,
+    pub is_system_tx: bool,
+    // Position among all of the block's logs, system transactions first
+    pub log_index: usize,
+    pub address: String,
+    pub topics: Vec<String>,
+    pub data: String,
+}
+
+// Balances are decimal strings, since they do not fit in 64-bit columns
+#[derive(Debug, Serialize)]
+pub struct AccountDiffRow {
+    pub chain: String,
+    pub block: u64,
+    pub address: String,
+    pub balance_before: Option<String>,
+    pub balance_after: Option<String>,
+    pub nonce_before: Option<u64>,
+    pub nonce_after: Option<u64>,
+    pub code_hash_after: Option<String>,
+    pub storage_cleared: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StorageDiffRow {
+    pub chain: String,
+    pub block: u64,
+    pub address: String,
+    pub slot: String,
+    pub before: String,
+    pub after: String,
+}
+
+// Everything recorded for a run of consecutive blocks
+#[derive(Debug, Default)]
+pub struct Batch {
+    pub last_block: u64,
+    pub blocks: Vec<BlockRow>,
+    pub transactions: Vec<TransactionRow>,
+    pub logs: Vec<LogRow>,
+    pub account_diffs: Vec<AccountDiffRow>,
+    pub storage_diffs: Vec<StorageDiffRow>,
+}
+
+impl Batch {
+    fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    fn extend(&mut self, other: Self) {
+        self.last_block = other.last_block;
+        self.blocks.extend(other.blocks);
+        self.transactions.extend(other.transactions);
+        self.logs.extend(other.logs);
+        self.account_diffs.extend(other.account_diffs);
+        self.storage_diffs.extend(other.storage_diffs);
+    }
+
+    // The rows of each table as a JSON array, in the order they should be written
+    pub fn tables(&self) -> Result<Vec<(&'static str, serde_json::Value)>> {
+        Ok(vec![
+            ("blocks", serde_json::to_value(&self.blocks)?),
+            ("transactions", serde_json::to_value(&self.transactions)?),
+            ("logs", serde_json::to_value(&self.logs)?),
+            ("account_diffs", serde_json::to_value(&self.account_diffs)?),
+            ("storage_diffs", serde_json::to_value(&self.storage_diffs)?),
+        ])
+    }
+}
+
+// A destination that stores each batch together with the last block it holds, so a restarted sync resumes after it
+pub trait SinkWriter: Send {
+    fn offset(&mut self, chain: &str) -> Result<Option<u64>>;
+    fn write(&mut self, chain: &str, batch: &Batch) -> Result<()>;
+}
+
+// Message buses keep their offset in a file next to the snapshots, since they cannot be queried for it
+fn connect(chain: &str, url: &str, snapshot_dir: Option<&Path>) -> Result<Box<dyn SinkWriter>> {
+    match url.split_once("://").map(|(scheme, _)| scheme) {
+        Some("postgres" | "postgresql") => Ok(Box::new(Postgres::connect(url)?)),
+        Some("clickhouse") => Ok(Box::new(ClickHouse::connect(url)?)),
+        Some("kafka" | "nats") => {
+            let dir = snapshot_dir.ok_or_else(|| anyhow!("{url} needs --snapshot-dir to keep its resume offset in"))?;
+            let prefix = format!("hyperevm-{}", chain.to_lowercase());
+            Ok(Box::new(MessageBus::connect(url, &prefix, dir)?))
+        }
+        _ => Err(anyhow!("unsupported sink {url}: expected a postgres://, clickhouse://, kafka://, or nats:// URL")),
+    }
+}
+
+// Collects rows as blocks execute and hands each finished block to a writer thread, which batches them. Blocks up to
+// the sink's stored offset were written by an earlier run and are skipped.
+pub struct BlockSink {
+    chain: String,
+    resume_after: Option<u64>,
+    pending: Mutex<Batch>,
+    sender: Option<SyncSender<Batch>>,
+    writer: Option<JoinHandle<()>>,
+}
+
+impl BlockSink {
+    pub fn connect(chain: Chain, url: &str, snapshot_dir: Option<&Path>) -> Result<Self> {
+        let (name, url, snapshot_dir) = (chain.to_string(), url.to_owned(), snapshot_dir.map(Path::to_owned));
+        Self::spawn(chain, move || connect(&name, &url, snapshot_dir.as_deref()))
+    }
+
+    // The writer is opened on its own thread, since database clients block and must stay off the async runtime
+    pub fn spawn<F>(chain: Chain, open: F) -> Result<Self>
+    where
+        F: FnOnce() -> Result<Box<dyn SinkWriter>> + Send + 'static,
+    {
+        let chain = chain.to_string();
+        let (offset_sender, offset_receiver) = sync_channel(1);
+        let (sender, receiver) = sync_channel(QUEUE_BLOCKS);
+        let writer_chain = chain.clone();
+        let writer = std::thread::spawn(move || {
+            match open().and_then(|mut writer| Ok((writer.offset(&writer_chain)?, writer))) {
+                Ok((offset, mut writer)) => {
+                    let _ = offset_sender.send(Ok(offset));
+                    write_batches(&writer_chain, writer.as_mut(), &receiver);
+                }
+                Err(e) => {
+                    let _ = offset_sender.send(Err(e));
+                }
+            }
+        });
+        let resume_after = offset_receiver.recv()??;
+        if let Some(block) = resume_after {
+            println!("Sink already holds blocks up to {block}, which will not be written again");
+        }
+        Ok(Self { chain, resume_after, pending: Mutex::default(), sender: Some(sender), writer: Some(writer) })
+    }
+
+    fn skipped(&self, block_num: u64) -> bool {
+        self.resume_after.is_some_and(|block| block_num <= block)
+    }
+}
+
+fn write_batches(chain: &str, writer: &mut dyn SinkWriter, receiver: &Receiver<Batch>) {
+    let mut batch = Batch::default();
+    let mut batch_start = Instant::now();
+    loop {
+        let received = if batch.is_empty() {
+            receiver.recv().map_err(|_| RecvTimeoutError::Disconnected)
+        } else {
+            receiver.recv_timeout(BATCH_TIMEOUT.saturating_sub(batch_start.elapsed()))
+        };
+        let closed = match received {
+            Ok(block) => {
+                if batch.is_empty() {
+                    batch_start = Instant::now();
+                }
+                batch.extend(block);
+                false
+            }
+            Err(RecvTimeoutError::Timeout) => false,
+            Err(RecvTimeoutError::Disconnected) => true,
+        };
+        let due = batch.len() >= BATCH_BLOCKS || batch_start.elapsed() >= BATCH_TIMEOUT || closed;
+        if !batch.is_empty() && due {
+            // Retried until it succeeds, holding up execution once the queue is full rather than dropping blocks
+            while let Err(e) = writer.write(chain, &batch) {
+                eprintln!("Sink write of blocks up to {} failed, retrying: {e}", batch.last_block);
+                std::thread::sleep(RETRY_INTERVAL);
+            }
+            batch = Batch::default();
+        }
+        if closed {
+            return;
+        }
+    }
+}
+
+impl SyncHooks for BlockSink {
+    fn on_block_start(&self, _block: &SealedBlock) {
+        *self.pending.lock().unwrap() = Batch::default();
+    }
+
+    fn on_tx_executed(&self, tx: &ExecutedTx<'_>) {
+        if self.skipped(tx.block.number) {
+            return;
+        }
+        let mut pending = self.pending.lock().unwrap();
+        let (status, logs) = match tx.result {
+            ExecutionResult::Success { logs, .. } => ("success", logs.as_slice()),
+            ExecutionResult::Revert { .. } => ("revert", [].as_slice()),
+            ExecutionResult::Halt { .. } => ("halt", [].as_slice()),
+        };
+        for log in logs {
+            let log_index = pending.logs.len();
+            pending.logs.push(LogRow {
+                chain: self.chain.clone(),
+                block: tx.block.number,
+                tx_index: tx.tx_index,
+                is_system_tx: tx.is_system_tx,
+                log_index,
+                address: log.address.to_string(),
+                topics: log.topics().iter().map(ToString::to_string).collect(),
+                data: log.data.data.to_string(),
+            });
+        }
+        let hash = (!tx.is_system_tx)
+            .then(|| tx.block.body().transactions.get(tx.tx_index).map(|signed| signed.hash().to_string()))
+            .flatten();
+        pending.transactions.push(TransactionRow {
+            chain: self.chain.clone(),
+            block: tx.block.number,
+            tx_index: tx.tx_index,
+            is_system_tx: tx.is_system_tx,
+            hash,
+            sender: tx.sender.to_string(),
+            to: tx.transaction.to().map(|to| to.to_string()),
+            status,
+            gas_used: tx.result.gas_used(),
+        });
+    }
+
+    fn on_block_committed(&self, block: &SealedBlock, diff: &StateDiff) {
+        if self.skipped(block.number) {
+            return;
+        }
+        let mut batch = take(&mut *self.pending.lock().unwrap());
+        batch.last_block = block.number;
+        let system_transactions = batch.transactions.iter().filter(|tx| tx.is_system_tx).count();
+        batch.blocks.push(BlockRow {
+            chain: self.chain.clone(),
+            number: block.number,
+            hash: block.hash().to_string(),
+            timestamp: block.header().timestamp,
+            gas_used: block.header().gas_used,
+            transactions: block.body().transactions.len(),
+            system_transactions,
+        });
+        for (address, account) in &diff.accounts {
+            batch.account_diffs.push(AccountDiffRow {
+                chain: self.chain.clone(),
+                block: block.number,
+                address: address.to_string(),
+                balance_before: account.before.map(|fields| fields.balance.to_string()),
+                balance_after: account.after.map(|fields| fields.balance.to_string()),
+                nonce_before: account.before.map(|fields| fields.nonce),
+                nonce_after: account.after.map(|fields| fields.nonce),
+                code_hash_after: account.after.map(|fields| fields.code_hash.to_string()),
+                storage_cleared: account.storage_cleared,
+            });
+            for (slot, value) in &account.storage {
+                batch.storage_diffs.push(StorageDiffRow {
+                    chain: self.chain.clone(),
+                    block: block.number,
+                    address: address.to_string(),
+                    slot: slot.to_string(),
+                    before: value.before.to_string(),
+                    after: value.after.to_string(),
+                });
+            }
+        }
+        if let Some(sender) = &self.sender {
+            // Only fails if the writer thread panicked
+            if sender.send(batch).is_err() {
+                eprintln!("Sink writer stopped; block {} was not written", block.number);
+            }
+        }
+    }
+}
+
+// Waits for queued blocks to be written, so a finished sync has written everything it executed
+impl Drop for BlockSink {
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
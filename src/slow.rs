@@ -0,0 +1,25 @@
+use crate::jsonl::JsonLinesLog;
+use alloy::primitives::{Address, B256};
+use serde::Serialize;
+use std::time::Duration;
+
+// Transactions whose execution took at least `threshold`, appended to `log` after each block
+pub struct SlowTxLog {
+    pub threshold: Duration,
+    pub log: JsonLinesLog,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SlowTx {
+    pub block: u64,
+    pub tx_index: usize,
+    pub is_system_tx: bool,
+    // None for system transactions, which are not signed
+    pub hash: Option<B256>,
+    pub sender: Address,
+    // The contract called at the top level, None for contract creations
+    pub to: Option<Address>,
+    pub gas_used: u64,
+    // Of the EVM alone, without committing the state changes
+    pub duration_ms: f64,
+}
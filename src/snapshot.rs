@@ -0,0 +1,235 @@
+use crate::{
+    cli::Chain,
+    evm_map::TokenMap,
+    fs::{read_snapshot, write_snapshot_as},
+    types::{visit_flat_accounts, AccountSummary, EvmState},
+};
+use anyhow::{anyhow, Result};
+use clap::ValueEnum;
+use serde::{de::IgnoredAny, Deserialize, Serialize};
+use std::{
+    io::Read,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+// Snapshots are MAGIC, then the rmp-encoded header, then the state in the header's format version. Files without
+// MAGIC predate versioning and hold a bare `(next_block_num, EvmState)` tuple, which is read as version 0.
+const MAGIC: &[u8; 8] = b"HEVMSNAP";
+// Bump whenever the serialization of EvmState changes, and teach `decode_snapshot` to upgrade the previous version
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+// Encoding of the state after the header, which is always rmp
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SnapshotFormat {
+    #[default]
+    Rmp,
+    // Several times faster to read and write than rmp for large states, at a somewhat larger size
+    Bincode,
+    // Bincode split into segments of accounts, contracts, and block hashes that are encoded and decoded in parallel.
+    // The segment lengths come first, as an rmp-encoded list.
+    Segmented,
+    // Segments of sorted key-value entries with prefix-compressed keys (see `flat`), laid out like Segmented. Much
+    // smaller than the others for states with many storage slots.
+    Flat,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotHeader {
+    pub version: u32,
+    pub next_block_num: u64,
+    // Seconds since the Unix epoch
+    pub created_at: u64,
+    // Version of this crate that wrote the snapshot
+    pub writer: String,
+    #[serde(default)]
+    pub format: SnapshotFormat,
+    // None in snapshots written before the chain was recorded
+    #[serde(default)]
+    pub chain: Option<Chain>,
+    // The token map of the sync that wrote the snapshot, if it has one
+    #[serde(default)]
+    pub token_map: Option<TokenMap>,
+}
+
+impl SnapshotHeader {
+    fn new(next_block_num: u64, format: SnapshotFormat, chain: Option<Chain>, token_map: Option<TokenMap>) -> Self {
+        Self {
+            version: SNAPSHOT_VERSION,
+            next_block_num,
+            created_at: SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs(),
+            writer: env!("CARGO_PKG_VERSION").to_owned(),
+            format,
+            chain,
+            token_map,
+        }
+    }
+
+    const fn legacy(next_block_num: u64) -> Self {
+        Self {
+            version: 0,
+            next_block_num,
+            created_at: 0,
+            writer: String::new(),
+            format: SnapshotFormat::Rmp,
+            chain: None,
+            token_map: None,
+        }
+    }
+}
+
+pub fn encode_snapshot(
+    next_block_num: u64,
+    state: &EvmState,
+    format: SnapshotFormat,
+    chain: Option<Chain>,
+    token_map: Option<&TokenMap>,
+) -> Result<Vec<u8>> {
+    let mut buffer = MAGIC.to_vec();
+    rmp_serde::encode::write(&mut buffer, &SnapshotHeader::new(next_block_num, format, chain, token_map.cloned()))?;
+    match format {
+        SnapshotFormat::Rmp => rmp_serde::encode::write(&mut buffer, state)?,
+        SnapshotFormat::Bincode => bincode::serialize_into(&mut buffer, state)?,
+        SnapshotFormat::Segmented => write_segments(&mut buffer, state.encode_segments()?)?,
+        SnapshotFormat::Flat => write_segments(&mut buffer, state.encode_flat()?)?,
+    }
+    Ok(buffer)
+}
+
+// Older versions are upgraded as they are read; newer ones are rejected rather than misread
+pub fn decode_snapshot(data: &[u8]) -> Result<(SnapshotHeader, EvmState)> {
+    let Some(mut data) = data.strip_prefix(MAGIC.as_slice()) else {
+        let (next_block_num, state): (u64, EvmState) = rmp_serde::from_slice(data)?;
+        return Ok((SnapshotHeader::legacy(next_block_num), state));
+    };
+    let header: SnapshotHeader = rmp_serde::decode::from_read(&mut data)?;
+    match header.version {
+        SNAPSHOT_VERSION => {
+            let state = match header.format {
+                SnapshotFormat::Rmp => rmp_serde::from_slice(data)?,
+                SnapshotFormat::Bincode => bincode::deserialize(data)?,
+                SnapshotFormat::Segmented => EvmState::decode_segments(&split_segments(data)?)?,
+                SnapshotFormat::Flat => EvmState::decode_flat(&split_segments(data)?)?,
+            };
+            Ok((header, state))
+        }
+        version => Err(anyhow!(
+            "snapshot format version {version} (written by {}) is not supported; this build reads up to version \
+             {SNAPSHOT_VERSION}",
+            header.writer
+        )),
+    }
+}
+
+// Decodes the header at the start of `reader` without the state after it. Pre-versioning snapshots have no header,
+// but their block number comes first; None for anything else, such as encrypted snapshots.
+pub fn decode_snapshot_header(mut reader: impl Read) -> Result<Option<SnapshotHeader>> {
+    let mut magic = [0; MAGIC.len()];
+    reader.read_exact(&mut magic)?;
+    if &magic == MAGIC {
+        return Ok(Some(rmp_serde::decode::from_read(reader)?));
+    }
+    match rmp_serde::decode::from_read::<_, (u64, IgnoredAny)>(magic.as_slice().chain(reader)) {
+        Ok((next_block_num, _)) => Ok(Some(SnapshotHeader::legacy(next_block_num))),
+        Err(_) => Ok(None),
+    }
+}
+
+// Visits the accounts of a decrypted snapshot in address order. Flat snapshots are walked entry by entry; the other
+// formats have to be decoded in full first.
+pub fn visit_snapshot_accounts(
+    data: &[u8],
+    visit: &mut dyn FnMut(AccountSummary) -> Result<bool>,
+) -> Result<SnapshotHeader> {
+    if let Some(mut rest) = data.strip_prefix(MAGIC.as_slice()) {
+        let header: SnapshotHeader = rmp_serde::decode::from_read(&mut rest)?;
+        if header.version == SNAPSHOT_VERSION && header.format == SnapshotFormat::Flat {
+            visit_flat_accounts(&split_segments(rest)?, visit)?;
+            return Ok(header);
+        }
+    }
+    let (header, state) = decode_snapshot(data)?;
+    for summary in state.account_summaries() {
+        if !visit(summary)? {
+            break;
+        }
+    }
+    Ok(header)
+}
+
+fn write_segments(buffer: &mut Vec<u8>, segments: Vec<Vec<u8>>) -> Result<()> {
+    let lengths: Vec<u64> = segments.iter().map(|segment| segment.len() as u64).collect();
+    rmp_serde::encode::write(buffer, &lengths)?;
+    buffer.extend(segments.concat());
+    Ok(())
+}
+
+fn split_segments(mut data: &[u8]) -> Result<Vec<&[u8]>> {
+    let lengths: Vec<u64> = rmp_serde::decode::from_read(&mut data)?;
+    let mut segments = Vec::with_capacity(lengths.len());
+    for length in lengths {
+        let length = usize::try_from(length)?;
+        if length > data.len() {
+            return Err(anyhow!("snapshot segment of {length} bytes is truncated"));
+        }
+        let (segment, rest) = data.split_at(length);
+        segments.push(segment);
+        data = rest;
+    }
+    Ok(segments)
+}
+
+// Rewrites a snapshot in the current version and the given format, in place unless `out` is given, returning the
+// version it had
+pub fn migrate_snapshot(fln: &Path, out: Option<&Path>, format: SnapshotFormat) -> Result<u32> {
+    let (header, state) = read_snapshot(fln)?;
+    let target = out.map_or_else(|| PathBuf::from(format!("{}.migrating", fln.display())), ToOwned::to_owned);
+    write_snapshot_as(header.next_block_num, &state, &target, format, header.chain, header.token_map.as_ref())?;
+    if out.is_none() {
+        std::fs::rename(target, fln)?;
+    }
+    Ok(header.version)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        cli::Chain,
+        snapshot::{decode_snapshot, decode_snapshot_header, encode_snapshot, SnapshotFormat, SNAPSHOT_VERSION},
+        state::State,
+        types::EvmState,
+    };
+    use alloy::primitives::{address, Bytes, B256, U256};
+    use revm::InMemoryDB;
+
+    #[test]
+    fn test_decode_legacy_and_current_snapshots() {
+        let state: EvmState = InMemoryDB::default().into();
+        let legacy = rmp_serde::to_vec(&(42u64, &state)).unwrap();
+        let (header, _) = decode_snapshot(&legacy).unwrap();
+        assert_eq!((header.version, header.next_block_num), (0, 42));
+        assert_eq!(decode_snapshot_header(legacy.as_slice()).unwrap(), Some(header));
+        for format in [SnapshotFormat::Rmp, SnapshotFormat::Bincode, SnapshotFormat::Segmented, SnapshotFormat::Flat] {
+            let encoded = encode_snapshot(43, &state, format, Some(Chain::Testnet), None).unwrap();
+            let (header, _) = decode_snapshot(&encoded).unwrap();
+            assert_eq!((header.version, header.next_block_num, header.format), (SNAPSHOT_VERSION, 43, format));
+            assert_eq!(decode_snapshot_header(encoded.as_slice()).unwrap(), Some(header));
+        }
+    }
+
+    #[test]
+    fn test_flat_snapshot_roundtrip() {
+        let mut db = InMemoryDB::genesis();
+        let contract = address!("0x5555555555555555555555555555555555555555");
+        db.inject_contract(contract, Bytes::from_static(&[0x60, 0x00, 0x60, 0x00, 0xf3]));
+        for slot in [0u64, 1, 300, u64::MAX] {
+            db.insert_storage(contract, U256::from(slot), U256::from(slot) << 100);
+        }
+        db.insert_block_hash(7, B256::repeat_byte(7));
+        let hash = db.blake3_hash_slow();
+        let (_, state) =
+            decode_snapshot(&encode_snapshot(8, &db.into(), SnapshotFormat::Flat, None, None).unwrap()).unwrap();
+        assert_eq!(InMemoryDB::from(state).blake3_hash_slow(), hash);
+    }
+}
@@ -8,17 +8,20 @@ use revm::{
     primitives::{Account, AccountInfo, Bytecode, HashMap},
     Database, DatabaseCommit, DatabaseRef, InMemoryDB,
 };
+use serde::{Deserialize, Serialize};
 
 pub trait State: Database + DatabaseRef {
     fn genesis() -> Self;
     fn commit(&mut self, changes: HashMap<Address, Account>);
     fn insert_block_hash(&mut self, block_num: u64, hash: B256);
     fn inject_contract(&mut self, contract_address: Address, deployed_bytecode: Bytes);
+    // Unlike inject_contract, keeps the account's balance, nonce, and storage
+    fn replace_code(&mut self, address: Address, deployed_bytecode: Bytes);
     fn insert_storage(&mut self, address: Address, key: U256, value: U256);
     fn blake3_hash_slow(&self) -> StateHash;
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct StateHash {
     pub accounts_hash: B256,
     pub contracts_hash: B256,
@@ -26,6 +29,17 @@ pub struct StateHash {
     pub block_hashes_hash: B256,
 }
 
+impl StateHash {
+    // A single hash of the four, short enough to pass on the command line
+    pub fn digest(&self) -> B256 {
+        let mut hasher = blake3::Hasher::new();
+        for hash in [self.accounts_hash, self.contracts_hash, self.storage_hash, self.block_hashes_hash] {
+            hasher.update(hash.as_slice());
+        }
+        hasher.finalize().as_bytes().into()
+    }
+}
+
 impl State for InMemoryDB {
     fn genesis() -> Self {
         let mut state = Self::default();
@@ -60,6 +74,18 @@ impl State for InMemoryDB {
         self.contracts.insert(bytecode_hash, bytecode);
     }
 
+    fn replace_code(&mut self, address: Address, deployed_bytecode: Bytes) {
+        let bytecode = Bytecode::new_raw(deployed_bytecode);
+        let code_hash = bytecode.hash_slow();
+        let account = self.accounts.entry(address).or_default();
+        account.info.code_hash = code_hash;
+        account.info.code = Some(bytecode.clone());
+        if account.account_state == AccountState::NotExisting {
+            account.account_state = AccountState::Touched;
+        }
+        self.contracts.insert(code_hash, bytecode);
+    }
+
     fn insert_storage(&mut self, address: Address, key: U256, value: U256) {
         self.accounts.entry(address).or_default().storage.insert(key, value);
     }
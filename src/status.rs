@@ -0,0 +1,88 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+const MAX_RECENT_ERRORS: usize = 20;
+
+// Progress of a running sync, shared between the processor and anything observing it
+#[derive(Debug, Default)]
+pub struct SyncStatus {
+    pub start_block: AtomicU64,
+    pub end_block: AtomicU64,
+    pub current_block: AtomicU64,
+    pub last_snapshot_block: AtomicU64,
+    pub paused: AtomicBool,
+    // Following the chain and every published block has been executed
+    pub caught_up: AtomicBool,
+    // Totals over the executed blocks, for observers to derive rates from
+    pub transactions: AtomicU64,
+    pub gas_used: AtomicU64,
+    pub last_block_timestamp: AtomicU64,
+    recent_errors: Mutex<VecDeque<String>>,
+}
+
+impl SyncStatus {
+    pub fn new(start_block: u64, end_block: u64) -> Self {
+        let status = Self::default();
+        status.start_block.store(start_block, Ordering::Relaxed);
+        status.end_block.store(end_block, Ordering::Relaxed);
+        status.current_block.store(start_block.saturating_sub(1), Ordering::Relaxed);
+        status
+    }
+
+    pub fn start_block(&self) -> u64 {
+        self.start_block.load(Ordering::Relaxed)
+    }
+
+    pub fn end_block(&self) -> u64 {
+        self.end_block.load(Ordering::Relaxed)
+    }
+
+    // Last fully executed block
+    pub fn current_block(&self) -> u64 {
+        self.current_block.load(Ordering::Relaxed)
+    }
+
+    pub fn last_snapshot_block(&self) -> u64 {
+        self.last_snapshot_block.load(Ordering::Relaxed)
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    pub fn set_paused(&self, paused: bool) {
+        self.paused.store(paused, Ordering::Relaxed);
+    }
+
+    pub fn is_caught_up(&self) -> bool {
+        self.caught_up.load(Ordering::Relaxed)
+    }
+
+    pub fn set_caught_up(&self, caught_up: bool) {
+        self.caught_up.store(caught_up, Ordering::Relaxed);
+    }
+
+    pub fn record_block(&self, transactions: usize, gas_used: u64, timestamp: u64) {
+        self.transactions.fetch_add(transactions as u64, Ordering::Relaxed);
+        self.gas_used.fetch_add(gas_used, Ordering::Relaxed);
+        self.last_block_timestamp.store(timestamp, Ordering::Relaxed);
+    }
+
+    pub fn record_error(&self, error: String) {
+        let mut errors = self.recent_errors.lock().unwrap();
+        if errors.len() == MAX_RECENT_ERRORS {
+            errors.pop_front();
+        }
+        errors.push_back(error);
+    }
+
+    // Oldest first
+    pub fn recent_errors(&self) -> Vec<String> {
+        self.recent_errors.lock().unwrap().iter().cloned().collect()
+    }
+}
@@ -0,0 +1,107 @@
+use crate::types::EvmState;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+
+// A state stream is STREAM_MAGIC, then frames of a little-endian u64 length and that many bytes: the rmp-encoded
+// header, then the flat-encoded state segments, then an empty frame and the blake3 hash of every frame before it.
+// Nothing needs to be seekable, so it can be piped, e.g. over ssh.
+const STREAM_MAGIC: &[u8; 8] = b"HEVMSTRM";
+// Bounds what a corrupted length prefix can make the reader allocate
+const MAX_FRAME_LEN: u64 = 1 << 32;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct StreamHeader {
+    next_block_num: u64,
+    // Version of this crate that wrote the stream
+    writer: String,
+}
+
+struct FrameWriter<W> {
+    out: W,
+    hasher: blake3::Hasher,
+}
+
+impl<W: Write> FrameWriter<W> {
+    fn write(&mut self, frame: &[u8]) -> Result<()> {
+        let len = (frame.len() as u64).to_le_bytes();
+        self.hasher.update(&len);
+        self.hasher.update(frame);
+        self.out.write_all(&len)?;
+        self.out.write_all(frame)?;
+        Ok(())
+    }
+}
+
+// Returns the number of bytes written
+pub fn export_state<W: Write>(next_block_num: u64, state: &EvmState, out: W) -> Result<u64> {
+    let mut out = FrameWriter { out, hasher: blake3::Hasher::new() };
+    out.out.write_all(STREAM_MAGIC)?;
+    let header = StreamHeader { next_block_num, writer: env!("CARGO_PKG_VERSION").to_owned() };
+    out.write(&rmp_serde::to_vec(&header)?)?;
+    let mut written = STREAM_MAGIC.len() as u64;
+    for segment in state.encode_flat()? {
+        out.write(&segment)?;
+        written += 8 + segment.len() as u64;
+    }
+    out.out.write_all(&0u64.to_le_bytes())?;
+    out.out.write_all(out.hasher.finalize().as_bytes())?;
+    out.out.flush()?;
+    Ok(written + 8 + 32)
+}
+
+pub fn import_state<R: Read>(mut input: R) -> Result<(u64, EvmState)> {
+    let mut magic = [0; 8];
+    input.read_exact(&mut magic)?;
+    if &magic != STREAM_MAGIC {
+        return Err(anyhow!("not a state stream"));
+    }
+    let mut hasher = blake3::Hasher::new();
+    let mut frames = Vec::new();
+    loop {
+        let mut len = [0; 8];
+        input.read_exact(&mut len).map_err(|e| anyhow!("state stream ended early: {e}"))?;
+        let frame_len = u64::from_le_bytes(len);
+        if frame_len == 0 {
+            break;
+        }
+        if frame_len > MAX_FRAME_LEN {
+            return Err(anyhow!("state stream frame of {frame_len} bytes is too large"));
+        }
+        let mut frame = vec![0; usize::try_from(frame_len)?];
+        input.read_exact(&mut frame).map_err(|e| anyhow!("state stream ended early: {e}"))?;
+        hasher.update(&len);
+        hasher.update(&frame);
+        frames.push(frame);
+    }
+    let mut expected = [0; 32];
+    input.read_exact(&mut expected)?;
+    if hasher.finalize().as_bytes() != &expected {
+        return Err(anyhow!("state stream is corrupted: checksum mismatch"));
+    }
+    let (header, segments) = frames.split_first().ok_or_else(|| anyhow!("state stream has no header"))?;
+    let header: StreamHeader = rmp_serde::from_slice(header)?;
+    let segments: Vec<&[u8]> = segments.iter().map(Vec::as_slice).collect();
+    Ok((header.next_block_num, EvmState::decode_flat(&segments)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        state::State,
+        stream::{export_state, import_state},
+    };
+    use revm::InMemoryDB;
+
+    #[test]
+    fn test_state_stream_roundtrip() {
+        let db = InMemoryDB::genesis();
+        let hash = db.blake3_hash_slow();
+        let mut stream = Vec::new();
+        export_state(9, &db.into(), &mut stream).unwrap();
+        let (next_block_num, state) = import_state(stream.as_slice()).unwrap();
+        assert_eq!((next_block_num, InMemoryDB::from(state).blake3_hash_slow()), (9, hash));
+        stream[20] ^= 1;
+        assert!(import_state(stream.as_slice()).is_err());
+    }
+}
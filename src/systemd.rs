@@ -0,0 +1,52 @@
+use sd_notify::NotifyState;
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+// Service state reported to systemd's notify socket. Heartbeats come from the processor, so a sync wedged in execution
+// misses them and is restarted once WatchdogSec= runs out.
+pub struct SystemdNotifier {
+    // Half of WatchdogSec=, as systemd recommends; None without a watchdog
+    watchdog_interval: Option<Duration>,
+    last_heartbeat: Mutex<Instant>,
+}
+
+impl SystemdNotifier {
+    // None when not started by systemd with Type=notify
+    pub fn from_env() -> Option<Self> {
+        std::env::var_os("NOTIFY_SOCKET")?;
+        let mut usec = 0;
+        let watchdog_interval = sd_notify::watchdog_enabled(false, &mut usec).then(|| Duration::from_micros(usec / 2));
+        Some(Self { watchdog_interval, last_heartbeat: Mutex::new(Instant::now()) })
+    }
+
+    fn notify(&self, states: &[NotifyState<'_>]) {
+        if let Err(e) = sd_notify::notify(false, states) {
+            eprintln!("Failed to notify systemd: {e}");
+        }
+    }
+
+    pub fn ready(&self, next_block_num: u64) {
+        let status = format!("Loaded the state before block {next_block_num}");
+        self.notify(&[NotifyState::Ready, NotifyState::Watchdog, NotifyState::Status(&status)]);
+    }
+
+    // Cheap enough to call after every block; only sends once per interval
+    pub fn heartbeat(&self, block_num: u64) {
+        let Some(interval) = self.watchdog_interval else {
+            return;
+        };
+        let mut last_heartbeat = self.last_heartbeat.lock().unwrap();
+        if last_heartbeat.elapsed() < interval {
+            return;
+        }
+        *last_heartbeat = Instant::now();
+        let status = format!("Executed block {block_num}");
+        self.notify(&[NotifyState::Watchdog, NotifyState::Status(&status)]);
+    }
+
+    pub fn stopping(&self) {
+        self.notify(&[NotifyState::Stopping]);
+    }
+}
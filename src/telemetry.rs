@@ -0,0 +1,82 @@
+use anyhow::Result;
+use opentelemetry::{
+    global::{self, BoxedSpan},
+    trace::{Span, TraceContextExt, Tracer},
+    Context, KeyValue,
+};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{runtime, trace::TracerProvider, Resource};
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+};
+
+const TRACER: &str = "hyper-evm-sync";
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+// The open trace of each chunk between the reader and the executor, by the chunk's first block
+static CHUNKS: Mutex<BTreeMap<u64, Context>> = Mutex::new(BTreeMap::new());
+
+fn attribute(key: &'static str, value: u64) -> KeyValue {
+    KeyValue::new(key, i64::try_from(value).unwrap_or(i64::MAX))
+}
+
+// Exports spans over OTLP/gRPC in batches; needs to run in the tokio runtime
+pub fn init(endpoint: &str) -> Result<TracerProvider> {
+    let exporter = opentelemetry_otlp::SpanExporter::builder().with_tonic().with_endpoint(endpoint).build()?;
+    let provider = TracerProvider::builder()
+        .with_batch_exporter(exporter, runtime::Tokio)
+        .with_resource(Resource::new([KeyValue::new("service.name", TRACER)]))
+        .build();
+    global::set_tracer_provider(provider.clone());
+    ENABLED.store(true, Ordering::Relaxed);
+    println!("Exporting traces to {endpoint}");
+    Ok(provider)
+}
+
+// Flushes the spans not exported yet
+pub async fn shutdown(provider: TracerProvider) {
+    ENABLED.store(false, Ordering::Relaxed);
+    if let Ok(Err(e)) = tokio::task::spawn_blocking(move || provider.shutdown()).await {
+        eprintln!("Failed to export the last traces: {e}");
+    }
+}
+
+// Opens the trace of a chunk when the reader starts on it. The executor closes it, so the time a chunk waited in the
+// queue shows up between its stages.
+pub fn start_chunk(first_block: u64, last_block: u64) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    let tracer = global::tracer(TRACER);
+    let span = tracer
+        .span_builder("chunk")
+        .with_attributes([attribute("first_block", first_block), attribute("last_block", last_block)])
+        .start(&tracer);
+    CHUNKS.lock().unwrap().insert(first_block, Context::current_with_span(span));
+}
+
+pub fn finish_chunk(first_block: u64) {
+    if let Some(context) = CHUNKS.lock().unwrap().remove(&first_block) {
+        context.span().end();
+    }
+}
+
+// A stage of the chunk starting at `chunk`, ended when dropped; a no-op without --otlp-endpoint
+pub fn stage_span(name: &'static str, chunk: u64, block: Option<u64>) -> BoxedSpan {
+    let tracer = global::tracer(TRACER);
+    let context = CHUNKS.lock().unwrap().get(&chunk).cloned();
+    let mut span = match context {
+        Some(context) => tracer.start_with_context(name, &context),
+        None => tracer.start(name),
+    };
+    span.set_attribute(attribute("chunk", chunk));
+    if let Some(block) = block {
+        span.set_attribute(attribute("block", block));
+    }
+    span
+}
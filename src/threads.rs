@@ -0,0 +1,116 @@
+use crate::error::ErrorKind;
+use anyhow::{anyhow, Context, Result};
+use core_affinity::CoreId;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    OnceLock,
+};
+use tokio::runtime::Runtime;
+
+// Tokio threads when pinning without --io-threads; they mostly wait on downloads and servers
+const DEFAULT_PINNED_IO_THREADS: usize = 2;
+
+static EXECUTION_CPU: OnceLock<usize> = OnceLock::new();
+
+#[derive(Debug, Default)]
+pub struct ThreadOptions {
+    // Rayon threads, which decode blocks, recover signers, and hash states
+    pub exec_threads: Option<usize>,
+    // Tokio worker threads
+    pub io_threads: Option<usize>,
+    pub pin_cpus: Option<Vec<usize>>,
+}
+
+// "0-3,8,10-11" style, as in taskset and cgroups
+pub fn parse_cpu_list(list: &str) -> Result<Vec<usize>> {
+    let mut cpus = Vec::new();
+    for part in list.split(',').map(str::trim).filter(|part| !part.is_empty()) {
+        let invalid = || anyhow!("invalid CPU list {list}").context(ErrorKind::Usage);
+        match part.split_once('-') {
+            Some((first, last)) => {
+                let (first, last): (usize, usize) =
+                    (first.parse().map_err(|_| invalid())?, last.parse().map_err(|_| invalid())?);
+                if first > last {
+                    return Err(invalid());
+                }
+                cpus.extend(first..=last);
+            }
+            None => cpus.push(part.parse().map_err(|_| invalid())?),
+        }
+    }
+    if cpus.is_empty() {
+        return Err(anyhow!("empty CPU list").context(ErrorKind::Usage));
+    }
+    Ok(cpus)
+}
+
+fn pin(cpus: &[usize], next: &AtomicUsize) {
+    let cpu = cpus[next.fetch_add(1, Ordering::Relaxed) % cpus.len()];
+    if !core_affinity::set_for_current(CoreId { id: cpu }) {
+        eprintln!("Failed to pin a thread to CPU {cpu}");
+    }
+}
+
+// With pinning, the first CPU goes to the execution loop, the next ones to tokio, and the rest to rayon, so that the
+// single-threaded execution never waits for a core. Tokio's blocking threads share the tokio CPUs.
+pub fn build_runtime(options: &ThreadOptions) -> Result<Runtime> {
+    let mut io_cpus = Vec::new();
+    let mut exec_cpus = Vec::new();
+    let mut exec_threads = options.exec_threads;
+    let mut io_threads = options.io_threads;
+    if let Some(cpus) = &options.pin_cpus {
+        let io = io_threads.unwrap_or(DEFAULT_PINNED_IO_THREADS);
+        if cpus.len() < io + 2 {
+            return Err(anyhow!(
+                "--pin-cpus needs at least {} CPUs: one for execution, {io} for I/O, and one for the thread pool",
+                io + 2
+            ))
+            .context(ErrorKind::Usage);
+        }
+        let _ = EXECUTION_CPU.set(cpus[0]);
+        io_cpus = cpus[1..=io].to_vec();
+        exec_cpus = cpus[io + 1..].to_vec();
+        io_threads = Some(io);
+        exec_threads = Some(exec_threads.unwrap_or(exec_cpus.len()));
+    }
+
+    let mut pool = rayon::ThreadPoolBuilder::new();
+    if let Some(threads) = exec_threads {
+        pool = pool.num_threads(threads);
+    }
+    if !exec_cpus.is_empty() {
+        let next = AtomicUsize::new(0);
+        pool = pool.start_handler(move |_| pin(&exec_cpus, &next));
+    }
+    pool.build_global().context("failed to start the thread pool")?;
+
+    let mut runtime = tokio::runtime::Builder::new_multi_thread();
+    runtime.enable_all();
+    if let Some(threads) = io_threads {
+        runtime.worker_threads(threads);
+    }
+    if !io_cpus.is_empty() {
+        let next = AtomicUsize::new(0);
+        runtime.on_thread_start(move || pin(&io_cpus, &next));
+    }
+    Ok(runtime.build()?)
+}
+
+// Called by the thread running the execution loop
+pub fn pin_execution_thread() {
+    if let Some(&cpu) = EXECUTION_CPU.get() {
+        pin(&[cpu], &AtomicUsize::new(0));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::threads::parse_cpu_list;
+
+    #[test]
+    fn test_parse_cpu_list() {
+        assert_eq!(parse_cpu_list("0-3, 8,10-11").unwrap(), [0, 1, 2, 3, 8, 10, 11]);
+        assert!(parse_cpu_list("3-1").is_err());
+        assert!(parse_cpu_list("a").is_err());
+    }
+}
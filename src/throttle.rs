@@ -0,0 +1,66 @@
+use std::time::Duration;
+use tokio::{sync::Mutex, time::Instant};
+
+// Throttled responses double the spacing between requests, up to this much
+const MAX_THROTTLED_INTERVAL: Duration = Duration::from_secs(10);
+const MIN_THROTTLED_INTERVAL: Duration = Duration::from_millis(10);
+// Each successful request shrinks the slowdown by this factor until the configured rate is reached again
+const RECOVERY_FACTOR: f64 = 0.98;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DownloadLimits {
+    pub max_requests_per_second: Option<f64>,
+    // Bytes per second
+    pub max_bandwidth: Option<u64>,
+}
+
+struct Schedule {
+    next_request: Instant,
+    request_interval: Duration,
+    bandwidth_free_at: Instant,
+}
+
+// Paces downloads shared by all concurrent fetches. Every request reserves the next send slot, and every completed
+// body pushes back later requests by its transfer time at the bandwidth limit.
+pub struct Throttle {
+    limits: DownloadLimits,
+    min_interval: Duration,
+    schedule: Mutex<Schedule>,
+}
+
+impl Throttle {
+    pub fn new(limits: DownloadLimits) -> Self {
+        let min_interval =
+            limits.max_requests_per_second.map_or(Duration::ZERO, |rps| Duration::from_secs_f64(1.0 / rps));
+        let now = Instant::now();
+        let schedule = Schedule { next_request: now, request_interval: min_interval, bandwidth_free_at: now };
+        Self { limits, min_interval, schedule: Mutex::new(schedule) }
+    }
+
+    pub async fn before_request(&self) {
+        let wait_until = {
+            let mut schedule = self.schedule.lock().await;
+            let slot = schedule.next_request.max(Instant::now()).max(schedule.bandwidth_free_at);
+            schedule.next_request = slot + schedule.request_interval;
+            slot
+        };
+        tokio::time::sleep_until(wait_until).await;
+    }
+
+    pub async fn after_response(&self, bytes: u64) {
+        let mut schedule = self.schedule.lock().await;
+        schedule.request_interval = schedule.request_interval.mul_f64(RECOVERY_FACTOR).max(self.min_interval);
+        if let Some(bandwidth) = self.limits.max_bandwidth {
+            let transfer_time = Duration::from_secs_f64(bytes as f64 / bandwidth as f64);
+            schedule.bandwidth_free_at = schedule.bandwidth_free_at.max(Instant::now()) + transfer_time;
+        }
+    }
+
+    pub async fn throttled(&self) {
+        let mut schedule = self.schedule.lock().await;
+        schedule.request_interval =
+            (schedule.request_interval * 2).clamp(MIN_THROTTLED_INTERVAL, MAX_THROTTLED_INTERVAL);
+        schedule.next_request = schedule.next_request.max(Instant::now()) + schedule.request_interval;
+        println!("Server is throttling downloads; spacing requests {:?} apart", schedule.request_interval);
+    }
+}
@@ -0,0 +1,274 @@
+use crate::{cli::Chain, error::ErrorKind, status::SyncStatus};
+use anyhow::{anyhow, Context, Result};
+use indicatif::HumanDuration;
+use ratatui::{
+    backend::CrosstermBackend,
+    crossterm::{
+        event::{self, Event, KeyCode, KeyModifiers},
+        execute,
+        terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+    },
+    layout::{Constraint, Layout},
+    style::{Color, Style},
+    text::Line,
+    widgets::{Block, Gauge, List, Paragraph, Sparkline},
+    Frame, Terminal,
+};
+use std::{
+    collections::VecDeque,
+    io::{IsTerminal, Stderr},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+const REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+// Seconds of throughput shown in the graphs
+const HISTORY: usize = 300;
+
+type DashboardTerminal = Terminal<CrosstermBackend<Stderr>>;
+
+// Used and total slots of the channel between the reader and the processor, None once it is closed
+pub type QueueDepth = Box<dyn Fn() -> Option<(usize, usize)> + Send>;
+
+fn restore_terminal() {
+    let _ = disable_raw_mode();
+    let _ = execute!(std::io::stderr(), LeaveAlternateScreen);
+}
+
+fn resident_memory_bytes() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kib: u64 = line.trim_start_matches("VmRSS:").trim().trim_end_matches("kB").trim().parse().ok()?;
+    Some(kib * 1024)
+}
+
+struct Sample {
+    at: Instant,
+    block: u64,
+    transactions: u64,
+    gas_used: u64,
+}
+
+impl Sample {
+    fn take(status: &SyncStatus) -> Self {
+        Self {
+            at: Instant::now(),
+            block: status.current_block(),
+            transactions: status.transactions.load(Ordering::Relaxed),
+            gas_used: status.gas_used.load(Ordering::Relaxed),
+        }
+    }
+}
+
+// Per-second rates, most recent last
+#[derive(Default)]
+struct Throughput {
+    blocks: VecDeque<u64>,
+    transactions: VecDeque<u64>,
+    mgas: VecDeque<u64>,
+}
+
+impl Throughput {
+    fn record(&mut self, previous: &Sample, sample: &Sample) {
+        let secs = sample.at.duration_since(previous.at).as_secs_f64().max(f64::EPSILON);
+        let rate = |delta: u64| delta as f64 / secs;
+        for (history, value) in [
+            (&mut self.blocks, rate(sample.block.saturating_sub(previous.block))),
+            (&mut self.transactions, rate(sample.transactions - previous.transactions)),
+            (&mut self.mgas, rate(sample.gas_used - previous.gas_used) / 1e6),
+        ] {
+            let value = value.round() as u64;
+            if history.len() == HISTORY {
+                history.pop_front();
+            }
+            history.push_back(value);
+        }
+    }
+}
+
+struct DashboardState {
+    chain: Chain,
+    status: Arc<SyncStatus>,
+    queue: QueueDepth,
+    started: Instant,
+    last_sample: Sample,
+    throughput: Throughput,
+    // The last snapshot block seen, and when it was first seen
+    last_snapshot: (u64, Instant),
+}
+
+impl DashboardState {
+    fn refresh(&mut self) {
+        if self.last_sample.at.elapsed() >= REFRESH_INTERVAL {
+            let sample = Sample::take(&self.status);
+            self.throughput.record(&self.last_sample, &sample);
+            self.last_sample = sample;
+        }
+        let last_snapshot = self.status.last_snapshot_block();
+        if last_snapshot != self.last_snapshot.0 {
+            self.last_snapshot = (last_snapshot, Instant::now());
+        }
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let status = &self.status;
+        let [header_area, gauge_area, graphs_area, details_area, errors_area] = Layout::vertical([
+            Constraint::Length(3),
+            Constraint::Length(3),
+            Constraint::Length(8),
+            Constraint::Length(6),
+            Constraint::Min(3),
+        ])
+        .areas(frame.area());
+
+        let (start_block, end_block, current_block) =
+            (status.start_block(), status.end_block(), status.current_block());
+        let state = if status.is_paused() {
+            "paused"
+        } else if status.is_caught_up() {
+            "caught up"
+        } else {
+            "running"
+        };
+        let header = format!(
+            "{} sync of blocks {start_block}-{end_block}, {state} for {} (q to close the dashboard)",
+            self.chain,
+            HumanDuration(self.started.elapsed())
+        );
+        frame.render_widget(Paragraph::new(header).block(Block::bordered().title("hyper-evm-sync")), header_area);
+
+        let done = (current_block + 1).saturating_sub(start_block) as f64;
+        let total = (end_block + 1).saturating_sub(start_block).max(1) as f64;
+        let gauge = Gauge::default()
+            .block(Block::bordered().title("progress"))
+            .gauge_style(Style::default().fg(Color::Cyan))
+            .ratio((done / total).clamp(0.0, 1.0))
+            .label(format!("block {current_block} / {end_block}"));
+        frame.render_widget(gauge, gauge_area);
+
+        let graph_areas: [_; 3] = Layout::horizontal([Constraint::Ratio(1, 3); 3]).areas(graphs_area);
+        for (area, (title, history)) in graph_areas.into_iter().zip([
+            ("blocks/s", &self.throughput.blocks),
+            ("tx/s", &self.throughput.transactions),
+            ("Mgas/s", &self.throughput.mgas),
+        ]) {
+            let data: Vec<u64> = history.iter().copied().collect();
+            let title = format!("{title} {}", data.last().copied().unwrap_or_default());
+            // The most recent samples that fit, one per column
+            let visible = &data[data.len().saturating_sub(usize::from(area.width.saturating_sub(2)))..];
+            frame.render_widget(Sparkline::default().block(Block::bordered().title(title)).data(visible), area);
+        }
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let timestamp = status.last_block_timestamp.load(Ordering::Relaxed);
+        let lag = if timestamp == 0 {
+            "-".to_owned()
+        } else {
+            HumanDuration(Duration::from_secs(now.saturating_sub(timestamp))).to_string()
+        };
+        let queue = (self.queue)().map_or_else(|| "closed".to_owned(), |(used, total)| format!("{used}/{total}"));
+        let memory = resident_memory_bytes()
+            .map_or_else(|| "-".to_owned(), |bytes| format!("{:.0} MiB", bytes as f64 / (1 << 20) as f64));
+        let snapshot = match self.last_snapshot {
+            (0, _) => "none yet".to_owned(),
+            (block, at) => format!("block {block}, {} ago", HumanDuration(at.elapsed())),
+        };
+        let details = vec![
+            Line::from(format!("behind the chain: {lag}")),
+            Line::from(format!("reader queue: {queue} batches")),
+            Line::from(format!("memory (RSS): {memory}")),
+            Line::from(format!("last snapshot: {snapshot}")),
+        ];
+        frame.render_widget(Paragraph::new(details).block(Block::bordered().title("details")), details_area);
+
+        let errors: Vec<_> = status.recent_errors().into_iter().rev().collect();
+        let errors =
+            List::new(errors).style(Style::default().fg(Color::Red)).block(Block::bordered().title("recent errors"));
+        frame.render_widget(errors, errors_area);
+    }
+}
+
+fn run_dashboard(terminal: &mut DashboardTerminal, mut state: DashboardState, stop: &AtomicBool) -> Result<()> {
+    while !stop.load(Ordering::Relaxed) {
+        state.refresh();
+        terminal.draw(|frame| state.draw(frame))?;
+        if !event::poll(REFRESH_INTERVAL)? {
+            continue;
+        }
+        if let Event::Key(key) = event::read()? {
+            match key.code {
+                // The terminal is in raw mode, so Ctrl-C arrives as a key rather than as SIGINT
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    restore_terminal();
+                    std::process::exit(130);
+                }
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                _ => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+// A live view of a sync, drawn on stderr by its own thread until stopped or closed with q
+pub struct Dashboard {
+    stop: Arc<AtomicBool>,
+    handle: JoinHandle<()>,
+}
+
+impl Dashboard {
+    pub fn start(chain: Chain, status: Arc<SyncStatus>, queue: QueueDepth) -> Result<Self> {
+        // The sync keeps logging to stdout, which would scribble over the dashboard
+        if std::io::stdout().is_terminal() {
+            return Err(anyhow!("--tui needs the sync's output redirected, e.g. > sync.log")).context(ErrorKind::Usage);
+        }
+        if !std::io::stderr().is_terminal() {
+            return Err(anyhow!("--tui needs a terminal on stderr")).context(ErrorKind::Usage);
+        }
+        enable_raw_mode()?;
+        execute!(std::io::stderr(), EnterAlternateScreen)?;
+        let previous_hook = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            restore_terminal();
+            previous_hook(info);
+        }));
+        let mut terminal = match Terminal::new(CrosstermBackend::new(std::io::stderr())) {
+            Ok(terminal) => terminal,
+            Err(e) => {
+                restore_terminal();
+                return Err(e.into());
+            }
+        };
+        let last_sample = Sample::take(&status);
+        let state = DashboardState {
+            chain,
+            last_snapshot: (status.last_snapshot_block(), Instant::now()),
+            status,
+            queue,
+            started: Instant::now(),
+            last_sample,
+            throughput: Throughput::default(),
+        };
+        let stop = Arc::new(AtomicBool::new(false));
+        let handle = std::thread::spawn({
+            let stop = stop.clone();
+            move || {
+                let result = run_dashboard(&mut terminal, state, &stop);
+                restore_terminal();
+                if let Err(e) = result {
+                    eprintln!("Dashboard failed: {e}");
+                }
+            }
+        });
+        Ok(Self { stop, handle })
+    }
+
+    pub fn stop(self) {
+        self.stop.store(true, Ordering::Relaxed);
+        let _ = self.handle.join();
+    }
+}
@@ -1,7 +1,13 @@
+use crate::flat::{read_varint, write_varint, RunReader, RunWriter};
 use alloy::{
     consensus::constants::KECCAK_EMPTY,
     primitives::{Address, Bytes, Log, B256, U256},
 };
+use anyhow::{anyhow, Result};
+use rayon::{
+    iter::{IntoParallelIterator, IntoParallelRefIterator, ParallelIterator},
+    slice::ParallelSliceMut,
+};
 use reth_primitives::{Receipt, SealedBlock, Transaction, TxType};
 use revm::{
     db::AccountState,
@@ -10,6 +16,7 @@ use revm::{
 };
 use serde::{Deserialize, Serialize};
 
+#[derive(Clone)]
 pub struct PreprocessedBlock {
     pub block_num: u64,
     pub block_and_receipts: BlockAndReceipts,
@@ -184,6 +191,286 @@ impl From<EvmState> for InMemoryDB {
     }
 }
 
+// Accounts plus storage slots per segment, so accounts with large storage get segments of their own
+const SEGMENT_ENTRIES: usize = 1 << 18;
+const CONTRACTS_PER_SEGMENT: usize = 4096;
+
+// Independently decodable parts of an EvmState. Both enums must keep the same variants in the same order, since
+// segments are written borrowed and read owned.
+#[derive(Serialize)]
+enum SegmentRef<'a> {
+    BlockHashes(&'a [(U256, B256)]),
+    Contracts(&'a [(B256, Bytecode)]),
+    Accounts(&'a [(Address, DbAccount)]),
+}
+
+#[derive(Deserialize)]
+enum Segment {
+    BlockHashes(Vec<(U256, B256)>),
+    Contracts(Vec<(B256, Bytecode)>),
+    Accounts(Vec<(Address, DbAccount)>),
+}
+
+impl EvmState {
+    // Bincode-encoded segments, encoded in parallel
+    pub(crate) fn encode_segments(&self) -> Result<Vec<Vec<u8>>> {
+        let EvmDb::InMemory { accounts, contracts } = &self.evm_db;
+        let mut segments = vec![SegmentRef::BlockHashes(&self.block_hashes)];
+        segments.extend(contracts.chunks(CONTRACTS_PER_SEGMENT).map(SegmentRef::Contracts));
+        let (mut start, mut entries) = (0, 0);
+        for (i, (_, account)) in accounts.iter().enumerate() {
+            entries += 1 + account.storage.len();
+            if entries >= SEGMENT_ENTRIES {
+                segments.push(SegmentRef::Accounts(&accounts[start..=i]));
+                (start, entries) = (i + 1, 0);
+            }
+        }
+        if start < accounts.len() {
+            segments.push(SegmentRef::Accounts(&accounts[start..]));
+        }
+        Ok(segments.into_par_iter().map(|segment| bincode::serialize(&segment)).collect::<Result<_, _>>()?)
+    }
+
+    pub(crate) fn decode_segments(segments: &[&[u8]]) -> Result<Self> {
+        let segments: Vec<Segment> =
+            segments.par_iter().map(|segment| bincode::deserialize(segment)).collect::<Result<_, _>>()?;
+        let (mut accounts, mut contracts, mut block_hashes) = (Vec::new(), Vec::new(), Vec::new());
+        for segment in segments {
+            match segment {
+                Segment::BlockHashes(hashes) => block_hashes.extend(hashes),
+                Segment::Contracts(codes) => contracts.extend(codes),
+                Segment::Accounts(entries) => accounts.extend(entries),
+            }
+        }
+        Ok(Self { evm_db: EvmDb::InMemory { accounts, contracts }, block_hashes })
+    }
+
+    // Runs of flat entries, sorted and encoded in parallel. Each run holds one kind of entry, and an account's storage
+    // slots follow it in the same run.
+    pub(crate) fn encode_flat(&self) -> Result<Vec<Vec<u8>>> {
+        let EvmDb::InMemory { accounts, contracts } = &self.evm_db;
+        let mut block_hashes: Vec<_> = self.block_hashes.iter().collect();
+        block_hashes.par_sort_unstable_by_key(|(number, _)| *number);
+        let mut contracts: Vec<_> = contracts.iter().collect();
+        contracts.par_sort_unstable_by_key(|(code_hash, _)| *code_hash);
+        let mut accounts: Vec<_> = accounts.iter().collect();
+        accounts.par_sort_unstable_by_key(|(address, _)| *address);
+        let mut segments: Vec<FlatSegment<'_>> =
+            block_hashes.chunks(SEGMENT_ENTRIES).map(FlatSegment::BlockHashes).collect();
+        segments.extend(contracts.chunks(CONTRACTS_PER_SEGMENT).map(FlatSegment::Contracts));
+        let (mut start, mut entries) = (0, 0);
+        for (i, (_, account)) in accounts.iter().enumerate() {
+            entries += 1 + account.storage.len();
+            if entries >= SEGMENT_ENTRIES {
+                segments.push(FlatSegment::Accounts(&accounts[start..=i]));
+                (start, entries) = (i + 1, 0);
+            }
+        }
+        if start < accounts.len() {
+            segments.push(FlatSegment::Accounts(&accounts[start..]));
+        }
+        segments.into_par_iter().map(FlatSegment::encode).collect()
+    }
+
+    pub(crate) fn decode_flat(runs: &[&[u8]]) -> Result<Self> {
+        let segments: Vec<Segment> = runs.par_iter().map(|run| decode_flat_run(run)).collect::<Result<_>>()?;
+        let (mut accounts, mut contracts, mut block_hashes) = (Vec::new(), Vec::new(), Vec::new());
+        for segment in segments {
+            match segment {
+                Segment::BlockHashes(hashes) => block_hashes.extend(hashes),
+                Segment::Contracts(codes) => contracts.extend(codes),
+                Segment::Accounts(entries) => accounts.extend(entries),
+            }
+        }
+        Ok(Self { evm_db: EvmDb::InMemory { accounts, contracts }, block_hashes })
+    }
+}
+
+// Flat keys start with the kind of entry. An account's key is the tag and its address, and its storage slots' keys
+// append the slot, so they sort right after it.
+const FLAT_ACCOUNT: u8 = b'a';
+const FLAT_CONTRACT: u8 = b'c';
+const FLAT_BLOCK_HASH: u8 = b'h';
+
+enum FlatSegment<'a> {
+    BlockHashes(&'a [&'a (U256, B256)]),
+    Contracts(&'a [&'a (B256, Bytecode)]),
+    Accounts(&'a [&'a (Address, DbAccount)]),
+}
+
+fn flat_key(tag: u8, parts: &[&[u8]]) -> Vec<u8> {
+    let mut key = vec![tag];
+    for part in parts {
+        key.extend_from_slice(part);
+    }
+    key
+}
+
+// Big-endian without leading zeros
+fn trimmed(value: &U256) -> Vec<u8> {
+    let bytes = value.to_be_bytes::<32>();
+    let start = bytes.iter().position(|&byte| byte != 0).unwrap_or(bytes.len());
+    bytes[start..].to_vec()
+}
+
+fn untrimmed(bytes: &[u8]) -> Result<U256> {
+    U256::try_from_be_slice(bytes).ok_or_else(|| anyhow!("flat value of {} bytes is not a word", bytes.len()))
+}
+
+impl FlatSegment<'_> {
+    fn encode(self) -> Result<Vec<u8>> {
+        let mut run = RunWriter::default();
+        match self {
+            Self::BlockHashes(hashes) => {
+                for (number, hash) in hashes {
+                    run.push(&flat_key(FLAT_BLOCK_HASH, &[&number.to_be_bytes::<32>()]), hash.as_slice());
+                }
+            }
+            Self::Contracts(codes) => {
+                for (code_hash, bytecode) in codes {
+                    run.push(&flat_key(FLAT_CONTRACT, &[code_hash.as_slice()]), &bincode::serialize(bytecode)?);
+                }
+            }
+            Self::Accounts(accounts) => {
+                for (address, DbAccount { info, storage }) in accounts {
+                    let key = flat_key(FLAT_ACCOUNT, &[address.as_slice()]);
+                    // The balance, the nonce, and the code hash unless there is no code
+                    let mut value = Vec::new();
+                    let balance = trimmed(&info.balance);
+                    write_varint(&mut value, balance.len() as u64);
+                    value.extend(balance);
+                    write_varint(&mut value, info.nonce);
+                    if info.code_hash != KECCAK_EMPTY {
+                        value.extend_from_slice(info.code_hash.as_slice());
+                    }
+                    run.push(&key, &value);
+                    let mut storage: Vec<_> = storage.iter().collect();
+                    storage.sort_unstable_by_key(|(slot, _)| *slot);
+                    for (slot, value) in storage {
+                        run.push(
+                            &flat_key(FLAT_ACCOUNT, &[address.as_slice(), &slot.to_be_bytes::<32>()]),
+                            &trimmed(value),
+                        );
+                    }
+                }
+            }
+        }
+        Ok(run.finish())
+    }
+}
+
+fn decode_flat_account(mut value: &[u8]) -> Result<DbAccountInfo> {
+    let len = usize::try_from(read_varint(&mut value)?)?;
+    if len > value.len() {
+        return Err(anyhow!("flat account balance of {len} bytes is truncated"));
+    }
+    let (balance, mut rest) = value.split_at(len);
+    let balance = untrimmed(balance)?;
+    let nonce = read_varint(&mut rest)?;
+    let code_hash = if rest.is_empty() { KECCAK_EMPTY } else { B256::try_from(rest)? };
+    Ok(DbAccountInfo { balance, nonce, code_hash })
+}
+
+fn decode_flat_run(run: &[u8]) -> Result<Segment> {
+    let mut reader = RunReader::new(run);
+    let (mut accounts, mut contracts, mut block_hashes) = (Vec::new(), Vec::new(), Vec::new());
+    while let Some((key, value)) = reader.next_entry()? {
+        match (key.first().copied(), key.len()) {
+            (Some(FLAT_BLOCK_HASH), 33) => {
+                block_hashes.push((untrimmed(&key[1..])?, B256::try_from(value)?));
+            }
+            (Some(FLAT_CONTRACT), 33) => contracts.push((B256::from_slice(&key[1..]), bincode::deserialize(value)?)),
+            (Some(FLAT_ACCOUNT), 21) => {
+                let info = decode_flat_account(value)?;
+                accounts.push((Address::from_slice(&key[1..]), DbAccount { info, storage: Vec::new() }));
+            }
+            (Some(FLAT_ACCOUNT), 53) => {
+                let (address, account) =
+                    accounts.last_mut().ok_or_else(|| anyhow!("flat storage slot before any account"))?;
+                if address.as_slice() != &key[1..21] {
+                    return Err(anyhow!(
+                        "flat storage slot of {} follows account {address}",
+                        Address::from_slice(&key[1..21])
+                    ));
+                }
+                account.storage.push((untrimmed(&key[21..])?, untrimmed(value)?));
+            }
+            _ => return Err(anyhow!("unknown flat entry key {}", alloy::hex::encode(key))),
+        }
+    }
+    Ok(match (accounts.is_empty(), contracts.is_empty()) {
+        (false, _) => Segment::Accounts(accounts),
+        (true, false) => Segment::Contracts(contracts),
+        (true, true) => Segment::BlockHashes(block_hashes),
+    })
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AccountSummary {
+    pub address: Address,
+    pub balance: U256,
+    pub nonce: u64,
+    pub code_hash: B256,
+    pub storage_slots: usize,
+}
+
+impl AccountSummary {
+    pub fn has_code(&self) -> bool {
+        self.code_hash != KECCAK_EMPTY
+    }
+}
+
+impl EvmState {
+    pub(crate) fn account_summaries(&self) -> Vec<AccountSummary> {
+        let EvmDb::InMemory { accounts, .. } = &self.evm_db;
+        let mut summaries: Vec<_> = accounts
+            .iter()
+            .map(|(address, DbAccount { info, storage })| AccountSummary {
+                address: *address,
+                balance: info.balance,
+                nonce: info.nonce,
+                code_hash: info.code_hash,
+                storage_slots: storage.len(),
+            })
+            .collect();
+        summaries.par_sort_unstable_by_key(|summary| summary.address);
+        summaries
+    }
+}
+
+// Walks the accounts of flat runs in the order they were written, which is address order, until `visit` returns
+// false. Storage values and code are skipped rather than decoded, and runs of other entries after their first one.
+pub(crate) fn visit_flat_accounts(runs: &[&[u8]], visit: &mut dyn FnMut(AccountSummary) -> Result<bool>) -> Result<()> {
+    for run in runs {
+        let mut reader = RunReader::new(run);
+        let mut current: Option<AccountSummary> = None;
+        while let Some((key, value)) = reader.next_entry()? {
+            match (key.first().copied(), key.len()) {
+                (Some(FLAT_ACCOUNT), 21) => {
+                    if let Some(summary) = current.take() {
+                        if !visit(summary)? {
+                            return Ok(());
+                        }
+                    }
+                    let DbAccountInfo { balance, nonce, code_hash } = decode_flat_account(value)?;
+                    let address = Address::from_slice(&key[1..]);
+                    current = Some(AccountSummary { address, balance, nonce, code_hash, storage_slots: 0 });
+                }
+                (Some(FLAT_ACCOUNT), 53) => {
+                    current.as_mut().ok_or_else(|| anyhow!("flat storage slot before any account"))?.storage_slots += 1;
+                }
+                _ => break,
+            }
+        }
+        if let Some(summary) = current {
+            if !visit(summary)? {
+                return Ok(());
+            }
+        }
+    }
+    Ok(())
+}
+
 impl From<LegacyReceipt> for Receipt {
     fn from(value: LegacyReceipt) -> Self {
         let LegacyReceipt { tx_type, success, cumulative_gas_used, logs } = value;
@@ -0,0 +1,153 @@
+use crate::{
+    bench::StageTimings,
+    chainspec::ChainSpec,
+    cli::CHUNK_SIZE,
+    fs::{read_blocks, read_evm_state, read_snapshot_header, BlockSource},
+    inspector::ReplayInspector,
+    run::{run_blocks, RunConfig},
+    state::State,
+    status::SyncStatus,
+};
+use alloy::primitives::Address;
+use anyhow::{anyhow, Result};
+use rayon::{
+    iter::{IntoParallelIterator, ParallelIterator},
+    ThreadPoolBuilder,
+};
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    panic::{catch_unwind, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Instant,
+};
+
+// only store this many blocks of an interval in memory
+const READ_LIMIT: u64 = 100000;
+
+#[derive(Debug, Serialize)]
+pub struct IntervalResult {
+    pub from_snapshot: String,
+    pub to_snapshot: String,
+    pub start_block: u64,
+    pub end_block: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub duration_secs: f64,
+}
+
+impl Display for IntervalResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let status = self.error.as_deref().unwrap_or("ok");
+        write!(
+            f,
+            "{}-{} ({} -> {}): {status} in {:.1}s",
+            self.start_block, self.end_block, self.from_snapshot, self.to_snapshot, self.duration_secs
+        )
+    }
+}
+
+// Expands directories to the .rmp snapshots they contain and orders everything by the block each snapshot resumes at
+pub fn collect_snapshots(paths: &[PathBuf]) -> Result<Vec<(u64, PathBuf)>> {
+    let mut flns = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            for entry in std::fs::read_dir(path)? {
+                let entry = entry?.path();
+                if entry.extension().is_some_and(|ext| ext == "rmp") {
+                    flns.push(entry);
+                }
+            }
+        } else {
+            flns.push(path.clone());
+        }
+    }
+    let mut snapshots = Vec::new();
+    for fln in flns {
+        snapshots.push((read_snapshot_header(&fln)?.next_block_num, fln));
+    }
+    snapshots.sort();
+    snapshots.dedup_by_key(|(next_block_num, _)| *next_block_num);
+    Ok(snapshots)
+}
+
+fn validate_interval(
+    spec: &ChainSpec,
+    erc20_contract_to_system_address: BTreeMap<Address, Address>,
+    source: &BlockSource,
+    from: &Path,
+    to: &Path,
+) -> Result<()> {
+    let (start_block, mut state) = read_evm_state(from)?;
+    let (next_block_num, expected_state) = read_evm_state(to)?;
+    let expected = expected_state.blake3_hash_slow();
+    drop(expected_state);
+    let end_block = next_block_num - 1;
+    // Only the final state of the interval is hashed
+    let config = RunConfig {
+        spec: spec.clone(),
+        chunk_size: u64::MAX,
+        status: Arc::new(SyncStatus::new(start_block, end_block)),
+        ..RunConfig::new(spec.chain, erc20_contract_to_system_address)
+    };
+    let mut timings = StageTimings::default();
+    let mut inspector = ReplayInspector::default();
+    let mut computed = None;
+    let mut cur_block = start_block;
+    while cur_block <= end_block {
+        let last_block_in_chunk = end_block.min(cur_block + READ_LIMIT - 1);
+        let blocks = read_blocks(source, cur_block, last_block_in_chunk, CHUNK_SIZE, &mut timings)?;
+        computed = run_blocks(None, &config, &mut state, blocks, &mut timings, &mut inspector)?.or(computed);
+        cur_block = last_block_in_chunk + 1;
+    }
+    match computed {
+        Some(computed) if computed == expected => Ok(()),
+        Some(computed) => Err(anyhow!("state hash mismatch: computed {computed:?}, snapshot {expected:?}")),
+        None => Err(anyhow!("no state hash computed")),
+    }
+}
+
+// Replays the blocks between every pair of consecutive snapshots, up to `jobs` intervals at a time, checking that
+// each interval ends in the next snapshot's state
+pub fn validate_range(
+    spec: &ChainSpec,
+    erc20_contract_to_system_address: &BTreeMap<Address, Address>,
+    source: &BlockSource,
+    snapshots: &[(u64, PathBuf)],
+    jobs: usize,
+) -> Result<Vec<IntervalResult>> {
+    let intervals: Vec<_> = snapshots.windows(2).map(|pair| (&pair[0], &pair[1])).collect();
+    let pool = ThreadPoolBuilder::new().num_threads(jobs).build()?;
+    Ok(pool.install(|| {
+        intervals
+            .into_par_iter()
+            .map(|((start_block, from), (next_block_num, to))| {
+                let start = Instant::now();
+                let res = catch_unwind(AssertUnwindSafe(|| {
+                    validate_interval(spec, erc20_contract_to_system_address.clone(), source, from, to)
+                }));
+                let error = match res {
+                    Ok(Ok(())) => None,
+                    Ok(Err(e)) => Some(e.to_string()),
+                    Err(panic) => Some(
+                        panic
+                            .downcast_ref::<String>()
+                            .cloned()
+                            .or_else(|| panic.downcast_ref::<&str>().map(ToString::to_string))
+                            .unwrap_or_else(|| "replay panicked".to_owned()),
+                    ),
+                };
+                IntervalResult {
+                    from_snapshot: from.display().to_string(),
+                    to_snapshot: to.display().to_string(),
+                    start_block: *start_block,
+                    end_block: next_block_num - 1,
+                    error,
+                    duration_secs: start.elapsed().as_secs_f64(),
+                }
+            })
+            .collect()
+    }))
+}
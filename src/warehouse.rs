@@ -0,0 +1,166 @@
+use crate::{
+    http::blocking_client_builder,
+    sink::{Batch, SinkWriter},
+};
+use anyhow::{anyhow, Result};
+use postgres::{Client, NoTls};
+use reqwest::{blocking, Url};
+use std::time::Duration;
+
+// Rows are keyed so that blocks written twice, e.g. by syncs of overlapping ranges into one database, do not duplicate
+const POSTGRES_SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS blocks (
+    chain TEXT, number BIGINT, hash TEXT, timestamp BIGINT, gas_used BIGINT, transactions BIGINT,
+    system_transactions BIGINT, PRIMARY KEY (chain, number)
+);
+CREATE TABLE IF NOT EXISTS transactions (
+    chain TEXT, block BIGINT, tx_index BIGINT, is_system_tx BOOLEAN, hash TEXT, sender TEXT, \"to\" TEXT, status TEXT,
+    gas_used BIGINT, PRIMARY KEY (chain, block, is_system_tx, tx_index)
+);
+CREATE TABLE IF NOT EXISTS logs (
+    chain TEXT, block BIGINT, tx_index BIGINT, is_system_tx BOOLEAN, log_index BIGINT, address TEXT, topics TEXT[],
+    data TEXT, PRIMARY KEY (chain, block, log_index)
+);
+CREATE TABLE IF NOT EXISTS account_diffs (
+    chain TEXT, block BIGINT, address TEXT, balance_before NUMERIC, balance_after NUMERIC, nonce_before BIGINT,
+    nonce_after BIGINT, code_hash_after TEXT, storage_cleared BOOLEAN, PRIMARY KEY (chain, block, address)
+);
+CREATE TABLE IF NOT EXISTS storage_diffs (
+    chain TEXT, block BIGINT, address TEXT, slot NUMERIC, before NUMERIC, after NUMERIC,
+    PRIMARY KEY (chain, block, address, slot)
+);
+CREATE TABLE IF NOT EXISTS sink_offsets (chain TEXT PRIMARY KEY, block BIGINT);
+";
+
+pub struct Postgres {
+    client: Client,
+}
+
+impl Postgres {
+    pub fn connect(url: &str) -> Result<Self> {
+        let mut client = Client::connect(url, NoTls)?;
+        client.batch_execute(POSTGRES_SCHEMA)?;
+        Ok(Self { client })
+    }
+}
+
+impl SinkWriter for Postgres {
+    fn offset(&mut self, chain: &str) -> Result<Option<u64>> {
+        let row = self.client.query_opt("SELECT block FROM sink_offsets WHERE chain = $1", &[&chain])?;
+        Ok(row.map(|row| row.get::<_, i64>(0).try_into()).transpose()?)
+    }
+
+    // One statement per table, with the rows passed as a single JSON array, and the offset in the same transaction
+    fn write(&mut self, chain: &str, batch: &Batch) -> Result<()> {
+        let mut transaction = self.client.transaction()?;
+        for (table, rows) in batch.tables()? {
+            let statement = format!(
+                "INSERT INTO {table} SELECT * FROM json_populate_recordset(NULL::{table}, $1) ON CONFLICT DO NOTHING"
+            );
+            transaction.execute(&statement, &[&rows])?;
+        }
+        transaction.execute(
+            "INSERT INTO sink_offsets (chain, block) VALUES ($1, $2) ON CONFLICT (chain) DO UPDATE SET block = $2",
+            &[&chain, &i64::try_from(batch.last_block)?],
+        )?;
+        transaction.commit()?;
+        Ok(())
+    }
+}
+
+// ReplacingMergeTree drops rows written twice when parts are merged
+const CLICKHOUSE_SCHEMA: [&str; 6] = [
+    "CREATE TABLE IF NOT EXISTS blocks (chain String, number UInt64, hash String, timestamp UInt64, gas_used UInt64, \
+     transactions UInt64, system_transactions UInt64) ENGINE = ReplacingMergeTree ORDER BY (chain, number)",
+    "CREATE TABLE IF NOT EXISTS transactions (chain String, block UInt64, tx_index UInt64, is_system_tx Bool, hash \
+     Nullable(String), sender String, `to` Nullable(String), status String, gas_used UInt64) ENGINE = \
+     ReplacingMergeTree ORDER BY (chain, block, is_system_tx, tx_index)",
+    "CREATE TABLE IF NOT EXISTS logs (chain String, block UInt64, tx_index UInt64, is_system_tx Bool, log_index \
+     UInt64, address String, topics Array(String), data String) ENGINE = ReplacingMergeTree ORDER BY (chain, block, \
+     log_index)",
+    "CREATE TABLE IF NOT EXISTS account_diffs (chain String, block UInt64, address String, balance_before \
+     Nullable(UInt256), balance_after Nullable(UInt256), nonce_before Nullable(UInt64), nonce_after Nullable(UInt64), \
+     code_hash_after Nullable(String), storage_cleared Bool) ENGINE = ReplacingMergeTree ORDER BY (chain, block, \
+     address)",
+    "CREATE TABLE IF NOT EXISTS storage_diffs (chain String, block UInt64, address String, slot UInt256, before \
+     UInt256, after UInt256) ENGINE = ReplacingMergeTree ORDER BY (chain, block, address, slot)",
+    "CREATE TABLE IF NOT EXISTS sink_offsets (chain String, block UInt64) ENGINE = ReplacingMergeTree(block) ORDER BY \
+     chain",
+];
+const CLICKHOUSE_PORT: u16 = 8123;
+const CLICKHOUSE_TIMEOUT: Duration = Duration::from_secs(60);
+
+// Talks to the HTTP interface, given as clickhouse://[user[:password]@]host[:port][/database]
+pub struct ClickHouse {
+    client: blocking::Client,
+    url: Url,
+    user: String,
+    password: String,
+}
+
+impl ClickHouse {
+    pub fn connect(url: &str) -> Result<Self> {
+        let parsed = Url::parse(url)?;
+        let host = parsed.host_str().ok_or_else(|| anyhow!("no host in {url}"))?;
+        let mut http = Url::parse(&format!("http://{host}:{}/", parsed.port().unwrap_or(CLICKHOUSE_PORT)))?;
+        if let Some(database) =
+            parsed.path_segments().and_then(|mut segments| segments.next()).filter(|s| !s.is_empty())
+        {
+            http.query_pairs_mut().append_pair("database", database);
+        }
+        let user = if parsed.username().is_empty() { "default" } else { parsed.username() };
+        let sink = Self {
+            client: blocking_client_builder().timeout(CLICKHOUSE_TIMEOUT).build()?,
+            url: http,
+            user: user.to_owned(),
+            password: parsed.password().unwrap_or_default().to_owned(),
+        };
+        for statement in CLICKHOUSE_SCHEMA {
+            sink.query(statement, String::new())?;
+        }
+        Ok(sink)
+    }
+
+    fn query(&self, query: &str, body: String) -> Result<String> {
+        let mut url = self.url.clone();
+        url.query_pairs_mut().append_pair("query", query);
+        let response = self
+            .client
+            .post(url)
+            .header("X-ClickHouse-User", &self.user)
+            .header("X-ClickHouse-Key", &self.password)
+            .body(body)
+            .send()?;
+        let status = response.status();
+        let text = response.text()?;
+        if !status.is_success() {
+            return Err(anyhow!("ClickHouse returned {status}: {}", text.trim()));
+        }
+        Ok(text)
+    }
+}
+
+impl SinkWriter for ClickHouse {
+    fn offset(&mut self, chain: &str) -> Result<Option<u64>> {
+        let text = self.query(
+            &format!("SELECT block FROM sink_offsets FINAL WHERE chain = '{chain}' FORMAT TabSeparated"),
+            String::new(),
+        )?;
+        Ok(text.lines().next().map(str::parse).transpose()?)
+    }
+
+    // ClickHouse has no transactions, so the offset is only advanced once every table's rows are in
+    fn write(&mut self, chain: &str, batch: &Batch) -> Result<()> {
+        for (table, rows) in batch.tables()? {
+            let rows = rows.as_array().map(Vec::as_slice).unwrap_or_default();
+            if rows.is_empty() {
+                continue;
+            }
+            let body = rows.iter().map(ToString::to_string).collect::<Vec<_>>().join("\n");
+            self.query(&format!("INSERT INTO {table} FORMAT JSONEachRow"), body)?;
+        }
+        let offset = serde_json::json!({ "chain": chain, "block": batch.last_block });
+        self.query("INSERT INTO sink_offsets FORMAT JSONEachRow", offset.to_string())?;
+        Ok(())
+    }
+}
@@ -0,0 +1,276 @@
+use crate::{
+    diff::{AccountFields, SlotDiff, StateDiff},
+    error::ErrorKind,
+    events::{SyncEvent, SyncEvents},
+    hooks::SyncHooks,
+    notify::{Notification, Notifier},
+};
+use alloy::primitives::{Address, U256};
+use anyhow::{anyhow, Context, Result};
+use reth_primitives::SealedBlock;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    str::FromStr,
+    sync::Arc,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Slots {
+    Any,
+    Only(BTreeSet<U256>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Threshold {
+    Below(U256),
+    Above(U256),
+}
+
+impl Threshold {
+    fn holds(self, balance: U256) -> bool {
+        match self {
+            Self::Below(threshold) => balance < threshold,
+            Self::Above(threshold) => balance > threshold,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Condition {
+    // Any change to the accounts, storage included
+    Account(BTreeSet<Address>),
+    Storage(BTreeSet<Address>, Slots),
+    // Matches on the block the balance crosses the threshold, not on every block it stays past it
+    Balance(Address, Threshold),
+}
+
+// `[NAME=]account:ADDRS`, `[NAME=]storage:ADDRS:SLOTS`, or `[NAME=]balance:ADDR<WEI` (or `>WEI`). ADDRS is a comma
+// separated list, where `@FILE` stands for the addresses in FILE, one per line. SLOTS is a list of slots, or `*` for
+// any slot. The name defaults to the expression.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct WatchFilter {
+    pub name: String,
+    condition: Condition,
+}
+
+fn parse_addresses(list: &str) -> Result<BTreeSet<Address>> {
+    let mut addresses = BTreeSet::new();
+    for item in list.split(',').map(str::trim).filter(|item| !item.is_empty()) {
+        match item.strip_prefix('@') {
+            Some(path) => {
+                let contents =
+                    std::fs::read_to_string(path).with_context(|| format!("failed to read addresses from {path}"))?;
+                for line in contents.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')) {
+                    addresses.insert(line.parse().with_context(|| format!("invalid address {line} in {path}"))?);
+                }
+            }
+            None => {
+                addresses.insert(item.parse().with_context(|| format!("invalid address {item}"))?);
+            }
+        }
+    }
+    if addresses.is_empty() {
+        return Err(anyhow!("no addresses given"));
+    }
+    Ok(addresses)
+}
+
+fn parse_slots(list: &str) -> Result<Slots> {
+    if list == "*" {
+        return Ok(Slots::Any);
+    }
+    let slots = list
+        .split(',')
+        .map(|slot| U256::from_str(slot.trim()).with_context(|| format!("invalid slot {slot}")))
+        .collect::<Result<_>>()?;
+    Ok(Slots::Only(slots))
+}
+
+impl FromStr for WatchFilter {
+    type Err = anyhow::Error;
+
+    fn from_str(expression: &str) -> Result<Self> {
+        let parse = || -> Result<Self> {
+            let (name, rest) = match expression.split_once('=') {
+                Some((name, rest)) if !name.contains(':') => (name.to_owned(), rest),
+                _ => (expression.to_owned(), expression),
+            };
+            let (kind, args) = rest.split_once(':').ok_or_else(|| anyhow!("expected KIND:ARGS"))?;
+            let condition = match kind {
+                "account" => Condition::Account(parse_addresses(args)?),
+                "storage" => {
+                    let (addresses, slots) = args.rsplit_once(':').unwrap_or((args, "*"));
+                    Condition::Storage(parse_addresses(addresses)?, parse_slots(slots)?)
+                }
+                "balance" => {
+                    let i = args.find(['<', '>']).ok_or_else(|| anyhow!("expected ADDR<WEI or ADDR>WEI"))?;
+                    let address =
+                        args[..i].trim().parse().with_context(|| format!("invalid address {}", &args[..i]))?;
+                    let wei =
+                        U256::from_str(args[i + 1..].trim()).with_context(|| format!("invalid balance {args}"))?;
+                    let threshold =
+                        if args[i..].starts_with('<') { Threshold::Below(wei) } else { Threshold::Above(wei) };
+                    Condition::Balance(address, threshold)
+                }
+                _ => return Err(anyhow!("unknown kind {kind}: expected account, storage, or balance")),
+            };
+            Ok(Self { name, condition })
+        };
+        parse().with_context(|| format!("invalid watch filter {expression}")).context(ErrorKind::Usage)
+    }
+}
+
+impl TryFrom<String> for WatchFilter {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "change", rename_all = "snake_case")]
+pub enum WatchChange {
+    // None before an account existed, or after it was destroyed
+    Account { before: Option<AccountFields>, after: Option<AccountFields>, storage_slots: usize },
+    Storage { slots: BTreeMap<U256, SlotDiff> },
+    Balance { before: U256, after: U256 },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct WatchMatch {
+    pub filter: String,
+    pub block: u64,
+    pub address: Address,
+    #[serde(flatten)]
+    pub change: WatchChange,
+}
+
+impl WatchFilter {
+    fn matches(&self, block: u64, diff: &StateDiff) -> Vec<WatchMatch> {
+        let matched = |address: Address, change| WatchMatch { filter: self.name.clone(), block, address, change };
+        match &self.condition {
+            Condition::Account(addresses) => addresses
+                .iter()
+                .filter_map(|&address| {
+                    let account = diff.accounts.get(&address)?;
+                    let change = WatchChange::Account {
+                        before: account.before,
+                        after: account.after,
+                        storage_slots: account.storage.len(),
+                    };
+                    Some(matched(address, change))
+                })
+                .collect(),
+            Condition::Storage(addresses, slots) => addresses
+                .iter()
+                .filter_map(|&address| {
+                    let account = diff.accounts.get(&address)?;
+                    let changed: BTreeMap<_, _> = account
+                        .storage
+                        .iter()
+                        .filter(|(slot, value)| {
+                            value.before != value.after
+                                && match slots {
+                                    Slots::Any => true,
+                                    Slots::Only(slots) => slots.contains(slot),
+                                }
+                        })
+                        .map(|(&slot, &value)| (slot, value))
+                        .collect();
+                    (!changed.is_empty()).then(|| matched(address, WatchChange::Storage { slots: changed }))
+                })
+                .collect(),
+            Condition::Balance(address, threshold) => {
+                let Some(account) = diff.accounts.get(address) else {
+                    return Vec::new();
+                };
+                let balance = |fields: Option<AccountFields>| fields.map_or(U256::ZERO, |fields| fields.balance);
+                let (before, after) = (balance(account.before), balance(account.after));
+                if threshold.holds(before) || !threshold.holds(after) {
+                    return Vec::new();
+                }
+                vec![matched(*address, WatchChange::Balance { before, after })]
+            }
+        }
+    }
+}
+
+// Checks every committed block against the filters, reporting matches to the webhook and the event stream
+pub struct Watchlist {
+    filters: Vec<WatchFilter>,
+    notifier: Option<Notifier>,
+    events: Option<SyncEvents>,
+}
+
+impl Watchlist {
+    pub fn new(filters: Vec<WatchFilter>, notifier: Option<Notifier>, events: Option<SyncEvents>) -> Self {
+        Self { filters, notifier, events }
+    }
+
+    fn matches(&self, block: u64, diff: &StateDiff) -> Vec<WatchMatch> {
+        self.filters.iter().flat_map(|filter| filter.matches(block, diff)).collect()
+    }
+}
+
+impl SyncHooks for Watchlist {
+    fn on_block_committed(&self, block: &SealedBlock, diff: &StateDiff) {
+        for matched in self.matches(block.number, diff) {
+            println!("Watch {} matched {} in block {}", matched.filter, matched.address, matched.block);
+            if let Some(events) = &self.events {
+                let _ = events.send(SyncEvent::WatchMatched(Arc::new(matched.clone())));
+            }
+            if let Some(notifier) = &self.notifier {
+                notifier.notify(Notification::WatchMatched(matched));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        diff::{AccountDiff, AccountFields, SlotDiff, StateDiff},
+        watch::{WatchChange, WatchFilter, Watchlist},
+    };
+    use alloy::primitives::{Address, B256, U256};
+
+    #[test]
+    fn test_watch_filters() {
+        let (multisig, whale) = (Address::repeat_byte(1), Address::repeat_byte(2));
+        let fields =
+            |balance: u64| Some(AccountFields { balance: U256::from(balance), nonce: 0, code_hash: B256::ZERO });
+        let slot = |before: u64, after: u64| SlotDiff { before: U256::from(before), after: U256::from(after) };
+        let mut diff = StateDiff::default();
+        diff.accounts.insert(
+            multisig,
+            AccountDiff {
+                before: fields(0),
+                after: fields(0),
+                storage: [(U256::from(3), slot(1, 2)), (U256::from(4), slot(5, 5))].into(),
+                ..AccountDiff::default()
+            },
+        );
+        diff.accounts.insert(whale, AccountDiff { before: fields(500), after: fields(50), ..AccountDiff::default() });
+
+        let filters = [
+            format!("owners=storage:{multisig}:3,4"),
+            format!("storage:{multisig}:5"),
+            format!("drained=balance:{whale}<100"),
+            format!("balance:{whale}>100"),
+            format!("account:{whale},{}", Address::repeat_byte(3)),
+        ];
+        let watchlist = Watchlist::new(filters.iter().map(|filter| filter.parse().unwrap()).collect(), None, None);
+        let matches = watchlist.matches(7, &diff);
+        let names: Vec<_> = matches.iter().map(|matched| matched.filter.as_str()).collect();
+        assert_eq!(names, ["owners", "drained", filters[4].as_str()]);
+        // Slot 4 was written with the value it held
+        assert_eq!(matches[0].change, WatchChange::Storage { slots: [(U256::from(3), slot(1, 2))].into() });
+        assert_eq!(matches[1].change, WatchChange::Balance { before: U256::from(500), after: U256::from(50) });
+
+        assert!("balance:0x01".parse::<WatchFilter>().is_err());
+        assert!("code:0x01".parse::<WatchFilter>().is_err());
+    }
+}
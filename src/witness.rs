@@ -0,0 +1,118 @@
+use crate::{
+    diff::{AccountFields, StateDiff},
+    state::State,
+};
+use alloy::primitives::{Address, Bytes, B256, U256};
+use anyhow::Result;
+use revm::{
+    primitives::{AccountInfo, Bytecode},
+    Database, InMemoryDB,
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fs::create_dir_all, path::Path};
+
+// Everything a block's execution read from the pre-block state: each entry holds the value as of the start of the
+// block, so the block can be re-executed against the witness alone
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockWitness {
+    pub block_number: u64,
+    // None for accounts that were read but did not exist
+    pub accounts: BTreeMap<Address, Option<AccountFields>>,
+    pub storage: BTreeMap<Address, BTreeMap<U256, U256>>,
+    pub contracts: BTreeMap<B256, Bytes>,
+    pub block_hashes: BTreeMap<u64, B256>,
+    // The changes the block made, for checking a re-execution against
+    pub post_state: StateDiff,
+}
+
+impl BlockWitness {
+    pub fn new(block_number: u64) -> Self {
+        Self { block_number, ..Self::default() }
+    }
+
+    // A partial state holding only what the block read; everything else reads as empty
+    pub fn into_state(self) -> InMemoryDB {
+        let Self { accounts, storage, contracts, block_hashes, .. } = self;
+        let mut state = InMemoryDB::default();
+        for (address, fields) in accounts {
+            let Some(AccountFields { balance, nonce, code_hash }) = fields else {
+                continue;
+            };
+            let code = contracts.get(&code_hash).map(|code| Bytecode::new_raw(code.clone()));
+            state.insert_account_info(address, AccountInfo { balance, nonce, code_hash, code });
+        }
+        for (address, slots) in storage {
+            for (slot, value) in slots {
+                state.insert_storage(address, slot, value);
+            }
+        }
+        for (block_num, hash) in block_hashes {
+            state.insert_block_hash(block_num, hash);
+        }
+        state
+    }
+
+    fn record_code(&mut self, code_hash: B256, code: &Bytecode) {
+        if !code.is_empty() {
+            self.contracts.entry(code_hash).or_insert_with(|| code.original_bytes());
+        }
+    }
+}
+
+// Only the first read of each item is recorded, since later reads in the same block may observe earlier transactions
+pub(crate) struct WitnessRecorder<'a, DB> {
+    pub db: DB,
+    pub witness: Option<&'a mut BlockWitness>,
+}
+
+impl<DB: Database> Database for WitnessRecorder<'_, DB> {
+    type Error = DB::Error;
+
+    fn basic(&mut self, address: Address) -> Result<Option<AccountInfo>, Self::Error> {
+        let info = self.db.basic(address)?;
+        if let Some(witness) = self.witness.as_deref_mut() {
+            if !witness.accounts.contains_key(&address) {
+                witness.accounts.insert(address, info.as_ref().filter(|info| !info.is_empty()).map(Into::into));
+                if let Some(AccountInfo { code_hash, code: Some(code), .. }) = &info {
+                    witness.record_code(*code_hash, code);
+                }
+            }
+        }
+        Ok(info)
+    }
+
+    fn code_by_hash(&mut self, code_hash: B256) -> Result<Bytecode, Self::Error> {
+        let code = self.db.code_by_hash(code_hash)?;
+        if let Some(witness) = self.witness.as_deref_mut() {
+            witness.record_code(code_hash, &code);
+        }
+        Ok(code)
+    }
+
+    fn storage(&mut self, address: Address, index: U256) -> Result<U256, Self::Error> {
+        let value = self.db.storage(address, index)?;
+        if let Some(witness) = self.witness.as_deref_mut() {
+            witness.storage.entry(address).or_default().entry(index).or_insert(value);
+        }
+        Ok(value)
+    }
+
+    fn block_hash(&mut self, number: u64) -> Result<B256, Self::Error> {
+        let hash = self.db.block_hash(number)?;
+        if let Some(witness) = self.witness.as_deref_mut() {
+            witness.block_hashes.entry(number).or_insert(hash);
+        }
+        Ok(hash)
+    }
+}
+
+pub fn write_witness(dir: &Path, witness: &BlockWitness) -> Result<String> {
+    create_dir_all(dir)?;
+    let path = dir.join(format!("{}.witness.rmp", witness.block_number));
+    std::fs::write(&path, rmp_serde::to_vec(witness)?)?;
+    Ok(path.display().to_string())
+}
+
+pub fn read_witness(fln: &Path) -> Result<BlockWitness> {
+    Ok(rmp_serde::from_slice(&std::fs::read(fln)?)?)
+}
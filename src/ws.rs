@@ -0,0 +1,95 @@
+use crate::events::{ExecutedBlock, SyncEvent, SyncEvents};
+use anyhow::Result;
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Query, State,
+    },
+    response::Response,
+    routing::get,
+    Router,
+};
+use serde::Deserialize;
+use std::net::SocketAddr;
+use tokio::{
+    net::TcpListener,
+    sync::broadcast::{error::RecvError, Receiver},
+};
+
+#[derive(Debug, Deserialize)]
+struct SubscribeParams {
+    #[serde(default)]
+    state_diffs: bool,
+}
+
+async fn subscribe(
+    ws: WebSocketUpgrade,
+    Query(params): Query<SubscribeParams>,
+    State(events): State<SyncEvents>,
+) -> Response {
+    let rx = events.subscribe();
+    ws.on_upgrade(move |socket| stream_blocks(socket, rx, params.state_diffs))
+}
+
+fn encode(block: &ExecutedBlock, state_diffs: bool) -> serde_json::Result<String> {
+    if state_diffs {
+        serde_json::to_string(block)
+    } else {
+        serde_json::to_string(&block.summary)
+    }
+}
+
+async fn stream_blocks(mut socket: WebSocket, mut rx: Receiver<SyncEvent>, state_diffs: bool) {
+    loop {
+        let block = match rx.recv().await {
+            Ok(SyncEvent::BlockExecuted(block)) => block,
+            Ok(_) => continue,
+            // Subscribers can detect the gap from the block numbers
+            Err(RecvError::Lagged(skipped)) => {
+                eprintln!("WebSocket subscriber lagged, skipped {skipped} events");
+                continue;
+            }
+            Err(RecvError::Closed) => break,
+        };
+        let Ok(text) = encode(&block, state_diffs) else {
+            continue;
+        };
+        if socket.send(Message::Text(text)).await.is_err() {
+            break;
+        }
+    }
+}
+
+async fn subscribe_watch(ws: WebSocketUpgrade, State(events): State<SyncEvents>) -> Response {
+    let rx = events.subscribe();
+    ws.on_upgrade(move |socket| stream_watch_matches(socket, rx))
+}
+
+async fn stream_watch_matches(mut socket: WebSocket, mut rx: Receiver<SyncEvent>) {
+    loop {
+        let matched = match rx.recv().await {
+            Ok(SyncEvent::WatchMatched(matched)) => matched,
+            Ok(_) => continue,
+            Err(RecvError::Lagged(skipped)) => {
+                eprintln!("WebSocket watch subscriber lagged, skipped {skipped} events");
+                continue;
+            }
+            Err(RecvError::Closed) => break,
+        };
+        let Ok(text) = serde_json::to_string(&*matched) else {
+            continue;
+        };
+        if socket.send(Message::Text(text)).await.is_err() {
+            break;
+        }
+    }
+}
+
+// Streams a JSON summary of every executed block to clients connected to /ws; pass ?state_diffs=true to also
+// receive the block's state changes (requires --ws-state-diffs). Matches of the --watch filters go to /watch.
+pub async fn serve_ws(addr: SocketAddr, events: SyncEvents) -> Result<()> {
+    let app = Router::new().route("/ws", get(subscribe)).route("/watch", get(subscribe_watch)).with_state(events);
+    let listener = TcpListener::bind(addr).await?;
+    axum::serve(listener, app).await?;
+    Ok(())
+}